@@ -0,0 +1,451 @@
+// Annotations from commented source
+//
+// utils/vrom.txt is the BIOS as as09 source with a comment on most lines.
+// It has no addresses past the banners over each routine, so importing it
+// walks the source along a ROM image, using the disassembler to size each
+// instruction and check it's the one the source says is there. Labels and
+// line comments end up keyed by address.
+//
+// The copy we have has lost text in places, a line cut off after its
+// mnemonic and everything up to somewhere further down gone. Anything that
+// doesn't line up drops the address until the source gives it again with a
+// banner or an LXXXX label.
+//
+// The image's sha1 is kept with them so a saved set can be checked against
+// the image it's used with, rom.dat and fastrom.dat differ by two bytes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use regex::Regex;
+use sha1::Sha1;
+
+use crate::cpu::AddrMode;
+use crate::diss::Disassembler;
+use crate::mem::{MemBlock, MemMap};
+use crate::symfile::{as09_banner, parse_value};
+use crate::symtab::SymbolTable;
+
+// BIOS images we know by sha1
+const KNOWN_IMAGES : &[(&str, &str)] = &[
+    ("65d07426b520ddd3115d40f255511e0fd2e20ae7", "rom.dat, the original BIOS"),
+    ("e0900be6d6858b985fd7f0999d864b2fceaf01a1", "fastrom.dat, the BIOS with a shorter power on screen"),
+];
+
+// as09 directives that don't make any bytes
+const NO_BYTES : &[&str] = &["code", "data", "bss", "opt", "noopt", "include", "end"];
+
+lazy_static! {
+    // Labels that are their own address
+    static ref ADDR_LABEL_RE : Regex = Regex::new(r"^L(?P<val>[[:xdigit:]]{4})$").unwrap();
+}
+
+pub fn image_sha1(image : &[u8]) -> String {
+    let mut m = Sha1::new();
+    m.update(image);
+    m.digest().to_string()
+}
+
+// What an image is if we know it, otherwise its sha1
+pub fn describe_image(sha1 : &str) -> String {
+    KNOWN_IMAGES
+        .iter()
+        .find(|(known, _)| *known == sha1)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("an image with sha1 {}", sha1))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub addr : u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels : Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment : Option<String>,
+    // Line of the source it came from
+    pub line : usize,
+}
+
+// How well a source lined up with the image
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImportStats {
+    // Instructions and data the image agreed with
+    pub matched : usize,
+    // Instructions that aren't what the image has
+    pub mismatched : usize,
+    // Lines cut off, followed by a gap
+    pub truncated : usize,
+    // Lines passed over until the address was known again
+    pub skipped : usize,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// An as09 line in its fields, FCC's operand can have spaces and semicolons
+// in it
+#[derive(Debug, Default)]
+struct As09Line<'a> {
+    label : Option<&'a str>,
+    op : Option<&'a str>,
+    operand : &'a str,
+    comment : Option<&'a str>,
+}
+
+fn is_space(c : char) -> bool {
+    c.is_whitespace()
+}
+
+impl<'a> As09Line<'a> {
+    fn split(line : &'a str) -> Self {
+        let mut ret = Self::default();
+        let mut rest = line.trim_end();
+
+        if rest.trim_start().starts_with(';') || rest.starts_with('*') {
+            return ret;
+        }
+
+        if !rest.starts_with(is_space) {
+            let end = rest.find(is_space).unwrap_or(rest.len());
+            ret.label = Some(rest[.. end].trim_end_matches(':')).filter(|l| !l.is_empty());
+            rest = &rest[end ..];
+        }
+
+        rest = rest.trim_start();
+
+        if !rest.is_empty() && !rest.starts_with(';') {
+            let end = rest.find(is_space).unwrap_or(rest.len());
+            ret.op = Some(&rest[.. end]);
+            rest = rest[end ..].trim_start();
+
+            let end = if ret.op.is_some_and(|op| op.eq_ignore_ascii_case("fcc")) {
+                // Up to the closing delimiter, all of it if there isn't one
+                let delim = rest.chars().next().unwrap_or(' ');
+                rest.char_indices().skip(1).find(|(_, c)| *c == delim).map_or(rest.len(), |(i, _)| i + 1)
+            } else if rest.starts_with(';') {
+                0
+            } else {
+                rest.find(is_space).unwrap_or(rest.len())
+            };
+
+            ret.operand = &rest[.. end];
+            rest = rest[end ..].trim_start();
+        }
+
+        ret.comment = rest.strip_prefix(';').map(|c| c.trim()).filter(|c| !c.is_empty());
+        ret
+    }
+}
+
+// Items in an fcb or fdb, None if there aren't any
+fn items(operand : &str) -> Option<usize> {
+    if operand.is_empty() {
+        None
+    } else {
+        Some(operand.split(',').count())
+    }
+}
+
+// Length of an fcc string, None if it's been cut off
+fn string_len(operand : &str) -> Option<usize> {
+    let delim = operand.chars().next()?;
+
+    if operand.len() >= 2 && operand.ends_with(delim) {
+        Some(operand.len() - 2)
+    } else {
+        None
+    }
+}
+
+// Addresses the source gives without needing to be counted to
+fn known_addrs(text : &str) -> HashMap<String, u16> {
+    let mut ret = HashMap::new();
+
+    for line in text.lines() {
+        if let Some((name, val)) = as09_banner(line) {
+            ret.entry(name).or_insert(val);
+        } else if let Some(label) = As09Line::split(line).label {
+            if let Some(caps) = ADDR_LABEL_RE.captures(label) {
+                if let Ok(val) = u16::from_str_radix(&caps["val"], 16) {
+                    ret.insert(label.to_string(), val);
+                }
+            }
+        }
+    }
+
+    ret
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Json
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonAnnotations {
+    sha1 : String,
+    base : u16,
+    len : usize,
+    annotations : Vec<Annotation>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    // Image they were made against
+    sha1 : String,
+    base : u16,
+    len : usize,
+    notes : BTreeMap<u16, Annotation>,
+    // From the import, nothing if they were loaded
+    stats : ImportStats,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Saved annotations that have to be for this image, or as09 source to
+    // import against it
+    pub fn load(file : &str, image : &[u8], base : u16) -> Result<Self, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+
+        let ret = if text.trim_start().starts_with('{') {
+            let ret = Self::from_json(&text)?;
+            ret.check(image, base)?;
+            ret
+        } else {
+            Self::import_as09(&text, image, base)?
+        };
+
+        Ok(ret)
+    }
+
+    pub fn import_as09(text : &str, image : &[u8], base : u16) -> Result<Self, String> {
+        let end = u32::from(base) + image.len() as u32;
+
+        if image.is_empty() || end > 0x1_0000 {
+            return Err(format!("{} bytes won't fit at ${:04x}", image.len(), base));
+        }
+
+        let in_image = |addr : u16, len : usize| addr >= base && u32::from(addr) + len as u32 <= end;
+
+        let mut mem = MemMap::new();
+        mem.add_block(MemBlock::from_data(base, "rom", image, false));
+
+        let mut diss = Disassembler::new();
+        let known = known_addrs(text);
+
+        let mut ret = Self {
+            sha1 : image_sha1(image),
+            base,
+            len : image.len(),
+            .. Default::default()
+        };
+
+        // None after a gap until the source says where it is again
+        let mut addr = None;
+
+        for (i, line) in text.lines().enumerate() {
+            if let Some((_, val)) = as09_banner(line) {
+                addr = Some(val);
+                continue;
+            }
+
+            let fields = As09Line::split(line);
+
+            if let Some(val) = fields.label.and_then(|l| known.get(l)) {
+                addr = Some(*val);
+            }
+
+            let here = match addr {
+                Some(here) => here,
+                None => {
+                    if fields.op.is_some() {
+                        ret.stats.skipped += 1;
+                    }
+                    continue;
+                }
+            };
+
+            let op = fields.op.map(|op| op.to_lowercase());
+
+            let len = match op.as_deref() {
+                None => Some(0),
+                Some(op) if NO_BYTES.contains(&op) => Some(0),
+
+                Some("org") => {
+                    addr = parse_value(fields.operand);
+                    continue;
+                }
+
+                Some("fcb") => items(fields.operand),
+                Some("fdb") => items(fields.operand).map(|n| n * 2),
+                Some("fcc") => string_len(fields.operand),
+
+                Some(op) => {
+                    let ins = if in_image(here, 1) { diss.decode(&mem, here) } else { None };
+
+                    match ins {
+                        Some(ref ins) if ins.name.split('_').any(|n| n == op) => {
+                            if fields.operand.is_empty() && ins.mode != AddrMode::Inherent {
+                                None
+                            } else {
+                                Some(ins.bytes.len())
+                            }
+                        }
+
+                        _ => {
+                            // A label the source gave the address of is
+                            // still right
+                            if let Some(label) = fields.label.filter(|l| known.contains_key(*l)) {
+                                ret.note(here, i + 1).labels.push(label.to_string());
+                            }
+
+                            ret.stats.mismatched += 1;
+                            addr = None;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            if !in_image(here, len.unwrap_or(0)) {
+                ret.stats.skipped += 1;
+                addr = None;
+                continue;
+            }
+
+            // What's left of a cut off line is still where it says
+            if fields.label.is_some() || fields.comment.is_some() {
+                let note = ret.note(here, i + 1);
+
+                note.labels.extend(fields.label.map(|l| l.to_string()));
+
+                if let Some(comment) = fields.comment {
+                    note.comment = Some(match note.comment.take() {
+                        Some(before) => format!("{} {}", before, comment),
+                        None => comment.to_string(),
+                    });
+                }
+            }
+
+            match len {
+                Some(len) => {
+                    if op.is_some() {
+                        ret.stats.matched += 1;
+                    }
+                    addr = Some(here.wrapping_add(len as u16));
+                }
+
+                None => {
+                    ret.stats.truncated += 1;
+                    addr = None;
+                }
+            }
+        }
+
+        if ret.notes.is_empty() {
+            return Err(format!("Nothing in the source lines up with {} at ${:04x}", describe_image(&ret.sha1), base));
+        }
+
+        info!("Annotated {} addresses in {}, {} lines matched, {} mismatched, {} cut off and {} skipped",
+              ret.notes.len(), describe_image(&ret.sha1), ret.stats.matched, ret.stats.mismatched, ret.stats.truncated, ret.stats.skipped);
+
+        Ok(ret)
+    }
+
+    fn note(&mut self, addr : u16, line : usize) -> &mut Annotation {
+        self.notes.entry(addr).or_insert(Annotation {
+            addr,
+            labels : vec![],
+            comment : None,
+            line,
+        })
+    }
+
+    // Ok if these were made against this image at this address
+    pub fn check(&self, image : &[u8], base : u16) -> Result<(), String> {
+        let sha1 = image_sha1(image);
+
+        if sha1 != self.sha1 || base != self.base {
+            return Err(format!("Annotations are for {} at ${:04x}, not {} at ${:04x}",
+                               describe_image(&self.sha1), self.base, describe_image(&sha1), base));
+        }
+
+        Ok(())
+    }
+
+    pub fn sha1(&self) -> &str {
+        &self.sha1
+    }
+
+    pub fn image(&self) -> String {
+        describe_image(&self.sha1)
+    }
+
+    pub fn stats(&self) -> ImportStats {
+        self.stats
+    }
+
+    // How many addresses have something
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn get(&self, addr : u16) -> Option<&Annotation> {
+        self.notes.get(&addr)
+    }
+
+    pub fn comment(&self, addr : u16) -> Option<&str> {
+        self.get(addr).and_then(|n| n.comment.as_deref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Annotation> {
+        self.notes.values()
+    }
+
+    // Labels the table doesn't already have
+    pub fn add_labels(&self, syms : &mut SymbolTable) {
+        for note in self.iter() {
+            for label in &note.labels {
+                if syms.get(label).is_none() {
+                    syms.add(label.clone(), note.addr);
+                }
+            }
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Json
+
+    pub fn to_json(&self) -> String {
+        let json = JsonAnnotations {
+            sha1 : self.sha1.clone(),
+            base : self.base,
+            len : self.len,
+            annotations : self.notes.values().cloned().collect(),
+        };
+
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+
+    pub fn from_json(text : &str) -> Result<Self, String> {
+        let json : JsonAnnotations = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            sha1 : json.sha1,
+            base : json.base,
+            len : json.len,
+            notes : json.annotations.into_iter().map(|n| (n.addr, n)).collect(),
+            stats : ImportStats::default(),
+        })
+    }
+
+    pub fn write(&self, file : &str) -> Result<(), String> {
+        fs::write(file, self.to_json()).map_err(|e| format!("{}: {}", file, e))
+    }
+}
@@ -0,0 +1,720 @@
+// A 6809 assembler
+//
+// Takes the lwasm flavour of source the programs in asm/ are written in,
+// labels in column 0, @local labels scoped to the last global one, equ,
+// struct and the usual data directives. Includes are pulled in up front and
+// then the source is passed over until the symbols stop moving, with a last
+// strict pass that turns unknown symbols and out of range values into errors.
+//
+// Like lwasm an operand ends at the first whitespace, anything after it is
+// comment.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::mem::MemoryIO;
+use crate::symtab::{SymbolTable, SymKind};
+
+use super::expr::{self, Scope, Value};
+use super::ops::{self, Target};
+
+// Passes allowed for the symbols to settle
+const MAX_PASSES : usize = 20;
+
+// How deep includes can nest
+const MAX_INCLUDE_DEPTH : usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file : String,
+    pub line : usize,
+    pub msg : String,
+}
+
+impl AsmError {
+    fn new(file : &str, line : usize, msg : &str) -> Self {
+        Self { file : file.to_string(), line, msg : msg.to_string() }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.msg)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Assembled code, chunks are (addr, bytes) in the order they were emitted
+#[derive(Debug, Default)]
+pub struct Assembled {
+    pub chunks : Vec<(u16, Vec<u8>)>,
+    pub symbols : SymbolTable,
+}
+
+impl Assembled {
+    // The chunks back to back with no padding, what lwasm -r writes
+    pub fn raw(&self) -> Vec<u8> {
+        self.chunks.iter().flat_map(|(_, data)| data.iter().cloned()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn upload<M : MemoryIO>(&self, mem : &mut M) {
+        for (addr, data) in &self.chunks {
+            mem.upload(*addr, data);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Line {
+    file : usize,
+    num : usize,
+    text : String,
+}
+
+// The label, lower cased opcode and operand of a line
+struct Fields<'a> {
+    label : Option<&'a str>,
+    op : Option<String>,
+    operand : &'a str,
+}
+
+fn split_line(text : &str) -> Fields<'_> {
+    let mut ret = Fields { label : None, op : None, operand : "" };
+
+    let trimmed = text.trim_start();
+
+    if trimmed.is_empty() || trimmed.starts_with(';') || text.starts_with('*') {
+        return ret;
+    }
+
+    let mut rest = text;
+
+    if !text.starts_with(char::is_whitespace) {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        ret.label = Some(text[..end].trim_end_matches(':'));
+        rest = &text[end..];
+    }
+
+    let rest = rest.trim_start();
+
+    if rest.is_empty() || rest.starts_with(';') {
+        return ret;
+    }
+
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let op = rest[..end].to_lowercase();
+    let rest = rest[end..].trim_start();
+
+    ret.operand = match op.as_str() {
+        // strings carry their own delimiters
+        "fcc" | "fcn" | "fcs" => rest.trim_end(),
+        _ => operand_token(rest),
+    };
+
+    ret.op = Some(op);
+    ret
+}
+
+// Operand up to whitespace or a comment, quotes and 'c constants can hold either
+fn operand_token(text : &str) -> &str {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' => i += 1,
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += 1;
+                }
+            }
+            b';' => break,
+            c if c.is_ascii_whitespace() => break,
+            _ => (),
+        }
+        i += 1;
+    }
+
+    &text[..i.min(bytes.len())]
+}
+
+// Items of a comma separated list, commas in quotes or 'c don't count
+fn split_list(text : &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut ret = vec![];
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' => i += 1,
+            b',' => {
+                ret.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    ret.push(&text[start.min(text.len())..]);
+    ret
+}
+
+// Text between the delimiters of "text" or /text/
+fn delimited(text : &str) -> Result<&str, String> {
+    let delim = text.chars().next().ok_or_else(|| "missing string".to_string())?;
+    let body = &text[delim.len_utf8()..];
+
+    match body.find(delim) {
+        Some(end) => Ok(&body[..end]),
+        None => Err(format!("unterminated string {}", text)),
+    }
+}
+
+fn file_name(text : &str) -> &str {
+    text.trim_matches('"')
+}
+
+fn is_local(name : &str) -> bool {
+    name.starts_with('@')
+}
+
+// A label on one of these names data rather than code
+fn is_data(op : &str) -> bool {
+    matches!(op, "fcb" | "fdb" | "fcc" | "fcn" | "fcs" | "rmb" | "rmd" | "zmb" | "zmd" | "fill" | "includebin")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Assembler {
+    dp : Option<u8>,
+    syms : SymbolTable,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        // lwasm assumes dp is 0 until told otherwise
+        Self { dp : Some(0), syms : SymbolTable::default() }
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Direct page to assume before any setdp, None never uses direct
+    // addressing unless it's forced with <
+    pub fn with_dp(mut self, dp : Option<u8>) -> Self {
+        self.dp = dp;
+        self
+    }
+
+    // Symbols the source can use without defining, like the BIOS entry points
+    pub fn with_symbols(mut self, syms : SymbolTable) -> Self {
+        self.syms = syms;
+        self
+    }
+
+    pub fn assemble_file(&self, file : &str) -> Result<Assembled, AsmError> {
+        let text = fs::read_to_string(file)
+            .map_err(|e| AsmError::new(file, 0, &e.to_string()))?;
+
+        self.assemble_str(file, &text)
+    }
+
+    // Assemble text as if it came from file, includes are relative to it
+    pub fn assemble_str(&self, file : &str, text : &str) -> Result<Assembled, AsmError> {
+        let mut files = vec![];
+        let mut lines = vec![];
+
+        flatten(Path::new(file), text, &mut files, &mut lines, 0)?;
+
+        let mut prev = HashMap::new();
+
+        for _ in 0..MAX_PASSES {
+            let pass = self.pass(&files, &lines, &prev, false)?;
+
+            if pass.syms == prev {
+                let done = self.pass(&files, &lines, &prev, true)?;
+                return Ok(done.into_assembled());
+            }
+
+            prev = pass.syms;
+        }
+
+        Err(AsmError::new(file, 0, "symbols didn't settle"))
+    }
+
+    fn pass<'a>(&'a self, files : &'a [String], lines : &[Line], prev : &'a HashMap<String, i64>, strict : bool) -> Result<Pass<'a>, AsmError> {
+        let mut pass = Pass::new(self, files, prev, strict);
+
+        for line in lines {
+            let more = pass.line(line).map_err(|msg| {
+                AsmError::new(&files[line.file], line.num, &msg)
+            })?;
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(pass)
+    }
+}
+
+// Assemble one line at addr, for patching from a debugger. The line has no
+// label field, it's the instruction or directive on its own.
+pub fn assemble_line(text : &str, addr : u16, syms : Option<&SymbolTable>, dp : Option<u8>) -> Result<Vec<u8>, String> {
+    let mut asm = Assembler::new().with_dp(dp);
+
+    if let Some(syms) = syms {
+        asm = asm.with_symbols(syms.clone());
+    }
+
+    let source = format!(" org ${:04x}\n {}\n", addr, text.trim());
+
+    asm.assemble_str("<line>", &source)
+        .map(|done| done.raw())
+        .map_err(|e| e.msg)
+}
+
+// Source with includes replaced by the lines they include
+fn flatten(path : &Path, text : &str, files : &mut Vec<String>, lines : &mut Vec<Line>, depth : usize) -> Result<(), AsmError> {
+    let file = files.len();
+    files.push(path.to_string_lossy().into_owned());
+
+    for (i, text) in text.lines().enumerate() {
+        let fields = split_line(text);
+
+        if fields.op.as_deref() == Some("include") {
+            let err = |msg : &str| AsmError::new(&files[file], i + 1, msg);
+
+            if depth == MAX_INCLUDE_DEPTH {
+                return Err(err("includes nested too deep"));
+            }
+
+            let inc = resolve(path, file_name(fields.operand));
+            let inc_text = fs::read_to_string(&inc)
+                .map_err(|e| err(&format!("{}: {}", inc.display(), e)))?;
+
+            flatten(&inc, &inc_text, files, lines, depth + 1)?;
+        } else {
+            lines.push(Line { file, num : i + 1, text : text.to_string() });
+        }
+    }
+
+    Ok(())
+}
+
+// Files are looked for next to the file including them, then from the cwd
+fn resolve(from : &Path, name : &str) -> PathBuf {
+    let local = from.parent().map(|dir| dir.join(name));
+
+    match local {
+        Some(path) if path.exists() => path,
+        _ => PathBuf::from(name),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Pass<'a> {
+    asm : &'a Assembler,
+    files : &'a [String],
+    // What the last pass ended up with, for forward references
+    prev : &'a HashMap<String, i64>,
+    strict : bool,
+
+    syms : HashMap<String, i64>,
+    kinds : HashMap<String, SymKind>,
+    chunks : Vec<(u16, Vec<u8>)>,
+    pc : u16,
+    dp : Option<u8>,
+    // Global label the @locals belong to
+    scope : String,
+    // Name and size so far of the struct being defined
+    in_struct : Option<(String, i64)>,
+    // Symbols that couldn't be found on the current line
+    missing : RefCell<Vec<String>>,
+}
+
+// Symbols defined further down the source have the last pass's value but
+// aren't known, like lwasm anything sized from them takes the long form
+impl<'a> Scope for Pass<'a> {
+    fn lookup(&self, name : &str) -> Option<Value> {
+        let key = self.key(name);
+
+        let ret = self.syms.get(&key).map(|v| Value::known(*v))
+            .or_else(|| self.prev.get(&key).map(|v| Value { val : *v, known : false }))
+            .or_else(|| self.asm.syms.lookup(name).map(|v| Value::known(i64::from(v))));
+
+        if ret.is_none() {
+            self.missing.borrow_mut().push(name.to_string());
+        }
+
+        ret
+    }
+}
+
+impl<'a> Pass<'a> {
+    fn new(asm : &'a Assembler, files : &'a [String], prev : &'a HashMap<String, i64>, strict : bool) -> Self {
+        Self {
+            asm, files, prev, strict,
+            syms : HashMap::new(),
+            kinds : HashMap::new(),
+            chunks : vec![],
+            pc : 0,
+            dp : asm.dp,
+            scope : String::new(),
+            in_struct : None,
+            missing : RefCell::new(vec![]),
+        }
+    }
+
+    fn into_assembled(self) -> Assembled {
+        let mut symbols = SymbolTable::default();
+
+        for (name, val) in self.syms {
+            if !name.contains("::") {
+                let kind = self.kinds.get(&name).copied().unwrap_or_default();
+                symbols.add_kind(name, val as u16, kind);
+            }
+        }
+
+        Assembled { chunks : self.chunks, symbols }
+    }
+
+    // @locals are stored as scope::@name
+    fn key(&self, name : &str) -> String {
+        if is_local(name) {
+            format!("{}::{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn define(&mut self, name : &str, val : i64, kind : SymKind, redefine : bool) -> Result<(), String> {
+        let key = self.key(name);
+
+        if !redefine && self.syms.contains_key(&key) {
+            return Err(format!("symbol {} redefined", name));
+        }
+
+        self.kinds.insert(key.clone(), kind);
+        self.syms.insert(key, val);
+        Ok(())
+    }
+
+    fn eval(&self, text : &str) -> Result<Value, String> {
+        let v = expr::eval(text, self.pc, self)?;
+        self.check_missing()?;
+        Ok(v)
+    }
+
+    // Where a value that isn't known yet is taken as 0, like an org or a count
+    fn eval_val(&self, text : &str) -> Result<i64, String> {
+        self.eval(text).map(|v| v.val)
+    }
+
+    // Unknown symbols are only an error once every label has had a chance
+    // to be defined
+    fn check_missing(&self) -> Result<(), String> {
+        let mut missing = self.missing.borrow_mut();
+
+        match missing.first() {
+            Some(name) if self.strict => Err(format!("undefined symbol {}", name)),
+            _ => {
+                missing.clear();
+                Ok(())
+            }
+        }
+    }
+
+    fn emit(&mut self, bytes : &[u8]) {
+        let pc = self.pc;
+
+        match self.chunks.last_mut() {
+            Some((start, data)) if start.wrapping_add(data.len() as u16) == pc && !data.is_empty() => {
+                data.extend_from_slice(bytes)
+            }
+            _ => self.chunks.push((pc, bytes.to_vec())),
+        }
+
+        self.pc = pc.wrapping_add(bytes.len() as u16);
+    }
+
+    fn check(&self, v : Value, lo : i64, hi : i64) -> Result<i64, String> {
+        ops::in_range(v, lo, hi, self.strict)
+    }
+
+    // Returns false at an end directive
+    fn line(&mut self, line : &Line) -> Result<bool, String> {
+        let fields = split_line(&line.text);
+        let op = fields.op.as_deref();
+        let operand = fields.operand;
+
+        if self.in_struct.is_some() {
+            self.struct_line(&fields)?;
+            return Ok(true);
+        }
+
+        let label = fields.label;
+
+        match op {
+            Some("equ") | Some("=") | Some("set") => {
+                let label = label.ok_or_else(|| "missing label".to_string())?;
+                let v = expr::eval(operand, self.pc, self)?;
+                let resolved = self.missing.borrow().is_empty();
+                self.check_missing()?;
+
+                if resolved {
+                    self.define(label, v.val, SymKind::Equate, op == Some("set"))?;
+                }
+
+                return Ok(true);
+            }
+
+            Some("struct") => {
+                let label = label.ok_or_else(|| "struct needs a name".to_string())?;
+                self.in_struct = Some((label.to_string(), 0));
+                return Ok(true);
+            }
+
+            _ => (),
+        }
+
+        if let Some(label) = label {
+            let kind = match op {
+                Some(op) if is_data(op) => SymKind::Data,
+                _ => SymKind::Code,
+            };
+
+            self.define(label, i64::from(self.pc), kind, false)?;
+
+            if !is_local(label) {
+                self.scope = label.to_string();
+            }
+        }
+
+        let op = match op {
+            Some(op) => op,
+            None => return Ok(true),
+        };
+
+        match op {
+            "org" => self.pc = self.eval_val(operand)? as u16,
+
+            "setdp" => {
+                let v = self.eval_val(operand)?;
+                self.dp = if v < 0 { None } else { Some(v as u8) };
+            }
+
+            "fcb" => {
+                for item in split_list(operand) {
+                    let v = self.eval(item)?;
+                    let b = self.check(v, -0x80, 0xff)? as u8;
+                    self.emit(&[b]);
+                }
+            }
+
+            "fdb" => {
+                for item in split_list(operand) {
+                    let v = self.eval(item)?;
+                    let w = self.check(v, -0x8000, 0xffff)? as u16;
+                    self.emit(&w.to_be_bytes());
+                }
+            }
+
+            "fcc" | "fcn" | "fcs" => {
+                let mut bytes = delimited(operand)?.as_bytes().to_vec();
+
+                match op {
+                    "fcn" => bytes.push(0),
+                    "fcs" => if let Some(last) = bytes.last_mut() {
+                        *last |= 0x80
+                    },
+                    _ => (),
+                }
+
+                self.emit(&bytes);
+            }
+
+            "rmb" | "rmd" => {
+                let size = if op == "rmd" { 2 } else { 1 };
+                let n = self.eval_val(operand)?;
+                self.pc = self.pc.wrapping_add((n * size) as u16);
+            }
+
+            "zmb" | "zmd" => {
+                let size = if op == "zmd" { 2 } else { 1 };
+                let n = self.eval_val(operand)?;
+                self.emit(&vec![0; (n * size) as usize]);
+            }
+
+            "fill" => {
+                let items = split_list(operand);
+
+                if items.len() != 2 {
+                    return Err("fill needs a value and a count".to_string());
+                }
+
+                let v = self.eval(items[0])?;
+                let b = self.check(v, -0x80, 0xff)? as u8;
+                let n = self.eval_val(items[1])?;
+                self.emit(&vec![b; n as usize]);
+            }
+
+            "includebin" => {
+                let path = resolve(Path::new(&self.files[line.file]), file_name(operand));
+                let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                self.emit(&data);
+            }
+
+            "end" => return Ok(false),
+
+            "endstruct" | "ends" => return Err("endstruct without struct".to_string()),
+
+            _ => {
+                let bytes = {
+                    let target = Target { pc : self.pc, dp : self.dp, scope : self, strict : self.strict };
+                    ops::assemble(op, operand, &target)?
+                };
+
+                self.check_missing()?;
+                self.emit(&bytes);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Fields define struct.field as an offset, endstruct defines sizeof{struct}
+    fn struct_line(&mut self, fields : &Fields) -> Result<(), String> {
+        let (name, offset) = self.in_struct.clone().unwrap();
+
+        let size = match fields.op.as_deref() {
+            None => 0,
+            Some("rmb") => self.eval_val(fields.operand)?,
+            Some("rmd") => self.eval_val(fields.operand)? * 2,
+            Some("endstruct") | Some("ends") => {
+                self.define(&format!("sizeof{{{}}}", name), offset, SymKind::Equate, false)?;
+                self.in_struct = None;
+                return Ok(());
+            }
+            Some(op) => return Err(format!("{} can't be used in a struct", op)),
+        };
+
+        if let Some(label) = fields.label {
+            self.define(&format!("{}.{}", name, label), offset, SymKind::Equate, false)?;
+        }
+
+        self.in_struct = Some((name, offset + size));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::romdiss::RomDisassembler;
+
+    fn assemble(text : &str) -> Vec<u8> {
+        Assembler::new().assemble_str("test.s", text).unwrap().raw()
+    }
+
+    // Disassembles data and checks the source assembles back to it
+    fn round_trip(file : &str, base : u16, syms : Option<&str>) {
+        let data = fs::read(file).unwrap();
+        let mut diss = RomDisassembler::new(&data, base).unwrap();
+
+        if let Some(syms) = syms {
+            diss.set_symbols(SymbolTable::from_file(syms).unwrap());
+            let addrs : Vec<u16> = diss.symbols().iter().map(|(_, v)| *v).collect();
+
+            for addr in addrs {
+                diss.add_entry(addr);
+            }
+        }
+
+        diss.trace();
+
+        let mut source = vec![];
+        diss.write(&mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+
+        let done = Assembler::new().assemble_str(file, &source).unwrap_or_else(|e| panic!("{}", e));
+        let bytes = done.raw();
+        assert_eq!(bytes.len(), data.len(), "{} reassembled to a different size", file);
+
+        if let Some(i) = bytes.iter().zip(&data).position(|(a, b)| a != b) {
+            panic!("{} differs at ${:04x}", file, usize::from(base) + i);
+        }
+    }
+
+    #[test]
+    fn all_s_matches_lwasm() {
+        let done = Assembler::new().assemble_file("asm/all.s").unwrap_or_else(|e| panic!("{}", e));
+        let expected = fs::read("asm/out/all.bin").unwrap();
+        assert!(done.raw() == expected, "asm/all.s doesn't match asm/out/all.bin");
+    }
+
+    #[test]
+    fn diss_round_trips() {
+        round_trip("resources/rom.dat", 0xe000, None);
+        round_trip("resources/rom.dat", 0xe000, Some("resources/syms.yaml"));
+        round_trip("resources/fastrom.dat", 0xe000, Some("resources/syms.yaml"));
+        round_trip("resources/ROCKS.BIN", 0x0000, None);
+    }
+
+    #[test]
+    fn forward_references_size_as_16_bit() {
+        // fwd isn't known on the first pass so stays extended
+        let fwd = assemble(" org $1000\n lda fwd,x\n lda fwd\nfwd equ 4\n");
+        assert_eq!(fwd, vec![0xa6, 0x89, 0x00, 0x04, 0xb6, 0x00, 0x04]);
+
+        let back = assemble(" org $1000\nback equ 4\n lda back,x\n lda back\n");
+        assert_eq!(back, vec![0xa6, 0x04, 0x96, 0x04]);
+    }
+
+    #[test]
+    fn forward_branches_fit() {
+        let bytes = assemble(" org $1000\n bra next\n nop\nnext rts\n");
+        assert_eq!(bytes, vec![0x20, 0x01, 0x12, 0x39]);
+    }
+
+    #[test]
+    fn setdp_picks_direct() {
+        let bytes = assemble(" org $1000\n setdp $c8\n lda $c880\n setdp $d0\n lda $c880\n");
+        assert_eq!(bytes, vec![0x96, 0x80, 0xb6, 0xc8, 0x80]);
+    }
+
+    #[test]
+    fn assemble_line_with_dp() {
+        assert_eq!(assemble_line("lda $c880", 0x1000, None, Some(0xc8)).unwrap(), vec![0x96, 0x80]);
+        assert_eq!(assemble_line("lda $c880", 0x1000, None, None).unwrap(), vec![0xb6, 0xc8, 0x80]);
+        assert_eq!(assemble_line("lda $0080", 0x1000, None, None).unwrap(), vec![0xb6, 0x00, 0x80]);
+        assert_eq!(assemble_line("bra *", 0x1000, None, None).unwrap(), vec![0x20, 0xfe]);
+
+        let mut syms = SymbolTable::default();
+        syms.add("Vec_Btn_State".to_string(), 0xc80f);
+        assert_eq!(assemble_line("lda Vec_Btn_State", 0, Some(&syms), Some(0xc8)).unwrap(), vec![0x96, 0x0f]);
+
+        assert!(assemble_line("lda nowhere", 0, None, None).is_err());
+    }
+}
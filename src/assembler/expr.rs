@@ -0,0 +1,310 @@
+// Operand expressions
+//
+// C precedence with the usual 6809 assembler number forms, $ff 0xff %101
+// @17 'c and * for the pc. A symbol that can't be found isn't an error here,
+// the value comes back unknown and it's up to the caller whether that matters.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value {
+    pub val : i64,
+    pub known : bool,
+}
+
+impl Value {
+    pub fn known(val : i64) -> Self {
+        Self { val, known : true }
+    }
+
+    pub fn unknown() -> Self {
+        Self { val : 0, known : false }
+    }
+
+    fn op(self, rhs : Value, f : impl Fn(i64, i64) -> i64) -> Self {
+        Self { val : f(self.val, rhs.val), known : self.known && rhs.known }
+    }
+}
+
+// Where symbols are looked up. A symbol can have a value and still not be
+// known, the assembler's forward references are like that, and sizes are
+// picked as if the value could be anything.
+pub trait Scope {
+    fn lookup(&self, name : &str) -> Option<Value>;
+}
+
+pub fn eval(text : &str, pc : u16, scope : &dyn Scope) -> Result<Value, String> {
+    let mut parser = Parser { text : text.as_bytes(), pos : 0, pc, scope };
+
+    let ret = parser.expr(0)?;
+    parser.skip_space();
+
+    if parser.pos != parser.text.len() {
+        return Err(format!("bad expression \"{}\"", text));
+    }
+
+    Ok(ret)
+}
+
+pub fn is_symbol_start(c : u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c == b'.' || c == b'@' || c == b'?'
+}
+
+pub fn is_symbol_char(c : u8) -> bool {
+    is_symbol_start(c) || c.is_ascii_digit() || c == b'$'
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Binary operators from loosest to tightest binding
+const BINARY_OPS : [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    text : &'a [u8],
+    pos : usize,
+    pc : u16,
+    scope : &'a dyn Scope,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, tok : &str) -> bool {
+        self.skip_space();
+
+        if self.text[self.pos..].starts_with(tok.as_bytes()) {
+            self.pos += tok.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary_op(&mut self, level : usize) -> Option<&'static str> {
+        self.skip_space();
+
+        for op in BINARY_OPS[level] {
+            if self.text[self.pos..].starts_with(op.as_bytes()) {
+                self.pos += op.len();
+                return Some(op);
+            }
+        }
+
+        None
+    }
+
+    fn expr(&mut self, level : usize) -> Result<Value, String> {
+        if level == BINARY_OPS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.expr(level + 1)?;
+
+        while let Some(op) = self.binary_op(level) {
+            let rhs = self.expr(level + 1)?;
+
+            lhs = match op {
+                "|" => lhs.op(rhs, |a, b| a | b),
+                "^" => lhs.op(rhs, |a, b| a ^ b),
+                "&" => lhs.op(rhs, |a, b| a & b),
+                "<<" => lhs.op(rhs, |a, b| a.wrapping_shl(b as u32)),
+                ">>" => lhs.op(rhs, |a, b| a.wrapping_shr(b as u32)),
+                "+" => lhs.op(rhs, |a, b| a.wrapping_add(b)),
+                "-" => lhs.op(rhs, |a, b| a.wrapping_sub(b)),
+                "*" => lhs.op(rhs, |a, b| a.wrapping_mul(b)),
+                _ if rhs.known && rhs.val == 0 => return Err("division by zero".to_string()),
+                "/" => lhs.op(rhs, |a, b| if b == 0 { 0 } else { a / b }),
+                _ => lhs.op(rhs, |a, b| if b == 0 { 0 } else { a % b }),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat("-") {
+            let v = self.unary()?;
+            Ok(Value { val : v.val.wrapping_neg(), ..v })
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.eat("~") {
+            let v = self.unary()?;
+            Ok(Value { val : !v.val, ..v })
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        self.skip_space();
+
+        let c = self.peek().ok_or_else(|| "missing operand".to_string())?;
+
+        match c {
+            b'(' => {
+                self.pos += 1;
+                let v = self.expr(0)?;
+
+                if self.eat(")") {
+                    Ok(v)
+                } else {
+                    Err("missing )".to_string())
+                }
+            }
+
+            b'*' => {
+                self.pos += 1;
+                Ok(Value::known(i64::from(self.pc)))
+            }
+
+            b'\'' => {
+                let ch = self.text.get(self.pos + 1).ok_or_else(|| "missing character".to_string())?;
+                self.pos += 2;
+
+                // closing quote is optional
+                if self.peek() == Some(b'\'') {
+                    self.pos += 1;
+                }
+
+                Ok(Value::known(i64::from(*ch)))
+            }
+
+            b'$' => self.number(1, 16),
+            b'%' => self.number(1, 2),
+            b'@' if self.text.get(self.pos + 1).is_some_and(|c| c.is_ascii_digit()) => self.number(1, 8),
+            b'0' if matches!(self.text.get(self.pos + 1), Some(b'x') | Some(b'X')) => self.number(2, 16),
+            b'0'..=b'9' => self.number(0, 10),
+
+            _ if is_symbol_start(c) => {
+                let name = self.symbol();
+
+                Ok(self.scope.lookup(&name).unwrap_or_else(Value::unknown))
+            }
+
+            _ => Err(format!("unexpected '{}'", c as char)),
+        }
+    }
+
+    fn number(&mut self, skip : usize, radix : u32) -> Result<Value, String> {
+        self.pos += skip;
+
+        let start = self.pos;
+
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+
+        let digits = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+
+        i64::from_str_radix(digits, radix)
+            .map(Value::known)
+            .map_err(|_| format!("bad number \"{}\"", digits))
+    }
+
+    fn symbol(&mut self) -> String {
+        let start = self.pos;
+
+        while self.peek().is_some_and(is_symbol_char) {
+            self.pos += 1;
+        }
+
+        // sizeof{name} is how a struct's size is named
+        if &self.text[start..self.pos] == b"sizeof" && self.peek() == Some(b'{') {
+            while let Some(c) = self.peek() {
+                self.pos += 1;
+                if c == b'}' {
+                    break;
+                }
+            }
+        }
+
+        String::from_utf8_lossy(&self.text[start..self.pos]).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Syms(HashMap<&'static str, Value>);
+
+    impl Scope for Syms {
+        fn lookup(&self, name : &str) -> Option<Value> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    fn syms() -> Syms {
+        let mut map = HashMap::new();
+        map.insert("ten", Value::known(10));
+        map.insert("fwd", Value { val : 4, known : false });
+        Syms(map)
+    }
+
+    fn val(text : &str) -> i64 {
+        let v = eval(text, 0x1000, &syms()).unwrap();
+        assert!(v.known, "{} isn't known", text);
+        v.val
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(val("1+2*3"), 7);
+        assert_eq!(val("(1+2)*3"), 9);
+        assert_eq!(val("10-4-3"), 3);
+        assert_eq!(val("20/5/2"), 2);
+        assert_eq!(val("1+2<<3"), 24);
+        assert_eq!(val("1<<2+1"), 8);
+        assert_eq!(val("6&3|8"), 10);
+        assert_eq!(val("1|6&3"), 3);
+        assert_eq!(val("5^1&3"), 4);
+        assert_eq!(val("-2*3"), -6);
+        assert_eq!(val("~0&$ff"), 0xff);
+        assert_eq!(val("17%5+1"), 3);
+    }
+
+    #[test]
+    fn number_forms() {
+        assert_eq!(val("$ff"), 0xff);
+        assert_eq!(val("0xFF"), 0xff);
+        assert_eq!(val("%101"), 5);
+        assert_eq!(val("@17"), 0o17);
+        assert_eq!(val("'A"), 65);
+        assert_eq!(val("'A'+1"), 66);
+        assert_eq!(val("*+2"), 0x1002);
+        assert_eq!(val(" ten * 2 "), 20);
+    }
+
+    #[test]
+    fn unknown_symbols_spread() {
+        let v = eval("ten+missing", 0, &syms()).unwrap();
+        assert!(!v.known);
+
+        // a forward reference has a value but it isn't known yet
+        let v = eval("fwd*2", 0, &syms()).unwrap();
+        assert_eq!(v, Value { val : 8, known : false });
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("1+", 0, &syms()).is_err());
+        assert!(eval("(1+2", 0, &syms()).is_err());
+        assert!(eval("1 2", 0, &syms()).is_err());
+        assert!(eval("4/0", 0, &syms()).is_err());
+        assert!(eval("#1", 0, &syms()).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+mod expr;
+mod ops;
+mod asmcore;
+
+pub use self::asmcore::*;
@@ -0,0 +1,500 @@
+// Instruction encoding
+//
+// Mnemonics come from the documented 6809 op table so the assembler and the
+// core agree on what each opcode is. Alternative names in the table, like
+// lsl_asl or bhs_bcc, are all accepted.
+
+use std::collections::HashMap;
+
+use crate::cpu::{AddrMode, OPS_6809, index_to_op};
+
+use super::expr::{eval, Scope, Value};
+
+lazy_static! {
+    static ref OPCODES : HashMap<&'static str, Vec<(AddrMode, u16)>> = {
+        let mut ret : HashMap<&'static str, Vec<(AddrMode, u16)>> = HashMap::new();
+
+        for (i, info) in OPS_6809.iter().enumerate() {
+            if let Some(info) = info {
+                // not something anyone would want assembled
+                if info.name == "reset" {
+                    continue;
+                }
+
+                for name in info.name.split('_') {
+                    ret.entry(name).or_default().push((info.mode, index_to_op(i)));
+                }
+            }
+        }
+
+        ret
+    };
+}
+
+// Immediate operands that are a word rather than a byte
+const IMMEDIATE_16 : [&str; 12] = [
+    "addd", "subd", "cmpd", "cmpx", "cmpy", "cmpu", "cmps",
+    "ldd", "ldx", "ldy", "ldu", "lds",
+];
+
+// What assembling an instruction needs to know about where it's going
+pub struct Target<'a> {
+    pub pc : u16,
+    pub dp : Option<u8>,
+    pub scope : &'a dyn Scope,
+    // Every symbol has its final value, out of range values are errors
+    // rather than truncated
+    pub strict : bool,
+}
+
+impl<'a> Target<'a> {
+    fn eval(&self, text : &str) -> Result<Value, String> {
+        eval(text, self.pc, self.scope)
+    }
+
+    fn check(&self, v : Value, lo : i64, hi : i64) -> Result<i64, String> {
+        in_range(v, lo, hi, self.strict)
+    }
+}
+
+pub fn in_range(v : Value, lo : i64, hi : i64, strict : bool) -> Result<i64, String> {
+    if strict && (v.val < lo || v.val > hi) {
+        Err(format!("value ${:x} out of range", v.val))
+    } else {
+        Ok(v.val)
+    }
+}
+
+// Bytes for mnemonic operand, mnemonic is lower case
+pub fn assemble(mnemonic : &str, operand : &str, target : &Target) -> Result<Vec<u8>, String> {
+    let modes = OPCODES.get(mnemonic)
+        .ok_or_else(|| format!("unknown instruction \"{}\"", mnemonic))?;
+
+    let find = |mode : AddrMode| modes.iter().find(|(m, _)| *m == mode).map(|(_, op)| *op);
+
+    let mut ret = vec![];
+
+    match mnemonic {
+        "pshs" | "puls" | "pshu" | "pulu" => {
+            push_op(&mut ret, modes[0].1);
+            ret.push(reg_list(operand, mnemonic.ends_with('s'))?);
+        }
+
+        "tfr" | "exg" => {
+            push_op(&mut ret, modes[0].1);
+            ret.push(reg_pair(operand)?);
+        }
+
+        "cwai" => {
+            push_op(&mut ret, modes[0].1);
+            let v = immediate(operand, target)?;
+            ret.push(target.check(v, -0x80, 0xff)? as u8);
+        }
+
+        _ if find(AddrMode::Inherent).is_some() => {
+            push_op(&mut ret, find(AddrMode::Inherent).unwrap());
+        }
+
+        _ if find(AddrMode::Relative).is_some() => {
+            let op = find(AddrMode::Relative).unwrap();
+            let long = mnemonic.starts_with("lb");
+
+            push_op(&mut ret, op);
+
+            let len = ret.len() + if long { 2 } else { 1 };
+            let dest = target.eval(operand)?;
+            let offset = dest.val - (i64::from(target.pc) + len as i64);
+
+            if long {
+                push_word(&mut ret, offset);
+            } else {
+                if target.strict && !(-0x80..=0x7f).contains(&offset) {
+                    return Err(format!("branch to ${:04x} out of range", dest.val));
+                }
+                ret.push(offset as u8);
+            }
+        }
+
+        _ if operand.starts_with('#') => {
+            let op = find(AddrMode::Immediate)
+                .ok_or_else(|| format!("{} has no immediate mode", mnemonic))?;
+
+            push_op(&mut ret, op);
+
+            let v = immediate(operand, target)?;
+
+            if IMMEDIATE_16.contains(&mnemonic) {
+                push_word(&mut ret, target.check(v, -0x8000, 0xffff)?);
+            } else {
+                ret.push(target.check(v, -0x80, 0xff)? as u8);
+            }
+        }
+
+        _ if operand.is_empty() => return Err(format!("{} needs an operand", mnemonic)),
+
+        _ if operand.starts_with('[') || top_level_comma(operand).is_some() => {
+            let op = find(AddrMode::Indexed)
+                .ok_or_else(|| format!("{} has no indexed mode", mnemonic))?;
+
+            push_op(&mut ret, op);
+            let len = ret.len();
+            ret.extend(indexed(operand, len, target)?);
+        }
+
+        _ => {
+            let (force, text) = force_prefix(operand);
+            let v = target.eval(text)?;
+
+            let direct = match force {
+                Force::Bits8 | Force::Bits5 => true,
+                Force::Bits16 => false,
+                Force::None => v.known && target.dp.is_some_and(|dp| v.val >> 8 == i64::from(dp)),
+            };
+
+            match (direct, find(AddrMode::Direct), find(AddrMode::Extended)) {
+                (true, Some(op), _) => {
+                    push_op(&mut ret, op);
+                    ret.push(v.val as u8);
+                }
+                (_, _, Some(op)) => {
+                    push_op(&mut ret, op);
+                    push_word(&mut ret, target.check(v, -0x8000, 0xffff)?);
+                }
+                _ => return Err(format!("{} can't address memory", mnemonic)),
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn push_op(bytes : &mut Vec<u8>, op : u16) {
+    if op > 0xff {
+        bytes.push((op >> 8) as u8);
+    }
+    bytes.push(op as u8);
+}
+
+fn push_word(bytes : &mut Vec<u8>, val : i64) {
+    bytes.push((val >> 8) as u8);
+    bytes.push(val as u8);
+}
+
+fn immediate(operand : &str, target : &Target) -> Result<Value, String> {
+    match operand.strip_prefix('#') {
+        Some(text) => target.eval(text),
+        None => Err("expected #immediate".to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Force {
+    None,
+    Bits5,
+    Bits8,
+    Bits16,
+}
+
+// The << < > size prefixes
+fn force_prefix(text : &str) -> (Force, &str) {
+    if let Some(rest) = text.strip_prefix("<<") {
+        (Force::Bits5, rest)
+    } else if let Some(rest) = text.strip_prefix('<') {
+        (Force::Bits8, rest)
+    } else if let Some(rest) = text.strip_prefix('>') {
+        (Force::Bits16, rest)
+    } else {
+        (Force::None, text)
+    }
+}
+
+// Position of the comma splitting offset and register, skipping any in
+// brackets or character constants
+fn top_level_comma(text : &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b'\'' => i += 1,
+            b',' if depth == 0 => return Some(i),
+            _ => (),
+        }
+        i += 1;
+    }
+
+    None
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Register operands
+
+fn reg_list(text : &str, system_stack : bool) -> Result<u8, String> {
+    let mut mask = 0;
+
+    if text.is_empty() {
+        return Ok(mask);
+    }
+
+    for reg in text.split(',') {
+        mask |= match reg.to_lowercase().as_str() {
+            "pc" => 0x80,
+            "u" if system_stack => 0x40,
+            "s" if !system_stack => 0x40,
+            "y" => 0x20,
+            "x" => 0x10,
+            "dp" => 0x08,
+            "d" => 0x06,
+            "b" => 0x04,
+            "a" => 0x02,
+            "cc" => 0x01,
+            _ => return Err(format!("can't stack \"{}\"", reg)),
+        };
+    }
+
+    Ok(mask)
+}
+
+fn tfr_code(text : &str) -> Result<u8, String> {
+    let code = match text.to_lowercase().as_str() {
+        "d" => 0x0,
+        "x" => 0x1,
+        "y" => 0x2,
+        "u" => 0x3,
+        "s" => 0x4,
+        "pc" => 0x5,
+        "a" => 0x8,
+        "b" => 0x9,
+        "cc" => 0xa,
+        "dp" => 0xb,
+        _ => return Err(format!("bad register \"{}\"", text)),
+    };
+
+    Ok(code)
+}
+
+fn reg_pair(text : &str) -> Result<u8, String> {
+    let mut regs = text.split(',');
+
+    match (regs.next(), regs.next(), regs.next()) {
+        (Some(a), Some(b), None) => Ok(tfr_code(a)? << 4 | tfr_code(b)?),
+        _ => Err(format!("expected two registers, got \"{}\"", text)),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Indexed postbytes
+
+fn index_reg(text : &str) -> Option<u8> {
+    match text.to_lowercase().as_str() {
+        "x" => Some(0x00),
+        "y" => Some(0x20),
+        "u" => Some(0x40),
+        "s" => Some(0x60),
+        _ => None,
+    }
+}
+
+// Postbyte and offset bytes, op_len is the bytes already in front of them
+fn indexed(operand : &str, op_len : usize, target : &Target) -> Result<Vec<u8>, String> {
+    let (indirect, text) = match operand.strip_prefix('[') {
+        Some(rest) => {
+            let inner = rest.strip_suffix(']').ok_or_else(|| "missing ]".to_string())?;
+            (true, inner)
+        }
+        None => (false, operand),
+    };
+
+    let ind = if indirect { 0x10 } else { 0 };
+
+    // [addr] is extended indirect
+    let comma = match top_level_comma(text) {
+        Some(comma) => comma,
+        None => {
+            let (_, text) = force_prefix(text);
+            let v = target.eval(text)?;
+            let mut ret = vec![0x9f];
+            push_word(&mut ret, target.check(v, -0x8000, 0xffff)?);
+            return Ok(ret);
+        }
+    };
+
+    let offset = &text[..comma];
+    let reg = &text[comma + 1..];
+
+    // auto increment and decrement
+    let auto = reg.strip_prefix("--").map(|r| (r, 0x83))
+        .or_else(|| reg.strip_prefix('-').map(|r| (r, 0x82)))
+        .or_else(|| reg.strip_suffix("++").map(|r| (r, 0x81)))
+        .or_else(|| reg.strip_suffix('+').map(|r| (r, 0x80)));
+
+    if let Some((r, post)) = auto {
+        let rr = index_reg(r).ok_or_else(|| format!("bad index register \"{}\"", r))?;
+
+        if !offset.is_empty() {
+            return Err("auto increment and decrement can't have an offset".to_string());
+        }
+
+        if indirect && (post == 0x80 || post == 0x82) {
+            return Err("indirect auto increment and decrement must be by two".to_string());
+        }
+
+        return Ok(vec![post | rr | ind]);
+    }
+
+    let reg_lower = reg.to_lowercase();
+
+    if reg_lower == "pc" || reg_lower == "pcr" {
+        return pc_relative(offset, reg_lower == "pcr", op_len, ind, target);
+    }
+
+    let rr = index_reg(reg).ok_or_else(|| format!("bad index register \"{}\"", reg))?;
+
+    // accumulator offsets
+    let acc = match offset.to_lowercase().as_str() {
+        "a" => Some(0x86),
+        "b" => Some(0x85),
+        "d" => Some(0x8b),
+        _ => None,
+    };
+
+    if let Some(post) = acc {
+        return Ok(vec![post | rr | ind]);
+    }
+
+    if offset.is_empty() {
+        return Ok(vec![0x84 | rr | ind]);
+    }
+
+    let (force, text) = force_prefix(offset);
+    let v = target.eval(text)?;
+
+    let force = match force {
+        Force::None if !v.known => Force::Bits16,
+        Force::None if v.val == 0 => return Ok(vec![0x84 | rr | ind]),
+        Force::None if (-0x10..=0x0f).contains(&v.val) && !indirect => Force::Bits5,
+        Force::None if (-0x80..=0x7f).contains(&v.val) => Force::Bits8,
+        Force::None => Force::Bits16,
+        // there's no 5 bit indirect
+        Force::Bits5 if indirect => Force::Bits8,
+        f => f,
+    };
+
+    let ret = match force {
+        Force::Bits5 => {
+            let off = target.check(v, -0x10, 0x0f)?;
+            vec![rr | (off as u8 & 0x1f)]
+        }
+        Force::Bits8 => vec![0x88 | rr | ind, target.check(v, -0x80, 0x7f)? as u8],
+        _ => {
+            let mut ret = vec![0x89 | rr | ind];
+            push_word(&mut ret, target.check(v, -0x8000, 0xffff)?);
+            ret
+        }
+    };
+
+    Ok(ret)
+}
+
+// n,PC takes n as the offset, n,PCR takes it as an address
+fn pc_relative(offset : &str, pcr : bool, op_len : usize, ind : u8, target : &Target) -> Result<Vec<u8>, String> {
+    let (force, text) = force_prefix(offset);
+
+    let v = if text.is_empty() {
+        Value::known(0)
+    } else {
+        target.eval(text)?
+    };
+
+    let end = |len : usize| i64::from(target.pc) + (op_len + len) as i64;
+
+    let off8 = if pcr { v.val - end(2) } else { v.val };
+    let off16 = if pcr { v.val - end(3) } else { v.val };
+
+    let long = match force {
+        Force::Bits16 => true,
+        Force::Bits8 | Force::Bits5 => false,
+        Force::None => !v.known || !(-0x80..=0x7f).contains(&off8),
+    };
+
+    let ret = if long {
+        let mut ret = vec![0x8d | ind];
+        push_word(&mut ret, off16);
+        ret
+    } else {
+        if target.strict && !(-0x80..=0x7f).contains(&off8) {
+            return Err(format!("pc offset ${:x} out of range", off8));
+        }
+        vec![0x8c | ind, off8 as u8]
+    };
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fwd hasn't been defined yet
+    struct Syms;
+
+    impl Scope for Syms {
+        fn lookup(&self, name : &str) -> Option<Value> {
+            match name {
+                "fwd" => Some(Value { val : 0, known : false }),
+                _ => None,
+            }
+        }
+    }
+
+    fn asm(mnemonic : &str, operand : &str) -> Vec<u8> {
+        let target = Target { pc : 0x1000, dp : Some(0xc8), scope : &Syms, strict : false };
+        assemble(mnemonic, operand, &target).unwrap()
+    }
+
+    #[test]
+    fn indexed_offset_sizes() {
+        assert_eq!(asm("lda", "0,x"), vec![0xa6, 0x84]);
+        assert_eq!(asm("lda", ",x"), vec![0xa6, 0x84]);
+        assert_eq!(asm("lda", "15,x"), vec![0xa6, 0x0f]);
+        assert_eq!(asm("lda", "-16,y"), vec![0xa6, 0x30]);
+        assert_eq!(asm("lda", "16,x"), vec![0xa6, 0x88, 0x10]);
+        assert_eq!(asm("lda", "127,u"), vec![0xa6, 0xc8, 0x7f]);
+        assert_eq!(asm("lda", "-128,s"), vec![0xa6, 0xe8, 0x80]);
+        assert_eq!(asm("lda", "128,x"), vec![0xa6, 0x89, 0x00, 0x80]);
+        assert_eq!(asm("lda", "-129,x"), vec![0xa6, 0x89, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn indirect_has_no_5_bit_offset() {
+        assert_eq!(asm("lda", "[15,x]"), vec![0xa6, 0x98, 0x0f]);
+        assert_eq!(asm("lda", "[,x]"), vec![0xa6, 0x94]);
+        assert_eq!(asm("lda", "[$1234]"), vec![0xa6, 0x9f, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn forced_offset_sizes() {
+        assert_eq!(asm("lda", "<<1,x"), vec![0xa6, 0x01]);
+        assert_eq!(asm("lda", "<1,x"), vec![0xa6, 0x88, 0x01]);
+        assert_eq!(asm("lda", ">1,x"), vec![0xa6, 0x89, 0x00, 0x01]);
+        assert_eq!(asm("lda", "[<<1,x]"), vec![0xa6, 0x98, 0x01]);
+    }
+
+    #[test]
+    fn unknown_offsets_are_16_bit() {
+        assert_eq!(asm("lda", "fwd,x"), vec![0xa6, 0x89, 0x00, 0x00]);
+        assert_eq!(asm("lda", "fwd"), vec![0xb6, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn direct_page() {
+        assert_eq!(asm("lda", "$c880"), vec![0x96, 0x80]);
+        assert_eq!(asm("lda", ">$c880"), vec![0xb6, 0xc8, 0x80]);
+        assert_eq!(asm("lda", "$c780"), vec![0xb6, 0xc7, 0x80]);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::mem::MemoryIO;
-use crate::cpu::{ Regs, InstructionDecoder, IndexedFlags, IndexModes, CpuErr};
+use crate::cpu::{ Regs, RegEnum, InstructionDecoder, IndexedFlags, IndexModes, CpuErr};
 
 pub trait AddressLines {
 
@@ -48,6 +48,7 @@ impl AddressLines for Direct {
 
     fn ea<M: MemoryIO>(mem : &mut M, regs : &mut Regs, ins : &mut InstructionDecoder) -> Result<u16,CpuErr> {
         let index = u16::from(ins.fetch_byte(mem));
+        mem.dummy_cycle(0xffff);
         Ok(regs.get_dp_ptr().wrapping_add(index))
     }
 
@@ -92,7 +93,9 @@ pub struct Extended { }
 impl AddressLines for Extended {
     fn ea<M: MemoryIO>(mem : &mut M, _regs : &mut Regs, ins : &mut InstructionDecoder) -> Result<u16,CpuErr>{
         ins.add_cycles(2);
-        Ok(ins.fetch_word(mem))
+        let addr = ins.fetch_word(mem);
+        mem.dummy_cycle(0xffff);
+        Ok(addr)
     }
 
     fn name() -> String {
@@ -182,12 +185,27 @@ impl Indexed {
 
         let index_mode = IndexedFlags::new(index_mode_id) ;
 
-        let itype = index_mode.get_index_type();
+        let itype = if regs.is_6309() {
+            index_mode.get_index_type_6309()
+        } else {
+            index_mode.get_index_type()
+        };
+
+        // Dummy bus cycles, only the 6809 modes are in 6809cyc.txt
+        let dummies = |mem : &mut M, ins : &InstructionDecoder, after_pc : u16, vma : u16| {
+            for i in 0..after_pc {
+                mem.dummy_cycle(ins.next_addr.wrapping_add(i));
+            }
+            for _ in 0..vma {
+                mem.dummy_cycle(0xffff);
+            }
+        };
 
         match itype {
             IndexModes::RPlus(r) => { 
                 // format!(",{:?}+",r)
                 ins.add_cycles(3);
+                dummies(mem, ins, 1, 2);
                 let addr = regs.get(&r);
                 regs.inc(&r);
                 Ok(( addr,index_mode ))
@@ -195,6 +213,7 @@ impl Indexed {
 
             IndexModes::RPlusPlus(r) => {
                 ins.add_cycles(4);
+                dummies(mem, ins, 1, 3);
                 let addr = regs.get(&r);
                 regs.incinc(&r);
                 Ok(( addr,index_mode ))
@@ -202,27 +221,32 @@ impl Indexed {
 
             IndexModes::RSub(r) => {
                 ins.add_cycles(3);
+                dummies(mem, ins, 1, 2);
                 Ok((  regs.dec(&r),index_mode  ))
             },
 
             IndexModes::RSubSub(r) => {
                 ins.add_cycles(4);
+                dummies(mem, ins, 1, 3);
                 Ok((  regs.decdec(&r), index_mode  ))
             },
 
             IndexModes::RZero(r) => { 
                 ins.add_cycles(1);
+                dummies(mem, ins, 1, 0);
                 Ok((  regs.get(&r), index_mode  ))
             },
 
             IndexModes::RAddB(r) => { 
                 // format!("B,{:?}", r) 
+                dummies(mem, ins, 1, 1);
                 let add_r = u16::from(regs.b);
                 Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
             },
 
             IndexModes::RAddA(r) => {
                 // format!("A,{:?}", r) 
+                dummies(mem, ins, 1, 1);
                 let add_r = u16::from(regs.a);
                 Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
             },
@@ -230,17 +254,20 @@ impl Indexed {
             IndexModes::RAddi8(r) => {
                 // format!("{},{:?}",diss.fetch_byte(mem) as i8, r)
                 let v = ins.fetch_byte_as_i16(mem) as u16;
+                dummies(mem, ins, 0, 1);
                 Ok((  regs.get(&r).wrapping_add(v), index_mode  ))
             },
 
             IndexModes::RAddi16(r) => {
                 // format!("{},{:?}",diss.fetch_word(mem) as i16, r)
                 let v = ins.fetch_word(mem);
+                dummies(mem, ins, 1, 2);
                 Ok((  regs.get(&r).wrapping_add(v), index_mode  ))
             },
 
             IndexModes::RAddD(r) => {
                 // format!("D,{:?}", r) 
+                dummies(mem, ins, 3, 2);
                 let add_r = regs.get_d();
                 Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
             },
@@ -248,12 +275,14 @@ impl Indexed {
             IndexModes::PCAddi8 => {
                 // format!("PC,{:?}",diss.fetch_byte(mem) as i8)
                 let offset = ins.fetch_byte_as_i16(mem) as u16;
+                dummies(mem, ins, 0, 1);
                 Ok((  regs.pc.wrapping_add(offset), index_mode  ))
             },
 
             IndexModes::PCAddi16 => {
                 // format!("PC,{:?}",diss.fetch_word(mem) as i16)
                 let offset = ins.fetch_word(mem);
+                dummies(mem, ins, 1, 3);
                 Ok((  regs.pc.wrapping_add(offset), index_mode  ))
             },
 
@@ -264,13 +293,56 @@ impl Indexed {
             IndexModes::Ea=> {
                 // format!("0x{:04X}", diss.fetch_word(mem))
                 ins.add_cycles(6);
-                Ok((  ins.fetch_word(mem), index_mode  ))
+                let addr = ins.fetch_word(mem);
+                dummies(mem, ins, 1, 0);
+                Ok((  addr, index_mode  ))
             },
 
             IndexModes::ROff(r,offset)=> {
                 // format!("{}, {:?}", offset, r) 
+                dummies(mem, ins, 1, 1);
                 Ok((  regs.get(&r).wrapping_add(offset), index_mode  ))
             },
+
+            IndexModes::RAddE(r) => {
+                ins.add_cycles(1);
+                let add_r = u16::from(regs.e);
+                Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
+            },
+
+            IndexModes::RAddF(r) => {
+                ins.add_cycles(1);
+                let add_r = u16::from(regs.f);
+                Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
+            },
+
+            IndexModes::RAddW(r) => {
+                ins.add_cycles(4);
+                let add_r = regs.get_w();
+                Ok((  regs.get(&r).wrapping_add(add_r), index_mode  ))
+            },
+
+            IndexModes::WZero => {
+                Ok((  regs.get_w(), index_mode  ))
+            },
+
+            IndexModes::WAddi16 => {
+                ins.add_cycles(2);
+                let v = ins.fetch_word(mem);
+                Ok((  regs.get_w().wrapping_add(v), index_mode  ))
+            },
+
+            IndexModes::WPlusPlus => {
+                ins.add_cycles(1);
+                let addr = regs.get_w();
+                regs.incinc(&RegEnum::W);
+                Ok((  addr, index_mode  ))
+            },
+
+            IndexModes::WSubSub => {
+                ins.add_cycles(1);
+                Ok((  regs.decdec(&RegEnum::W), index_mode  ))
+            },
         }
     }
 
@@ -283,9 +355,17 @@ impl AddressLines for Indexed {
 
         let (ea,index_mode) = Indexed::get_index_mode::<M>(mem,regs, ins)?;
 
-        let ea = if index_mode.is_indirect() {
+        let indirect = if regs.is_6309() {
+            index_mode.is_indirect_6309()
+        } else {
+            index_mode.is_indirect()
+        };
+
+        let ea = if indirect {
             ins.add_cycles(3);
-            mem.load_word(ea)
+            let ea = mem.load_word(ea);
+            mem.dummy_cycle(0xffff);
+            ea
         }  else {
             ea
         };
@@ -0,0 +1,242 @@
+// Cycle accurate bus
+//
+// In fast mode the cpu makes its memory accesses as it needs them and the
+// clock is bumped by the instruction's cycle count once it's done.
+//
+// In cycle accurate mode memory is wrapped in a CycleBus. Every access is
+// one bus cycle and the memory is told its cycle number first through
+// MemoryIO::bus_cycle. Words are split into
+// bytes in the order the 6809 puts them on the bus and the cpu adds the
+// "don't care" cycles from utils/6809cyc.txt as dummy cycles. Anything left
+// over at the end of an instruction is padded with dummy cycles at $ffff.
+
+use crate::mem::{MemoryIO, BusAccess, Sha1};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BusMode {
+    #[default]
+    Fast,
+    CycleAccurate,
+}
+
+// Counts its own cycles from start, the caller moves the clock on once the
+// instruction is done
+pub struct CycleBus<'a, M : 'a + MemoryIO> {
+    mem : &'a mut M,
+    start : u64,
+    cycles : u32,
+}
+
+impl<'a, M : 'a + MemoryIO> CycleBus<'a, M> {
+    pub fn new(mem : &'a mut M, start : u64) -> Self {
+        Self { mem, start, cycles : 0 }
+    }
+
+    // Bus cycles made since this was created
+    pub fn get_cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    pub fn pad_to(&mut self, cycles : u32) {
+        while self.cycles < cycles {
+            self.tick(0xffff, BusAccess::Dummy);
+        }
+    }
+
+    fn tick(&mut self, addr : u16, access : BusAccess) {
+        let cycle = self.start + u64::from(self.cycles);
+        self.mem.bus_cycle(cycle, addr, access);
+        self.cycles += 1;
+    }
+}
+
+impl<'a, M : 'a + MemoryIO> MemoryIO for CycleBus<'a, M> {
+    fn peek(&self, addr : u16) -> u8 {
+        self.mem.peek(addr)
+    }
+
+    fn poke(&mut self, addr : u16, val : u8) {
+        self.mem.poke(addr, val)
+    }
+
+    fn get_range(&self) -> (u16, u16) {
+        self.mem.get_range()
+    }
+
+    fn update_sha1(&self, digest : &mut Sha1) {
+        self.mem.update_sha1(digest)
+    }
+
+    fn load_byte(&mut self, addr : u16) -> u8 {
+        self.tick(addr, BusAccess::Read);
+        self.mem.load_byte(addr)
+    }
+
+    fn store_byte(&mut self, addr : u16, val : u8) {
+        self.tick(addr, BusAccess::Write);
+        self.mem.store_byte(addr, val)
+    }
+
+    // High byte first for both
+    fn load_word(&mut self, addr : u16) -> u16 {
+        let hi = u16::from(self.load_byte(addr));
+        let lo = u16::from(self.load_byte(addr.wrapping_add(1)));
+        hi << 8 | lo
+    }
+
+    fn store_word(&mut self, addr : u16, val : u16) {
+        self.store_byte(addr, (val >> 8) as u8);
+        self.store_byte(addr.wrapping_add(1), val as u8);
+    }
+
+    fn dummy_cycle(&mut self, addr : u16) {
+        self.tick(addr, BusAccess::Dummy);
+    }
+
+    fn get_name(&self) -> String {
+        self.mem.get_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::cpu::{Regs, Interrupts, StandardClock, Clock, step};
+    use BusAccess::{Read, Write, Dummy};
+
+    // 64K of NOPs that logs every bus cycle it's told about
+    struct Recorder {
+        ram : Vec<u8>,
+        log : Vec<(u64, u16, BusAccess)>,
+    }
+
+    impl MemoryIO for Recorder {
+        fn peek(&self, addr : u16) -> u8 {
+            self.ram[usize::from(addr)]
+        }
+
+        fn poke(&mut self, addr : u16, val : u8) {
+            self.ram[usize::from(addr)] = val
+        }
+
+        fn get_range(&self) -> (u16, u16) {
+            (0, 0xffff)
+        }
+
+        fn update_sha1(&self, _digest : &mut Sha1) {
+        }
+
+        fn load_byte(&mut self, addr : u16) -> u8 {
+            self.peek(addr)
+        }
+
+        fn store_byte(&mut self, addr : u16, val : u8) {
+            self.poke(addr, val)
+        }
+
+        fn bus_cycle(&mut self, cycle : u64, addr : u16, access : BusAccess) {
+            self.log.push((cycle, addr, access))
+        }
+    }
+
+    // Runs one step of code at $1000 with S at $8000 and the clock at 100,
+    // FIRQ goes to $5000 and IRQ to $4000.
+    // gives the accesses made and the cycles taken
+    fn bus_trace(code : &[u8], ints : &mut Interrupts) -> (Vec<(u16, BusAccess)>, u64) {
+        let mut mem = Recorder { ram : vec![0x12; 0x1_0000], log : vec![] };
+        mem.upload(0x1000, code);
+        mem.upload(0xfff6, &[0x50, 0x00, 0x40, 0x00]);
+
+        let mut regs = Regs::new();
+        regs.pc = 0x1000;
+        regs.s = 0x8000;
+        regs.bus = BusMode::CycleAccurate;
+
+        let clock = Rc::new(RefCell::new(StandardClock::new(1_000_000)));
+        clock.borrow_mut().add_cycles(100);
+
+        step(&mut regs, &mut mem, &clock, ints).unwrap();
+
+        let cycles = clock.borrow().get_cycles() - 100;
+
+        // Every cycle's numbered in turn from where the clock was
+        for (i, &(cycle, _, _)) in mem.log.iter().enumerate() {
+            assert_eq!(cycle, 100 + i as u64);
+        }
+
+        let trace = mem.log.iter().map(|&(_, addr, access)| (addr, access)).collect();
+        (trace, cycles)
+    }
+
+    #[test]
+    fn lda_extended() {
+        let (trace, cycles) = bus_trace(&[0xb6, 0x20, 0x00], &mut Interrupts::new());
+
+        assert_eq!(trace, [
+            (0x1000, Read),     // opcode
+            (0x1001, Read),     // address high
+            (0x1002, Read),     // address low
+            (0xffff, Dummy),
+            (0x2000, Read),     // data
+        ]);
+        assert_eq!(cycles, 5);
+    }
+
+    #[test]
+    fn jsr_extended() {
+        let (trace, cycles) = bus_trace(&[0xbd, 0x20, 0x00], &mut Interrupts::new());
+
+        assert_eq!(trace, [
+            (0x1000, Read),     // opcode
+            (0x1001, Read),     // address high
+            (0x1002, Read),     // address low
+            (0xffff, Dummy),
+            (0x2000, Dummy),
+            (0xffff, Dummy),
+            (0x7fff, Write),    // pc low
+            (0x7ffe, Write),    // pc high
+        ]);
+        assert_eq!(cycles, 8);
+    }
+
+    #[test]
+    fn irq_entry() {
+        let mut ints = Interrupts::new();
+        ints.set_irq(true);
+        let (trace, cycles) = bus_trace(&[], &mut ints);
+
+        let stacked : Vec<_> = (0x7ff4 ..= 0x7fff).rev().map(|a| (a, Write)).collect();
+
+        assert_eq!(trace[0..3], [(0x1000, Dummy), (0x1000, Dummy), (0xffff, Dummy)]);
+        assert_eq!(trace[3..15], stacked[..]);
+        assert_eq!(trace[15..19], [(0xffff, Dummy), (0xfff8, Read), (0xfff9, Read), (0xffff, Dummy)]);
+
+        // Then the handler's nop, its don't care cycle is padding
+        assert_eq!(trace[19..], [(0x4000, Read), (0xffff, Dummy)]);
+        assert_eq!(cycles, 19 + 2);
+    }
+
+    #[test]
+    fn firq_entry() {
+        let mut ints = Interrupts::new();
+        ints.set_firq(true);
+        let (trace, cycles) = bus_trace(&[], &mut ints);
+
+        assert_eq!(trace[..10], [
+            (0x1000, Dummy),
+            (0x1000, Dummy),
+            (0xffff, Dummy),
+            (0x7fff, Write),    // pc low
+            (0x7ffe, Write),    // pc high
+            (0x7ffd, Write),    // cc
+            (0xffff, Dummy),
+            (0xfff6, Read),     // vector
+            (0xfff7, Read),
+            (0xffff, Dummy),
+        ]);
+        assert_eq!(trace[10], (0x5000, Read));
+        assert_eq!(cycles, 10 + 2);
+    }
+}
@@ -0,0 +1,381 @@
+// Shadow call stack
+//
+// An observer that follows calls and returns so a crash can say how it got
+// there. Frames are pushed on JSR / BSR / LBSR, SWI* and interrupt entry
+// and popped on RTS, RTI and PULS with PC.
+//
+// Each frame remembers where S will be once it has returned. A return that
+// doesn't land there, or S being moved past a frame without a return
+// (LEAS, LDS, TFR to S) is recorded as a mismatch and the frames it skipped
+// are dropped so the stack stays in step with the real one.
+
+use crate::cpu::{Regs, RegEnum, Flags, InstructionDecoder, Interrupt, Observer};
+
+// Deeper than this and something has gone wrong, stop growing
+const MAX_DEPTH : usize = 1024;
+
+// Mismatches kept, oldest go first
+const MAX_MISMATCHES : usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Swi(u8),
+    Interrupt(Interrupt),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub kind : FrameKind,
+    // Address of the calling instruction, or where the interrupt hit
+    pub call_site : u16,
+    // Entry point of the called code
+    pub target : u16,
+    pub return_addr : u16,
+    // S once this frame has returned
+    pub s_return : u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    // A return with nothing on the shadow stack
+    Underflow { at : u16, to : u16 },
+    // A return that didn't match the top frame
+    BadReturn { at : u16, to : u16, s : u16, frame : Frame },
+    // S moved past the frame without it returning
+    Unwound { at : u16, s : u16, frame : Frame },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames : Vec<Frame>,
+    mismatches : Vec<Mismatch>,
+    // Interrupt frame waiting to see where the vector took us
+    pending_target : bool,
+    // Last two bytes pushed onto S this instruction, top of stack first
+    pushed : [u8; 2],
+    // Waiting for the PULS postbyte
+    want_postbyte : bool,
+    postbyte : Option<u8>,
+}
+
+pub fn is_call(op : u16) -> bool {
+    match op {
+        // BSR, LBSR, JSR direct, indexed, extended
+        0x8d | 0x17 | 0x9d | 0xad | 0xbd => true,
+        _ => false,
+    }
+}
+
+pub fn swi_number(op : u16) -> Option<u8> {
+    match op {
+        0x3f => Some(1),
+        0x103f => Some(2),
+        0x113f => Some(3),
+        _ => None,
+    }
+}
+
+// State stacked by an interrupt or SWI, 6309 native mode adds W
+fn entire_state_size(regs : &Regs) -> u16 {
+    if regs.is_native() { 14 } else { 12 }
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // Outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches
+    }
+
+    pub fn clear_mismatches(&mut self) {
+        self.mismatches.clear()
+    }
+
+    fn add_mismatch(&mut self, mismatch : Mismatch) {
+        warn!("call stack mismatch {:?}", mismatch);
+
+        if self.mismatches.len() == MAX_MISMATCHES {
+            self.mismatches.remove(0);
+        }
+
+        self.mismatches.push(mismatch);
+    }
+
+    fn push(&mut self, frame : Frame) {
+        if self.frames.len() < MAX_DEPTH {
+            self.frames.push(frame);
+        }
+    }
+
+    // Drop anything S has moved past
+    fn unwind_to(&mut self, at : u16, s : u16) {
+        while let Some(frame) = self.frames.last().cloned() {
+            if s < frame.s_return {
+                break;
+            }
+            self.frames.pop();
+            self.add_mismatch(Mismatch::Unwound { at, s, frame });
+        }
+    }
+
+    fn do_return(&mut self, at : u16, after : &Regs) {
+        match self.frames.last().cloned() {
+            None => {
+                self.add_mismatch(Mismatch::Underflow { at, to : after.pc });
+            },
+
+            Some(frame) => {
+                if frame.return_addr == after.pc && frame.s_return == after.s {
+                    self.frames.pop();
+                } else {
+                    self.add_mismatch(Mismatch::BadReturn { at, to : after.pc, s : after.s, frame });
+
+                    // It still went, whatever it returned to
+                    if after.s >= frame.s_return {
+                        self.frames.pop();
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Observer for CallStack {
+
+    fn before_execute(&mut self, ins : &InstructionDecoder, _regs : &Regs) {
+        self.want_postbyte = ins.op_code == 0x35;
+        self.postbyte = None;
+
+        if self.pending_target {
+            // The interrupt was taken before this instruction
+            if let Some(frame) = self.frames.last_mut() {
+                frame.target = ins.addr;
+            }
+            self.pending_target = false;
+        }
+    }
+
+    fn after_execute(&mut self, ins : &InstructionDecoder, _before : &Regs, after : &Regs, _cycles : u32) {
+        let op = ins.op_code;
+        let at = ins.addr;
+
+        if is_call(op) {
+            let return_addr = (u16::from(self.pushed[0]) << 8) | u16::from(self.pushed[1]);
+            self.push(Frame {
+                kind : FrameKind::Call,
+                call_site : at,
+                target : after.pc,
+                return_addr,
+                s_return : after.s.wrapping_add(2),
+            });
+
+        } else if let Some(n) = swi_number(op) {
+            let len = if op > 0xff { 2 } else { 1 };
+            self.push(Frame {
+                kind : FrameKind::Swi(n),
+                call_site : at,
+                target : after.pc,
+                return_addr : at.wrapping_add(len),
+                s_return : after.s.wrapping_add(entire_state_size(after)),
+            });
+
+        } else {
+            let pulls_pc = match op {
+                0x39 | 0x3b => true,
+                0x35 => self.postbyte.map(|b| b & 0x80 != 0).unwrap_or(false),
+                _ => false,
+            };
+
+            if pulls_pc {
+                self.do_return(at, after);
+            }
+
+            self.unwind_to(at, after.s);
+        }
+    }
+
+    fn mem_read(&mut self, _addr : u16, val : u8) {
+        // First read after the opcode is PULS's register mask
+        if self.want_postbyte {
+            self.postbyte = Some(val);
+            self.want_postbyte = false;
+        }
+    }
+
+    fn interrupt(&mut self, int : Interrupt, regs : &Regs) {
+        // FIRQ only stacks PC and CC
+        let stacked = if regs.flags.contains(Flags::E) {
+            entire_state_size(regs)
+        } else {
+            3
+        };
+
+        self.push(Frame {
+            kind : FrameKind::Interrupt(int),
+            call_site : regs.pc,
+            target : 0,
+            return_addr : regs.pc,
+            s_return : regs.s.wrapping_add(stacked),
+        });
+
+        self.pending_target = true;
+    }
+
+    fn stack_push(&mut self, stack : RegEnum, _addr : u16, val : u8) {
+        if let RegEnum::S = stack {
+            self.pushed[1] = self.pushed[0];
+            self.pushed[0] = val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::testing::Machine;
+
+    fn machine(code : &[(u16, &[u8])]) -> Machine<CallStack> {
+        Machine::with_observer(code, CallStack::new())
+    }
+
+    #[test]
+    fn jsr_and_rts() {
+        let mut m = machine(&[
+            (0x1000, &[0xbd, 0x20, 0x00]),  // jsr $2000
+            (0x2000, &[0xbd, 0x30, 0x00,    // jsr $3000
+                       0x39]),              // rts
+            (0x3000, &[0x39]),              // rts
+        ]);
+
+        m.step(2);
+
+        assert_eq!(m.obs.frames(), &[
+            Frame { kind : FrameKind::Call, call_site : 0x1000, target : 0x2000, return_addr : 0x1003, s_return : 0x8000 },
+            Frame { kind : FrameKind::Call, call_site : 0x2000, target : 0x3000, return_addr : 0x2003, s_return : 0x7ffe },
+        ]);
+
+        m.step(1);
+        assert_eq!(m.obs.depth(), 1);
+
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x1003);
+        assert_eq!(m.obs.depth(), 0);
+        assert!(m.obs.mismatches().is_empty());
+    }
+
+    #[test]
+    fn irq_stacks_entire_state() {
+        let mut m = machine(&[
+            (0x1000, &[0x1c, 0xaf]),        // andcc #$af
+            (0x4000, &[0x12, 0x3b]),        // nop, rti
+            (0xfff8, &[0x40, 0x00]),
+        ]);
+
+        m.step(1);
+        m.ints.set_irq(true);
+        m.step(1);
+        m.ints.set_irq(false);
+
+        assert_eq!(m.regs.s, 0x8000 - 12);
+        assert_eq!(m.obs.frames(), &[
+            Frame { kind : FrameKind::Interrupt(Interrupt::Irq), call_site : 0x1002, target : 0x4000, return_addr : 0x1002, s_return : 0x8000 },
+        ]);
+
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x1002);
+        assert_eq!(m.obs.depth(), 0);
+        assert!(m.obs.mismatches().is_empty());
+    }
+
+    #[test]
+    fn firq_only_stacks_pc_and_cc() {
+        let mut m = machine(&[
+            (0x1000, &[0x1c, 0xaf]),        // andcc #$af
+            (0x5000, &[0x12, 0x3b]),        // nop, rti
+            (0xfff6, &[0x50, 0x00]),
+        ]);
+
+        m.step(1);
+        m.ints.set_firq(true);
+        m.step(1);
+        m.ints.set_firq(false);
+
+        assert_eq!(m.obs.frames(), &[
+            Frame { kind : FrameKind::Interrupt(Interrupt::Firq), call_site : 0x1002, target : 0x5000, return_addr : 0x1002, s_return : 0x8000 },
+        ]);
+        assert_eq!(m.obs.frames()[0].s_return, m.regs.s + 3);
+
+        m.step(1);
+        assert_eq!(m.obs.depth(), 0);
+        assert!(m.obs.mismatches().is_empty());
+    }
+
+    #[test]
+    fn puls_pc_returns() {
+        let mut m = machine(&[
+            (0x1000, &[0xbd, 0x20, 0x00]),  // jsr $2000
+            (0x2000, &[0x34, 0x02,          // pshs a
+                       0x35, 0x02,          // puls a
+                       0x35, 0x80]),        // puls pc
+        ]);
+
+        m.step(3);
+        assert_eq!(m.obs.depth(), 1, "puls without pc isn't a return");
+
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x1003);
+        assert_eq!(m.obs.depth(), 0);
+        assert!(m.obs.mismatches().is_empty());
+    }
+
+    #[test]
+    fn leas_past_a_frame_unwinds_it() {
+        let mut m = machine(&[
+            (0x1000, &[0xbd, 0x20, 0x00]),  // jsr $2000
+            (0x2000, &[0x32, 0x62]),        // leas 2,s
+        ]);
+
+        m.step(2);
+
+        let frame = Frame { kind : FrameKind::Call, call_site : 0x1000, target : 0x2000, return_addr : 0x1003, s_return : 0x8000 };
+        assert_eq!(m.obs.depth(), 0);
+        assert_eq!(m.obs.mismatches(), &[Mismatch::Unwound { at : 0x2000, s : 0x8000, frame }]);
+    }
+
+    #[test]
+    fn return_somewhere_else() {
+        let mut m = machine(&[
+            (0x1000, &[0xbd, 0x20, 0x00]),  // jsr $2000
+            (0x2000, &[0x8e, 0x30, 0x00,    // ldx #$3000
+                       0xaf, 0xe4,          // stx ,s
+                       0x39]),              // rts
+            (0x3000, &[0x39]),              // rts
+        ]);
+
+        m.step(4);
+
+        let frame = Frame { kind : FrameKind::Call, call_site : 0x1000, target : 0x2000, return_addr : 0x1003, s_return : 0x8000 };
+        assert_eq!(m.regs.pc, 0x3000);
+        assert_eq!(m.obs.depth(), 0, "it still returned");
+        assert_eq!(m.obs.mismatches(), &[Mismatch::BadReturn { at : 0x2005, to : 0x3000, s : 0x8000, frame }]);
+
+        m.step(1);
+        assert_eq!(m.obs.mismatches()[1], Mismatch::Underflow { at : 0x3000, to : 0x1212 });
+    }
+}
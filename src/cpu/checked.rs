@@ -0,0 +1,388 @@
+// Stepping on memory that can fault
+//
+// CheckedMemoryIo holds an error rather than returning one from every load
+// and store, so the core runs on it unchanged. step_checked looks for it
+// after every access. Once there's a fault nothing else the instruction does
+// reaches memory, and at the end everything it did before the fault is put
+// back: the stores it made, the registers, the clock and the interrupt state.
+// Observers are only told about the instruction once it's finished without
+// faulting. A watchpoint isn't a fault, the instruction finishes and the
+// error comes back with it done.
+//
+// Anything a device did on a load before the fault, like a VIA clearing its
+// interrupt flags, can't be taken back.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::mem::{MemoryIO, CheckedMemoryIo, MemError, MemMapping, BusAccess, Sha1};
+use crate::cpu::{Regs, RegEnum, InstructionDecoder, Interrupt, Interrupts, Clock, CpuErr, Observer};
+use crate::cpu::step_with_observer;
+
+// Address and what was there before for every store, oldest first
+type Undo = Vec<(u16, u8)>;
+
+// Memory for a checked step, stores are recorded so they can be undone
+struct Checked<'a, M : 'a + CheckedMemoryIo> {
+    mem : &'a mut M,
+    error : Option<MemError>,
+    undo : Undo,
+}
+
+impl<'a, M : 'a + CheckedMemoryIo> Checked<'a, M> {
+    fn new(mem : &'a mut M, undo : Undo) -> Self {
+        Self { mem, error : None, undo }
+    }
+
+    fn faulted(&self) -> bool {
+        self.error.is_some_and(|e| !e.is_watch())
+    }
+
+    // A fault takes over from a watchpoint, it has to be undone
+    fn check(&mut self) {
+        if let Some(err) = self.mem.take_error() {
+            if self.error.is_none() || (!err.is_watch() && !self.faulted()) {
+                self.error = Some(err)
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        for (addr, val) in self.undo.drain(..).rev() {
+            self.mem.poke(addr, val)
+        }
+    }
+
+    fn done(mut self) -> Undo {
+        self.undo.clear();
+        self.undo
+    }
+}
+
+impl<'a, M : 'a + CheckedMemoryIo> MemoryIO for Checked<'a, M> {
+    fn peek(&self, addr : u16) -> u8 {
+        self.mem.peek(addr)
+    }
+
+    fn poke(&mut self, addr : u16, val : u8) {
+        self.mem.poke(addr, val)
+    }
+
+    fn get_range(&self) -> (u16, u16) {
+        self.mem.get_range()
+    }
+
+    fn update_sha1(&self, digest : &mut Sha1) {
+        self.mem.update_sha1(digest)
+    }
+
+    #[inline]
+    fn load_byte(&mut self, addr : u16) -> u8 {
+        if self.faulted() {
+            return self.mem.peek(addr);
+        }
+
+        let val = self.mem.load_byte(addr);
+        self.check();
+        val
+    }
+
+    #[inline]
+    fn store_byte(&mut self, addr : u16, val : u8) {
+        if self.faulted() {
+            return;
+        }
+
+        self.undo.push((addr, self.mem.peek(addr)));
+        self.mem.store_byte(addr, val);
+        self.check();
+    }
+
+    // Words go through whole, the bus gives them to devices that way
+    #[inline]
+    fn load_word(&mut self, addr : u16) -> u16 {
+        if self.faulted() {
+            return self.mem.peek_word(addr);
+        }
+
+        let val = self.mem.load_word(addr);
+        self.check();
+        val
+    }
+
+    #[inline]
+    fn store_word(&mut self, addr : u16, val : u16) {
+        if self.faulted() {
+            return;
+        }
+
+        let next = addr.wrapping_add(1);
+        self.undo.push((addr, self.mem.peek(addr)));
+        self.undo.push((next, self.mem.peek(next)));
+        self.mem.store_word(addr, val);
+        self.check();
+    }
+
+    fn bus_cycle(&mut self, cycle : u64, addr : u16, access : BusAccess) {
+        if !self.faulted() {
+            self.mem.bus_cycle(cycle, addr, access)
+        }
+    }
+
+    fn dummy_cycle(&mut self, addr : u16) {
+        if !self.faulted() {
+            self.mem.dummy_cycle(addr)
+        }
+    }
+
+    fn get_name(&self) -> String {
+        self.mem.get_name()
+    }
+
+    fn mapping(&self) -> MemMapping {
+        self.mem.mapping()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum Event {
+    Before(InstructionDecoder, Regs),
+    After(InstructionDecoder, Regs, Regs, u32),
+    Read(u16, u8),
+    Write(u16, u8),
+    Interrupt(Interrupt, Regs),
+    Push(RegEnum, u16, u8),
+    Pull(RegEnum, u16, u8),
+}
+
+// Keeps what O would be told until the instruction's known not to have
+// faulted
+struct Held<O : Observer> {
+    events : Vec<Event>,
+    obs : PhantomData<O>,
+}
+
+impl<O : Observer> Held<O> {
+    fn new(events : Vec<Event>) -> Self {
+        Self { events, obs : PhantomData }
+    }
+
+    #[inline]
+    fn hold(&mut self, event : impl FnOnce() -> Event) {
+        if O::ACTIVE {
+            self.events.push(event())
+        }
+    }
+
+    fn tell(&mut self, obs : &mut O) {
+        for event in self.events.drain(..) {
+            match event {
+                Event::Before(ins, regs) => obs.before_execute(&ins, &regs),
+                Event::After(ins, before, after, cycles) => obs.after_execute(&ins, &before, &after, cycles),
+                Event::Read(addr, val) => obs.mem_read(addr, val),
+                Event::Write(addr, val) => obs.mem_write(addr, val),
+                Event::Interrupt(int, regs) => obs.interrupt(int, &regs),
+                Event::Push(stack, addr, val) => obs.stack_push(stack, addr, val),
+                Event::Pull(stack, addr, val) => obs.stack_pull(stack, addr, val),
+            }
+        }
+    }
+}
+
+impl<O : Observer> Observer for Held<O> {
+    const ACTIVE : bool = O::ACTIVE;
+
+    fn before_execute(&mut self, ins : &InstructionDecoder, regs : &Regs) {
+        self.hold(|| Event::Before(ins.clone(), regs.clone()))
+    }
+
+    fn after_execute(&mut self, ins : &InstructionDecoder, before : &Regs, after : &Regs, cycles : u32) {
+        self.hold(|| Event::After(ins.clone(), before.clone(), after.clone(), cycles))
+    }
+
+    fn mem_read(&mut self, addr : u16, val : u8) {
+        self.hold(|| Event::Read(addr, val))
+    }
+
+    fn mem_write(&mut self, addr : u16, val : u8) {
+        self.hold(|| Event::Write(addr, val))
+    }
+
+    fn interrupt(&mut self, int : Interrupt, regs : &Regs) {
+        self.hold(|| Event::Interrupt(int, regs.clone()))
+    }
+
+    fn stack_push(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.hold(|| Event::Push(stack, addr, val))
+    }
+
+    fn stack_pull(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.hold(|| Event::Pull(stack, addr, val))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+thread_local! {
+    // Undo and held events, kept between steps so they're only allocated
+    // the once
+    static BUFFERS : RefCell<(Undo, Vec<Event>)> = const { RefCell::new((vec![], vec![])) };
+}
+
+// step_with_observer for memory that can refuse an access or stop on one.
+// A fault ends with CpuErr::Memory and the machine as it was before the
+// instruction, so it's the one stopped at. A watchpoint ends with
+// CpuErr::Memory after the instruction.
+pub fn step_checked<M: CheckedMemoryIo, C : Clock, O : Observer>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>, ints : &mut Interrupts, obs : &mut O) -> Result<InstructionDecoder, CpuErr> {
+    let before = (regs.clone(), ints.clone(), ref_clock.borrow().get_cycles());
+
+    let (undo, events) = BUFFERS.with(|b| std::mem::take(&mut *b.borrow_mut()));
+
+    let mut checked = Checked::new(mem, undo);
+    let mut held = Held::new(events);
+    let res = step_with_observer(regs, &mut checked, ref_clock, ints, &mut held);
+
+    let ret = match (res, checked.error) {
+        (Ok(_), Some(err)) if !err.is_watch() => {
+            checked.undo();
+            held.events.clear();
+            *regs = before.0;
+            *ints = before.1;
+            ref_clock.borrow_mut().set_cycles(before.2);
+            Err(CpuErr::Memory(err))
+        }
+
+        (Ok(_), Some(err)) => {
+            held.tell(obs);
+            Err(CpuErr::Memory(err))
+        }
+
+        (res, _) => {
+            held.tell(obs);
+            res
+        }
+    };
+
+    let buffers = (checked.done(), held.events);
+    BUFFERS.with(|b| *b.borrow_mut() = buffers);
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::testing::Machine;
+    use crate::mem::Unmapped;
+
+    #[derive(Default)]
+    struct Counter {
+        executed : usize,
+        writes : usize,
+    }
+
+    impl Observer for Counter {
+        fn after_execute(&mut self, _ins : &InstructionDecoder, _before : &Regs, _after : &Regs, _cycles : u32) {
+            self.executed += 1
+        }
+
+        fn mem_write(&mut self, _addr : u16, _val : u8) {
+            self.writes += 1
+        }
+    }
+
+    // RAM with ROM under it at $0000-$00ff and nothing from $8000 up bar
+    // the vectors, the irq vector goes to $1000
+    fn machine(code : &[u8]) -> Machine<Counter> {
+        let mut m = Machine::with_observer(&[(0x1000, code)], Counter::default());
+
+        let rom = m.mem.add_rom("rom", &[0; 0x100]);
+        m.mem.map_mem(0x0000..=0x00ff, rom, 0);
+        m.mem.unmap(0x8000..=0xffef);
+
+        let mut vectors = [0; 0x10];
+        vectors[0x8] = 0x10;
+        let vectors = m.mem.add_rom("vectors", &vectors);
+        m.mem.map_mem(0xfff0..=0xffff, vectors, 0);
+
+        m.mem.set_halt_on_rom_write(true);
+        m.mem.set_unmapped(Unmapped::Halt);
+        m.regs.s = 0x2000;
+        m
+    }
+
+    fn mem_err(res : Result<InstructionDecoder, CpuErr>) -> Option<MemError> {
+        match res {
+            Err(CpuErr::Memory(err)) => Some(err),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn runs_like_step_without_errors() {
+        // ldx #$1234 ; pshs x
+        let mut m = machine(&[0x8e, 0x12, 0x34, 0x34, 0x10]);
+        m.step_checked().unwrap();
+        m.step_checked().unwrap();
+
+        assert_eq!(m.regs.s, 0x1ffe);
+        assert_eq!(m.mem.peek_word(0x1ffe), 0x1234);
+        assert_eq!(m.obs.executed, 2);
+        assert_eq!(m.obs.writes, 2);
+    }
+
+    #[test]
+    fn fault_part_way_undoes_the_instruction() {
+        // pshs a,b,x with s at $0102, x goes into RAM then b hits ROM
+        let mut m = machine(&[0x34, 0x16]);
+        m.regs.s = 0x0102;
+        m.regs.x = 0x1234;
+        m.regs.a = 0x56;
+        m.mem.poke(0x0100, 0xaa);
+        m.mem.poke(0x0101, 0xbb);
+        let regs = m.regs.clone();
+
+        assert_eq!(mem_err(m.step_checked()), Some(MemError::IllegalWrite(0x00ff)));
+
+        assert_eq!(m.regs, regs);
+        assert_eq!(m.mem.peek(0x0100), 0xaa);
+        assert_eq!(m.mem.peek(0x0101), 0xbb);
+        assert_eq!(m.mem.peek(0x00fe), 0x00);
+        assert_eq!(m.cycles(), 0);
+        assert_eq!(m.obs.executed, 0);
+        assert_eq!(m.obs.writes, 0);
+    }
+
+    #[test]
+    fn fault_undoes_an_interrupt() {
+        // the irq's taken then the handler's lda $9000 reads nothing
+        let mut m = machine(&[0xb6, 0x90, 0x00]);
+        m.regs.pc = 0x1100;
+        m.ints.set_irq(true);
+        let (regs, ints) = (m.regs.clone(), m.ints.clone());
+
+        assert_eq!(mem_err(m.step_checked()), Some(MemError::AddressError(0x9000)));
+
+        assert_eq!(m.regs, regs);
+        assert_eq!(m.ints, ints);
+        assert!((0x1ff4 .. 0x2000).all(|a| m.mem.peek(a) == 0x12));
+        assert_eq!(m.cycles(), 0);
+        assert_eq!(m.obs.writes, 0);
+    }
+
+    #[test]
+    fn watchpoint_finishes_the_instruction() {
+        // sta $0200
+        let mut m = machine(&[0xb7, 0x02, 0x00]);
+        m.regs.a = 0x42;
+        m.mem.set_watch(0x0200, BusAccess::Write, true);
+
+        assert_eq!(mem_err(m.step_checked()), Some(MemError::BreakPointWrite(0x0200)));
+        assert_eq!(m.regs.pc, 0x1003);
+        assert_eq!(m.mem.peek(0x0200), 0x42);
+        assert_eq!(m.cycles(), 5);
+        assert_eq!(m.obs.executed, 1);
+    }
+}
@@ -11,6 +11,7 @@ pub trait Clock {
     fn cycles_per_second(&self) -> u64;
     fn add_cycles(&mut self, v : usize) -> u64;
     fn set_cycles(&mut self, v : u64);
+    fn get_cycles(&self) -> u64;
 
     fn inc_cycles(&mut self) -> u64 {
         self.add_cycles(1)
@@ -35,6 +36,10 @@ impl Clock for StandardClock {
         self.cycles = v;
     }
 
+    fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
     fn add_cycles(&mut self, v : usize) -> u64 {
         let r = self.cycles.wrapping_add(v as u64);
         self.cycles = r;
@@ -4,6 +4,10 @@ use crate::mem::{ MemoryIO, MemError };
 use crate::cpu::{Regs, RegEnum, Flags, InstructionDecoder};
 use crate::cpu::{AddressLines, Direct, Extended, Immediate, Inherent, Relative, Indexed};
 use crate::cpu::{Clock, alu};
+use crate::cpu::{OP_TABLE_SIZE, op_to_index};
+use crate::cpu::{Interrupts, Interrupt, RunState};
+use crate::cpu::{MD_ILLEGAL, MD_DIV_ZERO, TRAP_VECTOR, native_cycle_adjust};
+use crate::cpu::{BusMode, CycleBus, Observer, NullObserver, Observed, CoreBus};
 
 use crate::cpu::alu::{GazAlu};
 
@@ -16,11 +20,13 @@ pub enum CpuErr {
     Unimplemented(InstructionDecoder),
     IllegalAddressingMode,
     Memory(MemError),
+    // Undocumented opcode or register combination hit in strict mode
+    Undocumented { op : u16, pc : u16 },
 }
 // use cpu::alu;
 
 fn get_tfr_reg(op : u8 ) -> RegEnum {
-    match op {
+    match op & 0xf {
         0 => RegEnum::D,
         1 => RegEnum::X,
         2 => RegEnum::Y,
@@ -31,10 +37,12 @@ fn get_tfr_reg(op : u8 ) -> RegEnum {
         9 => RegEnum::B,
         10 =>RegEnum::CC,
         11 =>RegEnum::DP,
-        _ => {
-            println!("op of {:02X}", op);
-            panic!("illegal tfr regs")
-        },
+        // 6309 only
+        6 => RegEnum::W,
+        7 => RegEnum::V,
+        12 | 13 => RegEnum::Zero,
+        14 => RegEnum::E,
+        _ => RegEnum::F,
     }
 }
 
@@ -42,26 +50,43 @@ pub fn get_tfr_regs(op : u8) -> (RegEnum, RegEnum) {
     ( get_tfr_reg(op>>4), get_tfr_reg(op&0xf) ) 
 }
 
-pub struct Context<'a, C : 'a + Clock, M : 'a + MemoryIO> {
+pub struct Context<'a, M : 'a + CoreBus> {
     regs : &'a mut Regs,
     mem : &'a mut M,
-    ref_clock : &'a Rc<RefCell<C>>,
+    ints : &'a mut Interrupts,
     ins : InstructionDecoder,
+    // Cycles on top of the decoder's count, survives the decoder being
+    // replaced when an interrupt is taken
+    extra_cycles : u32,
 }
 
-impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
     fn inc_cycles(&mut self) {
-        // self.ins.inc_cycles();
-        self.ref_clock.borrow_mut().inc_cycles();
+        self.add_cycles(1);
     }
 
     fn add_cycles(&mut self, i0 : usize) {
-        // self.ins.add_cycles(i0 as u32);
-        self.ref_clock.borrow_mut().add_cycles(i0);
+        self.extra_cycles += i0 as u32;
+    }
+
+    // Don't care bus cycles, only seen by a cycle accurate bus
+    fn dummy(&mut self, addr : u16) {
+        self.mem.dummy_cycle(addr);
+    }
+
+    fn dummy_pc(&mut self) {
+        let pc = self.get_pc();
+        self.dummy(pc);
+    }
+
+    fn dummy_vma(&mut self, n : usize) {
+        for _ in 0..n {
+            self.dummy(0xffff);
+        }
     }
 }
 
-impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
 
     fn set_pc(&mut self, v : u16) {
         self.ins.next_addr = v;
@@ -177,6 +202,7 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
         let r = func(&mut self.regs.flags, write_mask, v );
 
+        self.dummy_vma(1);
         self.mem.store_byte(ea,r);
 
         Ok(r)
@@ -224,57 +250,64 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 ////////////////////////////////////////////////////////////////////////////////
 // Stakc functions
 
-impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
 
     fn pushu_byte(&mut self, v : u8) -> Result<(), CpuErr> {
         let u = self.regs.u.wrapping_sub(1);
         self.mem.store_byte(u,v);
+        self.mem.observer().stack_push(RegEnum::U, u, v);
         self.regs.u = u;
         Ok(())
     }
 
+    // Low byte goes on the bus first
     fn pushu_word(&mut self, v : u16) -> Result<(), CpuErr> {
-        let u = self.regs.u.wrapping_sub(2);
-        self.mem.store_word(u,v);
-        self.regs.u = u ;
-        Ok(())
+        self.pushu_byte(v as u8)?;
+        self.pushu_byte((v >> 8) as u8)
     }
 
     fn popu_byte(&mut self) -> Result<u8, CpuErr> {
         let r = self.mem.load_byte(self.regs.u);
+        self.mem.observer().stack_pull(RegEnum::U, self.regs.u, r);
         self.regs.u = self.regs.u.wrapping_add(1);
         Ok(r)
     }
 
     fn popu_word(&mut self) -> Result<u16, CpuErr> {
-        let r = self.mem.load_word(self.regs.u);
-        self.regs.u = self.regs.u.wrapping_add(2);
+        let u = self.regs.u;
+        let r = self.mem.load_word(u);
+        self.mem.observer().stack_pull(RegEnum::U, u, (r >> 8) as u8);
+        self.mem.observer().stack_pull(RegEnum::U, u.wrapping_add(1), r as u8);
+        self.regs.u = u.wrapping_add(2);
         Ok(r)
     }
 
     fn pushs_byte(&mut self, v : u8) -> Result<(), CpuErr>{
         let s = self.regs.s.wrapping_sub(1);
         self.mem.store_byte(s,v);
+        self.mem.observer().stack_push(RegEnum::S, s, v);
         self.regs.s = s;
         Ok(())
     }
 
     fn pushs_word(&mut self, v : u16) -> Result<(), CpuErr> {
-        let s = self.regs.s.wrapping_sub(2);
-        self.mem.store_word(s,v);
-        self.regs.s = s ;
-        Ok(())
+        self.pushs_byte(v as u8)?;
+        self.pushs_byte((v >> 8) as u8)
     }
 
     fn pops_byte(&mut self) -> Result<u8,CpuErr> {
         let r = self.mem.load_byte(self.regs.s);
+        self.mem.observer().stack_pull(RegEnum::S, self.regs.s, r);
         self.regs.s = self.regs.s.wrapping_add(1);
         Ok(r)
     }
 
     fn pops_word(&mut self) -> Result<u16, CpuErr> {
-        let r = self.mem.load_word(self.regs.s);
-        self.regs.s = self.regs.s.wrapping_add(2);
+        let s = self.regs.s;
+        let r = self.mem.load_word(s);
+        self.mem.observer().stack_pull(RegEnum::S, s, (r >> 8) as u8);
+        self.mem.observer().stack_pull(RegEnum::S, s.wrapping_add(1), r as u8);
+        self.regs.s = s.wrapping_add(2);
         Ok(r)
     }
 
@@ -282,7 +315,7 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
     fn orcc<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
         let v =  self.fetch_byte::<A>()?;
         let cc = self.regs.flags.bits();
@@ -354,9 +387,9 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn tfr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
         self.add_cycles(4);
         let operand = self.fetch_byte::<A>()?;
-        let (a,b) = get_tfr_regs(operand as u8);
-        let av = self.regs.get(&a);
-        self.regs.set(&b, av);
+        let (a,b) = self.get_tfr_regs(operand)?;
+        let av = self.tfr_value(a, b);
+        self.set_reg_value(b, av);
         Ok(())
     }
 
@@ -419,6 +452,7 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     }
 
     fn rts<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.dummy_pc();
         let pc = self.pops_word()?;
         self.set_pc(pc);
         Ok(())
@@ -427,6 +461,9 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn bsr< A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let offset = self.fetch_byte_as_i16::<A>()?;
         let next_op = self.get_pc();
+        self.dummy_vma(1);
+        self.dummy(next_op.wrapping_add(offset as u16));
+        self.dummy_vma(1);
         self.pushs_word( next_op)?;
         self.set_pc_rel(offset);
         Ok(())
@@ -710,18 +747,21 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
     //////////////////////////////////////////////////////////////////////////////// 
     fn exg<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
+        self.add_cycles(6);
         let operand = self.fetch_byte::<A>()?;
-        let (a,b) = get_tfr_regs(operand as u8);
-        let av = self.regs.get(&a);
-        let bv = self.regs.get(&b);
-        self.regs.set(&b, av);
-        self.regs.set(&a, bv);
+        let (a,b) = self.get_tfr_regs(operand)?;
+        let av = self.tfr_value(a, b);
+        let bv = self.tfr_value(b, a);
+        self.set_reg_value(b, av);
+        self.set_reg_value(a, bv);
         Ok(())
     }
 
     fn jsr<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let dest = self.ea::<A>()?;
         let next_op = self.get_pc();
+        self.dummy(dest);
+        self.dummy_vma(1);
         self.pushs_word(next_op)?;
         self.set_pc(dest);
         Ok(())
@@ -732,6 +772,9 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn lbsr<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let offset = self.fetch_word_as_i16::<A>()?;
         let next_op = self.get_pc();
+        self.dummy_vma(2);
+        self.dummy(next_op.wrapping_add(offset as u16));
+        self.dummy_vma(1);
         self.pushs_word(next_op)?;
         self.set_pc_rel(offset);
         Ok(())
@@ -859,6 +902,8 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn lds<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let i0 = self.load_reg_word::<A>()?;
         self.regs.s = i0;
+        // Only LDS arms NMI, other writes to S don't
+        self.ints.arm_nmi();
         Ok(())
     }
 
@@ -875,6 +920,10 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
         let op = self.fetch_byte::<A>()?;
 
+        self.dummy_vma(2);
+        let sp = self.regs.s;
+        self.dummy(sp);
+
         let is_set = |m : u8| (op & m) == m;
 
         if is_set(0x80) {
@@ -923,6 +972,10 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
         let op = self.fetch_byte::<A>()?;
 
+        self.dummy_vma(2);
+        let sp = self.regs.u;
+        self.dummy(sp);
+
         let is_set = |m : u8| (op & m) == m;
 
         if is_set(0x80) {
@@ -970,6 +1023,8 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn puls<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let op = self.fetch_byte::<A>()?;
 
+        self.dummy_vma(2);
+
         if ( op & 0x1 ) == 0x1  {
             let i0 = self.pops_byte()?;
             self.regs.flags.set_flags(i0);
@@ -1013,6 +1068,8 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn pulu<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let op = self.fetch_byte::<A>()?;
 
+        self.dummy_vma(2);
+
         if ( op & 0x1 ) == 0x1  {
             let i0 = self.popu_byte()?;
             self.regs.flags.set_flags(i0);
@@ -1078,19 +1135,19 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     fn leay<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let ea = self.ea::<A>()?;
         self.regs.flags.set(Flags::Z, ea == 0);
-        self.regs.y = ea;;
+        self.regs.y = ea;
         Ok(())
     }
 
     fn leas<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let ea = self.ea::<A>()?;
-        self.regs.s = ea;;
+        self.regs.s = ea;
         Ok(())
     }
 
     fn leau<A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
         let ea = self.ea::<A>()?;
-        self.regs.u = ea;;
+        self.regs.u = ea;
         Ok(())
     }
 
@@ -1201,26 +1258,14 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
     fn swi_base<A : AddressLines>(&mut self, vec : u16, flags : Flags)  -> Result<(), CpuErr> {
 
-        macro_rules! push8 {
-            ($val:expr) => (
-                { let i0 = $val; self.pushs_byte(i0 )? })}
-
-        macro_rules! push16 {
-            ($val:expr) => (
-                { let i0 = $val; self.pushs_word(i0)? })}
-
         self.regs.flags |= flags;
 
-        push16!(self.get_pc());
-        push16!(self.regs.u);
-        push16!(self.regs.y);
-        push16!(self.regs.x);
+        self.dummy_pc();
+        self.dummy_vma(1);
 
-        push8!(self.regs.dp);
-        push8!(self.regs.b);
-        push8!(self.regs.a);
+        self.push_entire_state()?;
 
-        push8!(self.regs.flags.bits());
+        self.dummy_vma(1);
 
         let pc = self.mem.load_word(vec);
         self.set_pc(pc);
@@ -1262,6 +1307,8 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
             () => { self.pops_word()? };
             ($val:expr) => ( { let i0 = pop16!(); $val = i0 })}
 
+        self.dummy_pc();
+
         let cc = pop8!();
 
         self.regs.flags.set_flags(cc);
@@ -1270,6 +1317,12 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
 
             pop8!(self.regs.a);
             pop8!(self.regs.b);
+
+            if self.regs.is_native() {
+                pop8!(self.regs.e);
+                pop8!(self.regs.f);
+            }
+
             pop8!(self.regs.dp);
             pop16!(self.regs.x);
             pop16!(self.regs.y);
@@ -1283,57 +1336,1730 @@ impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
     }
 
     fn cwai< A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
-        Err(CpuErr::Unimplemented(self.ins.clone()))
-    }
-
-    fn reset< A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
-        Err(CpuErr::Unimplemented(self.ins.clone()))
+        let mask = self.fetch_byte::<A>()?;
+        let cc = self.regs.flags.bits() & mask;
+        self.regs.flags.set_flags(cc);
+        self.regs.flags.set(Flags::E, true);
+        self.dummy_pc();
+        self.dummy_vma(1);
+        self.push_entire_state()?;
+        self.add_cycles(18);
+        self.ints.set_run_state(RunState::Waiting);
+        Ok(())
     }
 
     fn sync< A : AddressLines>(&mut self)  -> Result<(), CpuErr> {
+        self.add_cycles(2);
+        self.ints.set_run_state(RunState::Syncing);
         Ok(())
     }
 
     fn unimplemented(&mut self) -> Result<(), CpuErr> {
         Err(CpuErr::Unimplemented(self.ins.clone()))
     }
+
+    fn undocumented(&mut self) -> Result<(), CpuErr> {
+        Err(CpuErr::Undocumented { op : self.ins.op_code, pc : self.ins.addr })
+    }
 }
 
-impl<'a, C : 'a + Clock, M : 'a + MemoryIO> Context<'a, C, M> {
+////////////////////////////////////////////////////////////////////////////////
+// Undocumented 6809
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
 
-    fn new(mem : &'a mut M, regs : &'a mut Regs, ref_clock: &'a Rc<RefCell<C>>) -> Context<'a, C,M> {
-        let ins = InstructionDecoder::new(regs.pc);
-        Context { regs, mem, ref_clock, ins, }
+    // NEG with carry clear, COM with carry set
+    fn ngc_op(flags : &mut Flags, write_mask : u8, v : u32) -> u8 {
+        if flags.contains(Flags::C) {
+            u8::com(flags, write_mask, v)
+        } else {
+            u8::neg(flags, write_mask, v)
+        }
     }
 
-    pub fn fetch_instruction(&mut self) -> u16 {
-        self.ins.fetch_instruction(self.mem)
+    fn ngc<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.rwmod8::<A>(Flags::NZVC.bits(), Self::ngc_op)?;
+        Ok(())
+    }
+
+    fn ngca<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.moda(Flags::NZVC.bits(), Self::ngc_op);
+        Ok(())
+    }
+
+    fn ngcb<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modb(Flags::NZVC.bits(), Self::ngc_op);
+        Ok(())
+    }
+
+    // Immediate byte is read and thrown away
+    fn scc<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.fetch_byte::<A>()?;
+        self.regs.flags.insert(Flags::N);
+        self.regs.flags.remove(Flags::Z | Flags::V);
+        Ok(())
+    }
+
+    // SWI through the reset vector
+    fn reset<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        if self.regs.is_6309() {
+            return self.illegal_trap();
+        }
+
+        if !self.regs.emulate_undocumented() {
+            return self.undocumented();
+        }
+
+        self.regs.flags.insert(Flags::E);
+        self.dummy_pc();
+        self.dummy_vma(1);
+        self.push_entire_state()?;
+        self.regs.flags.insert(Flags::I | Flags::F);
+        self.dummy_vma(1);
+        let pc = self.mem.load_word(0xfffe);
+        self.set_pc(pc);
+        Ok(())
+    }
+
+    // Real silicon needs a reset, we just keep executing the same opcode
+    fn hcf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let pc = self.ins.addr;
+        self.set_pc(pc);
+        Ok(())
+    }
+
+    // Mixed size transfers on a 6809 put $ff in the top byte of an 8 bit
+    // source
+    fn tfr_value(&self, src : RegEnum, dst : RegEnum) -> u16 {
+        let v = self.get_reg_value(src);
+
+        if !self.regs.is_6309() && !src.is_16_bit() && dst.is_16_bit() {
+            0xff00 | v
+        } else {
+            v
+        }
     }
 }
 
-pub fn reset<M: MemoryIO>(regs : &mut Regs, mem : &mut M) {
-    *regs = Regs {
-        pc : mem.load_word(0xfffe),
-        flags : Flags::I | Flags::F,
-        .. Default::default()
-    };
+////////////////////////////////////////////////////////////////////////////////
+// Hardware interrupts
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
+
+    fn push_entire_state(&mut self) -> Result<(), CpuErr> {
+        let pc = self.get_pc();
+        self.pushs_word(pc)?;
+        let u = self.regs.u;
+        self.pushs_word(u)?;
+        let y = self.regs.y;
+        self.pushs_word(y)?;
+        let x = self.regs.x;
+        self.pushs_word(x)?;
+        let dp = self.regs.dp;
+        self.pushs_byte(dp)?;
+
+        if self.regs.is_native() {
+            let w = self.regs.get_w();
+            self.pushs_word(w)?;
+        }
+
+        let b = self.regs.b;
+        self.pushs_byte(b)?;
+        let a = self.regs.a;
+        self.pushs_byte(a)?;
+        let cc = self.regs.flags.bits();
+        self.pushs_byte(cc)
+    }
+
+    // Stack the machine state, mask and jump through the vector
+    fn take_interrupt(&mut self, int : Interrupt) -> Result<(), CpuErr> {
+
+        let pc = self.get_pc();
+        self.dummy(pc);
+        self.dummy(pc);
+        self.dummy_vma(1);
+
+        // The 6309 can be told to treat FIRQ like IRQ
+        if int == Interrupt::Firq && !self.regs.is_firq_irq() {
+            self.regs.flags.set(Flags::E, false);
+            let pc = self.get_pc();
+            self.pushs_word(pc)?;
+            let cc = self.regs.flags.bits();
+            self.pushs_byte(cc)?;
+        } else {
+            self.regs.flags.set(Flags::E, true);
+            self.push_entire_state()?;
+        }
+
+        self.add_cycles(int.cycles());
+
+        if self.regs.is_native() {
+            self.add_cycles(2);
+        }
+
+        self.dummy_vma(1);
+        self.vector_to(int);
+
+        Ok(())
+    }
+
+    // Mask and jump through the vector, state has already been stacked
+    fn vector_to(&mut self, int : Interrupt) {
+        let mask = if int == Interrupt::Irq {
+            Flags::I
+        } else {
+            Flags::I | Flags::F
+        };
+
+        self.regs.flags.insert(mask);
+
+        self.ints.acknowledge(int);
+        self.mem.observer().interrupt(int, self.regs);
+
+        let pc = self.mem.load_word(int.vector());
+        self.dummy_vma(1);
+        self.regs.pc = pc;
+        self.ins = InstructionDecoder::new(pc);
+    }
+
+    // Returns true if the cpu is still waiting for an interrupt
+    fn handle_wait_state(&mut self) -> bool {
+
+        let pending = self.ints.pending(self.regs.flags);
+
+        let still_waiting = match self.ints.get_run_state() {
+            RunState::Running => false,
+
+            RunState::Syncing => {
+                !self.ints.any_asserted()
+            },
+
+            RunState::Waiting => {
+                if let Some(int) = pending {
+                    self.add_cycles(3);
+                    self.vector_to(int);
+                }
+                pending.is_none()
+            },
+        };
+
+        if !still_waiting {
+            self.ints.set_run_state(RunState::Running);
+        }
+
+        still_waiting
+    }
+
+    // What step reports while the cpu is waiting for an interrupt
+    fn waiting_ins(&self) -> InstructionDecoder {
+        let mut ins = InstructionDecoder::new(self.regs.pc);
+
+        ins.op_code = match self.ints.get_run_state() {
+            RunState::Waiting => 0x3c,
+            _ => 0x13,
+        };
+
+        ins.cycles = 1;
+        ins
+    }
 }
 
-pub fn step<M: MemoryIO, C : Clock>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>) -> Result<InstructionDecoder, CpuErr> {
+////////////////////////////////////////////////////////////////////////////////
+// HD6309
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
 
-    let mut ctx = Context::new(mem,regs,ref_clock);
+    fn get_tfr_regs(&self, op : u8) -> Result<(RegEnum, RegEnum), CpuErr> {
+        let (a,b) = get_tfr_regs(op);
 
-    macro_rules! handle_op {
-        ($addr:ident, $action:ident) => ({ ctx.$action::<$addr>() }) }
+        if !self.regs.is_6309() {
+            let undefined = a.is_6309_only() || b.is_6309_only();
+            let mixed = a.is_16_bit() != b.is_16_bit();
 
-    op_table!(ctx.fetch_instruction(), { ctx.unimplemented() })?;
+            if (undefined || mixed) && !self.regs.emulate_undocumented() {
+                return Err(CpuErr::Undocumented { op : self.ins.op_code, pc : self.ins.addr });
+            }
+        }
 
-    ctx.regs.pc =  ctx.ins.next_addr;
+        Ok((a,b))
+    }
 
-    Ok(ctx.ins.clone())
+    // PC reads and writes need to go through the decoder
+    // Register codes the 6809 doesn't define read as $ffff and ignore writes
+    fn get_reg_value(&self, r : RegEnum) -> u16 {
+        match r {
+            RegEnum::PC => self.get_pc(),
+            _ if r.is_6309_only() && !self.regs.is_6309() => 0xffff,
+            _ => self.regs.get(&r),
+        }
+    }
+
+    fn set_reg_value(&mut self, r : RegEnum, v : u16) {
+        match r {
+            RegEnum::PC => self.set_pc(v),
+            _ if r.is_6309_only() && !self.regs.is_6309() => (),
+            _ => self.regs.set(&r, v),
+        }
+    }
+
+    // Mixed size inter register ops use the 16 bit register an 8 bit one
+    // lives in
+    fn get_reg_value_16(&self, r : RegEnum) -> u16 {
+        match r {
+            RegEnum::A | RegEnum::B => self.regs.get_d(),
+            RegEnum::E | RegEnum::F => self.regs.get_w(),
+            _ => self.get_reg_value(r),
+        }
+    }
+
+    fn trap(&mut self, err_bit : u8) -> Result<(), CpuErr> {
+        self.regs.md |= err_bit;
+        self.regs.flags.set(Flags::E, true);
+        self.push_entire_state()?;
+        self.regs.flags.insert(Flags::I | Flags::F);
+        self.add_cycles(20);
+        let pc = self.mem.load_word(TRAP_VECTOR);
+        self.set_pc(pc);
+        Ok(())
+    }
+
+    fn illegal_trap(&mut self) -> Result<(), CpuErr> {
+        self.trap(MD_ILLEGAL)
+    }
+
+    fn div_zero_trap(&mut self) -> Result<(), CpuErr> {
+        self.trap(MD_DIV_ZERO)
+    }
+
+    fn nz32(&mut self, v : u32) {
+        self.regs.flags.set(Flags::N, v & 0x8000_0000 != 0);
+        self.regs.flags.set(Flags::Z, v == 0);
+    }
+
+    fn modr8( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32) -> u8 ) -> u8 {
+        let i0 = self.regs.get(&r);
+        let v = func(&mut self.regs.flags, write_mask, u32::from(i0));
+        self.regs.set(&r, u16::from(v));
+        v
+    }
+
+    fn modr16( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32) -> u16 ) -> u16 {
+        let i0 = self.regs.get(&r);
+        let v = func(&mut self.regs.flags, write_mask, u32::from(i0));
+        self.regs.set(&r, v);
+        v
+    }
+
+    fn opr8_2< A : AddressLines>( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32, u32) -> u8 ) -> Result<u8, CpuErr> {
+        let i0 = self.regs.get(&r);
+        let i1 = self.fetch_byte::<A>()?;
+        Ok(func(&mut self.regs.flags, write_mask,u32::from(i0),u32::from(i1)))
+    }
+
+    fn modr8_2< A : AddressLines>( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32, u32) -> u8 ) -> Result<u8, CpuErr> {
+        let v = self.opr8_2::<A>(r, write_mask, func)?;
+        self.regs.set(&r, u16::from(v));
+        Ok(v)
+    }
+
+    fn opr16_2< A : AddressLines>( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32, u32) -> u16 ) -> Result<u16, CpuErr> {
+        let i0 = self.regs.get(&r);
+        self.op16_2::<A>(write_mask, func, i0)
+    }
+
+    fn modr16_2< A : AddressLines>( &mut self, r : RegEnum, write_mask : u8, func : fn(&mut Flags,u8, u32, u32) -> u16 ) -> Result<u16, CpuErr> {
+        let v = self.opr16_2::<A>(r, write_mask, func)?;
+        self.regs.set(&r, v);
+        Ok(v)
+    }
+
+    fn clr_reg(&mut self, r : RegEnum) -> Result<(), CpuErr> {
+        self.regs.set(&r, 0);
+        self.post_clear();
+        Ok(())
+    }
+
+    // r1 = r1 op r0, the result size is the size of r1
+    fn inter_reg<A : AddressLines>(&mut self, write_mask : u8,
+                                   func8 : fn(&mut Flags,u8, u32, u32) -> u8,
+                                   func16 : fn(&mut Flags,u8, u32, u32) -> u16,
+                                   write : bool) -> Result<(), CpuErr> {
+        let operand = self.fetch_byte::<A>()?;
+        let (r0, r1) = self.get_tfr_regs(operand)?;
+
+        if r1.is_16_bit() {
+            let i0 = self.get_reg_value_16(r0);
+            let i1 = self.get_reg_value(r1);
+            let r = func16(&mut self.regs.flags, write_mask, u32::from(i1), u32::from(i0));
+            if write {
+                self.set_reg_value(r1, r);
+            }
+        } else {
+            let i0 = self.get_reg_value(r0) & 0xff;
+            let i1 = self.get_reg_value(r1);
+            let r = func8(&mut self.regs.flags, write_mask, u32::from(i1), u32::from(i0));
+            if write {
+                self.set_reg_value(r1, u16::from(r));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Logical op of an immediate byte with memory
+    fn imm_mem<A : AddressLines>(&mut self, func : fn(&mut Flags,u8, u32, u32) -> u8, write : bool) -> Result<(), CpuErr> {
+        let imm = self.ins.fetch_byte(self.mem);
+        let ea = self.ea::<A>()?;
+        let v = self.mem.load_byte(ea);
+        let r = func(&mut self.regs.flags, Flags::NZV.bits(), u32::from(imm), u32::from(v));
+
+        if write {
+            self.mem.store_byte(ea, r);
+        }
+
+        Ok(())
+    }
+
+    // Single bit ops between a register and a direct page byte
+    // post byte is rr sss ddd, register, source (memory) bit and dest bit
+    fn bit_op<A : AddressLines>(&mut self, func : fn(bool, bool) -> bool) -> Result<(), CpuErr> {
+        let post = self.ins.fetch_byte(self.mem);
+        let ea = self.ea::<A>()?;
+        let m = self.mem.load_byte(ea);
+
+        let r = match post >> 6 {
+            0 => RegEnum::CC,
+            1 => RegEnum::A,
+            2 => RegEnum::B,
+            _ => return self.illegal_trap(),
+        };
+
+        let src_mask = 1 << ((post >> 3) & 7);
+        let dst_mask = 1 << (post & 7);
+
+        let rv = self.regs.get(&r) as u8;
+
+        let bit = func(rv & dst_mask != 0, m & src_mask != 0);
+
+        let rv = if bit { rv | dst_mask } else { rv & !dst_mask };
+
+        self.regs.set(&r, u16::from(rv));
+
+        Ok(())
+    }
+
+    fn tfm(&mut self, src_inc : u16, dst_inc : u16) -> Result<(), CpuErr> {
+        let operand = self.ins.fetch_byte(self.mem);
+        let (r0, r1) = self.get_tfr_regs(operand)?;
+
+        let ok_reg = |r : RegEnum| match r {
+            RegEnum::D | RegEnum::X | RegEnum::Y | RegEnum::U | RegEnum::S => true,
+            _ => false,
+        };
+
+        if !ok_reg(r0) || !ok_reg(r1) {
+            return self.illegal_trap();
+        }
+
+        // The real chip is interruptible mid transfer, we do it all at once
+        self.ins.add_cycles(3);
+
+        while self.regs.get_w() != 0 {
+            let src = self.regs.get(&r0);
+            let dst = self.regs.get(&r1);
+            let v = self.mem.load_byte(src);
+            self.mem.store_byte(dst, v);
+            self.regs.wrapping_add_and_set(&r0, src_inc);
+            self.regs.wrapping_add_and_set(&r1, dst_inc);
+            self.regs.dec(&RegEnum::W);
+            self.ins.add_cycles(3);
+        }
+
+        Ok(())
+    }
 }
 
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
 
-//
-// }}}
+    fn oim<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.imm_mem::<A>(u8::or, true)
+    }
+
+    fn aim<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.imm_mem::<A>(u8::and, true)
+    }
+
+    fn eim<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.imm_mem::<A>(u8::eor, true)
+    }
+
+    fn tim<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.imm_mem::<A>(u8::and, false)
+    }
+
+    fn sexw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let d = if self.regs.e & 0x80 == 0x80 { 0xffff } else { 0 };
+        self.regs.set_d(d);
+        let q = self.regs.get_q();
+        self.nz32(q);
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Q
+
+    fn ldq_immediate<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let hi = u32::from(self.ins.fetch_word(self.mem));
+        let lo = u32::from(self.ins.fetch_word(self.mem));
+        let q = hi << 16 | lo;
+        self.regs.set_q(q);
+        self.nz32(q);
+        self.regs.flags.set(Flags::V, false);
+        Ok(())
+    }
+
+    fn ldq<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let ea = self.ea::<A>()?;
+        let hi = u32::from(self.mem.load_word(ea));
+        let lo = u32::from(self.mem.load_word(ea.wrapping_add(2)));
+        let q = hi << 16 | lo;
+        self.regs.set_q(q);
+        self.nz32(q);
+        self.regs.flags.set(Flags::V, false);
+        Ok(())
+    }
+
+    fn stq<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let ea = self.ea::<A>()?;
+        let q = self.regs.get_q();
+        self.mem.store_word(ea, (q >> 16) as u16);
+        self.mem.store_word(ea.wrapping_add(2), q as u16);
+        self.nz32(q);
+        self.regs.flags.set(Flags::V, false);
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Inter register
+
+    fn addr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZVC.bits(), u8::add, u16::add, true)
+    }
+
+    fn adcr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZVC.bits(), u8::adc, u16::adc, true)
+    }
+
+    fn subr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZVC.bits(), u8::sub, u16::sub, true)
+    }
+
+    fn sbcr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZVC.bits(), u8::sbc, u16::sbc, true)
+    }
+
+    fn andr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZV.bits(), u8::and, u16::and, true)
+    }
+
+    fn orr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZV.bits(), u8::or, u16::or, true)
+    }
+
+    fn eorr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZV.bits(), u8::eor, u16::eor, true)
+    }
+
+    fn cmpr<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.inter_reg::<A>(Flags::NZVC.bits(), u8::sub, u16::sub, false)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // W stacking
+
+    fn pshsw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let w = self.regs.get_w();
+        self.pushs_word(w)
+    }
+
+    fn pulsw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let w = self.pops_word()?;
+        self.regs.set_w(w);
+        Ok(())
+    }
+
+    fn pshuw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let w = self.regs.get_w();
+        self.pushu_word(w)
+    }
+
+    fn puluw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let w = self.popu_word()?;
+        self.regs.set_w(w);
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // D, W, E and F inherent
+
+    fn negd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZVC.bits(), u16::neg);
+        Ok(())
+    }
+
+    fn comd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZVC.bits(), u16::com);
+        Ok(())
+    }
+
+    fn lsrd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZC.bits(), u16::lsr);
+        Ok(())
+    }
 
+    fn rord<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZC.bits(), u16::ror);
+        Ok(())
+    }
+
+    fn asrd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZVC.bits(), u16::asr);
+        Ok(())
+    }
+
+    fn lsld_asld<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZVC.bits(), u16::asl);
+        Ok(())
+    }
+
+    fn rold<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZVC.bits(), u16::rol);
+        Ok(())
+    }
+
+    fn decd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZV.bits(), u16::dec);
+        Ok(())
+    }
+
+    fn incd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZV.bits(), u16::inc);
+        Ok(())
+    }
+
+    fn tstd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::D, Flags::NZV.bits(), u16::tst);
+        Ok(())
+    }
+
+    fn clrd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.clr_reg(RegEnum::D)
+    }
+
+    fn comw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZVC.bits(), u16::com);
+        Ok(())
+    }
+
+    fn lsrw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZC.bits(), u16::lsr);
+        Ok(())
+    }
+
+    fn rorw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZC.bits(), u16::ror);
+        Ok(())
+    }
+
+    fn rolw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZVC.bits(), u16::rol);
+        Ok(())
+    }
+
+    fn decw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZV.bits(), u16::dec);
+        Ok(())
+    }
+
+    fn incw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZV.bits(), u16::inc);
+        Ok(())
+    }
+
+    fn tstw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16(RegEnum::W, Flags::NZV.bits(), u16::tst);
+        Ok(())
+    }
+
+    fn clrw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.clr_reg(RegEnum::W)
+    }
+
+    fn come<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::E, Flags::NZVC.bits(), u8::com);
+        Ok(())
+    }
+
+    fn dece<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::E, Flags::NZV.bits(), u8::dec);
+        Ok(())
+    }
+
+    fn ince<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::E, Flags::NZV.bits(), u8::inc);
+        Ok(())
+    }
+
+    fn tste<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::E, Flags::NZV.bits(), u8::tst);
+        Ok(())
+    }
+
+    fn clre<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.clr_reg(RegEnum::E)
+    }
+
+    fn comf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::F, Flags::NZVC.bits(), u8::com);
+        Ok(())
+    }
+
+    fn decf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::F, Flags::NZV.bits(), u8::dec);
+        Ok(())
+    }
+
+    fn incf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::F, Flags::NZV.bits(), u8::inc);
+        Ok(())
+    }
+
+    fn tstf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8(RegEnum::F, Flags::NZV.bits(), u8::tst);
+        Ok(())
+    }
+
+    fn clrf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.clr_reg(RegEnum::F)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // 16 bit memory ops on D and W
+
+    fn subw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16_2::<A>(RegEnum::W, Flags::NZVC.bits(), u16::sub)?;
+        Ok(())
+    }
+
+    fn cmpw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.opr16_2::<A>(RegEnum::W, Flags::NZVC.bits(), u16::sub)?;
+        Ok(())
+    }
+
+    fn addw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr16_2::<A>(RegEnum::W, Flags::NZVC.bits(), u16::add)?;
+        Ok(())
+    }
+
+    fn ldw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let i0 = self.load_reg_word::<A>()?;
+        self.regs.set_w(i0);
+        Ok(())
+    }
+
+    fn stw<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let r = self.regs.get_w();
+        self.st16::<A>(r)
+    }
+
+    fn sbcd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modd_2::<A>(Flags::NZVC.bits(), u16::sbc)?;
+        Ok(())
+    }
+
+    fn adcd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modd_2::<A>(Flags::NZVC.bits(), u16::adc)?;
+        Ok(())
+    }
+
+    fn andd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modd_2::<A>(Flags::NZV.bits(), u16::and)?;
+        Ok(())
+    }
+
+    fn bitd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.opd_2::<A>(Flags::NZV.bits(), u16::and)?;
+        Ok(())
+    }
+
+    fn eord<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modd_2::<A>(Flags::NZV.bits(), u16::eor)?;
+        Ok(())
+    }
+
+    fn ord<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modd_2::<A>(Flags::NZV.bits(), u16::or)?;
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // 8 bit memory ops on E and F
+
+    fn sube<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8_2::<A>(RegEnum::E, Flags::NZVC.bits(), u8::sub)?;
+        Ok(())
+    }
+
+    fn cmpe<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.opr8_2::<A>(RegEnum::E, Flags::NZVC.bits(), u8::sub)?;
+        Ok(())
+    }
+
+    fn adde<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8_2::<A>(RegEnum::E, Flags::NZVCH.bits(), u8::add)?;
+        Ok(())
+    }
+
+    fn lde<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let i0 = self.load_reg_byte::<A>()?;
+        self.regs.e = i0;
+        Ok(())
+    }
+
+    fn ste<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let r = self.regs.e;
+        self.st8::<A>(r)
+    }
+
+    fn subf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8_2::<A>(RegEnum::F, Flags::NZVC.bits(), u8::sub)?;
+        Ok(())
+    }
+
+    fn cmpf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.opr8_2::<A>(RegEnum::F, Flags::NZVC.bits(), u8::sub)?;
+        Ok(())
+    }
+
+    fn addf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.modr8_2::<A>(RegEnum::F, Flags::NZVCH.bits(), u8::add)?;
+        Ok(())
+    }
+
+    fn ldf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let i0 = self.load_reg_byte::<A>()?;
+        self.regs.f = i0;
+        Ok(())
+    }
+
+    fn stf<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let r = self.regs.f;
+        self.st8::<A>(r)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Bit manipulation
+
+    fn band<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r & m)
+    }
+
+    fn biand<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r & !m)
+    }
+
+    fn bor<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r | m)
+    }
+
+    fn bior<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r | !m)
+    }
+
+    fn beor<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r ^ m)
+    }
+
+    fn bieor<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|r, m| r ^ !m)
+    }
+
+    fn ldbt<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.bit_op::<A>(|_, m| m)
+    }
+
+    // Same post byte as the other bit ops but memory is the destination
+    fn stbt<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        let post = self.ins.fetch_byte(self.mem);
+        let ea = self.ea::<A>()?;
+
+        let r = match post >> 6 {
+            0 => RegEnum::CC,
+            1 => RegEnum::A,
+            2 => RegEnum::B,
+            _ => return self.illegal_trap(),
+        };
+
+        let mem_mask = 1 << ((post >> 3) & 7);
+        let reg_mask = 1 << (post & 7);
+
+        let rv = self.regs.get(&r) as u8;
+        let m = self.mem.load_byte(ea);
+
+        let m = if rv & reg_mask != 0 { m | mem_mask } else { m & !mem_mask };
+
+        self.mem.store_byte(ea, m);
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Block transfers
+
+    fn tfm_pp<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.tfm(1, 1)
+    }
+
+    fn tfm_mm<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.tfm(0xffff, 0xffff)
+    }
+
+    fn tfm_pn<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.tfm(1, 0)
+    }
+
+    fn tfm_np<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.tfm(0, 1)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // MD
+
+    fn bitmd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        // Only the error bits can be tested, testing clears them
+        self.ins.add_cycles(3);
+        let mask = self.fetch_byte::<A>()? & (MD_ILLEGAL | MD_DIV_ZERO);
+        let md = self.regs.md;
+        self.regs.flags.set(Flags::Z, md & mask == 0);
+        self.regs.md = md & !mask;
+        Ok(())
+    }
+
+    fn ldmd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        // Only the mode bits can be written
+        self.ins.add_cycles(2);
+        let v = self.fetch_byte::<A>()? & 3;
+        self.regs.md = (self.regs.md & !3) | v;
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Maths
+
+    // Signed D / byte, quotient in B and remainder in A
+    fn divd<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.ins.add_cycles(22);
+
+        let divisor = i32::from(self.fetch_byte::<A>()? as i8);
+
+        if divisor == 0 {
+            return self.div_zero_trap();
+        }
+
+        let dividend = i32::from(self.regs.get_d() as i16);
+        let quotient = dividend / divisor;
+        let remainder = dividend % divisor;
+
+        self.regs.flags.set(Flags::V, false);
+
+        // Way out of range and the registers are left alone
+        if quotient > 255 || quotient < -256 {
+            self.regs.flags.set(Flags::V, true);
+            self.regs.flags.set(Flags::N | Flags::Z | Flags::C, false);
+            return Ok(())
+        }
+
+        self.regs.a = remainder as u8;
+        self.regs.b = quotient as u8;
+
+        self.regs.flags.set(Flags::V, quotient > 127 || quotient < -128);
+        self.regs.flags.set(Flags::N, quotient as u8 & 0x80 != 0);
+        self.regs.flags.set(Flags::Z, quotient as u8 == 0);
+        self.regs.flags.set(Flags::C, quotient & 1 != 0);
+        Ok(())
+    }
+
+    // Signed Q / word, quotient in W and remainder in D
+    fn divq<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.ins.add_cycles(30);
+
+        let divisor = i64::from(self.fetch_word::<A>()? as i16);
+
+        if divisor == 0 {
+            return self.div_zero_trap();
+        }
+
+        let dividend = i64::from(self.regs.get_q() as i32);
+        let quotient = dividend / divisor;
+        let remainder = dividend % divisor;
+
+        self.regs.flags.set(Flags::V, false);
+
+        if quotient > 65535 || quotient < -65536 {
+            self.regs.flags.set(Flags::V, true);
+            self.regs.flags.set(Flags::N | Flags::Z | Flags::C, false);
+            return Ok(())
+        }
+
+        self.regs.set_d(remainder as u16);
+        self.regs.set_w(quotient as u16);
+
+        self.regs.flags.set(Flags::V, quotient > 32767 || quotient < -32768);
+        self.regs.flags.set(Flags::N, quotient as u16 & 0x8000 != 0);
+        self.regs.flags.set(Flags::Z, quotient as u16 == 0);
+        self.regs.flags.set(Flags::C, quotient & 1 != 0);
+        Ok(())
+    }
+
+    // Signed D * word into Q
+    fn muld<A : AddressLines>(&mut self) -> Result<(), CpuErr> {
+        self.ins.add_cycles(24);
+
+        let i0 = i32::from(self.regs.get_d() as i16);
+        let i1 = i32::from(self.fetch_word::<A>()? as i16);
+        let q = i0.wrapping_mul(i1) as u32;
+
+        self.regs.set_q(q);
+        self.nz32(q);
+        Ok(())
+    }
+}
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
+
+    fn new(mem : &'a mut M, regs : &'a mut Regs, ints : &'a mut Interrupts) -> Context<'a, M> {
+        let ins = InstructionDecoder::new(regs.pc);
+        Context { regs, mem, ints, ins, extra_cycles : 0 }
+    }
+
+    pub fn fetch_instruction(&mut self) -> u16 {
+        self.ins.fetch_instruction(self.mem)
+    }
+}
+
+pub fn reset<M: MemoryIO>(regs : &mut Regs, mem : &mut M) {
+    // The cpu config is fixed and the 6309's V survives a reset
+    *regs = Regs {
+        pc : mem.load_word(0xfffe),
+        flags : Flags::I | Flags::F,
+        v : regs.v,
+        cpu : regs.cpu,
+        undoc : regs.undoc,
+        bus : regs.bus,
+        .. Default::default()
+    };
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Dispatch
+//
+// One handler per opcode for each cpu setup, built at compile time from the
+// op tables in isa.rs, see opinfo.rs
+
+type OpFn<'a, M> = fn(&mut Context<'a, M>) -> Result<(), CpuErr>;
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
+    const OPS_6809 : [OpFn<'a, M>; OP_TABLE_SIZE] = {
+        macro_rules! handle_op {
+            ($addr:ident, $action:ident) => ({ Self::$action::<$addr> as OpFn<'a, M> }) }
+        build_ops!(OpFn<'a, M>, Self::undocumented, |op| op_table!(op, { Self::undocumented }))
+    };
+
+    const OPS_6809_UNDOC : [OpFn<'a, M>; OP_TABLE_SIZE] = {
+        macro_rules! handle_op {
+            ($addr:ident, $action:ident) => ({ Self::$action::<$addr> as OpFn<'a, M> }) }
+        build_ops!(OpFn<'a, M>, Self::undocumented, |op| op_table!(op, { op_table_undoc!(op, { Self::undocumented }) }))
+    };
+
+    const OPS_6309 : [OpFn<'a, M>; OP_TABLE_SIZE] = {
+        macro_rules! handle_op {
+            ($addr:ident, $action:ident) => ({ Self::$action::<$addr> as OpFn<'a, M> }) }
+        build_ops!(OpFn<'a, M>, Self::illegal_trap, |op| op_table_6309!(op, { op_table!(op, { Self::illegal_trap }) }))
+    };
+
+    fn execute(&mut self, op : u16) -> Result<(), CpuErr> {
+        // Taken by reference so the table isn't copied out of the const
+        let table : &[OpFn<'a, M>; OP_TABLE_SIZE] = if self.regs.is_6309() {
+            &Self::OPS_6309
+        } else if self.regs.emulate_undocumented() {
+            &Self::OPS_6809_UNDOC
+        } else {
+            &Self::OPS_6809
+        };
+
+        table[op_to_index(op)](self)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<'a, M : 'a + CoreBus> Context<'a, M> {
+
+    // Runs one instruction, the decoder is left describing it
+    fn step_ins(&mut self) -> Result<(), CpuErr> {
+        self.ins.reset(self.regs.pc);
+        self.extra_cycles = 0;
+
+        // Burn cycles until an interrupt releases us so devices keep ticking
+        if self.ints.is_waiting() && self.handle_wait_state() {
+            self.ins = self.waiting_ins();
+            return Ok(());
+        }
+
+        // Interrupts are sampled before the opcode fetch
+        if let Some(int) = self.ints.pending(self.regs.flags) {
+            self.take_interrupt(int)?;
+        }
+
+        let op = self.fetch_instruction();
+
+        // Only copied if someone is going to look at it
+        let before = if M::Obs::ACTIVE {
+            self.mem.observer().before_execute(&self.ins, self.regs);
+            Some(self.regs.clone())
+        } else {
+            None
+        };
+
+        self.execute(op)?;
+
+        self.ins.cycles += self.extra_cycles;
+
+        // Some ops count part of their time in extra_cycles so adjust the lot
+        if self.regs.is_native() {
+            let cycles = self.ins.cycles as i32 + native_cycle_adjust(op);
+            self.ins.cycles = cycles.max(1) as u32;
+        }
+
+        self.regs.pc = self.ins.next_addr;
+
+        if let Some(before) = before {
+            let cycles = self.ins.cycles;
+            self.mem.observer().after_execute(&self.ins, &before, self.regs, cycles);
+        }
+
+        Ok(())
+    }
+}
+
+// How far a call to run got
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    pub instructions : usize,
+    pub cycles : u64,
+}
+
+pub fn step<M: MemoryIO, C : Clock>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>, ints : &mut Interrupts) -> Result<InstructionDecoder, CpuErr> {
+    step_with_observer(regs, mem, ref_clock, ints, &mut NullObserver)
+}
+
+// Same as step with obs told about everything the cpu does
+pub fn step_with_observer<M: MemoryIO, C : Clock, O : Observer>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>, ints : &mut Interrupts, obs : &mut O) -> Result<InstructionDecoder, CpuErr> {
+
+    // The shared clock is only touched at either end of the instruction,
+    // cycles are counted locally while it runs
+    let ins = if regs.bus == BusMode::CycleAccurate {
+        // Anything we don't model cycle by cycle gets padded out at the end
+        let start = ref_clock.borrow().get_cycles();
+        let mut bus = CycleBus::new(mem, start);
+        let mut ins = {
+            let mut observed = Observed::new(&mut bus, obs);
+            step_on_bus(regs, &mut observed, ints)?
+        };
+        bus.pad_to(ins.cycles);
+        ins.cycles = bus.get_cycles();
+        ins
+    } else {
+        let mut observed = Observed::new(mem, obs);
+        step_on_bus(regs, &mut observed, ints)?
+    };
+
+    ref_clock.borrow_mut().add_cycles(ins.cycles as usize);
+    Ok(ins)
+}
+
+fn step_on_bus<M: CoreBus>(regs : &mut Regs, mem : &mut M, ints : &mut Interrupts) -> Result<InstructionDecoder, CpuErr> {
+    let mut ctx = Context::new(mem, regs, ints);
+    ctx.step_ins()?;
+    Ok(ctx.ins.clone())
+}
+
+// Runs up to instructions instructions without coming back out, for when
+// nothing needs to happen between them. Stops at the first error, the
+// stats cover everything before it.
+pub fn run<M: MemoryIO, C : Clock>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>, ints : &mut Interrupts, instructions : usize) -> (RunStats, Result<(), CpuErr>) {
+    run_with_observer(regs, mem, ref_clock, ints, instructions, &mut NullObserver)
+}
+
+pub fn run_with_observer<M: MemoryIO, C : Clock, O : Observer>(regs : &mut Regs, mem : &mut M, ref_clock : &Rc<RefCell<C>>, ints : &mut Interrupts, instructions : usize, obs : &mut O) -> (RunStats, Result<(), CpuErr>) {
+    let mut stats = RunStats::default();
+
+    // Needs the bus set up per instruction
+    if regs.bus == BusMode::CycleAccurate {
+        while stats.instructions < instructions {
+            match step_with_observer(regs, mem, ref_clock, ints, obs) {
+                Ok(ins) => {
+                    stats.instructions += 1;
+                    stats.cycles += u64::from(ins.cycles);
+                },
+                Err(e) => return (stats, Err(e)),
+            }
+        }
+        return (stats, Ok(()));
+    }
+
+    let res = {
+        let mut observed = Observed::new(mem, obs);
+        let mut ctx = Context::new(&mut observed, regs, ints);
+
+        let mut res = Ok(());
+
+        // The clock moves on every instruction so devices reading it
+        // mid batch see the right time
+        while stats.instructions < instructions {
+            if let Err(e) = ctx.step_ins() {
+                res = Err(e);
+                break;
+            }
+            stats.instructions += 1;
+            stats.cycles += u64::from(ctx.ins.cycles);
+            ref_clock.borrow_mut().add_cycles(ctx.ins.cycles as usize);
+        }
+
+        res
+    };
+
+    (stats, res)
+}
+//
+// }}}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::testing::Machine;
+    use crate::cpu::{CpuKind, MD_NATIVE, StandardClock};
+
+    // A 6309 in emulation mode with the trap vector pointing at $4000
+    fn hd6309(code : &[u8]) -> Machine {
+        let mut m = Machine::new(&[(0x1000, code), (TRAP_VECTOR, &[0x40, 0x00])]);
+        m.regs.cpu = CpuKind::Hd6309;
+        m
+    }
+
+    fn flags(m : &Machine) -> Flags {
+        m.regs.flags & (Flags::N | Flags::Z | Flags::V | Flags::C)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Division
+
+    #[test]
+    fn divd() {
+        // divd #2 with D = -7
+        let mut m = hd6309(&[0x11, 0x8d, 0x02]);
+        m.regs.set_d(0xfff9);
+        m.step(1);
+
+        assert_eq!((m.regs.a, m.regs.b), (0xff, 0xfd));
+        assert_eq!(flags(&m), Flags::N | Flags::C);
+        assert_eq!(m.cycles(), 25);
+    }
+
+    #[test]
+    fn divd_overflow() {
+        // divd #2 with D = 256, a 9 bit quotient is still written
+        let mut m = hd6309(&[0x11, 0x8d, 0x02]);
+        m.regs.set_d(0x0100);
+        m.step(1);
+
+        assert_eq!((m.regs.a, m.regs.b), (0x00, 0x80));
+        assert_eq!(flags(&m), Flags::N | Flags::V);
+
+        // divd #1 with D = $7fff, anything bigger leaves D alone
+        let mut m = hd6309(&[0x11, 0x8d, 0x01]);
+        m.regs.set_d(0x7fff);
+        m.regs.flags.insert(Flags::N | Flags::Z | Flags::C);
+        m.step(1);
+
+        assert_eq!(m.regs.get_d(), 0x7fff);
+        assert_eq!(flags(&m), Flags::V);
+    }
+
+    #[test]
+    fn divq() {
+        // divq #7 with Q = -100
+        let mut m = hd6309(&[0x11, 0x8e, 0x00, 0x07]);
+        m.regs.set_q(-100i32 as u32);
+        m.step(1);
+
+        assert_eq!(m.regs.get_w(), -14i16 as u16);
+        assert_eq!(m.regs.get_d(), -2i16 as u16);
+        assert_eq!(flags(&m), Flags::N);
+        assert_eq!(m.cycles(), 34);
+    }
+
+    #[test]
+    fn divq_overflow() {
+        // divq #3 with Q = 100000, a 17 bit quotient is still written
+        let mut m = hd6309(&[0x11, 0x8e, 0x00, 0x03]);
+        m.regs.set_q(100_000);
+        m.step(1);
+
+        assert_eq!(m.regs.get_w(), 33333);
+        assert_eq!(m.regs.get_d(), 1);
+        assert_eq!(flags(&m), Flags::N | Flags::V | Flags::C);
+
+        // divq #1 with Q = $7fffffff leaves Q alone
+        let mut m = hd6309(&[0x11, 0x8e, 0x00, 0x01]);
+        m.regs.set_q(0x7fff_ffff);
+        m.step(1);
+
+        assert_eq!(m.regs.get_q(), 0x7fff_ffff);
+        assert_eq!(flags(&m), Flags::V);
+    }
+
+    #[test]
+    fn division_by_zero_traps() {
+        // divd #0, then bitmd #$80 in the handler
+        let mut m = hd6309(&[0x11, 0x8d, 0x00]);
+        m.mem.upload(0x4000, &[0x11, 0x3c, 0x80]);
+        m.regs.set_d(0x1234);
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x4000);
+        assert_eq!(m.regs.get_d(), 0x1234);
+        assert_eq!(m.regs.md & MD_DIV_ZERO, MD_DIV_ZERO);
+        assert!(m.regs.flags.contains(Flags::E | Flags::I | Flags::F));
+
+        // Everything's stacked with the return address after the divd
+        assert_eq!(m.regs.s, 0x8000 - 12);
+        assert_eq!(m.mem.peek(0x8000 - 12), m.regs.flags.bits() & !(Flags::I | Flags::F).bits());
+        assert_eq!(m.mem.peek_word(0x8000 - 2), 0x1003);
+
+        // Testing the bit clears it
+        m.step(1);
+        assert!(!m.regs.flags.contains(Flags::Z));
+        assert_eq!(m.regs.md & MD_DIV_ZERO, 0);
+
+        // divq #0 traps the same way
+        let mut m = hd6309(&[0x11, 0x8e, 0x00, 0x00]);
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x4000);
+        assert_eq!(m.regs.md & MD_DIV_ZERO, MD_DIV_ZERO);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Block transfers
+
+    // Runs tfm op x,y moving 3 bytes from "abc" at $2000 to $3000
+    fn tfm(op : u8, x : u16, y : u16) -> Machine {
+        let mut m = hd6309(&[0x11, op, 0x12]);
+        m.mem.upload(0x2000, b"abc");
+        m.mem.upload(0x3000, b"...");
+        m.regs.x = x;
+        m.regs.y = y;
+        m.regs.set_w(3);
+        m.step(1);
+
+        assert_eq!(m.regs.get_w(), 0);
+        assert_eq!(m.cycles(), 6 + 3 * 3);
+        m
+    }
+
+    fn moved(m : &Machine) -> Vec<u8> {
+        (0x3000..0x3003).map(|a| m.mem.peek(a)).collect()
+    }
+
+    #[test]
+    fn tfm_both_up() {
+        let m = tfm(0x38, 0x2000, 0x3000);
+        assert_eq!((m.regs.x, m.regs.y), (0x2003, 0x3003));
+        assert_eq!(moved(&m), b"abc");
+    }
+
+    #[test]
+    fn tfm_both_down() {
+        let m = tfm(0x39, 0x2002, 0x3002);
+        assert_eq!((m.regs.x, m.regs.y), (0x1fff, 0x2fff));
+        assert_eq!(moved(&m), b"abc");
+    }
+
+    #[test]
+    fn tfm_source_up() {
+        let m = tfm(0x3a, 0x2000, 0x3000);
+        assert_eq!((m.regs.x, m.regs.y), (0x2003, 0x3000));
+        assert_eq!(moved(&m), b"c..");
+    }
+
+    #[test]
+    fn tfm_dest_up() {
+        let m = tfm(0x3b, 0x2000, 0x3000);
+        assert_eq!((m.regs.x, m.regs.y), (0x2000, 0x3003));
+        assert_eq!(moved(&m), b"aaa");
+    }
+
+    #[test]
+    fn tfm_with_nothing_to_move() {
+        let mut m = hd6309(&[0x11, 0x38, 0x12]);
+        m.step(1);
+        assert_eq!(m.cycles(), 6);
+    }
+
+    #[test]
+    fn tfm_from_a_byte_register_traps() {
+        // tfm a+,y+
+        let mut m = hd6309(&[0x11, 0x38, 0x82]);
+        m.regs.set_w(3);
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x4000);
+        assert_eq!(m.regs.md & MD_ILLEGAL, MD_ILLEGAL);
+        assert_eq!(m.regs.get_w(), 3);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Native mode
+
+    #[test]
+    fn native_mode_cycles() {
+        // op, emulation cycles, native cycles
+        let ops : &[(&[u8], u64, u64)] = &[
+            (&[0x12], 2, 1),                        // nop
+            (&[0x19], 2, 1),                        // daa
+            (&[0x1d], 2, 1),                        // sex
+            (&[0x1f, 0x89], 6, 4),                  // tfr a,b
+            (&[0x1e, 0x89], 8, 5),                  // exg a,b
+            (&[0xb6, 0x20, 0x00], 5, 4),            // lda $2000
+            (&[0xfc, 0x20, 0x00], 6, 5),            // ldd $2000
+            (&[0x11, 0x8d, 0x01], 25, 25),          // divd #1
+            (&[0x11, 0x9d, 0x20], 27, 26),          // divd <$20
+            (&[0x11, 0x8e, 0x00, 0x01], 34, 34),    // divq #1
+            (&[0x11, 0x9e, 0x20], 36, 35),          // divq <$20
+            (&[0x11, 0x8f, 0x00, 0x01], 28, 28),    // muld #1
+            (&[0x11, 0x9f, 0x20], 30, 29),          // muld <$20
+        ];
+
+        for &(code, emulation, native) in ops {
+            let mut m = hd6309(code);
+            m.step(1);
+            assert_eq!(m.cycles(), emulation, "{:02x?} in emulation mode", code);
+
+            let mut m = hd6309(code);
+            m.regs.md = MD_NATIVE;
+            m.step(1);
+            assert_eq!(m.cycles(), native, "{:02x?} in native mode", code);
+        }
+    }
+
+    #[test]
+    fn ldmd_switches_to_native_mode() {
+        // ldmd #1 ; nop
+        let mut m = hd6309(&[0x11, 0x3d, 0x01, 0x12]);
+        m.step(1);
+        assert!(m.regs.is_native());
+        assert_eq!(m.cycles(), 5);
+
+        m.step(1);
+        assert_eq!(m.cycles(), 6);
+    }
+
+    #[test]
+    fn native_mode_stacks_w() {
+        // An irq with the handler's nop at $4000
+        let mut m = hd6309(&[]);
+        m.mem.upload(0xfff8, &[0x40, 0x00]);
+        m.regs.md = MD_NATIVE;
+        m.regs.set_w(0xbeef);
+        m.ints.set_irq(true);
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x4001);
+        assert_eq!(m.regs.s, 0x8000 - 14);
+        assert_eq!(m.mem.peek_word(0x8000 - 14 + 3), 0xbeef);
+        assert_eq!(m.cycles(), 19 + 2 + 1);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Interrupts
+
+    // A 6809 with handlers of NOPs at $4000 for IRQ, $5000 for FIRQ and
+    // $6000 for NMI
+    fn mc6809(code : &[u8]) -> Machine {
+        Machine::new(&[
+            (0x1000, code),
+            (0xfff6, &[0x50, 0x00, 0x40, 0x00]),
+            (0xfffc, &[0x60, 0x00]),
+        ])
+    }
+
+    #[test]
+    fn irq_stacks_everything() {
+        let mut m = mc6809(&[]);
+        m.regs.set_d(0x1234);
+        m.regs.flags.insert(Flags::F);
+        m.ints.set_irq(true);
+        m.step(1);
+
+        // The handler's first instruction runs in the same step
+        assert_eq!(m.regs.pc, 0x4001);
+        assert_eq!(m.cycles(), 19 + 2);
+        assert!(m.regs.flags.contains(Flags::E | Flags::I | Flags::F));
+
+        assert_eq!(m.regs.s, 0x8000 - 12);
+        assert_eq!(m.mem.peek(0x8000 - 12), (Flags::E | Flags::F).bits());
+        assert_eq!(m.mem.peek_word(0x8000 - 11), 0x1234);
+        assert_eq!(m.mem.peek_word(0x8000 - 2), 0x1000);
+    }
+
+    #[test]
+    fn firq_stacks_pc_and_cc() {
+        let mut m = mc6809(&[]);
+        m.ints.set_firq(true);
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x5001);
+        assert_eq!(m.cycles(), 10 + 2);
+        assert!(m.regs.flags.contains(Flags::I | Flags::F));
+        assert!(!m.regs.flags.contains(Flags::E));
+
+        assert_eq!(m.regs.s, 0x8000 - 3);
+        assert_eq!(m.mem.peek(0x8000 - 3), 0);
+        assert_eq!(m.mem.peek_word(0x8000 - 2), 0x1000);
+    }
+
+    #[test]
+    fn rti_pulls_what_was_stacked() {
+        // Handlers are clra ; clrb ; rti
+        for &(vector, firq) in &[(0x4000, false), (0x5000, true)] {
+            let mut m = mc6809(&[]);
+            m.mem.upload(vector, &[0x4f, 0x5f, 0x3b]);
+            m.regs.set_d(0x1234);
+            m.ints.set_firq(firq);
+            m.ints.set_irq(!firq);
+            m.step(1);
+            m.ints.set_firq(false);
+            m.ints.set_irq(false);
+            m.step(2);
+
+            assert_eq!(m.regs.pc, 0x1000);
+            assert_eq!(m.regs.s, 0x8000);
+
+            // E comes back with the rest of the stacked cc
+            if firq {
+                assert_eq!(m.regs.flags, Flags::new(0));
+                assert_eq!(m.regs.get_d(), 0);
+            } else {
+                assert_eq!(m.regs.flags, Flags::E);
+                assert_eq!(m.regs.get_d(), 0x1234);
+            }
+        }
+    }
+
+    #[test]
+    fn masked_interrupts_wait() {
+        let mut m = mc6809(&[]);
+        m.regs.flags.insert(Flags::I | Flags::F);
+        m.ints.set_irq(true);
+        m.ints.set_firq(true);
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x1001);
+        assert_eq!(m.regs.s, 0x8000);
+
+        // FIRQ first once they're let in, IRQ's masked by it
+        m.regs.flags.remove(Flags::I | Flags::F);
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x5001);
+
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x5002);
+    }
+
+    #[test]
+    fn irq_is_level_triggered() {
+        // Handler does andcc #$ef so the still asserted line comes straight back
+        let mut m = mc6809(&[]);
+        m.mem.upload(0x4000, &[0x1c, 0xef]);
+        m.ints.set_irq(true);
+        m.step(2);
+
+        assert_eq!(m.regs.pc, 0x4002);
+        assert_eq!(m.regs.s, 0x8000 - 24);
+    }
+
+    #[test]
+    fn nmi_needs_lds_to_arm() {
+        // tfr x,s ; exg x,s ; leas ,x ; pulu s ; lds #$8000
+        let mut m = mc6809(&[0x1f, 0x14, 0x1e, 0x14, 0x32, 0x84, 0x37, 0x40, 0x10, 0xce, 0x80, 0x00]);
+        m.regs.x = 0x8000;
+        m.regs.u = 0x7000;
+        m.mem.upload(0x7000, &[0x80, 0x00]);
+        m.ints.set_nmi(true);
+
+        m.step(4);
+        assert_eq!(m.regs.pc, 0x1008);
+        assert!(!m.ints.is_nmi_armed());
+
+        m.step(1);
+        assert!(m.ints.is_nmi_armed());
+
+        // Taken even with everything masked
+        m.regs.flags.insert(Flags::I | Flags::F);
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x6001);
+        assert_eq!(m.regs.s, 0x8000 - 12);
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut m = mc6809(&[]);
+        m.ints.arm_nmi();
+        m.ints.set_nmi(true);
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x6001);
+
+        // Held high it's not taken again
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x6002);
+
+        m.ints.set_nmi(false);
+        m.ints.set_nmi(true);
+        m.step(1);
+        assert_eq!(m.regs.pc, 0x6001);
+        assert_eq!(m.regs.s, 0x8000 - 24);
+    }
+
+    #[test]
+    fn sync_waits_for_any_line() {
+        // sync ; nop
+        let mut m = mc6809(&[0x13, 0x12]);
+        m.step(1);
+        assert_eq!(m.ints.get_run_state(), RunState::Syncing);
+
+        // Burns a cycle a step while it waits
+        let cycles = m.cycles();
+        m.step(2);
+        assert_eq!(m.regs.pc, 0x1001);
+        assert_eq!(m.cycles(), cycles + 2);
+
+        // A masked line wakes it without vectoring
+        m.regs.flags.insert(Flags::I);
+        m.ints.set_irq(true);
+        m.step(1);
+        assert_eq!(m.ints.get_run_state(), RunState::Running);
+        assert_eq!(m.regs.pc, 0x1002);
+        assert_eq!(m.regs.s, 0x8000);
+    }
+
+    #[test]
+    fn sync_takes_an_unmasked_interrupt() {
+        let mut m = mc6809(&[0x13]);
+        m.step(1);
+        m.ints.set_firq(true);
+        m.step(1);
+
+        assert_eq!(m.ints.get_run_state(), RunState::Running);
+        assert_eq!(m.regs.pc, 0x5001);
+        assert_eq!(m.mem.peek_word(0x8000 - 2), 0x1001);
+    }
+
+    #[test]
+    fn sync_pulse_wakes_sync() {
+        let mut m = mc6809(&[0x13]);
+        m.step(1);
+        m.ints.sync_pulse();
+        m.step(1);
+
+        assert_eq!(m.regs.pc, 0x1002);
+        assert_eq!(m.regs.s, 0x8000);
+    }
+
+    #[test]
+    fn cwai_stacks_then_waits() {
+        // orcc #$50 ; cwai #$ef
+        let mut m = mc6809(&[0x1a, 0x50, 0x3c, 0xef]);
+        m.step(2);
+
+        assert_eq!(m.ints.get_run_state(), RunState::Waiting);
+        assert_eq!(m.regs.s, 0x8000 - 12);
+        assert_eq!(m.mem.peek(0x8000 - 12), (Flags::E | Flags::F).bits());
+        assert_eq!(m.mem.peek_word(0x8000 - 2), 0x1004);
+
+        // Masked FIRQ doesn't wake it
+        m.ints.set_firq(true);
+        m.step(1);
+        assert_eq!(m.ints.get_run_state(), RunState::Waiting);
+        assert_eq!(m.regs.pc, 0x1004);
+
+        // IRQ vectors without stacking again
+        let cycles = m.cycles();
+        m.ints.set_irq(true);
+        m.step(1);
+        assert_eq!(m.ints.get_run_state(), RunState::Running);
+        assert_eq!(m.regs.pc, 0x4001);
+        assert_eq!(m.regs.s, 0x8000 - 12);
+        assert_eq!(m.cycles(), cycles + 3 + 2);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Batches
+
+    // NOPs that note the clock on every read
+    struct ClockedRam {
+        ram : Vec<u8>,
+        clock : Rc<RefCell<StandardClock>>,
+        reads : Vec<u64>,
+    }
+
+    impl MemoryIO for ClockedRam {
+        fn peek(&self, addr : u16) -> u8 {
+            self.ram[usize::from(addr)]
+        }
+
+        fn poke(&mut self, addr : u16, val : u8) {
+            self.ram[usize::from(addr)] = val
+        }
+
+        fn get_range(&self) -> (u16, u16) {
+            (0, 0xffff)
+        }
+
+        fn update_sha1(&self, _digest : &mut crate::mem::Sha1) {
+        }
+
+        fn load_byte(&mut self, addr : u16) -> u8 {
+            self.reads.push(self.clock.borrow().get_cycles());
+            self.peek(addr)
+        }
+
+        fn store_byte(&mut self, addr : u16, val : u8) {
+            self.poke(addr, val)
+        }
+    }
+
+    #[test]
+    fn run_moves_the_clock_every_instruction() {
+        let clock = Rc::new(RefCell::new(StandardClock::new(1_000_000)));
+        let mut mem = ClockedRam { ram : vec![0x12; 0x1_0000], clock : clock.clone(), reads : vec![] };
+        let mut regs = Regs::new();
+        let mut ints = Interrupts::new();
+
+        let (stats, res) = run(&mut regs, &mut mem, &clock, &mut ints, 3);
+
+        assert!(res.is_ok());
+        assert_eq!(stats.cycles, 6);
+        assert_eq!(mem.reads, [0, 2, 4]);
+        assert_eq!(clock.borrow().get_cycles(), 6);
+    }
+}
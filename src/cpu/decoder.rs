@@ -19,6 +19,16 @@ impl InstructionDecoder {
             .. Default::default()
         }
     }
+
+    // Ready to decode the next instruction at addr, cheaper than new
+    pub fn reset(&mut self, addr : u16) {
+        self.op_code = 0;
+        self.cycles = 2;
+        self.addr = addr;
+        self.bytes = 0;
+        self.next_addr = addr;
+    }
+
     fn bump_fetch(&mut self, v : usize) {
         self.next_addr = self.next_addr.wrapping_add(v as u16);
         self.bytes +=  1;
@@ -0,0 +1,81 @@
+// Hitachi HD6309 support
+//
+// The 6309 is a superset of the 6809 with extra registers (E, F, W, Q, V
+// and MD), extra instructions and a native mode that runs most
+// instructions in fewer cycles. See utils/dragon.txt for the details
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CpuKind {
+    #[default]
+    Mc6809,
+    Hd6309,
+}
+
+impl CpuKind {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "6809" => Some(CpuKind::Mc6809),
+            "6309" => Some(CpuKind::Hd6309),
+            _ => None,
+        }
+    }
+}
+
+// MD register bits, 0 and 1 are write only, 6 and 7 read only
+pub const MD_NATIVE      : u8 = 1;
+pub const MD_FIRQ_IS_IRQ : u8 = 1 << 1;
+pub const MD_ILLEGAL     : u8 = 1 << 6;
+pub const MD_DIV_ZERO    : u8 = 1 << 7;
+
+// Illegal instruction and divide by zero share a vector
+pub const TRAP_VECTOR : u16 = 0xfff0;
+
+// Difference in cycles between native and emulation mode, from the opcode
+// table in utils/dragon.txt. Native mode stacks W as well so SWIs get slower
+pub fn native_cycle_adjust(op : u16) -> i32 {
+    match op {
+        0x39 => -4,
+
+        0x1e => -3,
+
+        0x0d | 0x17 | 0x1f | 0x3a | 0x3c | 0x7d | 0x93 | 0x9c |
+        0xb3 | 0xbc | 0xd3 | 0xf3 | 0x1090 | 0x1091 | 0x1092 | 0x1093 |
+        0x1094 | 0x1095 | 0x1098 | 0x1099 | 0x109a | 0x109b | 0x109c | 0x10b0 |
+        0x10b1 | 0x10b2 | 0x10b3 | 0x10b4 | 0x10b5 | 0x10b8 | 0x10b9 | 0x10ba |
+        0x10bb | 0x10bc | 0x1193 | 0x119c | 0x11b3 | 0x11bc => -2,
+
+        0x3f | 0x103f | 0x113f => 2,
+
+        0x00 | 0x03 | 0x04 | 0x06 | 0x07 | 0x08 | 0x09 | 0x0a |
+        0x0c | 0x0e | 0x0f | 0x12 | 0x13 | 0x16 | 0x19 | 0x1a |
+        0x1d | 0x34 | 0x35 | 0x36 | 0x37 | 0x3d | 0x40 | 0x43 |
+        0x44 | 0x46 | 0x47 | 0x48 | 0x49 | 0x4a | 0x4c | 0x4d |
+        0x4f | 0x50 | 0x53 | 0x54 | 0x56 | 0x57 | 0x58 | 0x59 |
+        0x5a | 0x5c | 0x5d | 0x5f | 0x6d | 0x70 | 0x73 | 0x74 |
+        0x76 | 0x77 | 0x78 | 0x79 | 0x7a | 0x7c | 0x7e | 0x7f |
+        0x83 | 0x8c | 0x8d | 0x90 | 0x91 | 0x92 | 0x94 | 0x95 |
+        0x96 | 0x97 | 0x98 | 0x99 | 0x9a | 0x9b | 0x9d | 0x9e |
+        0x9f | 0xa3 | 0xac | 0xad | 0xb0 | 0xb1 | 0xb2 | 0xb4 |
+        0xb5 | 0xb6 | 0xb7 | 0xb8 | 0xb9 | 0xba | 0xbb | 0xbd |
+        0xbe | 0xbf | 0xc3 | 0xd0 | 0xd1 | 0xd2 | 0xd4 | 0xd5 |
+        0xd6 | 0xd7 | 0xd8 | 0xd9 | 0xda | 0xdb | 0xdc | 0xdd |
+        0xde | 0xdf | 0xe3 | 0xf0 | 0xf1 | 0xf2 | 0xf4 | 0xf5 |
+        0xf6 | 0xf7 | 0xf8 | 0xf9 | 0xfa | 0xfb | 0xfc | 0xfd |
+        0xfe | 0xff | 0x1040 | 0x1043 | 0x1044 | 0x1046 | 0x1047 | 0x1048 |
+        0x1049 | 0x104a | 0x104c | 0x104d | 0x104f | 0x1053 | 0x1054 | 0x1056 |
+        0x1059 | 0x105a | 0x105c | 0x105d | 0x105f | 0x1080 | 0x1081 | 0x1082 |
+        0x1083 | 0x1084 | 0x1085 | 0x1086 | 0x1088 | 0x1089 | 0x108a | 0x108b |
+        0x108c | 0x108e | 0x1096 | 0x1097 | 0x109e | 0x109f | 0x10a0 | 0x10a1 |
+        0x10a2 | 0x10a3 | 0x10a4 | 0x10a5 | 0x10a8 | 0x10a9 | 0x10aa | 0x10ab |
+        0x10ac | 0x10b6 | 0x10b7 | 0x10be | 0x10bf | 0x10dc | 0x10dd | 0x10de |
+        0x10df | 0x10fe | 0x10ff | 0x1130 | 0x1131 | 0x1132 | 0x1133 | 0x1134 |
+        0x1135 | 0x1136 | 0x1137 | 0x1143 | 0x114a | 0x114c | 0x114d | 0x114f |
+        0x1153 | 0x115a | 0x115c | 0x115d | 0x115f | 0x1183 | 0x118c | 0x1190 |
+        0x1191 | 0x1196 | 0x1197 | 0x119b | 0x119d | 0x119e | 0x119f | 0x11a3 |
+        0x11b0 | 0x11b1 | 0x11b6 | 0x11b7 | 0x11bb | 0x11bd | 0x11be | 0x11bf |
+        0x11d0 | 0x11d1 | 0x11d6 | 0x11d7 | 0x11db | 0x11f0 | 0x11f1 | 0x11f6 |
+        0x11f7 | 0x11fb => -1,
+
+        _ => 0,
+    }
+}
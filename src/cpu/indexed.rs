@@ -1,6 +1,6 @@
 use crate::cpu::RegEnum;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IndexModes {
 
     ROff(RegEnum,u16),
@@ -19,6 +19,15 @@ pub enum IndexModes {
     PCAddi16,        //      (+/- 15 bit offset),PC    5 2 |
     Illegal,         //              Illegal           u u |
     Ea,
+
+    // 6309 only
+    RAddE(RegEnum),     //             (+/- E),R          1 0 |
+    RAddF(RegEnum),     //             (+/- F),R          1 0 |
+    RAddW(RegEnum),     //             (+/- W),R          4 0 |
+    WZero,              //               ,W               0 0 |
+    WAddi16,            //      (+/- 15 bit offset),W     2 2 |
+    WPlusPlus,          //               ,W++             1 0 |
+    WSubSub,            //               ,--W             1 0 |
 }
 
 bitflags! {
@@ -106,6 +115,49 @@ impl IndexedFlags {
 
         IndexModes::ROff(r, self.get_offset())
     }
+
+    // The 6309 fills in some of the 6809's illegal encodings
+    pub fn get_index_type_6309(self) -> IndexModes {
+
+        // W based modes use encodings the 6809 didn't
+        match self.bits {
+            0x8f => return IndexModes::WZero,
+            0xaf => return IndexModes::WAddi16,
+            0xcf => return IndexModes::WPlusPlus,
+            0xef => return IndexModes::WSubSub,
+            _ => (),
+        }
+
+        // and their indirect versions take over ,R+ indirect which was illegal
+        match self.bits {
+            0x90 => return IndexModes::WZero,
+            0xb0 => return IndexModes::WAddi16,
+            0xd0 => return IndexModes::WPlusPlus,
+            0xf0 => return IndexModes::WSubSub,
+            _ => (),
+        }
+
+        let r = self.get_reg();
+
+        if self.not_imm() {
+            match self.bits & IndexedFlags::TYPE.bits() {
+                0b0111 => return IndexModes::RAddE(r),
+                0b1010 => return IndexModes::RAddF(r),
+                0b1110 => return IndexModes::RAddW(r),
+                _ => (),
+            }
+        }
+
+        self.get_index_type()
+    }
+
+    pub fn is_indirect_6309(self) -> bool {
+        match self.bits {
+            0x90 | 0xb0 | 0xd0 | 0xf0 => true,
+            0x8f | 0xaf | 0xcf | 0xef => false,
+            _ => self.is_indirect(),
+        }
+    }
 }
 
 
@@ -0,0 +1,143 @@
+// Hardware interrupt lines into the 6809
+//
+// IRQ and FIRQ are level sensitive, the line stays asserted until the
+// device that raised it is acknowledged. NMI is edge triggered, we latch
+// the edge and it stays pending until the cpu takes it. NMI is also
+// ignored after reset until the first LDS (see utils/6809cyc.txt)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interrupt {
+    Nmi,
+    Firq,
+    Irq,
+}
+
+impl Interrupt {
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::Nmi  => 0xfffc,
+            Interrupt::Firq => 0xfff6,
+            Interrupt::Irq  => 0xfff8,
+        }
+    }
+
+    // Cycles from interrupt recognition to first opcode fetch
+    pub fn cycles(self) -> usize {
+        match self {
+            Interrupt::Firq => 10,
+            _ => 19,
+        }
+    }
+}
+
+// What the cpu is doing between calls to step
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RunState {
+    #[default]
+    Running,
+    // SYNC, released by any asserted line, masked or not, or a sync pulse
+    Syncing,
+    // CWAI, machine state is already stacked
+    Waiting,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interrupts {
+    irq         : bool,
+    firq        : bool,
+    nmi         : bool,
+    nmi_pending : bool,
+    nmi_armed   : bool,
+    run_state   : RunState,
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn set_irq(&mut self, v : bool) {
+        self.irq = v;
+    }
+
+    pub fn set_firq(&mut self, v : bool) {
+        self.firq = v;
+    }
+
+    pub fn set_nmi(&mut self, v : bool) {
+        if v && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = v;
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.irq
+    }
+
+    pub fn get_firq(&self) -> bool {
+        self.firq
+    }
+
+    pub fn get_nmi(&self) -> bool {
+        self.nmi
+    }
+
+    pub fn get_run_state(&self) -> RunState {
+        self.run_state
+    }
+
+    pub fn set_run_state(&mut self, run_state : RunState) {
+        self.run_state = run_state;
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.run_state != RunState::Running
+    }
+
+    // A signal that only wakes SYNC, it's not an interrupt line and never
+    // vectors
+    pub fn sync_pulse(&mut self) {
+        if self.run_state == RunState::Syncing {
+            self.run_state = RunState::Running;
+        }
+    }
+
+    // Any line asserted regardless of masking, used to release SYNC
+    pub fn any_asserted(&self) -> bool {
+        self.irq || self.firq || (self.nmi_pending && self.nmi_armed)
+    }
+
+    pub fn arm_nmi(&mut self) {
+        self.nmi_armed = true;
+    }
+
+    pub fn is_nmi_armed(&self) -> bool {
+        self.nmi_armed
+    }
+
+    // Highest priority interrupt that can be taken with these cc flags
+    pub fn pending(&self, flags : crate::cpu::Flags) -> Option<Interrupt> {
+        use crate::cpu::Flags;
+
+        if self.nmi_pending && self.nmi_armed {
+            Some(Interrupt::Nmi)
+        } else if self.firq && !flags.contains(Flags::F) {
+            Some(Interrupt::Firq)
+        } else if self.irq && !flags.contains(Flags::I) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    pub fn acknowledge(&mut self, int : Interrupt) {
+        if int == Interrupt::Nmi {
+            self.nmi_pending = false;
+        }
+    }
+}
@@ -579,3 +579,446 @@ macro_rules! decode_op {
 
 
 
+
+// Opcodes only the 6309 has, anything not in here falls back to op_table!
+macro_rules! op_table_6309 {
+    ($op:expr, $fail:block) => {
+        match $op {
+            0x01 => handle_op!(Direct, oim),
+            0x02 => handle_op!(Direct, aim),
+            0x05 => handle_op!(Direct, eim),
+            0x0b => handle_op!(Direct, tim),
+            0x14 => handle_op!(Inherent, sexw),
+            0x61 => handle_op!(Indexed, oim),
+            0x62 => handle_op!(Indexed, aim),
+            0x65 => handle_op!(Indexed, eim),
+            0x6b => handle_op!(Indexed, tim),
+            0x71 => handle_op!(Extended, oim),
+            0x72 => handle_op!(Extended, aim),
+            0x75 => handle_op!(Extended, eim),
+            0x7b => handle_op!(Extended, tim),
+            0xcd => handle_op!(Inherent, ldq_immediate),
+            0x1030 => handle_op!(Inherent, addr),
+            0x1031 => handle_op!(Inherent, adcr),
+            0x1032 => handle_op!(Inherent, subr),
+            0x1033 => handle_op!(Inherent, sbcr),
+            0x1034 => handle_op!(Inherent, andr),
+            0x1035 => handle_op!(Inherent, orr),
+            0x1036 => handle_op!(Inherent, eorr),
+            0x1037 => handle_op!(Inherent, cmpr),
+            0x1038 => handle_op!(Inherent, pshsw),
+            0x1039 => handle_op!(Inherent, pulsw),
+            0x103a => handle_op!(Inherent, pshuw),
+            0x103b => handle_op!(Inherent, puluw),
+            0x1040 => handle_op!(Inherent, negd),
+            0x1043 => handle_op!(Inherent, comd),
+            0x1044 => handle_op!(Inherent, lsrd),
+            0x1046 => handle_op!(Inherent, rord),
+            0x1047 => handle_op!(Inherent, asrd),
+            0x1048 => handle_op!(Inherent, lsld_asld),
+            0x1049 => handle_op!(Inherent, rold),
+            0x104a => handle_op!(Inherent, decd),
+            0x104c => handle_op!(Inherent, incd),
+            0x104d => handle_op!(Inherent, tstd),
+            0x104f => handle_op!(Inherent, clrd),
+            0x1053 => handle_op!(Inherent, comw),
+            0x1054 => handle_op!(Inherent, lsrw),
+            0x1056 => handle_op!(Inherent, rorw),
+            0x1059 => handle_op!(Inherent, rolw),
+            0x105a => handle_op!(Inherent, decw),
+            0x105c => handle_op!(Inherent, incw),
+            0x105d => handle_op!(Inherent, tstw),
+            0x105f => handle_op!(Inherent, clrw),
+            0x1080 => handle_op!(Immediate, subw),
+            0x1081 => handle_op!(Immediate, cmpw),
+            0x1082 => handle_op!(Immediate, sbcd),
+            0x1084 => handle_op!(Immediate, andd),
+            0x1085 => handle_op!(Immediate, bitd),
+            0x1086 => handle_op!(Immediate, ldw),
+            0x1088 => handle_op!(Immediate, eord),
+            0x1089 => handle_op!(Immediate, adcd),
+            0x108a => handle_op!(Immediate, ord),
+            0x108b => handle_op!(Immediate, addw),
+            0x1090 => handle_op!(Direct, subw),
+            0x1091 => handle_op!(Direct, cmpw),
+            0x1092 => handle_op!(Direct, sbcd),
+            0x1094 => handle_op!(Direct, andd),
+            0x1095 => handle_op!(Direct, bitd),
+            0x1096 => handle_op!(Direct, ldw),
+            0x1097 => handle_op!(Direct, stw),
+            0x1098 => handle_op!(Direct, eord),
+            0x1099 => handle_op!(Direct, adcd),
+            0x109a => handle_op!(Direct, ord),
+            0x109b => handle_op!(Direct, addw),
+            0x10a0 => handle_op!(Indexed, subw),
+            0x10a1 => handle_op!(Indexed, cmpw),
+            0x10a2 => handle_op!(Indexed, sbcd),
+            0x10a4 => handle_op!(Indexed, andd),
+            0x10a5 => handle_op!(Indexed, bitd),
+            0x10a6 => handle_op!(Indexed, ldw),
+            0x10a7 => handle_op!(Indexed, stw),
+            0x10a8 => handle_op!(Indexed, eord),
+            0x10a9 => handle_op!(Indexed, adcd),
+            0x10aa => handle_op!(Indexed, ord),
+            0x10ab => handle_op!(Indexed, addw),
+            0x10b0 => handle_op!(Extended, subw),
+            0x10b1 => handle_op!(Extended, cmpw),
+            0x10b2 => handle_op!(Extended, sbcd),
+            0x10b4 => handle_op!(Extended, andd),
+            0x10b5 => handle_op!(Extended, bitd),
+            0x10b6 => handle_op!(Extended, ldw),
+            0x10b7 => handle_op!(Extended, stw),
+            0x10b8 => handle_op!(Extended, eord),
+            0x10b9 => handle_op!(Extended, adcd),
+            0x10ba => handle_op!(Extended, ord),
+            0x10bb => handle_op!(Extended, addw),
+            0x10dc => handle_op!(Direct, ldq),
+            0x10dd => handle_op!(Direct, stq),
+            0x10ec => handle_op!(Indexed, ldq),
+            0x10ed => handle_op!(Indexed, stq),
+            0x10fc => handle_op!(Extended, ldq),
+            0x10fd => handle_op!(Extended, stq),
+            0x1130 => handle_op!(Direct, band),
+            0x1131 => handle_op!(Direct, biand),
+            0x1132 => handle_op!(Direct, bor),
+            0x1133 => handle_op!(Direct, bior),
+            0x1134 => handle_op!(Direct, beor),
+            0x1135 => handle_op!(Direct, bieor),
+            0x1136 => handle_op!(Direct, ldbt),
+            0x1137 => handle_op!(Direct, stbt),
+            0x1138 => handle_op!(Inherent, tfm_pp),
+            0x1139 => handle_op!(Inherent, tfm_mm),
+            0x113a => handle_op!(Inherent, tfm_pn),
+            0x113b => handle_op!(Inherent, tfm_np),
+            0x113c => handle_op!(Immediate, bitmd),
+            0x113d => handle_op!(Immediate, ldmd),
+            0x1143 => handle_op!(Inherent, come),
+            0x114a => handle_op!(Inherent, dece),
+            0x114c => handle_op!(Inherent, ince),
+            0x114d => handle_op!(Inherent, tste),
+            0x114f => handle_op!(Inherent, clre),
+            0x1153 => handle_op!(Inherent, comf),
+            0x115a => handle_op!(Inherent, decf),
+            0x115c => handle_op!(Inherent, incf),
+            0x115d => handle_op!(Inherent, tstf),
+            0x115f => handle_op!(Inherent, clrf),
+            0x1180 => handle_op!(Immediate, sube),
+            0x1181 => handle_op!(Immediate, cmpe),
+            0x1186 => handle_op!(Immediate, lde),
+            0x118b => handle_op!(Immediate, adde),
+            0x118d => handle_op!(Immediate, divd),
+            0x118e => handle_op!(Immediate, divq),
+            0x118f => handle_op!(Immediate, muld),
+            0x1190 => handle_op!(Direct, sube),
+            0x1191 => handle_op!(Direct, cmpe),
+            0x1196 => handle_op!(Direct, lde),
+            0x1197 => handle_op!(Direct, ste),
+            0x119b => handle_op!(Direct, adde),
+            0x119d => handle_op!(Direct, divd),
+            0x119e => handle_op!(Direct, divq),
+            0x119f => handle_op!(Direct, muld),
+            0x11a0 => handle_op!(Indexed, sube),
+            0x11a1 => handle_op!(Indexed, cmpe),
+            0x11a6 => handle_op!(Indexed, lde),
+            0x11a7 => handle_op!(Indexed, ste),
+            0x11ab => handle_op!(Indexed, adde),
+            0x11ad => handle_op!(Indexed, divd),
+            0x11ae => handle_op!(Indexed, divq),
+            0x11af => handle_op!(Indexed, muld),
+            0x11b0 => handle_op!(Extended, sube),
+            0x11b1 => handle_op!(Extended, cmpe),
+            0x11b6 => handle_op!(Extended, lde),
+            0x11b7 => handle_op!(Extended, ste),
+            0x11bb => handle_op!(Extended, adde),
+            0x11bd => handle_op!(Extended, divd),
+            0x11be => handle_op!(Extended, divq),
+            0x11bf => handle_op!(Extended, muld),
+            0x11c0 => handle_op!(Immediate, subf),
+            0x11c1 => handle_op!(Immediate, cmpf),
+            0x11c6 => handle_op!(Immediate, ldf),
+            0x11cb => handle_op!(Immediate, addf),
+            0x11d0 => handle_op!(Direct, subf),
+            0x11d1 => handle_op!(Direct, cmpf),
+            0x11d6 => handle_op!(Direct, ldf),
+            0x11d7 => handle_op!(Direct, stf),
+            0x11db => handle_op!(Direct, addf),
+            0x11e0 => handle_op!(Indexed, subf),
+            0x11e1 => handle_op!(Indexed, cmpf),
+            0x11e6 => handle_op!(Indexed, ldf),
+            0x11e7 => handle_op!(Indexed, stf),
+            0x11eb => handle_op!(Indexed, addf),
+            0x11f0 => handle_op!(Extended, subf),
+            0x11f1 => handle_op!(Extended, cmpf),
+            0x11f6 => handle_op!(Extended, ldf),
+            0x11f7 => handle_op!(Extended, stf),
+            0x11fb => handle_op!(Extended, addf),
+            _ => $fail
+        }
+    }
+}
+
+macro_rules! decode_op_6309 {
+    ($op:expr, $this:ident, $mem:expr, $res:expr, $fail:block) => {
+        match $op {
+            0x01 => single_op!(imm_direct, oim, $this, $mem, $res),
+            0x02 => single_op!(imm_direct, aim, $this, $mem, $res),
+            0x05 => single_op!(imm_direct, eim, $this, $mem, $res),
+            0x0b => single_op!(imm_direct, tim, $this, $mem, $res),
+            0x14 => single_op!(inherent, sexw, $this, $mem, $res),
+            0x61 => single_op!(imm_indexed, oim, $this, $mem, $res),
+            0x62 => single_op!(imm_indexed, aim, $this, $mem, $res),
+            0x65 => single_op!(imm_indexed, eim, $this, $mem, $res),
+            0x6b => single_op!(imm_indexed, tim, $this, $mem, $res),
+            0x71 => single_op!(imm_extended, oim, $this, $mem, $res),
+            0x72 => single_op!(imm_extended, aim, $this, $mem, $res),
+            0x75 => single_op!(imm_extended, eim, $this, $mem, $res),
+            0x7b => single_op!(imm_extended, tim, $this, $mem, $res),
+            0xcd => single_op!(immediate32, ldq_immediate, $this, $mem, $res),
+            0x1030 => single_op!(inherent_reg_reg, addr, $this, $mem, $res),
+            0x1031 => single_op!(inherent_reg_reg, adcr, $this, $mem, $res),
+            0x1032 => single_op!(inherent_reg_reg, subr, $this, $mem, $res),
+            0x1033 => single_op!(inherent_reg_reg, sbcr, $this, $mem, $res),
+            0x1034 => single_op!(inherent_reg_reg, andr, $this, $mem, $res),
+            0x1035 => single_op!(inherent_reg_reg, orr, $this, $mem, $res),
+            0x1036 => single_op!(inherent_reg_reg, eorr, $this, $mem, $res),
+            0x1037 => single_op!(inherent_reg_reg, cmpr, $this, $mem, $res),
+            0x1038 => single_op!(inherent, pshsw, $this, $mem, $res),
+            0x1039 => single_op!(inherent, pulsw, $this, $mem, $res),
+            0x103a => single_op!(inherent, pshuw, $this, $mem, $res),
+            0x103b => single_op!(inherent, puluw, $this, $mem, $res),
+            0x1040 => single_op!(inherent, negd, $this, $mem, $res),
+            0x1043 => single_op!(inherent, comd, $this, $mem, $res),
+            0x1044 => single_op!(inherent, lsrd, $this, $mem, $res),
+            0x1046 => single_op!(inherent, rord, $this, $mem, $res),
+            0x1047 => single_op!(inherent, asrd, $this, $mem, $res),
+            0x1048 => single_op!(inherent, lsld_asld, $this, $mem, $res),
+            0x1049 => single_op!(inherent, rold, $this, $mem, $res),
+            0x104a => single_op!(inherent, decd, $this, $mem, $res),
+            0x104c => single_op!(inherent, incd, $this, $mem, $res),
+            0x104d => single_op!(inherent, tstd, $this, $mem, $res),
+            0x104f => single_op!(inherent, clrd, $this, $mem, $res),
+            0x1053 => single_op!(inherent, comw, $this, $mem, $res),
+            0x1054 => single_op!(inherent, lsrw, $this, $mem, $res),
+            0x1056 => single_op!(inherent, rorw, $this, $mem, $res),
+            0x1059 => single_op!(inherent, rolw, $this, $mem, $res),
+            0x105a => single_op!(inherent, decw, $this, $mem, $res),
+            0x105c => single_op!(inherent, incw, $this, $mem, $res),
+            0x105d => single_op!(inherent, tstw, $this, $mem, $res),
+            0x105f => single_op!(inherent, clrw, $this, $mem, $res),
+            0x1080 => single_op!(immediate16, subw, $this, $mem, $res),
+            0x1081 => single_op!(immediate16, cmpw, $this, $mem, $res),
+            0x1082 => single_op!(immediate16, sbcd, $this, $mem, $res),
+            0x1084 => single_op!(immediate16, andd, $this, $mem, $res),
+            0x1085 => single_op!(immediate16, bitd, $this, $mem, $res),
+            0x1086 => single_op!(immediate16, ldw, $this, $mem, $res),
+            0x1088 => single_op!(immediate16, eord, $this, $mem, $res),
+            0x1089 => single_op!(immediate16, adcd, $this, $mem, $res),
+            0x108a => single_op!(immediate16, ord, $this, $mem, $res),
+            0x108b => single_op!(immediate16, addw, $this, $mem, $res),
+            0x1090 => single_op!(direct_16, subw, $this, $mem, $res),
+            0x1091 => single_op!(direct_16, cmpw, $this, $mem, $res),
+            0x1092 => single_op!(direct_16, sbcd, $this, $mem, $res),
+            0x1094 => single_op!(direct_16, andd, $this, $mem, $res),
+            0x1095 => single_op!(direct_16, bitd, $this, $mem, $res),
+            0x1096 => single_op!(direct_16, ldw, $this, $mem, $res),
+            0x1097 => single_op!(direct_16, stw, $this, $mem, $res),
+            0x1098 => single_op!(direct_16, eord, $this, $mem, $res),
+            0x1099 => single_op!(direct_16, adcd, $this, $mem, $res),
+            0x109a => single_op!(direct_16, ord, $this, $mem, $res),
+            0x109b => single_op!(direct_16, addw, $this, $mem, $res),
+            0x10a0 => single_op!(indexed_16, subw, $this, $mem, $res),
+            0x10a1 => single_op!(indexed_16, cmpw, $this, $mem, $res),
+            0x10a2 => single_op!(indexed_16, sbcd, $this, $mem, $res),
+            0x10a4 => single_op!(indexed_16, andd, $this, $mem, $res),
+            0x10a5 => single_op!(indexed_16, bitd, $this, $mem, $res),
+            0x10a6 => single_op!(indexed_16, ldw, $this, $mem, $res),
+            0x10a7 => single_op!(indexed_16, stw, $this, $mem, $res),
+            0x10a8 => single_op!(indexed_16, eord, $this, $mem, $res),
+            0x10a9 => single_op!(indexed_16, adcd, $this, $mem, $res),
+            0x10aa => single_op!(indexed_16, ord, $this, $mem, $res),
+            0x10ab => single_op!(indexed_16, addw, $this, $mem, $res),
+            0x10b0 => single_op!(extended_16, subw, $this, $mem, $res),
+            0x10b1 => single_op!(extended_16, cmpw, $this, $mem, $res),
+            0x10b2 => single_op!(extended_16, sbcd, $this, $mem, $res),
+            0x10b4 => single_op!(extended_16, andd, $this, $mem, $res),
+            0x10b5 => single_op!(extended_16, bitd, $this, $mem, $res),
+            0x10b6 => single_op!(extended_16, ldw, $this, $mem, $res),
+            0x10b7 => single_op!(extended_16, stw, $this, $mem, $res),
+            0x10b8 => single_op!(extended_16, eord, $this, $mem, $res),
+            0x10b9 => single_op!(extended_16, adcd, $this, $mem, $res),
+            0x10ba => single_op!(extended_16, ord, $this, $mem, $res),
+            0x10bb => single_op!(extended_16, addw, $this, $mem, $res),
+            0x10dc => single_op!(direct_16, ldq, $this, $mem, $res),
+            0x10dd => single_op!(direct_16, stq, $this, $mem, $res),
+            0x10ec => single_op!(indexed_16, ldq, $this, $mem, $res),
+            0x10ed => single_op!(indexed_16, stq, $this, $mem, $res),
+            0x10fc => single_op!(extended_16, ldq, $this, $mem, $res),
+            0x10fd => single_op!(extended_16, stq, $this, $mem, $res),
+            0x1130 => single_op!(bit_direct, band, $this, $mem, $res),
+            0x1131 => single_op!(bit_direct, biand, $this, $mem, $res),
+            0x1132 => single_op!(bit_direct, bor, $this, $mem, $res),
+            0x1133 => single_op!(bit_direct, bior, $this, $mem, $res),
+            0x1134 => single_op!(bit_direct, beor, $this, $mem, $res),
+            0x1135 => single_op!(bit_direct, bieor, $this, $mem, $res),
+            0x1136 => single_op!(bit_direct, ldbt, $this, $mem, $res),
+            0x1137 => single_op!(bit_direct, stbt, $this, $mem, $res),
+            0x1138 => single_op!(reg_reg_pp, tfm_pp, $this, $mem, $res),
+            0x1139 => single_op!(reg_reg_mm, tfm_mm, $this, $mem, $res),
+            0x113a => single_op!(reg_reg_pn, tfm_pn, $this, $mem, $res),
+            0x113b => single_op!(reg_reg_np, tfm_np, $this, $mem, $res),
+            0x113c => single_op!(immediate8, bitmd, $this, $mem, $res),
+            0x113d => single_op!(immediate8, ldmd, $this, $mem, $res),
+            0x1143 => single_op!(inherent, come, $this, $mem, $res),
+            0x114a => single_op!(inherent, dece, $this, $mem, $res),
+            0x114c => single_op!(inherent, ince, $this, $mem, $res),
+            0x114d => single_op!(inherent, tste, $this, $mem, $res),
+            0x114f => single_op!(inherent, clre, $this, $mem, $res),
+            0x1153 => single_op!(inherent, comf, $this, $mem, $res),
+            0x115a => single_op!(inherent, decf, $this, $mem, $res),
+            0x115c => single_op!(inherent, incf, $this, $mem, $res),
+            0x115d => single_op!(inherent, tstf, $this, $mem, $res),
+            0x115f => single_op!(inherent, clrf, $this, $mem, $res),
+            0x1180 => single_op!(immediate8, sube, $this, $mem, $res),
+            0x1181 => single_op!(immediate8, cmpe, $this, $mem, $res),
+            0x1186 => single_op!(immediate8, lde, $this, $mem, $res),
+            0x118b => single_op!(immediate8, adde, $this, $mem, $res),
+            0x118d => single_op!(immediate8, divd, $this, $mem, $res),
+            0x118e => single_op!(immediate16, divq, $this, $mem, $res),
+            0x118f => single_op!(immediate16, muld, $this, $mem, $res),
+            0x1190 => single_op!(direct_8, sube, $this, $mem, $res),
+            0x1191 => single_op!(direct_8, cmpe, $this, $mem, $res),
+            0x1196 => single_op!(direct_8, lde, $this, $mem, $res),
+            0x1197 => single_op!(direct_8, ste, $this, $mem, $res),
+            0x119b => single_op!(direct_8, adde, $this, $mem, $res),
+            0x119d => single_op!(direct_8, divd, $this, $mem, $res),
+            0x119e => single_op!(direct_16, divq, $this, $mem, $res),
+            0x119f => single_op!(direct_16, muld, $this, $mem, $res),
+            0x11a0 => single_op!(indexed_8, sube, $this, $mem, $res),
+            0x11a1 => single_op!(indexed_8, cmpe, $this, $mem, $res),
+            0x11a6 => single_op!(indexed_8, lde, $this, $mem, $res),
+            0x11a7 => single_op!(indexed_8, ste, $this, $mem, $res),
+            0x11ab => single_op!(indexed_8, adde, $this, $mem, $res),
+            0x11ad => single_op!(indexed_8, divd, $this, $mem, $res),
+            0x11ae => single_op!(indexed_16, divq, $this, $mem, $res),
+            0x11af => single_op!(indexed_16, muld, $this, $mem, $res),
+            0x11b0 => single_op!(extended_8, sube, $this, $mem, $res),
+            0x11b1 => single_op!(extended_8, cmpe, $this, $mem, $res),
+            0x11b6 => single_op!(extended_8, lde, $this, $mem, $res),
+            0x11b7 => single_op!(extended_8, ste, $this, $mem, $res),
+            0x11bb => single_op!(extended_8, adde, $this, $mem, $res),
+            0x11bd => single_op!(extended_8, divd, $this, $mem, $res),
+            0x11be => single_op!(extended_16, divq, $this, $mem, $res),
+            0x11bf => single_op!(extended_16, muld, $this, $mem, $res),
+            0x11c0 => single_op!(immediate8, subf, $this, $mem, $res),
+            0x11c1 => single_op!(immediate8, cmpf, $this, $mem, $res),
+            0x11c6 => single_op!(immediate8, ldf, $this, $mem, $res),
+            0x11cb => single_op!(immediate8, addf, $this, $mem, $res),
+            0x11d0 => single_op!(direct_8, subf, $this, $mem, $res),
+            0x11d1 => single_op!(direct_8, cmpf, $this, $mem, $res),
+            0x11d6 => single_op!(direct_8, ldf, $this, $mem, $res),
+            0x11d7 => single_op!(direct_8, stf, $this, $mem, $res),
+            0x11db => single_op!(direct_8, addf, $this, $mem, $res),
+            0x11e0 => single_op!(indexed_8, subf, $this, $mem, $res),
+            0x11e1 => single_op!(indexed_8, cmpf, $this, $mem, $res),
+            0x11e6 => single_op!(indexed_8, ldf, $this, $mem, $res),
+            0x11e7 => single_op!(indexed_8, stf, $this, $mem, $res),
+            0x11eb => single_op!(indexed_8, addf, $this, $mem, $res),
+            0x11f0 => single_op!(extended_8, subf, $this, $mem, $res),
+            0x11f1 => single_op!(extended_8, cmpf, $this, $mem, $res),
+            0x11f6 => single_op!(extended_8, ldf, $this, $mem, $res),
+            0x11f7 => single_op!(extended_8, stf, $this, $mem, $res),
+            0x11fb => single_op!(extended_8, addf, $this, $mem, $res),
+            _ => $fail
+        }
+    }
+}
+
+// Undocumented 6809 opcodes, see undocumented.rs
+macro_rules! op_table_undoc {
+    ($op:expr, $fail:block) => {
+        match $op {
+            0x01 =>  handle_op!(Direct, neg),
+            0x02 =>  handle_op!(Direct, ngc),
+            0x05 =>  handle_op!(Direct, lsr),
+            0x0b =>  handle_op!(Direct, dec),
+            0x14 =>  handle_op!(Inherent, hcf),
+            0x15 =>  handle_op!(Inherent, hcf),
+            0x1b =>  handle_op!(Inherent, nop),
+            0x38 =>  handle_op!(Immediate, andcc),
+            0x41 =>  handle_op!(Inherent, nega),
+            0x42 =>  handle_op!(Inherent, ngca),
+            0x45 =>  handle_op!(Inherent, lsra),
+            0x4b =>  handle_op!(Inherent, deca),
+            0x4e =>  handle_op!(Inherent, clra),
+            0x51 =>  handle_op!(Inherent, negb),
+            0x52 =>  handle_op!(Inherent, ngcb),
+            0x55 =>  handle_op!(Inherent, lsrb),
+            0x5b =>  handle_op!(Inherent, decb),
+            0x5e =>  handle_op!(Inherent, clrb),
+            0x61 =>  handle_op!(Indexed, neg),
+            0x62 =>  handle_op!(Indexed, ngc),
+            0x65 =>  handle_op!(Indexed, lsr),
+            0x6b =>  handle_op!(Indexed, dec),
+            0x71 =>  handle_op!(Extended, neg),
+            0x72 =>  handle_op!(Extended, ngc),
+            0x75 =>  handle_op!(Extended, lsr),
+            0x7b =>  handle_op!(Extended, dec),
+            0x87 =>  handle_op!(Immediate, scc),
+            0xc7 =>  handle_op!(Immediate, scc),
+            0xcd =>  handle_op!(Inherent, hcf),
+            _ => $fail
+        }
+    }
+}
+
+macro_rules! decode_op_undoc {
+    ($op:expr, $this:ident, $mem:expr, $res:expr, $fail:block) => {
+        match $op {
+            0x01 =>  single_op!(direct_8, neg, $this, $mem, $res),
+            0x02 =>  single_op!(direct_8, ngc, $this, $mem, $res),
+            0x05 =>  single_op!(direct_8, lsr, $this, $mem, $res),
+            0x0b =>  single_op!(direct_8, dec, $this, $mem, $res),
+            0x14 =>  single_op!(inherent, hcf, $this, $mem, $res),
+            0x15 =>  single_op!(inherent, hcf, $this, $mem, $res),
+            0x1b =>  single_op!(inherent, nop, $this, $mem, $res),
+            0x38 =>  single_op!(immediate8, andcc, $this, $mem, $res),
+            0x41 =>  single_op!(inherent, nega, $this, $mem, $res),
+            0x42 =>  single_op!(inherent, ngca, $this, $mem, $res),
+            0x45 =>  single_op!(inherent, lsra, $this, $mem, $res),
+            0x4b =>  single_op!(inherent, deca, $this, $mem, $res),
+            0x4e =>  single_op!(inherent, clra, $this, $mem, $res),
+            0x51 =>  single_op!(inherent, negb, $this, $mem, $res),
+            0x52 =>  single_op!(inherent, ngcb, $this, $mem, $res),
+            0x55 =>  single_op!(inherent, lsrb, $this, $mem, $res),
+            0x5b =>  single_op!(inherent, decb, $this, $mem, $res),
+            0x5e =>  single_op!(inherent, clrb, $this, $mem, $res),
+            0x61 =>  single_op!(indexed_8, neg, $this, $mem, $res),
+            0x62 =>  single_op!(indexed_8, ngc, $this, $mem, $res),
+            0x65 =>  single_op!(indexed_8, lsr, $this, $mem, $res),
+            0x6b =>  single_op!(indexed_8, dec, $this, $mem, $res),
+            0x71 =>  single_op!(extended_8, neg, $this, $mem, $res),
+            0x72 =>  single_op!(extended_8, ngc, $this, $mem, $res),
+            0x75 =>  single_op!(extended_8, lsr, $this, $mem, $res),
+            0x7b =>  single_op!(extended_8, dec, $this, $mem, $res),
+            0x87 =>  single_op!(immediate8, scc, $this, $mem, $res),
+            0xc7 =>  single_op!(immediate8, scc, $this, $mem, $res),
+            0xcd =>  single_op!(inherent, hcf, $this, $mem, $res),
+            _ => $fail
+        }
+    }
+}
+
+// Builds a flat table by evaluating $table with $op bound to every opcode,
+// see opinfo.rs for the layout
+macro_rules! build_ops {
+    ($ty:ty, $fail:expr, |$op:ident| $table:expr) => {{
+        let mut ret : [$ty; $crate::cpu::OP_TABLE_SIZE] = [$fail; $crate::cpu::OP_TABLE_SIZE];
+        let mut i = 0;
+        while i < $crate::cpu::OP_TABLE_SIZE {
+            let $op = $crate::cpu::index_to_op(i);
+            ret[i] = $table;
+            i += 1;
+        }
+        ret
+    }}
+}
@@ -11,6 +11,17 @@ mod addrmodes;
 mod decoder;
 mod alu;
 mod clock;
+mod interrupts;
+mod hd6309;
+mod undocumented;
+mod bus;
+mod observer;
+mod opinfo;
+mod callstack;
+mod checked;
+
+#[cfg(test)]
+mod testing;
 
 pub use self::registers::*;
 pub use self::isa::*;
@@ -20,4 +31,12 @@ pub use self::cpucore::*;
 pub use self::decoder::*;
 pub use self::addrmodes::*;
 pub use self::clock::*;
+pub use self::interrupts::*;
+pub use self::hd6309::*;
+pub use self::undocumented::*;
+pub use self::bus::*;
+pub use self::observer::*;
+pub use self::opinfo::*;
+pub use self::callstack::*;
+pub use self::checked::*;
 
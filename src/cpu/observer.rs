@@ -0,0 +1,177 @@
+// Execution hooks
+//
+// Tools that want to watch the cpu run (tracing, coverage, profiling, call
+// stacks) implement Observer and call step_with_observer instead of step.
+//
+// Everything is generic, step itself runs with a NullObserver whose hooks
+// are empty so they inline away to nothing. The observer rides along with
+// memory inside an Observed bus, that's how the core gets at it without a
+// second borrow and how every memory access gets seen.
+
+use crate::cpu::{Regs, RegEnum, InstructionDecoder, Interrupt};
+use crate::mem::{MemoryIO, BusAccess, Sha1};
+
+pub trait Observer {
+    // False skips the work needed to feed after_execute
+    const ACTIVE : bool = true;
+
+    // Opcode has been fetched, regs are as they were before it
+    fn before_execute(&mut self, _ins : &InstructionDecoder, _regs : &Regs) {
+    }
+
+    // Instruction finished, cycles includes any interrupt taken first
+    fn after_execute(&mut self, _ins : &InstructionDecoder, _before : &Regs, _after : &Regs, _cycles : u32) {
+    }
+
+    fn mem_read(&mut self, _addr : u16, _val : u8) {
+    }
+
+    fn mem_write(&mut self, _addr : u16, _val : u8) {
+    }
+
+    // State is stacked, called before jumping through the vector
+    fn interrupt(&mut self, _int : Interrupt, _regs : &Regs) {
+    }
+
+    // Stack is S or U
+    fn stack_push(&mut self, _stack : RegEnum, _addr : u16, _val : u8) {
+    }
+
+    fn stack_pull(&mut self, _stack : RegEnum, _addr : u16, _val : u8) {
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullObserver;
+
+impl Observer for NullObserver {
+    const ACTIVE : bool = false;
+}
+
+// Two observers on the one cpu, the first hears about everything first
+pub struct Both<'a, A : 'a + Observer, B : 'a + Observer>(pub &'a mut A, pub &'a mut B);
+
+impl<'a, A : 'a + Observer, B : 'a + Observer> Observer for Both<'a, A, B> {
+    const ACTIVE : bool = A::ACTIVE || B::ACTIVE;
+
+    fn before_execute(&mut self, ins : &InstructionDecoder, regs : &Regs) {
+        self.0.before_execute(ins, regs);
+        self.1.before_execute(ins, regs);
+    }
+
+    fn after_execute(&mut self, ins : &InstructionDecoder, before : &Regs, after : &Regs, cycles : u32) {
+        self.0.after_execute(ins, before, after, cycles);
+        self.1.after_execute(ins, before, after, cycles);
+    }
+
+    fn mem_read(&mut self, addr : u16, val : u8) {
+        self.0.mem_read(addr, val);
+        self.1.mem_read(addr, val);
+    }
+
+    fn mem_write(&mut self, addr : u16, val : u8) {
+        self.0.mem_write(addr, val);
+        self.1.mem_write(addr, val);
+    }
+
+    fn interrupt(&mut self, int : Interrupt, regs : &Regs) {
+        self.0.interrupt(int, regs);
+        self.1.interrupt(int, regs);
+    }
+
+    fn stack_push(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.0.stack_push(stack, addr, val);
+        self.1.stack_push(stack, addr, val);
+    }
+
+    fn stack_pull(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.0.stack_pull(stack, addr, val);
+        self.1.stack_pull(stack, addr, val);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Memory as the core sees it, with a way back to the observer
+pub trait CoreBus : MemoryIO {
+    type Obs : Observer;
+    fn observer(&mut self) -> &mut Self::Obs;
+}
+
+pub struct Observed<'a, M : 'a + MemoryIO, O : 'a + Observer> {
+    mem : &'a mut M,
+    obs : &'a mut O,
+}
+
+impl<'a, M : 'a + MemoryIO, O : 'a + Observer> Observed<'a, M, O> {
+    pub fn new(mem : &'a mut M, obs : &'a mut O) -> Self {
+        Self { mem, obs }
+    }
+}
+
+impl<'a, M : 'a + MemoryIO, O : 'a + Observer> CoreBus for Observed<'a, M, O> {
+    type Obs = O;
+
+    fn observer(&mut self) -> &mut O {
+        self.obs
+    }
+}
+
+impl<'a, M : 'a + MemoryIO, O : 'a + Observer> MemoryIO for Observed<'a, M, O> {
+    fn peek(&self, addr : u16) -> u8 {
+        self.mem.peek(addr)
+    }
+
+    fn poke(&mut self, addr : u16, val : u8) {
+        self.mem.poke(addr, val)
+    }
+
+    fn get_range(&self) -> (u16, u16) {
+        self.mem.get_range()
+    }
+
+    fn update_sha1(&self, digest : &mut Sha1) {
+        self.mem.update_sha1(digest)
+    }
+
+    #[inline]
+    fn load_byte(&mut self, addr : u16) -> u8 {
+        let val = self.mem.load_byte(addr);
+        self.obs.mem_read(addr, val);
+        val
+    }
+
+    #[inline]
+    fn store_byte(&mut self, addr : u16, val : u8) {
+        self.mem.store_byte(addr, val);
+        self.obs.mem_write(addr, val);
+    }
+
+    // Words go through whole so the bus underneath keeps its byte order
+    #[inline]
+    fn load_word(&mut self, addr : u16) -> u16 {
+        let val = self.mem.load_word(addr);
+        self.obs.mem_read(addr, (val >> 8) as u8);
+        self.obs.mem_read(addr.wrapping_add(1), val as u8);
+        val
+    }
+
+    #[inline]
+    fn store_word(&mut self, addr : u16, val : u16) {
+        self.mem.store_word(addr, val);
+        self.obs.mem_write(addr, (val >> 8) as u8);
+        self.obs.mem_write(addr.wrapping_add(1), val as u8);
+    }
+
+    fn bus_cycle(&mut self, cycle : u64, addr : u16, access : BusAccess) {
+        self.mem.bus_cycle(cycle, addr, access)
+    }
+
+    fn dummy_cycle(&mut self, addr : u16) {
+        self.mem.dummy_cycle(addr)
+    }
+
+    fn get_name(&self) -> String {
+        self.mem.get_name()
+    }
+}
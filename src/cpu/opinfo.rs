@@ -0,0 +1,124 @@
+// Precomputed opcode tables
+//
+// The op_table macros in isa.rs are run over every opcode at compile time to
+// build flat lookups. Each table is three pages of 256, unprefixed ops then
+// the $10 and $11 pages. The core builds its dispatch tables this way and
+// tools can use the OpInfo tables to see what an opcode is without decoding.
+
+use crate::cpu::{Regs, CpuKind};
+
+pub const OP_PAGES : usize = 3;
+pub const OP_TABLE_SIZE : usize = OP_PAGES * 256;
+
+pub const fn op_to_index(op : u16) -> usize {
+    let page = match op >> 8 {
+        0x10 => 1,
+        0x11 => 2,
+        _ => 0,
+    };
+    page * 256 + (op & 0xff) as usize
+}
+
+pub const fn index_to_op(i : usize) -> u16 {
+    let op = (i & 0xff) as u16;
+    match i >> 8 {
+        1 => 0x1000 | op,
+        2 => 0x1100 | op,
+        _ => op,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddrMode {
+    Direct,
+    Extended,
+    Immediate,
+    Inherent,
+    Indexed,
+    Relative,
+}
+
+// Name is the core's handler name, so it can be something like lsl_asl
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpInfo {
+    pub name : &'static str,
+    pub mode : AddrMode,
+}
+
+macro_rules! handle_op {
+    ($addr:ident, $action:ident) => ({
+        Some(OpInfo { name : stringify!($action), mode : AddrMode::$addr })
+    })
+}
+
+pub static OPS_6809 : [Option<OpInfo>; OP_TABLE_SIZE] =
+    build_ops!(Option<OpInfo>, None, |op| op_table!(op, { None }));
+
+pub static OPS_6809_UNDOC : [Option<OpInfo>; OP_TABLE_SIZE] =
+    build_ops!(Option<OpInfo>, None, |op| op_table!(op, { op_table_undoc!(op, { None }) }));
+
+pub static OPS_6309 : [Option<OpInfo>; OP_TABLE_SIZE] =
+    build_ops!(Option<OpInfo>, None, |op| op_table_6309!(op, { op_table!(op, { None }) }));
+
+pub fn get_op_table(cpu : CpuKind, undoc : bool) -> &'static [Option<OpInfo>; OP_TABLE_SIZE] {
+    match cpu {
+        CpuKind::Hd6309 => &OPS_6309,
+        _ if undoc => &OPS_6809_UNDOC,
+        _ => &OPS_6809,
+    }
+}
+
+// What the cpu configured in regs would do with op, None if it traps
+pub fn get_op_info(regs : &Regs, op : u16) -> Option<OpInfo> {
+    get_op_table(regs.cpu, regs.emulate_undocumented())[op_to_index(op)]
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Datasheet timing
+
+// Cycles for the documented 6809 ops, from utils/ins2.txt checked against
+// 6809cyc.txt. Indexed ops are before the postbyte's extra, stack ops before
+// a cycle per byte moved, long conditional branches if not taken and RTI if
+// it only pulls CC and PC. SYNC and CWAI are the least they take.
+pub fn base_cycles(op : u16) -> Option<u32> {
+    let cycles = match op {
+        0x12 | 0x19 | 0x1d | 0x40 | 0x43 | 0x44 | 0x46 | 0x47 | 0x48
+        | 0x49 | 0x4a | 0x4c | 0x4d | 0x4f | 0x50 | 0x53 | 0x54 | 0x56 | 0x57
+        | 0x58 | 0x59 | 0x5a | 0x5c | 0x5d | 0x5f | 0x80 | 0x81 | 0x82 | 0x84
+        | 0x85 | 0x86 | 0x88 | 0x89 | 0x8a | 0x8b | 0xc0 | 0xc1 | 0xc2 | 0xc4
+        | 0xc5 | 0xc6 | 0xc8 | 0xc9 | 0xca | 0xcb => 2,
+        0x0e | 0x1a | 0x1c | 0x20 | 0x21 | 0x22 | 0x23 | 0x24 | 0x25 | 0x26
+        | 0x27 | 0x28 | 0x29 | 0x2a | 0x2b | 0x2c | 0x2d | 0x2e | 0x2f | 0x3a
+        | 0x6e | 0x7e | 0x8e | 0xcc | 0xce => 3,
+        0x13 | 0x30 | 0x31 | 0x32 | 0x33 | 0x83 | 0x8c | 0x90 | 0x91 | 0x92
+        | 0x94 | 0x95 | 0x96 | 0x97 | 0x98 | 0x99 | 0x9a | 0x9b | 0xa0 | 0xa1
+        | 0xa2 | 0xa4 | 0xa5 | 0xa6 | 0xa7 | 0xa8 | 0xa9 | 0xaa | 0xab | 0xc3
+        | 0xd0 | 0xd1 | 0xd2 | 0xd4 | 0xd5 | 0xd6 | 0xd7 | 0xd8 | 0xd9 | 0xda
+        | 0xdb | 0xe0 | 0xe1 | 0xe2 | 0xe4 | 0xe5 | 0xe6 | 0xe7 | 0xe8 | 0xe9
+        | 0xea | 0xeb | 0x108e | 0x10ce => 4,
+        0x16 | 0x34 | 0x35 | 0x36 | 0x37 | 0x39 | 0x9e | 0x9f | 0xae | 0xaf
+        | 0xb0 | 0xb1 | 0xb2 | 0xb4 | 0xb5 | 0xb6 | 0xb7 | 0xb8 | 0xb9 | 0xba
+        | 0xbb | 0xdc | 0xdd | 0xde | 0xdf | 0xec | 0xed | 0xee | 0xef | 0xf0
+        | 0xf1 | 0xf2 | 0xf4 | 0xf5 | 0xf6 | 0xf7 | 0xf8 | 0xf9 | 0xfa | 0xfb
+        | 0x1021 ..= 0x102f | 0x1083 | 0x108c | 0x1183 | 0x118c => 5,
+        0x00 | 0x03 | 0x04 | 0x06 | 0x07 | 0x08 | 0x09 | 0x0a | 0x0c | 0x0d
+        | 0x0f | 0x1f | 0x3b | 0x60 | 0x63 | 0x64 | 0x66 | 0x67 | 0x68 | 0x69
+        | 0x6a | 0x6c | 0x6d | 0x6f | 0x93 | 0x9c | 0xa3 | 0xac | 0xbe | 0xbf
+        | 0xd3 | 0xe3 | 0xfc | 0xfd | 0xfe | 0xff | 0x109e | 0x109f | 0x10ae
+        | 0x10af | 0x10de | 0x10df | 0x10ee | 0x10ef => 6,
+        0x70 | 0x73 | 0x74 | 0x76 | 0x77 | 0x78 | 0x79 | 0x7a | 0x7c | 0x7d
+        | 0x7f | 0x8d | 0x9d | 0xad | 0xb3 | 0xbc | 0xf3 | 0x1093 | 0x109c
+        | 0x10a3 | 0x10ac | 0x10be | 0x10bf | 0x10fe | 0x10ff | 0x1193 | 0x119c
+        | 0x11a3 | 0x11ac => 7,
+        0x1e | 0xbd | 0x10b3 | 0x10bc | 0x11b3 | 0x11bc => 8,
+        0x17 => 9,
+        0x3d => 11,
+        0x3f => 19,
+        0x3c | 0x103f | 0x113f => 20,
+        _ => return None,
+    };
+
+    Some(cycles)
+}
@@ -1,8 +1,28 @@
-use crate::cpu::Flags;
+use crate::cpu::{Flags, CpuKind, UndocMode, BusMode, MD_NATIVE, MD_FIRQ_IS_IRQ};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RegEnum {
-    A, B, X, Y, U, S, D, DP, CC, PC
+    A, B, X, Y, U, S, D, DP, CC, PC,
+    // 6309 only
+    E, F, W, V, MD, Zero,
+}
+
+impl RegEnum {
+    pub fn is_16_bit(self) -> bool {
+        match self {
+            RegEnum::X | RegEnum::Y | RegEnum::U | RegEnum::S |
+            RegEnum::D | RegEnum::PC | RegEnum::W | RegEnum::V => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_6309_only(self) -> bool {
+        match self {
+            RegEnum::E | RegEnum::F | RegEnum::W |
+            RegEnum::V | RegEnum::MD | RegEnum::Zero => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
@@ -16,6 +36,25 @@ pub struct Regs {
     pub pc: u16,
     pub dp: u8,
     pub flags: Flags,
+
+    // 6309 registers, left at zero on a 6809
+    #[serde(default)]
+    pub e : u8,
+    #[serde(default)]
+    pub f : u8,
+    #[serde(default)]
+    pub v : u16,
+    #[serde(default)]
+    pub md : u8,
+
+    #[serde(skip)]
+    pub cpu : CpuKind,
+
+    #[serde(skip)]
+    pub undoc : UndocMode,
+
+    #[serde(skip)]
+    pub bus : BusMode,
 }
 
 impl Regs {
@@ -32,6 +71,12 @@ impl Regs {
             RegEnum::DP => self.dp = val as u8,
             RegEnum::CC => self.flags = Flags::new(val as u8),
             RegEnum::PC => self.pc = val,
+            RegEnum::E => self.e = val as u8,
+            RegEnum::F => self.f = val as u8,
+            RegEnum::W => self.set_w(val),
+            RegEnum::V => self.v = val,
+            RegEnum::MD => self.md = val as u8,
+            RegEnum::Zero => (),
         } 
     }
 
@@ -47,6 +92,12 @@ impl Regs {
             RegEnum::DP => u16::from(self.dp),
             RegEnum::CC => u16::from(self.flags.bits()),
             RegEnum::PC => self.pc,
+            RegEnum::E => u16::from(self.e),
+            RegEnum::F => u16::from(self.f),
+            RegEnum::W => self.get_w(),
+            RegEnum::V => self.v,
+            RegEnum::MD => u16::from(self.md),
+            RegEnum::Zero => 0,
         } 
     }
 
@@ -86,10 +137,53 @@ impl Regs {
         self.a = (d >> 8) as u8; self.b = d as u8; 
     }
 
+    pub fn get_w(&self) -> u16 {
+        ( u16::from( self.e ) << 8 ) | u16::from(self.f)
+    }
+
+    pub fn set_w(&mut self, w : u16) {
+        self.e = (w >> 8) as u8; self.f = w as u8; 
+    }
+
+    pub fn get_q(&self) -> u32 {
+        ( u32::from( self.get_d() ) << 16 ) | u32::from(self.get_w())
+    }
+
+    pub fn set_q(&mut self, q : u32) {
+        self.set_d((q >> 16) as u16); self.set_w(q as u16);
+    }
+
+    pub fn is_6309(&self) -> bool {
+        self.cpu == CpuKind::Hd6309
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.is_6309() && (self.md & MD_NATIVE) != 0
+    }
+
+    pub fn emulate_undocumented(&self) -> bool {
+        !self.is_6309() && self.undoc == UndocMode::Emulate
+    }
+
+    pub fn is_firq_irq(&self) -> bool {
+        self.is_6309() && (self.md & MD_FIRQ_IS_IRQ) != 0
+    }
+
     pub fn new() -> Regs {
         Regs {
             a : 0, b : 0, x : 0, y : 0, u : 0, s : 0, pc: 0, dp: 0,
             flags: Flags::new(0),
+            e : 0, f : 0, v : 0, md : 0,
+            cpu : CpuKind::Mc6809,
+            undoc : UndocMode::Strict,
+            bus : BusMode::Fast,
+        }
+    }
+
+    pub fn with_cpu(cpu : CpuKind) -> Regs {
+        Regs {
+            cpu,
+            .. Regs::new()
         }
     }
 
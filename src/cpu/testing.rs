@@ -0,0 +1,68 @@
+// A machine for the cpu's tests to run instructions on
+//
+// 64K of RAM full of NOPs with the pc at $1000 and S at $8000, tests
+// upload the code they want and map anything else they need over it.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::mem::{MemBus, MemoryIO, NoDevices};
+use crate::cpu::{Regs, Interrupts, StandardClock, Clock, InstructionDecoder, CpuErr};
+use crate::cpu::{Observer, NullObserver, step_with_observer, step_checked};
+
+pub struct Machine<O : Observer = NullObserver> {
+    pub regs : Regs,
+    pub mem : MemBus<NoDevices>,
+    pub clock : Rc<RefCell<StandardClock>>,
+    pub ints : Interrupts,
+    pub obs : O,
+}
+
+impl Machine {
+    pub fn new(code : &[(u16, &[u8])]) -> Self {
+        Machine::with_observer(code, NullObserver)
+    }
+}
+
+impl<O : Observer> Machine<O> {
+    pub fn with_observer(code : &[(u16, &[u8])], obs : O) -> Self {
+        let mut mem = MemBus::new("test", NoDevices);
+        let ram = mem.add_ram("ram", 0x1_0000);
+        mem.map_mem(0x0000..=0xffff, ram, 0);
+        mem.block_data_mut(ram).fill(0x12);
+
+        for (addr, bytes) in code {
+            mem.upload(*addr, bytes);
+        }
+
+        let mut regs = Regs::new();
+        regs.pc = 0x1000;
+        regs.s = 0x8000;
+
+        Self {
+            regs, mem, obs,
+            clock : Rc::new(RefCell::new(StandardClock::new(1_000_000))),
+            ints : Interrupts::new(),
+        }
+    }
+
+    // Runs n instructions, any error fails the test
+    pub fn step(&mut self, n : usize) {
+        for _ in 0..n {
+            let pc = self.regs.pc;
+            self.try_step().unwrap_or_else(|e| panic!("{:?} at ${:04x}", e, pc));
+        }
+    }
+
+    pub fn try_step(&mut self) -> Result<InstructionDecoder, CpuErr> {
+        step_with_observer(&mut self.regs, &mut self.mem, &self.clock, &mut self.ints, &mut self.obs)
+    }
+
+    pub fn step_checked(&mut self) -> Result<InstructionDecoder, CpuErr> {
+        step_checked(&mut self.regs, &mut self.mem, &self.clock, &mut self.ints, &mut self.obs)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.clock.borrow().get_cycles()
+    }
+}
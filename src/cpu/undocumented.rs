@@ -0,0 +1,30 @@
+// Undocumented MC6809 behaviour
+//
+// Holes in the opcode map mostly decode as a neighbouring instruction,
+// $01 / $02 / $05 / $0B alias NEG / COM / LSR / DEC in every addressing mode.
+// $02 is the odd one, it's NEG with carry clear and COM with it set.
+// $3E stacks everything like an SWI and jumps through the reset vector.
+// $87 / $C7 throw away their immediate byte and set N, clear Z and V.
+// $14 / $15 / $CD lock the cpu up until reset.
+//
+// TFR / EXG between different sized registers put $FF in the top byte of
+// an 8 bit source, undefined register codes read as $FFFF.
+//
+// Strict mode reports all of these as CpuErr::Undocumented instead
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UndocMode {
+    #[default]
+    Strict,
+    Emulate,
+}
+
+impl UndocMode {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "strict" => Some(UndocMode::Strict),
+            "emulate" => Some(UndocMode::Emulate),
+            _ => None,
+        }
+    }
+}
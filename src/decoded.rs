@@ -0,0 +1,867 @@
+// Structured disassembly
+//
+// Disassembler::decode gives one of these instead of text, for tools that
+// want to know what an instruction does without parsing the disassembly:
+// the operand as decoded, how its effective address is worked out, the
+// registers it reads and writes, where control can go next and how long the
+// datasheet says it takes.
+//
+// format renders one as source for lwasm, asm6809 or Motorola's assembler,
+// optionally with the raw bytes in front and the cycles as a comment.
+
+use std::fmt;
+
+use crate::mem::MemoryIO;
+use crate::cpu::{Regs, RegEnum, IndexModes, AddrMode, OpInfo, InstructionDecoder, base_cycles};
+use crate::diss::SymTab;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedOperand {
+    pub postbyte : u8,
+    pub mode : IndexModes,
+    pub indirect : bool,
+    // From the postbyte or the bytes after it
+    pub offset : Option<i16>,
+    // What PC relative and [n] refer to
+    pub addr : Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Operand {
+    #[default]
+    None,
+    Immediate8(u8),
+    Immediate16(u16),
+    Immediate32(u32),
+    // Low byte, DP gives the high
+    Direct(u8),
+    Extended(u16),
+    Relative { offset : i16, target : u16 },
+    Indexed(IndexedOperand),
+    // PSHS, PULS, PSHU and PULU in the order they're written
+    RegList(Vec<RegEnum>),
+    // TFR, EXG and the 6309 register to register ops
+    RegPair(RegEnum, RegEnum),
+    // 6309 AIM, OIM, EIM and TIM, the mask then where it's applied
+    ImmMem(u8, Box<Operand>),
+    // 6309 bit ops, None if the postbyte's register is invalid
+    Bit { reg : Option<RegEnum>, src_bit : u8, dst_bit : u8, addr : u8 },
+    // 6309 TFM, each pointer moves by its step after a byte
+    Tfm { src : RegEnum, dst : RegEnum, src_step : i8, dst_step : i8 },
+}
+
+// How the cpu gets to an operand in memory
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectiveAddr {
+    Absolute(u16),
+    // Low byte, DP is the high
+    DirectPage(u8),
+    // Register plus a constant, PC relative is already Absolute
+    Offset(RegEnum, i16),
+    // Register plus an accumulator, signed, (accumulator, register)
+    Accumulator(RegEnum, RegEnum),
+    // Register, which is then moved on by n
+    PostInc(RegEnum, u16),
+    // Register after it's been moved back by n
+    PreDec(RegEnum, u16),
+    // The word at the inner address
+    Indirect(Box<EffectiveAddr>),
+}
+
+impl EffectiveAddr {
+    // Address for regs as they are before the instruction runs, pointers are
+    // peeked at
+    pub fn resolve<M : MemoryIO>(&self, regs : &Regs, mem : &M) -> u16 {
+        match *self {
+            EffectiveAddr::Absolute(addr) => addr,
+
+            EffectiveAddr::DirectPage(lo) => (u16::from(regs.dp) << 8) | u16::from(lo),
+
+            EffectiveAddr::Offset(r, offset) => regs.get(&r).wrapping_add(offset as u16),
+
+            EffectiveAddr::Accumulator(acc, r) => {
+                let v = regs.get(&acc);
+                let offset = if acc.is_16_bit() { v } else { i16::from(v as u8 as i8) as u16 };
+                regs.get(&r).wrapping_add(offset)
+            },
+
+            EffectiveAddr::PostInc(r, _) => regs.get(&r),
+
+            EffectiveAddr::PreDec(r, n) => regs.get(&r).wrapping_sub(n),
+
+            EffectiveAddr::Indirect(ref inner) => {
+                let addr = inner.resolve(regs, mem);
+                mem.peek_word(addr)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    // Always carries on with the next instruction
+    Next,
+    // Conditional ones can fall through as well
+    Branch { target : u16, conditional : bool },
+    // JMP or a transfer into PC, target if it's known without running it
+    Jump(Option<u16>),
+    // JSR, BSR and LBSR
+    Call(Option<u16>),
+    // RTS, RTI and a pull of PC
+    Return,
+    Swi(u8),
+    // SYNC and CWAI stop until an interrupt, HCF for good
+    Wait,
+}
+
+// Datasheet timing, max is for a long branch that's taken or an RTI of the
+// entire state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycles {
+    pub min : u32,
+    pub max : u32,
+}
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}/{}", self.min, self.max)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub addr : u16,
+    pub next_addr : u16,
+    pub op_code : u16,
+    pub bytes : Vec<u8>,
+    // Handler name from the op tables, can carry alternatives like lsl_asl
+    pub name : &'static str,
+    pub mode : AddrMode,
+    pub operand : Operand,
+    pub ea : Option<EffectiveAddr>,
+    pub reads : Vec<RegEnum>,
+    pub writes : Vec<RegEnum>,
+    pub flow : Flow,
+    // Only known for the documented 6809 ops
+    pub cycles : Option<Cycles>,
+    // DP the disassembler was told about, resolves direct operands
+    pub dp : Option<u8>,
+}
+
+impl Decoded {
+    pub fn new(ins : &InstructionDecoder, bytes : Vec<u8>, info : OpInfo, operand : Operand, dp : Option<u8>) -> Self {
+        let mut ret = Decoded {
+            addr : ins.addr,
+            next_addr : ins.next_addr,
+            op_code : ins.op_code,
+            bytes,
+            name : info.name,
+            mode : info.mode,
+            ea : operand_ea(&operand),
+            operand,
+            reads : vec![],
+            writes : vec![],
+            flow : Flow::Next,
+            cycles : None,
+            dp,
+        };
+
+        ret.flow = ret.get_flow();
+        ret.cycles = ret.get_cycles();
+
+        let (reads, writes) = ret.get_reg_effects();
+        ret.reads = reads;
+        ret.writes = writes;
+
+        ret
+    }
+
+    // First of the handler's alternatives, lsl for lsl_asl
+    pub fn mnemonic(&self) -> &'static str {
+        self.name.split('_').next().unwrap_or(self.name)
+    }
+
+    // The last one, asl for lsl_asl, and the mnemonic if there's only one
+    pub fn alt_mnemonic(&self) -> &'static str {
+        match self.name {
+            "ldq_immediate" => "ldq",
+            _ if self.name.starts_with("tfm") => "tfm",
+            _ => self.name.rsplit('_').next().unwrap_or(self.name),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn is_branch(&self) -> bool {
+        matches!(self.flow, Flow::Branch { .. } | Flow::Jump(_))
+    }
+
+    pub fn is_call(&self) -> bool {
+        matches!(self.flow, Flow::Call(_))
+    }
+
+    pub fn is_return(&self) -> bool {
+        self.flow == Flow::Return
+    }
+
+    pub fn reads_reg(&self, r : RegEnum) -> bool {
+        self.reads.contains(&r)
+    }
+
+    pub fn writes_reg(&self, r : RegEnum) -> bool {
+        self.writes.contains(&r)
+    }
+
+    // The address the operand refers to if it's known without running it
+    pub fn operand_addr(&self) -> Option<u16> {
+        operand_addr(&self.operand, self.dp)
+    }
+
+    // Whether the operand in memory is read and written, LEA only works out
+    // the address and jumps go there rather than reading it
+    pub fn mem_access(&self) -> (bool, bool) {
+        if self.ea.is_none() || self.is_branch() || self.is_call() {
+            return (false, false);
+        }
+
+        let (stem, _) = split_mnemonic(self.mnemonic());
+
+        match stem {
+            "lea" => (false, false),
+            "st" | "clr" => (false, true),
+            "com" | "neg" | "inc" | "dec" | "lsl" | "lsr" | "asr" | "rol" | "ror" | "ngc" => (true, true),
+            "aim" | "oim" | "eim" | "stbt" => (true, true),
+            _ => (true, false),
+        }
+    }
+
+    fn get_flow(&self) -> Flow {
+        let m = self.mnemonic();
+
+        let target = match self.operand {
+            Operand::Indexed(ref ix) if ix.indirect => None,
+            _ => self.operand_addr(),
+        };
+
+        match m {
+            "bra" | "lbra" => Flow::Branch { target : target.unwrap_or(0), conditional : false },
+            "brn" | "lbrn" => Flow::Next,
+            "bsr" | "lbsr" | "jsr" => Flow::Call(target),
+            "jmp" => Flow::Jump(target),
+            "rts" | "rti" => Flow::Return,
+            "swi" => Flow::Swi(1),
+            "swi2" => Flow::Swi(2),
+            "swi3" => Flow::Swi(3),
+            "sync" | "cwai" | "hcf" => Flow::Wait,
+            _ => match self.operand {
+                Operand::Relative { target, .. } => Flow::Branch { target, conditional : true },
+                Operand::RegList(ref regs) if m.starts_with("pul") && regs.contains(&RegEnum::PC) => Flow::Return,
+                Operand::RegPair(_, RegEnum::PC) if m == "tfr" => Flow::Jump(None),
+                Operand::RegPair(a, b) if m == "exg" && (a == RegEnum::PC || b == RegEnum::PC) => Flow::Jump(None),
+                _ => Flow::Next,
+            },
+        }
+    }
+
+    fn get_cycles(&self) -> Option<Cycles> {
+        let base = base_cycles(self.op_code)?;
+
+        let extra = match self.operand {
+            Operand::Indexed(ref ix) => index_cycles(ix.mode, ix.indirect),
+            Operand::RegList(ref regs) => regs.iter().map(|r| if r.is_16_bit() { 2 } else { 1 }).sum(),
+            _ => 0,
+        };
+
+        let min = base + extra;
+
+        let max = match self.op_code {
+            // Long conditional branches take one more if they go
+            0x1022 ..= 0x102f => min + 1,
+            // RTI with E set pulls the lot
+            0x3b => 15,
+            _ => min,
+        };
+
+        Some(Cycles { min, max })
+    }
+
+    // Registers as the programmer sees them, D and W aren't split into
+    // their halves. PC is only there when it's used as data or changed by
+    // something other than moving on to the next instruction.
+    fn get_reg_effects(&self) -> (Vec<RegEnum>, Vec<RegEnum>) {
+        use crate::cpu::RegEnum::*;
+
+        let mut reads = vec![];
+        let mut writes = vec![];
+
+        let m = self.mnemonic();
+        let (stem, suffix) = split_mnemonic(m);
+        let regs = suffix_regs(suffix);
+        let on_reg = !regs.is_empty();
+
+        match stem {
+            "ld" => {
+                writes.extend(&regs);
+                writes.push(CC);
+            },
+
+            "st" => {
+                reads.extend(&regs);
+                writes.push(CC);
+            },
+
+            "add" | "sub" | "and" | "or" | "eor" | "adc" | "sbc" => {
+                if stem == "adc" || stem == "sbc" {
+                    reads.push(CC);
+                }
+
+                if let Operand::RegPair(a, b) = self.operand {
+                    reads.push(a);
+                    reads.push(b);
+                    writes.push(b);
+                } else {
+                    reads.extend(&regs);
+                    writes.extend(&regs);
+                }
+                writes.push(CC);
+            },
+
+            "cmp" | "bit" => {
+                if let Operand::RegPair(a, b) = self.operand {
+                    reads.push(a);
+                    reads.push(b);
+                } else {
+                    reads.extend(&regs);
+                }
+                writes.push(CC);
+            },
+
+            "clr" | "com" | "neg" | "inc" | "dec" | "tst" | "lsl" | "lsr" | "asr" | "rol" | "ror" | "ngc" => {
+                if matches!(stem, "rol" | "ror" | "ngc") {
+                    reads.push(CC);
+                }
+
+                if on_reg {
+                    if stem != "clr" {
+                        reads.extend(&regs);
+                    }
+                    if stem != "tst" {
+                        writes.extend(&regs);
+                    }
+                }
+                writes.push(CC);
+            },
+
+            "lea" => {
+                writes.extend(&regs);
+                // Only LEAX and LEAY set Z
+                if suffix == "x" || suffix == "y" {
+                    writes.push(CC);
+                }
+            },
+
+            _ => self.other_reg_effects(m, &mut reads, &mut writes),
+        }
+
+        reads.extend(ea_regs(self.ea.as_ref()));
+        writes.extend(ea_writes(self.ea.as_ref()));
+
+        if let Operand::Indexed(IndexedOperand { mode : IndexModes::PCAddi8, .. })
+            | Operand::Indexed(IndexedOperand { mode : IndexModes::PCAddi16, .. }) = self.operand {
+            reads.push(PC);
+        }
+
+        if self.is_branch() || self.is_call() || self.is_return() || matches!(self.flow, Flow::Swi(_)) {
+            writes.push(PC);
+        }
+
+        dedup(&mut reads);
+        dedup(&mut writes);
+
+        (reads, writes)
+    }
+
+    fn other_reg_effects(&self, m : &str, reads : &mut Vec<RegEnum>, writes : &mut Vec<RegEnum>) {
+        use crate::cpu::RegEnum::*;
+
+        // Everything an interrupt or SWI stacks
+        let entire = [CC, A, B, DP, X, Y, U, PC];
+
+        match m {
+            "abx" => { reads.extend(&[B, X]); writes.push(X) },
+            "mul" => { reads.extend(&[A, B]); writes.extend(&[D, CC]) },
+            "sex" => { reads.push(B); writes.extend(&[A, CC]) },
+            "sexw" => { reads.push(W); writes.extend(&[D, CC]) },
+            "daa" => { reads.extend(&[A, CC]); writes.extend(&[A, CC]) },
+            "andcc" | "orcc" => { reads.push(CC); writes.push(CC) },
+
+            "tfr" => if let Operand::RegPair(a, b) = self.operand {
+                reads.push(a);
+                writes.push(b);
+            },
+
+            "exg" => if let Operand::RegPair(a, b) = self.operand {
+                reads.extend(&[a, b]);
+                writes.extend(&[a, b]);
+            },
+
+            "pshs" | "pshu" | "puls" | "pulu" | "pshsw" | "pshuw" | "pulsw" | "puluw" => {
+                let stack = if m.starts_with("pshs") || m.starts_with("puls") { S } else { U };
+
+                let regs = match self.operand {
+                    Operand::RegList(ref regs) => regs.clone(),
+                    _ => vec![W],
+                };
+
+                reads.push(stack);
+                writes.push(stack);
+
+                if m.starts_with("psh") {
+                    reads.extend(regs);
+                } else {
+                    writes.extend(regs);
+                }
+            },
+
+            "bsr" | "lbsr" | "jsr" => { reads.extend(&[S, PC]); writes.push(S) },
+            "rts" => { reads.push(S); writes.push(S) },
+            "rti" => { reads.push(S); writes.push(S); writes.extend(&entire) },
+
+            "swi" | "swi2" | "swi3" => {
+                reads.push(S);
+                reads.extend(&entire);
+                writes.extend(&[S, CC]);
+            },
+
+            "cwai" => {
+                reads.push(S);
+                reads.extend(&entire);
+                writes.extend(&[S, CC]);
+            },
+
+            // Branches look at the flags unless they always or never go
+            _ if self.mode == AddrMode::Relative && !matches!(m, "bra" | "lbra" | "brn" | "lbrn") => {
+                reads.push(CC)
+            },
+
+            // 6309
+            "aim" | "oim" | "eim" | "tim" => writes.push(CC),
+            "divd" => { reads.push(D); writes.extend(&[D, CC]) },
+            "divq" => { reads.extend(&[D, W]); writes.extend(&[D, W, CC]) },
+            "muld" => { reads.push(D); writes.extend(&[D, W, CC]) },
+            "bitmd" => { reads.push(MD); writes.extend(&[MD, CC]) },
+            "ldmd" => writes.push(MD),
+
+            "band" | "biand" | "bor" | "bior" | "beor" | "bieor" | "ldbt" | "stbt" => {
+                if let Operand::Bit { reg : Some(r), .. } = self.operand {
+                    if m != "ldbt" {
+                        reads.push(r);
+                    }
+                    if m != "stbt" {
+                        writes.push(r);
+                    }
+                }
+            },
+
+            "tfm" => if let Operand::Tfm { src, dst, .. } = self.operand {
+                reads.extend(&[src, dst, W]);
+                writes.extend(&[src, dst, W]);
+            },
+
+            _ => (),
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Text
+
+    pub fn format(&self, opts : &FormatOptions, syms : Option<&dyn SymTab>) -> String {
+        let syntax = opts.syntax;
+
+        let mnemonic = match syntax {
+            Syntax::Motorola => self.alt_mnemonic().to_uppercase(),
+            _ => self.mnemonic().to_string(),
+        };
+
+        let operand = self.format_operand(&self.operand, syntax, syms);
+
+        let mut text = if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{:<5} {}", mnemonic, operand)
+        };
+
+        if opts.bytes {
+            let bytes : Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            text = format!("{:<15}{}", bytes.join(" "), text);
+        }
+
+        if opts.cycles {
+            let cycles = self.cycles.map(|c| c.to_string()).unwrap_or_else(|| "?".to_string());
+            text = format!("{:<29} ; ~{}", text, cycles);
+        }
+
+        text
+    }
+
+    fn format_operand(&self, operand : &Operand, syntax : Syntax, syms : Option<&dyn SymTab>) -> String {
+        let reg = |r : RegEnum| syntax.reg(r);
+
+        // Symbol for addr or the hex
+        let addr_text = |addr : u16, near : bool| {
+            let sym = syms.and_then(|s| if near { s.get_near_symbol(addr) } else { s.get_constant(addr) });
+            sym.unwrap_or_else(|| syntax.hex(addr, 4))
+        };
+
+        let reg_list = |regs : &[RegEnum]| regs.iter().map(|r| reg(*r)).collect::<Vec<_>>().join(",");
+
+        match *operand {
+            Operand::None => String::new(),
+
+            Operand::Immediate8(v) => format!("#{}", syntax.hex(u16::from(v), 2)),
+
+            Operand::Immediate16(v) => format!("#{}", addr_text(v, false)),
+
+            Operand::Immediate32(v) => format!("#{}", syntax.hex32(v)),
+
+            Operand::Direct(lo) => match self.dp {
+                Some(dp) => format!("<{}", addr_text((u16::from(dp) << 8) | u16::from(lo), true)),
+                None => format!("<{}", syntax.hex(u16::from(lo), 2)),
+            },
+
+            Operand::Extended(addr) => format!(">{}", addr_text(addr, true)),
+
+            Operand::Relative { target, .. } => addr_text(target, true),
+
+            Operand::Indexed(ref ix) => {
+                let text = self.format_indexed(ix, syntax, &addr_text);
+
+                if ix.indirect {
+                    format!("[{}]", text)
+                } else {
+                    text
+                }
+            },
+
+            Operand::RegList(ref regs) => reg_list(regs),
+
+            Operand::RegPair(a, b) => reg_list(&[a, b]),
+
+            Operand::ImmMem(imm, ref inner) => {
+                format!("#{},{}", syntax.hex(u16::from(imm), 2), self.format_operand(inner, syntax, syms))
+            },
+
+            Operand::Bit { reg : r, src_bit, dst_bit, addr } => {
+                let r = r.map(reg).unwrap_or_else(|| "?".to_string());
+                let direct = self.format_operand(&Operand::Direct(addr), syntax, syms);
+                format!("{},{},{},{}", r, src_bit, dst_bit, direct)
+            },
+
+            Operand::Tfm { src, dst, src_step, dst_step } => {
+                format!("{}{},{}{}", reg(src), tfm_suffix(src_step), reg(dst), tfm_suffix(dst_step))
+            },
+        }
+    }
+
+    fn format_indexed(&self, ix : &IndexedOperand, syntax : Syntax, addr_text : &dyn Fn(u16, bool) -> String) -> String {
+        let reg = |r : RegEnum| syntax.reg(r);
+        let offset = ix.offset.unwrap_or(0);
+
+        match ix.mode {
+            IndexModes::RPlus(r) => format!(",{}+", reg(r)),
+            IndexModes::RPlusPlus(r) => format!(",{}++", reg(r)),
+            IndexModes::RSub(r) => format!(",-{}", reg(r)),
+            IndexModes::RSubSub(r) => format!(",--{}", reg(r)),
+            IndexModes::RZero(r) => format!(",{}", reg(r)),
+            IndexModes::RAddA(r) => format!("{},{}", reg(RegEnum::A), reg(r)),
+            IndexModes::RAddB(r) => format!("{},{}", reg(RegEnum::B), reg(r)),
+            IndexModes::RAddD(r) => format!("{},{}", reg(RegEnum::D), reg(r)),
+            IndexModes::RAddE(r) => format!("{},{}", reg(RegEnum::E), reg(r)),
+            IndexModes::RAddF(r) => format!("{},{}", reg(RegEnum::F), reg(r)),
+            IndexModes::RAddW(r) => format!("{},{}", reg(RegEnum::W), reg(r)),
+            IndexModes::ROff(r, _) => format!("{}{},{}", syntax.force(5), offset, reg(r)),
+            IndexModes::RAddi8(r) => format!("{}{},{}", syntax.force(8), offset, reg(r)),
+            IndexModes::RAddi16(r) => format!("{}{},{}", syntax.force(16), offset, reg(r)),
+            IndexModes::WZero => format!(",{}", reg(RegEnum::W)),
+            IndexModes::WAddi16 => format!("{},{}", offset, reg(RegEnum::W)),
+            IndexModes::WPlusPlus => format!(",{}++", reg(RegEnum::W)),
+            IndexModes::WSubSub => format!(",--{}", reg(RegEnum::W)),
+
+            IndexModes::PCAddi8 | IndexModes::PCAddi16 => {
+                let bits = if ix.mode == IndexModes::PCAddi8 { 8 } else { 16 };
+                let target = addr_text(ix.addr.unwrap_or(0), true);
+                format!("{}{},{}", syntax.force(bits), target, syntax.pcr())
+            },
+
+            IndexModes::Ea => addr_text(ix.addr.unwrap_or(0), true),
+
+            IndexModes::Illegal => "?".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(&FormatOptions::default(), None))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Formatting options
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Syntax {
+    // Lower case mnemonics, sizes forced so it assembles back to the same bytes
+    #[default]
+    Lwasm,
+    // All lower case, 5 bit offsets forced with <<
+    Asm6809,
+    // Upper case with Motorola's preferred mnemonics, BCC rather than BHS
+    Motorola,
+}
+
+impl Syntax {
+    fn reg(self, r : RegEnum) -> String {
+        let text = match r {
+            RegEnum::Zero => "0".to_string(),
+            _ => format!("{:?}", r),
+        };
+
+        match self {
+            Syntax::Asm6809 => text.to_lowercase(),
+            _ => text,
+        }
+    }
+
+    fn hex(self, v : u16, digits : usize) -> String {
+        match self {
+            Syntax::Asm6809 => format!("${:0width$x}", v, width = digits),
+            _ => format!("${:0width$X}", v, width = digits),
+        }
+    }
+
+    fn hex32(self, v : u32) -> String {
+        match self {
+            Syntax::Asm6809 => format!("${:08x}", v),
+            _ => format!("${:08X}", v),
+        }
+    }
+
+    fn pcr(self) -> &'static str {
+        match self {
+            Syntax::Asm6809 => "pcr",
+            _ => "PCR",
+        }
+    }
+
+    // Prefix that stops the assembler picking a shorter indexed offset
+    fn force(self, bits : usize) -> &'static str {
+        match (self, bits) {
+            (Syntax::Motorola, _) => "",
+            (Syntax::Asm6809, 5) => "<<",
+            (_, 5) => "",
+            (_, 8) => "<",
+            _ => ">",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FormatOptions {
+    pub syntax : Syntax,
+    // Raw bytes in front of the instruction
+    pub bytes : bool,
+    // Datasheet cycles as a comment
+    pub cycles : bool,
+}
+
+impl FormatOptions {
+    pub fn new(syntax : Syntax) -> Self {
+        FormatOptions {
+            syntax,
+            .. Default::default()
+        }
+    }
+
+    pub fn with_bytes(self) -> Self {
+        FormatOptions { bytes : true, .. self }
+    }
+
+    pub fn with_cycles(self) -> Self {
+        FormatOptions { cycles : true, .. self }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn operand_addr(operand : &Operand, dp : Option<u8>) -> Option<u16> {
+    let direct = |lo : u8| dp.map(|dp| (u16::from(dp) << 8) | u16::from(lo));
+
+    match *operand {
+        Operand::Direct(lo) => direct(lo),
+        Operand::Bit { addr, .. } => direct(addr),
+        Operand::Extended(addr) => Some(addr),
+        Operand::Relative { target, .. } => Some(target),
+        Operand::Indexed(ref ix) => ix.addr,
+        Operand::ImmMem(_, ref inner) => operand_addr(inner, dp),
+        _ => None,
+    }
+}
+
+fn operand_ea(operand : &Operand) -> Option<EffectiveAddr> {
+    use self::EffectiveAddr::*;
+
+    match *operand {
+        Operand::Direct(lo) => Some(DirectPage(lo)),
+        Operand::Bit { addr, .. } => Some(DirectPage(addr)),
+        Operand::Extended(addr) => Some(Absolute(addr)),
+        Operand::ImmMem(_, ref inner) => operand_ea(inner),
+
+        Operand::Indexed(ref ix) => {
+            let offset = ix.offset.unwrap_or(0);
+
+            let ea = match ix.mode {
+                IndexModes::ROff(r, _) | IndexModes::RAddi8(r) | IndexModes::RAddi16(r) => Offset(r, offset),
+                IndexModes::RZero(r) => Offset(r, 0),
+                IndexModes::RPlus(r) => PostInc(r, 1),
+                IndexModes::RPlusPlus(r) => PostInc(r, 2),
+                IndexModes::RSub(r) => PreDec(r, 1),
+                IndexModes::RSubSub(r) => PreDec(r, 2),
+                IndexModes::RAddA(r) => Accumulator(RegEnum::A, r),
+                IndexModes::RAddB(r) => Accumulator(RegEnum::B, r),
+                IndexModes::RAddD(r) => Accumulator(RegEnum::D, r),
+                IndexModes::RAddE(r) => Accumulator(RegEnum::E, r),
+                IndexModes::RAddF(r) => Accumulator(RegEnum::F, r),
+                IndexModes::RAddW(r) => Accumulator(RegEnum::W, r),
+                IndexModes::WZero => Offset(RegEnum::W, 0),
+                IndexModes::WAddi16 => Offset(RegEnum::W, offset),
+                IndexModes::WPlusPlus => PostInc(RegEnum::W, 2),
+                IndexModes::WSubSub => PreDec(RegEnum::W, 2),
+                IndexModes::PCAddi8 | IndexModes::PCAddi16 | IndexModes::Ea => Absolute(ix.addr?),
+                IndexModes::Illegal => return None,
+            };
+
+            if ix.indirect {
+                Some(Indirect(Box::new(ea)))
+            } else {
+                Some(ea)
+            }
+        },
+
+        _ => None,
+    }
+}
+
+fn ea_regs(ea : Option<&EffectiveAddr>) -> Vec<RegEnum> {
+    use self::EffectiveAddr::*;
+
+    match ea {
+        Some(DirectPage(_)) => vec![RegEnum::DP],
+        Some(Offset(r, _)) | Some(PostInc(r, _)) | Some(PreDec(r, _)) => vec![*r],
+        Some(Accumulator(acc, r)) => vec![*acc, *r],
+        Some(Indirect(inner)) => ea_regs(Some(inner)),
+        _ => vec![],
+    }
+}
+
+fn ea_writes(ea : Option<&EffectiveAddr>) -> Vec<RegEnum> {
+    use self::EffectiveAddr::*;
+
+    match ea {
+        Some(PostInc(r, _)) | Some(PreDec(r, _)) => vec![*r],
+        Some(Indirect(inner)) => ea_writes(Some(inner)),
+        _ => vec![],
+    }
+}
+
+// Instruction families share a stem, the rest says which register
+const STEMS : [&str; 24] = [
+    "adc", "add", "and", "bit", "clr", "cmp", "com", "dec", "eor", "inc",
+    "lea", "lsl", "lsr", "asr", "neg", "ngc", "rol", "ror", "sbc", "sub",
+    "tst", "ld", "st", "or",
+];
+
+fn split_mnemonic(m : &str) -> (&str, &str) {
+    // These would otherwise look like a stem and a register
+    if matches!(m, "andcc" | "orcc" | "bitmd" | "ldmd" | "ldbt" | "stbt") {
+        return (m, "");
+    }
+
+    for stem in STEMS.iter() {
+        if let Some(suffix) = m.strip_prefix(stem) {
+            if suffix.is_empty() || !suffix_regs(suffix).is_empty() || suffix == "r" {
+                return (stem, suffix);
+            }
+        }
+    }
+
+    (m, "")
+}
+
+fn suffix_regs(suffix : &str) -> Vec<RegEnum> {
+    use crate::cpu::RegEnum::*;
+
+    match suffix {
+        "a" => vec![A],
+        "b" => vec![B],
+        "d" => vec![D],
+        "e" => vec![E],
+        "f" => vec![F],
+        "w" => vec![W],
+        "q" => vec![D, W],
+        "x" => vec![X],
+        "y" => vec![Y],
+        "u" => vec![U],
+        "s" => vec![S],
+        _ => vec![],
+    }
+}
+
+// Extra cycles for an indexed postbyte, the ~ column of the datasheet
+fn index_cycles(mode : IndexModes, indirect : bool) -> u32 {
+    let cycles = match mode {
+        IndexModes::RZero(_) | IndexModes::WZero => 0,
+        IndexModes::ROff(..) | IndexModes::RAddA(_) | IndexModes::RAddB(_)
+            | IndexModes::RAddE(_) | IndexModes::RAddF(_) | IndexModes::RAddi8(_)
+            | IndexModes::PCAddi8 | IndexModes::WPlusPlus | IndexModes::WSubSub => 1,
+        IndexModes::RPlus(_) | IndexModes::RSub(_) | IndexModes::WAddi16 => 2,
+        IndexModes::RPlusPlus(_) | IndexModes::RSubSub(_) => 3,
+        IndexModes::RAddi16(_) | IndexModes::RAddD(_) | IndexModes::RAddW(_) => 4,
+        IndexModes::PCAddi16 => 5,
+        // Always indirect, 5 in all
+        IndexModes::Ea => return 5,
+        IndexModes::Illegal => 0,
+    };
+
+    if indirect { cycles + 3 } else { cycles }
+}
+
+fn tfm_suffix(step : i8) -> &'static str {
+    match step {
+        1 => "+",
+        -1 => "-",
+        _ => "",
+    }
+}
+
+fn dedup(regs : &mut Vec<RegEnum>) {
+    let mut seen = vec![];
+    regs.retain(|r| {
+        if seen.contains(r) {
+            false
+        } else {
+            seen.push(*r);
+            true
+        }
+    });
+}
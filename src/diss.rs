@@ -1,6 +1,8 @@
-use crate::mem::MemoryIO;
+use crate::mem::{MemoryIO, PeekOnly};
 
-use crate::cpu::{RegEnum, IndexedFlags, IndexModes, InstructionDecoder, get_tfr_regs};
+use crate::cpu::{RegEnum, IndexedFlags, IndexModes, InstructionDecoder, CpuKind, get_tfr_regs};
+use crate::cpu::{get_op_table, op_to_index};
+use crate::decoded::{Decoded, Operand, IndexedOperand};
 
 pub trait SymTab {
     fn get_symbol(&self, val : u16) -> Option<String>;
@@ -11,16 +13,47 @@ pub trait SymTab {
             None => def.to_string()
         }
     }
-}
 
+    // An exact match or something close enough to show as symbol+offset
+    fn get_near_symbol(&self, val : u16) -> Option<String> {
+        self.get_symbol(val)
+    }
 
+    // An immediate's symbol, tables with equates can prefer those
+    fn get_constant(&self, val : u16) -> Option<String> {
+        self.get_symbol(val)
+    }
+
+    // Label line for an address that carries a symbol
+    fn get_label(&self, val : u16) -> Option<String> {
+        self.get_symbol(val).map(|text| format!("{}:", text))
+    }
+}
+
+// An operand that could be shown as a symbol, the address it refers to and
+// the text it was given without one. Immediates are as likely to be
+// constants as addresses so they only get exact matches.
+#[derive(Debug, Clone)]
+struct SymRef {
+    addr : u16,
+    text : String,
+    near : bool,
+}
 
 #[derive(Default)]
 pub struct Disassembler {
     text : String,
     is_upper_case : bool,
     hex_prefix: String,
-
+    cpu : CpuKind,
+    // Needed to resolve direct addresses
+    dp : Option<u8>,
+    sym_ref : Option<SymRef>,
+    // Source lwasm gives the same bytes back for
+    lwasm : bool,
+    exact : bool,
+    // The last instruction's operand for decode
+    operand : Operand,
 }
 
 impl Disassembler {
@@ -33,7 +66,46 @@ impl Disassembler {
         }
     }
 
+    pub fn with_cpu(cpu : CpuKind) -> Self {
+        Disassembler {
+            cpu,
+            .. Self::new()
+        }
+    }
+
+    // Output for lwasm, sizes are forced so it can't pick different
+    // encodings and branches are to addresses rather than offsets
+    pub fn for_lwasm() -> Self {
+        Disassembler {
+            lwasm : true,
+            .. Self::new()
+        }
+    }
+
+    // False if the last instruction has an encoding lwasm wouldn't produce
+    // from its text, only checked for the 6809
+    pub fn reassembles(&self) -> bool {
+        self.exact
+    }
+
+    // Address the last instruction's operand refers to, if it has one
+    pub fn operand_addr(&self) -> Option<u16> {
+        self.sym_ref.as_ref().map(|r| r.addr)
+    }
+
+    // Direct operands only get symbols once DP is known
+    pub fn set_dp(&mut self, dp : u8) {
+        self.dp = Some(dp)
+    }
+
     fn add_op<M: MemoryIO>(&mut self, _m : &M, _diss: &mut InstructionDecoder, txt : &'static str) {
+        // Handler names like lsl_asl carry the alternatives
+        let txt = if self.lwasm {
+            txt.split('_').next().unwrap_or(txt)
+        } else {
+            txt
+        };
+
         self.text = format!("{:width$} {}", txt, self.text, width = 5);
     }
 
@@ -54,17 +126,54 @@ impl Disassembler {
 
         let v = diss.fetch_byte(mem);
         let def_str  = format!("${:02X}", v);
+        self.operand = Operand::Immediate8(v);
         self.expand(u16::from(v), &def_str, text, mem, diss)
     }
 
     fn text_from_word_op<M : MemoryIO>(&mut self, text : &'static str, mem: &mut M, diss : &mut InstructionDecoder) { 
         let v = diss.fetch_word(mem);
         let def_str  = format!("${:04X}", v);
+        let near = !text.starts_with('#');
+        self.sym_ref = Some(SymRef { addr : v, text : def_str.clone(), near });
+        self.operand = if near { Operand::Extended(v) } else { Operand::Immediate16(v) };
         self.expand(v, &def_str, text, mem, diss)
     }
+
+    fn text_from_direct_op<M : MemoryIO>(&mut self, mem: &mut M, diss : &mut InstructionDecoder) { 
+        let v = diss.fetch_byte(mem);
+        let def_str  = format!("${:02X}", v);
+
+        if let Some(dp) = self.dp {
+            self.add_sym_ref((u16::from(dp) << 8) | u16::from(v), &def_str);
+        }
+
+        self.operand = Operand::Direct(v);
+
+        self.expand(u16::from(v), &def_str, "<OP", mem, diss)
+    }
+
+    fn add_sym_ref(&mut self, addr : u16, text : &str) {
+        self.sym_ref = Some(SymRef { addr, text : text.to_string(), near : true })
+    }
+
+    // Swap the operand for its symbol, it's always the last thing in the text
+    fn apply_syms(&mut self, syms : &dyn SymTab) {
+        if let Some(SymRef { addr, ref text, near }) = self.sym_ref {
+            let sym = if near {
+                syms.get_near_symbol(addr)
+            } else {
+                syms.get_constant(addr)
+            };
+
+            if let (Some(sym), Some(pos)) = (sym, self.text.rfind(text)) {
+                self.text.replace_range(pos .. pos + text.len(), &sym);
+            }
+        }
+    }
 }
 
-fn stack_regs(op : u8 ) -> Vec<RegEnum>{
+// other is the stack pointer that isn't being pushed to or pulled from
+fn stack_regs(op : u8, other : RegEnum) -> Vec<RegEnum>{
 
     let mut res = Vec::new();
 
@@ -73,7 +182,7 @@ fn stack_regs(op : u8 ) -> Vec<RegEnum>{
     }
 
     if ( op & 0x40 ) == 0x40  {
-        res.push(RegEnum::S)
+        res.push(other)
     }
 
     if ( op & 0x20 ) == 0x20  {
@@ -104,27 +213,83 @@ fn stack_regs(op : u8 ) -> Vec<RegEnum>{
 }
 
 
+fn s_stack_regs(op : u8) -> Vec<RegEnum> {
+    stack_regs(op, RegEnum::U)
+}
+
+fn u_stack_regs(op : u8) -> Vec<RegEnum> {
+    stack_regs(op, RegEnum::S)
+}
+
 fn tfr_regs(op : u8) -> Vec<RegEnum> {
     let (a,b) = get_tfr_regs(op);
     vec![a,b]
 }
 
+// Register pairs the 6809 has, same sized
+fn is_6809_tfr(op : u8) -> bool {
+    let is_valid = |r : u8| r <= 5 || (8 ..= 11).contains(&r);
+    let (a, b) = (op >> 4, op & 0xf);
+    is_valid(a) && is_valid(b) && (a >= 8) == (b >= 8)
+}
+
+fn index_reg_bits(r : &RegEnum) -> u8 {
+    match *r {
+        RegEnum::X => 0x00,
+        RegEnum::Y => 0x20,
+        RegEnum::U => 0x40,
+        _ => 0x60,
+    }
+}
+
+// The postbyte an assembler would produce for a 6809 indexed mode
+fn canonical_postbyte(mode : &IndexModes, indirect : bool) -> Option<u8> {
+    let ind = if indirect { 0x10 } else { 0 };
+
+    match *mode {
+        IndexModes::RPlus(ref r) if !indirect => Some(0x80 | index_reg_bits(r)),
+        IndexModes::RPlusPlus(ref r) => Some(0x81 | index_reg_bits(r) | ind),
+        IndexModes::RSub(ref r) if !indirect => Some(0x82 | index_reg_bits(r)),
+        IndexModes::RSubSub(ref r) => Some(0x83 | index_reg_bits(r) | ind),
+        IndexModes::RZero(ref r) => Some(0x84 | index_reg_bits(r) | ind),
+        IndexModes::RAddB(ref r) => Some(0x85 | index_reg_bits(r) | ind),
+        IndexModes::RAddA(ref r) => Some(0x86 | index_reg_bits(r) | ind),
+        IndexModes::RAddi8(ref r) => Some(0x88 | index_reg_bits(r) | ind),
+        IndexModes::RAddi16(ref r) => Some(0x89 | index_reg_bits(r) | ind),
+        IndexModes::RAddD(ref r) => Some(0x8b | index_reg_bits(r) | ind),
+        IndexModes::PCAddi8 => Some(0x8c | ind),
+        IndexModes::PCAddi16 => Some(0x8d | ind),
+        IndexModes::Ea => Some(0x9f),
+        // A zero offset assembles as ,R
+        IndexModes::ROff(ref r, offset) if offset != 0 && !indirect => Some(index_reg_bits(r) | (offset as u8 & 0x1f)),
+        _ => None,
+    }
+}
+
 fn regs_to_str(byte : u8, f : fn(u8) -> Vec<RegEnum>) ->  String {
 
     let regs : Vec<String> = f(byte)
         .into_iter()
-        .map(|x| format!("{:?}", x))
+        .map(|x| match x {
+            RegEnum::Zero => "0".to_string(),
+            _ => format!("{:?}", x),
+        })
         .collect();
 
     regs.join(",")
 } 
 
 impl Disassembler {
-    fn direct_8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_byte_op("<OP", mem,diss) }
-    fn direct_16<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_byte_op("<OP", mem,diss) }
+    fn direct_8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_direct_op(mem,diss) }
+    fn direct_16<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_direct_op(mem,diss) }
 
-    fn extended_16<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_word_op("OP", mem, diss) }
-    fn extended_8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_word_op("OP", mem, diss) }
+    fn extended_16<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.extended(mem, diss) }
+    fn extended_8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.extended(mem, diss) }
+
+    fn extended<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let text = if self.lwasm { ">OP" } else { "OP" };
+        self.text_from_word_op(text, mem, diss)
+    }
 
     fn immediate8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { self.text_from_byte_op("#OP", mem, diss) }
 
@@ -134,11 +299,27 @@ impl Disassembler {
 
     fn inherent_reg_stack<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { 
         let byte = diss.fetch_byte(mem);
-        self.text = regs_to_str(byte,stack_regs);
+
+        // pshu / pulu are $36 / $37
+        let f = if diss.op_code & 2 == 0 { s_stack_regs } else { u_stack_regs };
+
+        if byte == 0 {
+            self.exact = false;
+        }
+
+        self.operand = Operand::RegList(f(byte));
+        self.text = regs_to_str(byte,f);
     }
 
     fn inherent_reg_reg<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) { 
         let byte = diss.fetch_byte(mem);
+
+        if self.cpu != CpuKind::Hd6309 && !is_6809_tfr(byte) {
+            self.exact = false;
+        }
+
+        let (a, b) = get_tfr_regs(byte);
+        self.operand = Operand::RegPair(a, b);
         self.text = regs_to_str(byte,tfr_regs)
     }
 
@@ -152,9 +333,19 @@ impl Disassembler {
 
     fn indexed<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
 
-        let iflags = IndexedFlags::new(diss.fetch_byte(mem));
+        let postbyte = diss.fetch_byte(mem);
+        let iflags = IndexedFlags::new(postbyte);
 
-        let index_type = iflags.get_index_type();
+        let is_6309 = self.cpu == CpuKind::Hd6309;
+
+        let index_type = if is_6309 {
+            iflags.get_index_type_6309()
+        } else {
+            iflags.get_index_type()
+        };
+
+        let mut offset = None;
+        let mut addr = None;
 
         let mut s = match index_type {
 
@@ -187,11 +378,15 @@ impl Disassembler {
             },
 
             IndexModes::RAddi8(r) => {
-                format!("{},{:?}",diss.fetch_byte(mem) as i8, r)
+                let v = diss.fetch_byte(mem) as i8;
+                offset = Some(i16::from(v));
+                format!("{}{},{:?}", self.force(8), v, r)
             },
 
             IndexModes::RAddi16(r) => {
-                format!("{},{:?}",diss.fetch_word(mem) as i16, r)
+                let v = diss.fetch_word(mem) as i16;
+                offset = Some(v);
+                format!("{}{},{:?}", self.force(16), v, r)
             },
 
             IndexModes::RAddD(r) => {
@@ -199,11 +394,17 @@ impl Disassembler {
             },
 
             IndexModes::PCAddi8 => {
-                format!("PC,{:?}",diss.fetch_byte(mem) as i8)
+                let v = i16::from(diss.fetch_byte(mem) as i8);
+                offset = Some(v);
+                addr = Some(diss.next_addr.wrapping_add(v as u16));
+                self.pc_relative(v, 8, diss)
             },
 
             IndexModes::PCAddi16 => {
-                format!("PC,{:?}",diss.fetch_word(mem) as i16)
+                let v = diss.fetch_word(mem) as i16;
+                offset = Some(v);
+                addr = Some(diss.next_addr.wrapping_add(v as u16));
+                self.pc_relative(v, 16, diss)
             },
 
             IndexModes::Illegal => { 
@@ -211,30 +412,205 @@ impl Disassembler {
             },
 
             IndexModes::Ea=> {
-                format!("${:04X}", diss.fetch_word(mem))
+                let v = diss.fetch_word(mem);
+                let def_str = format!("${:04X}", v);
+                self.add_sym_ref(v, &def_str);
+                addr = Some(v);
+                def_str
+            },
+
+            IndexModes::ROff(r,v)=> {
+                offset = Some(v as i16);
+                format!("{},{:?}", v as i16, r) 
+            },
+
+            IndexModes::RAddE(r) => {
+                format!("E,{:?}", r)
+            },
+
+            IndexModes::RAddF(r) => {
+                format!("F,{:?}", r)
+            },
+
+            IndexModes::RAddW(r) => {
+                format!("W,{:?}", r)
+            },
+
+            IndexModes::WZero => {
+                ",W".to_string()
+            },
+
+            IndexModes::WAddi16 => {
+                let v = diss.fetch_word(mem) as i16;
+                offset = Some(v);
+                format!("{},W",v)
+            },
+
+            IndexModes::WPlusPlus => {
+                ",W++".to_string()
             },
 
-            IndexModes::ROff(r,offset)=> {
-                format!("{}, {:?}", offset as i16, r) 
+            IndexModes::WSubSub => {
+                ",--W".to_string()
             },
         };
 
-        if iflags.is_indirect() {
+        let is_indirect = if is_6309 {
+            iflags.is_indirect_6309()
+        } else {
+            iflags.is_indirect()
+        };
+
+        if !is_6309 && canonical_postbyte(&index_type, is_indirect) != Some(postbyte) {
+            self.exact = false;
+        }
+
+        if is_indirect {
             s = format!("[{}]", s);
         }
 
+        self.operand = Operand::Indexed(IndexedOperand {
+            postbyte,
+            mode : index_type,
+            indirect : is_indirect,
+            offset,
+            addr,
+        });
+
         self.text = s;
     }
 
+    // Size prefix that stops lwasm picking a shorter offset
+    fn force(&self, bits : usize) -> &'static str {
+        match (self.lwasm, bits) {
+            (false, _) => "",
+            (true, 8) => "<",
+            (true, _) => ">",
+        }
+    }
+
+    // lwasm wants the address for PCR, the offset is from the end of the
+    // instruction which is where the decoder is now
+    fn pc_relative(&mut self, v : i16, bits : usize, diss : &InstructionDecoder) -> String {
+        if self.lwasm {
+            let dest = diss.next_addr.wrapping_add(v as u16);
+            let def_str = format!("${:04X}", dest);
+            self.add_sym_ref(dest, &def_str);
+            format!("{}{},PCR", self.force(bits), def_str)
+        } else {
+            format!("{},PC", v)
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // 6309 only
+
+    // Put the mask in front of the memory operand that follows it
+    fn imm_prefix(&mut self, imm : u8) {
+        let operand = std::mem::take(&mut self.operand);
+        self.operand = Operand::ImmMem(imm, Box::new(operand));
+        self.text = format!("#${:02X},{}", imm, self.text);
+    }
+
+    fn imm_direct<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let imm = diss.fetch_byte(mem);
+        self.direct_8(mem, diss);
+        self.imm_prefix(imm);
+    }
+
+    fn imm_indexed<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let imm = diss.fetch_byte(mem);
+        self.indexed(mem, diss);
+        self.imm_prefix(imm);
+    }
+
+    fn imm_extended<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let imm = diss.fetch_byte(mem);
+        self.extended_8(mem, diss);
+        self.imm_prefix(imm);
+    }
+
+    fn immediate32<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let hi = diss.fetch_word(mem);
+        let lo = diss.fetch_word(mem);
+        self.operand = Operand::Immediate32((u32::from(hi) << 16) | u32::from(lo));
+        self.text = format!("#${:04X}{:04X}", hi, lo);
+    }
+
+    // reg,src bit,dst bit,<addr
+    fn bit_direct<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        let post = diss.fetch_byte(mem);
+
+        let reg = match post >> 6 {
+            0 => Some(RegEnum::CC),
+            1 => Some(RegEnum::A),
+            2 => Some(RegEnum::B),
+            _ => None,
+        };
+
+        let r = reg.map(|r| format!("{:?}", r)).unwrap_or_else(|| "?".to_string());
+
+        self.direct_8(mem, diss);
+
+        let addr = match self.operand {
+            Operand::Direct(addr) => addr,
+            _ => 0,
+        };
+
+        self.operand = Operand::Bit { reg, src_bit : (post >> 3) & 7, dst_bit : post & 7, addr };
+        self.text = format!("{},{},{},{}", r, (post >> 3) & 7, post & 7, self.text);
+    }
+
+    fn reg_reg_tfm<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder, src_step : i8, dst_step : i8) {
+        let (a,b) = get_tfr_regs(diss.fetch_byte(mem));
+
+        let suffix = |step : i8| match step {
+            1 => "+",
+            -1 => "-",
+            _ => "",
+        };
+
+        self.operand = Operand::Tfm { src : a, dst : b, src_step, dst_step };
+        self.text = format!("{:?}{},{:?}{}", a, suffix(src_step), b, suffix(dst_step));
+    }
+
+    fn reg_reg_pp<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        self.reg_reg_tfm(mem, diss, 1, 1)
+    }
+
+    fn reg_reg_mm<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        self.reg_reg_tfm(mem, diss, -1, -1)
+    }
+
+    fn reg_reg_pn<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        self.reg_reg_tfm(mem, diss, 1, 0)
+    }
+
+    fn reg_reg_np<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
+        self.reg_reg_tfm(mem, diss, 0, 1)
+    }
+
     fn relative8<M : MemoryIO>(&mut self, mem : &mut M, diss : &mut InstructionDecoder) {
         let v = diss.fetch_byte(mem) as i8;
-        let vstr = format!("{}", v);
-        self.text = vstr;
+        self.relative(i16::from(v), diss)
     }
 
     fn relative16<M : MemoryIO>(&mut self, mem: &mut M, diss : &mut InstructionDecoder) {
         let v = diss.fetch_word(mem) as i16;
-        let vstr = format!("{}", v);
+        self.relative(v, diss)
+    }
+
+    fn relative(&mut self, v : i16, diss : &InstructionDecoder) {
+        let dest = diss.next_addr.wrapping_add(v as u16);
+
+        let vstr = if self.lwasm {
+            format!("${:04X}", dest)
+        } else {
+            format!("{}", v)
+        };
+
+        self.add_sym_ref(dest, &vstr);
+        self.operand = Operand::Relative { offset : v, target : dest };
         self.text = vstr;
     }
 }
@@ -384,21 +760,173 @@ impl Disassembler {
     op!(lds);
     op!(sts);
 
+    // Undocumented 6809
+    op!(ngc);
+    op!(ngca);
+    op!(ngcb);
+    op!(scc);
+    op!(hcf);
+
+    // 6309
+    op!(adcd);
+    op!(adcr);
+    op!(adde);
+    op!(addf);
+    op!(addr);
+    op!(addw);
+    op!(aim);
+    op!(andd);
+    op!(andr);
+    op!(asrd);
+    op!(band);
+    op!(beor);
+    op!(biand);
+    op!(bieor);
+    op!(bior);
+    op!(bitd);
+    op!(bitmd);
+    op!(bor);
+    op!(clrd);
+    op!(clre);
+    op!(clrf);
+    op!(clrw);
+    op!(cmpe);
+    op!(cmpf);
+    op!(cmpr);
+    op!(cmpw);
+    op!(comd);
+    op!(come);
+    op!(comf);
+    op!(comw);
+    op!(decd);
+    op!(dece);
+    op!(decf);
+    op!(decw);
+    op!(divd);
+    op!(divq);
+    op!(eim);
+    op!(eord);
+    op!(eorr);
+    op!(incd);
+    op!(ince);
+    op!(incf);
+    op!(incw);
+    op!(ldbt);
+    op!(lde);
+    op!(ldf);
+    op!(ldmd);
+    op!(ldq);
+    op!(ldq_immediate, "ldq");
+    op!(ldw);
+    op!(lsld_asld);
+    op!(lsrd);
+    op!(lsrw);
+    op!(muld);
+    op!(negd);
+    op!(oim);
+    op!(ord);
+    op!(orr);
+    op!(pshsw);
+    op!(pshuw);
+    op!(pulsw);
+    op!(puluw);
+    op!(rold);
+    op!(rolw);
+    op!(rord);
+    op!(rorw);
+    op!(sbcd);
+    op!(sbcr);
+    op!(sexw);
+    op!(stbt);
+    op!(ste);
+    op!(stf);
+    op!(stq);
+    op!(stw);
+    op!(sube);
+    op!(subf);
+    op!(subr);
+    op!(subw);
+    op!(tfm_mm, "tfm");
+    op!(tfm_np, "tfm");
+    op!(tfm_pn, "tfm");
+    op!(tfm_pp, "tfm");
+    op!(tim);
+    op!(tstd);
+    op!(tste);
+    op!(tstf);
+    op!(tstw);
+
     fn unimplemented(&mut self, _diss : &mut InstructionDecoder) {
 
     }
 
-    pub fn diss<M: MemoryIO>(&mut self, mem : &mut M, addr : u16, _syms : Option<&dyn SymTab> ) -> (InstructionDecoder, String) {
+    // Memory is only peeked at, reading it has no side effects
+    pub fn diss<M: MemoryIO>(&mut self, mem : &M, addr : u16, syms : Option<&dyn SymTab> ) -> (InstructionDecoder, String) {
         self.text = "".to_string();
+        self.sym_ref = None;
+        self.exact = true;
+        self.operand = Operand::None;
 
         let mut diss = InstructionDecoder::new(addr);
+        let mem = &mut PeekOnly(mem);
 
         let op = diss.fetch_instruction(mem);
 
-        decode_op!(op, self, mem, &mut diss);
+        if self.cpu == CpuKind::Hd6309 {
+            decode_op_6309!(op, self, mem, &mut diss, { decode_op!(op, self, mem, &mut diss) });
+        } else {
+            decode_op_undoc!(op, self, mem, &mut diss, { decode_op!(op, self, mem, &mut diss) });
+        }
+
+        if let Some(syms) = syms {
+            self.apply_syms(syms);
+        }
 
         (diss, self.text.clone())
     }
 
+    // The same decode as diss as data rather than text, None for an opcode
+    // the cpu doesn't have
+    pub fn decode<M: MemoryIO>(&mut self, mem : &M, addr : u16) -> Option<Decoded> {
+        let (ins, _) = self.diss(mem, addr, None);
+
+        // The 6809 decode always includes the undocumented ops
+        let info = get_op_table(self.cpu, true)[op_to_index(ins.op_code)]?;
+
+        let len = ins.next_addr.wrapping_sub(addr);
+        let bytes = (0..len).map(|i| mem.peek(addr.wrapping_add(i))).collect();
+
+        Some(Decoded::new(&ins, bytes, info, self.operand.clone(), self.dp))
+    }
+
+    // count instructions from addr as trace lines, each preceded by its
+    // label if it has one
+    pub fn diss_lines<M: MemoryIO>(&mut self, mem : &M, addr : u16, count : usize, syms : Option<&dyn SymTab>) -> Vec<String> {
+        self.diss_lines_with(mem, addr, count, syms, |_| None)
+    }
+
+    // diss_lines with whatever comment has to say about each address
+    pub fn diss_lines_with<'a, M: MemoryIO>(&mut self, mem : &M, addr : u16, count : usize, syms : Option<&dyn SymTab>, comment : impl Fn(u16) -> Option<&'a str>) -> Vec<String> {
+        let mut ret = vec![];
+        let mut pc = addr;
+
+        for _ in 0..count {
+            if let Some(label) = syms.and_then(|s| s.get_label(pc)) {
+                ret.push(label);
+            }
+
+            let (ins, txt) = self.diss(mem, pc, syms);
+
+            match comment(pc) {
+                Some(comment) => ret.push(format!("${:04x}   {:20} ; {}", pc, txt, comment)),
+                None => ret.push(format!("${:04x}   {}", pc, txt)),
+            }
+
+            pc = ins.next_addr;
+        }
+
+        ret
+    }
+
 }
 
@@ -3,6 +3,8 @@ use std::io::{Read, Write};
 use crate::gdbstub::reply::{Reply, Endian};
 
 use crate::gdbstub::Sigs;
+use crate::linetable::parse_location;
+use crate::xref::XrefKind;
 
 ////////////////////////////////////////////////////////////////////////////////
 enum PacketResult {
@@ -33,8 +35,32 @@ pub trait DebuggerHost {
 
     fn get_reg(&self, _reg_num : usize) -> u16;
     fn set_reg(&self, _r_num : usize, _val : u16);
+
+    // One line per frame, innermost first
+    fn backtrace(&self) -> Vec<String>;
+
+    // Symbolic disassembly, from the pc if there's no addr
+    fn disassemble(&mut self, _addr : Option<u16>, _count : usize) -> Vec<String>;
+
+    // Assemble one line into memory at addr, the bytes written or why not
+    fn assemble(&mut self, _addr : u16, _text : &str) -> Result<Vec<u8>, String>;
+
+    // Address of file:line from the listings
+    fn find_line(&self, _file : &str, _line : usize) -> Result<u16, String>;
+
+    // file:line and source at addr, or the pc if there's no addr
+    fn source_line(&self, _addr : Option<u16>) -> Option<String>;
+
+    // What refers to a symbol or address, only refs of kind if there is one
+    fn xrefs(&self, _target : &str, _kind : Option<XrefKind>) -> Result<Vec<String>, String>;
+
+    // What's mapped where and which bank of it
+    fn memory_map(&self) -> Vec<String>;
 }
 
+// Instructions shown by monitor diss without a count
+const DISS_LINES : usize = 10;
+
 pub struct GdbRemote {
     remote: TcpStream,
     endian : Endian,
@@ -218,12 +244,16 @@ impl GdbRemote {
         Ok(())
     }
 
-    fn handle_query(&mut self, args : &[u8]) -> GdbResult {
+    fn handle_query(&mut self, host : &mut dyn DebuggerHost, args : &[u8]) -> GdbResult {
         use std::str;
 
-        let _text = str::from_utf8(args).unwrap();
+        let text = str::from_utf8(args).unwrap();
+
+        if text.starts_with("Rcmd,") {
+            return self.monitor(host, &args[5..]);
+        }
 
-        // empty reply we support nothing
+        // empty reply we support nothing else
 
         self.send_empty_reply()
 
@@ -238,6 +268,137 @@ impl GdbRemote {
             // }
     }
 
+    // gdb's monitor command, the command comes hex encoded
+    fn monitor(&mut self, host : &mut dyn DebuggerHost, args : &[u8]) -> GdbResult {
+        let cmd = args_as_string(&parse_data(args)?);
+
+        let mut words = cmd.split_whitespace();
+
+        match words.next().unwrap_or("") {
+            "bt" | "backtrace" => {
+                for line in host.backtrace() {
+                    self.send_console(&format!("{}\n", line))?;
+                }
+                self.send_ok()
+            }
+
+            // diss [addr] [count], addr in hex
+            "diss" => {
+                let args : Vec<&str> = words.collect();
+
+                match parse_diss_args(&args) {
+                    Ok((addr, count)) => {
+                        for line in host.disassemble(addr, count) {
+                            self.send_console(&format!("{}\n", line))?;
+                        }
+                        self.send_ok()
+                    }
+                    Err(_) => self.send_error(),
+                }
+            }
+
+            // asm addr instruction, addr in hex
+            "asm" => {
+                let args : Vec<&str> = words.collect();
+
+                match parse_asm_args(&args) {
+                    Ok((addr, text)) => {
+                        let msg = match host.assemble(addr, &text) {
+                            Ok(bytes) => {
+                                let hex : Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                                format!("${:04x} {}\n", addr, hex.join(" "))
+                            }
+                            Err(e) => format!("{}\n", e),
+                        };
+
+                        self.send_console(&msg)?;
+                        self.send_ok()
+                    }
+                    Err(_) => self.send_error(),
+                }
+            }
+
+            // break file:line or addr in hex, gdb can't do file:line itself
+            // without debug info
+            word @ ("break" | "b" | "delete" | "d") => {
+                let del = word.starts_with('d');
+                let args : Vec<&str> = words.collect();
+
+                let addr = match args.as_slice() {
+                    [loc] => parse_location(loc)
+                        .map(|(file, line)| host.find_line(file, line))
+                        .unwrap_or_else(|| parse_hex_addr(loc).map_err(|_| format!("bad location {}", loc))),
+                    _ => Err("break file:line or addr".to_string()),
+                };
+
+                let msg = match addr {
+                    Ok(addr) => {
+                        if del {
+                            host.del_breakpoint(addr);
+                        } else {
+                            host.add_breakpoint(addr);
+                        }
+
+                        let what = if del { "Deleted breakpoint" } else { "Breakpoint" };
+                        let source = host.source_line(Some(addr)).unwrap_or_default();
+                        format!("{} at ${:04x} {}\n", what, addr, source)
+                    }
+                    Err(e) => format!("{}\n", e),
+                };
+
+                self.send_console(&msg)?;
+                self.send_ok()
+            }
+
+            // line [addr], the source line at addr or the pc
+            "line" => {
+                let args : Vec<&str> = words.collect();
+
+                match args.first().map(|a| parse_hex_addr(a)).transpose() {
+                    Ok(addr) => {
+                        let msg = host.source_line(addr).unwrap_or_else(|| "No source line".to_string());
+                        self.send_console(&format!("{}\n", msg))?;
+                        self.send_ok()
+                    }
+                    Err(_) => self.send_error(),
+                }
+            }
+
+            // xref symbol|addr [read|write|jump|call]
+            "xref" => {
+                let args : Vec<&str> = words.collect();
+
+                let res = match args.as_slice() {
+                    [target] => host.xrefs(target, None),
+                    [target, kind] => match XrefKind::from_name(kind) {
+                        Some(kind) => host.xrefs(target, Some(kind)),
+                        None => Err(format!("bad kind {}, read, write, jump or call", kind)),
+                    },
+                    _ => Err("xref symbol|addr [read|write|jump|call]".to_string()),
+                };
+
+                let lines = res.unwrap_or_else(|e| vec![e]);
+
+                for line in lines {
+                    self.send_console(&format!("{}\n", line))?;
+                }
+                self.send_ok()
+            }
+
+            "map" => {
+                for line in host.memory_map() {
+                    self.send_console(&format!("{}\n", line))?;
+                }
+                self.send_ok()
+            }
+
+            _ => {
+                info!("unhandled monitor command {:?}", cmd);
+                self.send_empty_reply()
+            }
+        }
+    }
+
     fn get_reg(&mut self, host : &mut dyn DebuggerHost, args: &[u8]) -> GdbResult {
         let reg = parse_get_reg(args)?;
         let val = host.get_reg(reg);
@@ -277,7 +438,7 @@ impl GdbRemote {
         }
 
         let res = match command {
-            'q' => self.handle_query(args),
+            'q' => self.handle_query(host, args),
             '?' => self.send_status(),
             'D' => self.disconnect(),
             'M' => self.write_memory(host, args),
@@ -336,6 +497,18 @@ impl GdbRemote {
         self.send_reply(reply)
     }
 
+    // Text for gdb to print, O packet
+    fn send_console(&mut self, text : &str) -> GdbResult {
+        let mut reply = Reply::new(&self.endian);
+        reply.push(b"O");
+
+        for b in text.bytes() {
+            reply.push_u8(b);
+        }
+
+        self.send_reply(reply)
+    }
+
     fn send_error(&mut self) -> GdbResult {
         // GDB remote doesn't specify what the error codes should
         // be. Should be bother coming up with our own convention?
@@ -542,6 +715,32 @@ use itertools::Itertools;
 /// Parse an hexadecimal string and return the value as an
 /// integer. Return `None` if the string is invalid.
 
+fn parse_hex_addr(text : &str) -> Result<u16, ()> {
+    let text = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(text, 16).map_err(|_| ())
+}
+
+fn parse_diss_args(args : &[&str]) -> Result<(Option<u16>, usize), ()> {
+    let addr = match args.get(0) {
+        Some(a) => Some(parse_hex_addr(a)?),
+        None => None,
+    };
+
+    let count = match args.get(1) {
+        Some(c) => c.parse::<usize>().map_err(|_| ())?,
+        None => DISS_LINES,
+    };
+
+    Ok((addr, count))
+}
+
+fn parse_asm_args(args : &[&str]) -> Result<(u16, String), ()> {
+    match args.split_first() {
+        Some((addr, text)) if !text.is_empty() => Ok((parse_hex_addr(addr)?, text.join(" "))),
+        _ => Err(()),
+    }
+}
+
 fn parse_data(_hex: &[u8]) -> Result<Vec<u8>, ()> {
 
     let mut res = vec!();
@@ -2,9 +2,11 @@ mod reply;
 mod gdbcore;
 mod sigs;
 mod proxy;
+mod regs;
 
 pub use self::gdbcore::*;
 pub use self::reply::*;
 pub use self::proxy::*;
 pub use self::sigs::*;
+pub use self::regs::*;
 
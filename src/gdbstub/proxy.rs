@@ -3,6 +3,7 @@ use std::sync::mpsc;
 use std::thread;
 
 use crate::gdbstub::{ DebuggerHost, GdbRemote, Reply, Sigs};
+use crate::xref::XrefKind;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BreakPointTypes {
@@ -30,6 +31,20 @@ pub enum Message {
     DeleteBreakPoint(BreakPointTypes, u16),
     SetReg(usize, u16),
     GetReg(usize),
+    GetBacktrace,
+    Backtrace(Vec<String>),
+    Disassemble(Option<u16>, usize),
+    Disassembly(Vec<String>),
+    Assemble(u16, String),
+    Assembled(Result<Vec<u8>, String>),
+    FindLine(String, usize),
+    LineAddr(Result<u16, String>),
+    GetSourceLine(Option<u16>),
+    SourceLine(Option<String>),
+    GetXrefs(String, Option<XrefKind>),
+    Xrefs(Result<Vec<String>, String>),
+    GetMemoryMap,
+    MemoryMap(Vec<String>),
 }
 
 struct DebuggerProxy {
@@ -143,6 +158,76 @@ impl DebuggerHost for DebuggerProxy {
         self.send_wait_ack(Message::SetReg(reg_num, val));
     }
 
+    fn backtrace(&self) -> Vec<String> {
+        let reply = self.send(Message::GetBacktrace);
+
+        if let Message::Backtrace(lines) = reply {
+            lines
+        } else {
+            panic!("backtrace: expected Backtrace got {:?}", reply)
+        }
+    }
+
+    fn disassemble(&mut self, addr : Option<u16>, count : usize) -> Vec<String> {
+        let reply = self.send(Message::Disassemble(addr, count));
+
+        if let Message::Disassembly(lines) = reply {
+            lines
+        } else {
+            panic!("disassemble: expected Disassembly got {:?}", reply)
+        }
+    }
+
+    fn assemble(&mut self, addr : u16, text : &str) -> Result<Vec<u8>, String> {
+        let reply = self.send(Message::Assemble(addr, text.to_string()));
+
+        if let Message::Assembled(res) = reply {
+            res
+        } else {
+            panic!("assemble: expected Assembled got {:?}", reply)
+        }
+    }
+
+    fn find_line(&self, file : &str, line : usize) -> Result<u16, String> {
+        let reply = self.send(Message::FindLine(file.to_string(), line));
+
+        if let Message::LineAddr(res) = reply {
+            res
+        } else {
+            panic!("find_line: expected LineAddr got {:?}", reply)
+        }
+    }
+
+    fn source_line(&self, addr : Option<u16>) -> Option<String> {
+        let reply = self.send(Message::GetSourceLine(addr));
+
+        if let Message::SourceLine(source) = reply {
+            source
+        } else {
+            panic!("source_line: expected SourceLine got {:?}", reply)
+        }
+    }
+
+    fn xrefs(&self, target : &str, kind : Option<XrefKind>) -> Result<Vec<String>, String> {
+        let reply = self.send(Message::GetXrefs(target.to_string(), kind));
+
+        if let Message::Xrefs(res) = reply {
+            res
+        } else {
+            panic!("xrefs: expected Xrefs got {:?}", reply)
+        }
+    }
+
+    fn memory_map(&self) -> Vec<String> {
+        let reply = self.send(Message::GetMemoryMap);
+
+        if let Message::MemoryMap(lines) = reply {
+            lines
+        } else {
+            panic!("memory_map: expected MemoryMap got {:?}", reply)
+        }
+    }
+
     fn write(&mut self, addr : u16, val : u8) {
         self.send_wait_ack(Message::Write(addr, val));
     }
@@ -0,0 +1,61 @@
+// Register layout for the g / G packets
+//
+// cc a b dp x y u s pc, a 6309 follows with e f v md
+// 16 bit registers are big endian
+
+use crate::cpu::Regs;
+
+pub fn regs_to_bytes(regs : &Regs) -> Vec<u8> {
+    let mut ret = vec![ regs.flags.bits(), regs.a, regs.b, regs.dp ];
+
+    for &v in &[regs.x, regs.y, regs.u, regs.s, regs.pc] {
+        ret.push((v >> 8) as u8);
+        ret.push(v as u8);
+    }
+
+    if regs.is_6309() {
+        ret.extend_from_slice(&[
+            regs.e, regs.f,
+            (regs.v >> 8) as u8, regs.v as u8,
+            regs.md ]);
+    }
+
+    ret
+}
+
+// Missing trailing registers are left alone
+pub fn bytes_to_regs(regs : &mut Regs, data : &[u8]) {
+    let mut it = data.iter().cloned();
+
+    macro_rules! take8 {
+        ($dst:expr) => { if let Some(v) = it.next() { $dst = v } }
+    }
+
+    macro_rules! take16 {
+        ($dst:expr) => {
+            if let (Some(h), Some(l)) = (it.next(), it.next()) {
+                $dst = u16::from(h) << 8 | u16::from(l)
+            }
+        }
+    }
+
+    if let Some(cc) = it.next() {
+        regs.flags.set_flags(cc);
+    }
+
+    take8!(regs.a);
+    take8!(regs.b);
+    take8!(regs.dp);
+    take16!(regs.x);
+    take16!(regs.y);
+    take16!(regs.u);
+    take16!(regs.s);
+    take16!(regs.pc);
+
+    if regs.is_6309() {
+        take8!(regs.e);
+        take8!(regs.f);
+        take16!(regs.v);
+        take8!(regs.md);
+    }
+}
@@ -0,0 +1,102 @@
+// #![feature(plugin)]
+// #![plugin(clippy)]
+
+// #![allow(suspicious_arithmetic_impl)]
+// #![allow(redundant_field_names)]
+// #![allow(cast_lossless)]
+// #![allow(dead_code)]
+// #![allow(unused_variables)]
+
+#![allow(dead_code)]
+
+// Rustrex as a library, the 6809 / 6309 core, memory, the 6522 VIA, the
+// disassembler, the assembler and the machines built from them. The rustrex binary is a
+// command line front end over this.
+//
+// The window feature (on by default) brings in glium for the gl window and
+// the simple machine that draws into it. Without it nothing here needs a
+// display.
+
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate bitflags;
+#[macro_use] extern crate serde_derive;
+
+#[cfg(feature = "window")]
+#[macro_use] extern crate glium;
+
+extern crate notify;
+extern crate image;
+
+extern crate serde_yaml;
+extern crate serde_json;
+extern crate sha1;
+extern crate ctrlc;
+extern crate separator;
+extern crate itertools;
+
+extern crate regex;
+extern crate num;
+extern crate clap;
+
+#[macro_use] extern crate log;
+
+#[macro_use] pub mod cpu;
+
+#[cfg(feature = "window")]
+pub mod window;
+
+pub mod mem;
+pub mod symtab;
+pub mod symfile;
+pub mod linetable;
+pub mod utils;
+pub mod diss;
+pub mod decoded;
+pub mod proclog;
+pub mod breakpoints;
+pub mod tests;
+pub mod timer;
+pub mod gdbstub;
+pub mod m6522;
+pub mod vectrex;
+
+#[cfg(feature = "window")]
+pub mod simple;
+
+pub mod watcher;
+pub mod state;
+pub mod filewatcher;
+pub mod profiler;
+pub mod xref;
+pub mod annotate;
+pub mod romdiss;
+pub mod assembler;
+
+////////////////////////////////////////////////////////////////////////////////
+// The stable api, tools should reach for these rather than the module paths
+
+pub use crate::cpu::{Regs, RegEnum, Flags, InstructionDecoder, CpuErr};
+pub use crate::cpu::{step, step_with_observer, reset, run, run_with_observer, RunStats};
+pub use crate::cpu::{Clock, StandardClock};
+pub use crate::cpu::{Interrupts, Interrupt, RunState};
+pub use crate::cpu::{CpuKind, UndocMode, BusMode};
+pub use crate::cpu::{Observer, NullObserver, Both, CallStack, Frame, FrameKind, Mismatch};
+
+pub use crate::mem::{MemoryIO, MemMap, MemMapIO, MemBlock, MemError, BusAccess};
+
+pub use crate::m6522::M6522;
+pub use crate::diss::{Disassembler, SymTab};
+pub use crate::decoded::{Decoded, Operand, EffectiveAddr, Flow, Cycles, Syntax, FormatOptions};
+pub use crate::romdiss::RomDisassembler;
+pub use crate::assembler::{Assembler, Assembled, AsmError};
+pub use crate::symtab::{SymbolTable, SymbolicFrame, Symbol, SymKind};
+pub use crate::symfile::{SymFormat, SymbolLoader, SymIssue};
+pub use crate::linetable::{LineTable, SourceLine};
+pub use crate::profiler::Profiler;
+pub use crate::xref::{Xrefs, Xref, XrefKind};
+pub use crate::annotate::{Annotations, Annotation};
+
+pub use crate::vectrex::Vectrex;
+
+#[cfg(feature = "window")]
+pub use crate::simple::Simple;
@@ -0,0 +1,166 @@
+// Source lines from assembler listings
+//
+// An lwasm listing has the file and line every byte of code came from.
+// LineTable indexes that both ways, address to source line for traces and
+// file:line to address for breakpoints.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::symfile::ListingLine;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file : String,
+    pub line : usize,
+    pub addr : u16,
+    // Bytes of code or data the line made
+    pub len : usize,
+    pub text : String,
+}
+
+impl SourceLine {
+    pub fn contains(&self, addr : u16) -> bool {
+        let offset = addr.wrapping_sub(self.addr) as usize;
+        offset < self.len.max(1)
+    }
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} {}", self.file, self.line, self.text.trim())
+    }
+}
+
+// file:line as gdb takes it, all.s:120
+pub fn parse_location(text : &str) -> Option<(&str, usize)> {
+    let (file, line) = text.rsplit_once(':')?;
+
+    if file.is_empty() {
+        return None;
+    }
+
+    line.parse().ok().map(|line| (file, line))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    lines : Vec<SourceLine>,
+    // Start address to the line that made the code there
+    by_addr : BTreeMap<u16, usize>,
+    // File to its lines that have an address, by line number
+    by_file : HashMap<String, BTreeMap<usize, usize>>,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file(file : &str) -> Result<Self, String> {
+        Self::from_files(&[file])
+    }
+
+    pub fn from_files(files : &[&str]) -> Result<Self, String> {
+        let mut ret = Self::new();
+
+        for file in files {
+            let text = fs::read_to_string(file)
+                .map_err(|e| format!("{}: {}", file, e))?;
+
+            let added = ret.add_listing(&text);
+
+            if added == 0 {
+                return Err(format!("{}: no source lines, not an lwasm listing?", file));
+            }
+
+            info!("{} source lines from {}", added, file);
+        }
+
+        Ok(ret)
+    }
+
+    // Add the lines of a listing that have an address, how many there were
+    pub fn add_listing(&mut self, text : &str) -> usize {
+        let before = self.lines.len();
+
+        for line in text.lines().filter_map(ListingLine::parse) {
+            if let Some(addr) = line.addr {
+                self.add(line.file, line.line, addr, line.bytes.len(), line.source);
+            }
+        }
+
+        self.lines.len() - before
+    }
+
+    fn add(&mut self, file : String, line : usize, addr : u16, len : usize, text : String) {
+        let index = self.lines.len();
+
+        // a label on its own shares an address with the code after it, the
+        // code's line wins
+        if len > 0 {
+            self.by_addr.insert(addr, index);
+        } else {
+            self.by_addr.entry(addr).or_insert(index);
+        }
+
+        self.by_file
+            .entry(file.clone())
+            .or_default()
+            .entry(line)
+            .or_insert(index);
+
+        self.lines.push(SourceLine { file, line, addr, len, text });
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // The line that made the code or data at addr
+    pub fn lookup(&self, addr : u16) -> Option<&SourceLine> {
+        let (_, &index) = self.by_addr.range(..=addr).next_back()?;
+        let line = &self.lines[index];
+
+        if line.contains(addr) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    // Files are matched on their name if the path doesn't match
+    fn find_file(&self, file : &str) -> Option<&BTreeMap<usize, usize>> {
+        if let Some(lines) = self.by_file.get(file) {
+            return Some(lines);
+        }
+
+        let name = Path::new(file).file_name()?;
+
+        self.by_file
+            .iter()
+            .find(|(f, _)| Path::new(f).file_name() == Some(name))
+            .map(|(_, lines)| lines)
+    }
+
+    // Where file:line is, a line without code goes to the next one with some
+    // like gdb does
+    pub fn find_line(&self, file : &str, line : usize) -> Result<&SourceLine, String> {
+        let lines = self.find_file(file)
+            .ok_or_else(|| format!("No source file named {}", file))?;
+
+        lines
+            .range(line..)
+            .map(|(_, &index)| &self.lines[index])
+            .find(|l| l.len > 0)
+            .ok_or_else(|| format!("No code at or after {}:{}", file, line))
+    }
+}
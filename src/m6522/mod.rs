@@ -222,8 +222,12 @@ pub struct M6522<C : Clock> {
 
     aux_cntl : u8,
     cntl : u8,
+    int_enable : u8,
 
     shift_reg : u8,
+    // IFR bit 2, shifts aren't clocked any more than the timers are so
+    // nothing sets it but poke
+    sr_int_flag : bool,
 }
 
 #[derive(Debug, Clone)]
@@ -247,6 +251,11 @@ impl <C : Clock> M6522 <C> {
     pub fn comparator(&self) -> bool { self.port_b.bits.get_bit(5) }
     pub fn sample_hold(&self) -> bool { self.port_b.bits.get_bit(1)  }
 
+    // Level on a port b pin, one that's an input is pulled up
+    pub fn port_b_pin(&self, bit : usize) -> bool {
+        !self.port_b.ddr.get_bit(bit) || self.port_b.bits.get_bit(bit)
+    }
+
     pub fn sound(&self) -> SoundReg {
         match (self.port_b.bits >> 3) & 3 {
             _ => SoundReg::TBD,
@@ -319,7 +328,9 @@ impl <C : Clock> M6522 <C> {
             timer_2 : Timer::new(false),
             aux_cntl : 0,
             cntl : 0,
+            int_enable : 0,
             shift_reg : 0,
+            sr_int_flag : false,
         }
     }
 
@@ -431,6 +442,61 @@ impl <C : Clock> M6522 <C> {
     pub fn get_port_b_latch(&self) -> bool {
         self.aux_cntl.get_bit(1)
     }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Interrupts
+
+    fn get_int_flags(&self) -> u8 {
+        let mut flags = 0;
+
+        if self.timer_1.int_flag {
+            flags |= 1 << 6;
+        }
+
+        if self.timer_2.int_flag {
+            flags |= 1 << 5;
+        }
+
+        if self.sr_int_flag {
+            flags |= 1 << 2;
+        }
+
+        if flags & self.int_enable != 0 {
+            flags |= 1 << 7;
+        }
+
+        flags
+    }
+
+    fn write_int_flags(&mut self, val : u8) {
+        // writing a 1 clears the flag
+        if val.get_bit(6) {
+            self.timer_1.reset_int_flag();
+        }
+
+        if val.get_bit(5) {
+            self.timer_2.reset_int_flag();
+        }
+
+        if val.get_bit(2) {
+            self.sr_int_flag = false;
+        }
+    }
+
+    fn write_int_enable(&mut self, val : u8) {
+        let bits = val & 0x7f;
+
+        if val.get_bit(7) {
+            self.int_enable |= bits;
+        } else {
+            self.int_enable &= !bits;
+        }
+    }
+
+    // State of the /IRQ output, wired to the cpu's IRQ line
+    pub fn irq(&self) -> bool {
+        self.get_int_flags().get_bit(7)
+    }
 }
 
 
@@ -447,8 +513,61 @@ impl<C : Clock> MemoryIO for M6522<C> {
         panic!();
     }
 
-    fn upload(&mut self, _addr : u16, _data : &[u8]) {
-        panic!("tbd")
+    // What load_byte would give without clearing interrupt flags
+    fn peek(&self, addr:u16) -> u8 {
+        let (reg, _) = self.get_reg(addr);
+
+        use self::Reg::*;
+
+        match reg {
+            DdrA        => self.port_a.get_ddr(),
+            PortA       => self.port_a.read_port(),
+            PortANhs    => self.port_a.read_port(),
+            DdrB        => self.port_b.get_ddr(),
+            PortB       => self.port_b.read_port(),
+            AuxCntl     => self.aux_cntl,
+            Cntl        => self.cntl,
+            T1CntL      => self.timer_1.counter as u8,
+            T1CntH      => self.timer_1.read_hi(),
+            T1LatchLo   => self.timer_1.read_latch_lo(),
+            T1LatchHi   => self.timer_1.read_latch_hi(),
+            T2Lo        => self.timer_2.counter as u8,
+            T2Hi        => self.timer_2.read_hi(),
+            ShiftReg    => self.shift_reg,
+            IntFlags    => self.get_int_flags(),
+            IntEnable   => self.int_enable | 0x80,
+        }
+    }
+
+    // Sets registers as they'd read back, timers aren't started and flags
+    // are set as well as cleared
+    fn poke(&mut self, addr:u16, val:u8) {
+        let (reg, _) = self.get_reg(addr);
+
+        use self::Reg::*;
+
+        match reg {
+            DdrA        => self.port_a.set_ddr(val),
+            PortA       => self.port_a.set_val(val),
+            PortANhs    => self.port_a.set_val(val),
+            DdrB        => self.port_b.set_ddr(val),
+            PortB       => self.port_b.set_val(val),
+            AuxCntl     => self.write_aux_cntl(val),
+            Cntl        => self.write_cntl(val),
+            T1CntL      => self.timer_1.counter = (self.timer_1.counter & 0xff00) | u16::from(val),
+            T1CntH      => self.timer_1.counter = (self.timer_1.counter & 0xff) | u16::from(val) << 8,
+            T1LatchLo   => self.timer_1.latch = (self.timer_1.latch & 0xff00) | u16::from(val),
+            T1LatchHi   => self.timer_1.latch = (self.timer_1.latch & 0xff) | u16::from(val) << 8,
+            T2Lo        => self.timer_2.counter = (self.timer_2.counter & 0xff00) | u16::from(val),
+            T2Hi        => self.timer_2.counter = (self.timer_2.counter & 0xff) | u16::from(val) << 8,
+            ShiftReg    => self.shift_reg = val,
+            IntFlags    => {
+                self.timer_1.int_flag = val.get_bit(6);
+                self.timer_2.int_flag = val.get_bit(5);
+                self.sr_int_flag = val.get_bit(2);
+            }
+            IntEnable   => self.int_enable = val & 0x7f,
+        }
     }
 
     fn get_name(&self) -> String {
@@ -478,14 +597,16 @@ impl<C : Clock> MemoryIO for M6522<C> {
             T1LatchHi   => self.timer_1.read_latch_hi(),
             T2Lo        => self.timer_2.read_lo(),
             T2Hi        => self.timer_2.read_hi(),
+            IntFlags    => self.get_int_flags(),
+            IntEnable   => self.int_enable | 0x80,
 
-            _ => panic!("Unhandled read for {:?}", reg),
-
+            ShiftReg    => {
+                self.sr_int_flag = false;
+                self.shift_reg
+            }
 
-            // ShiftReg    => {10} ,
-            // IntFlags    => {13} ,
-            // IntEnable   => {14} ,
-            // PortANhs    => {15} ,
+            // port a without the handshake, there's none to skip
+            PortANhs    => self.port_a.read_port(),
         }
     }
 
@@ -529,28 +650,74 @@ impl<C : Clock> MemoryIO for M6522<C> {
                 // self.cntl_report()
             },
 
-            ShiftReg     => self.shift_reg = val,
+            ShiftReg     => {
+                self.sr_int_flag = false;
+                self.shift_reg = val
+            }
+
+            PortANhs     => self.port_a.write_port(val),
 
             T1LatchLo    => self.timer_1.write_latch_lo(val),
             T1LatchHi    => self.timer_1.write_latch_hi(val),
             T2Lo         => self.timer_2.write_lo(val),
             T2Hi         => self.timer_2.write_hi(val),
+            IntFlags     => self.write_int_flags(val),
+            IntEnable    => self.write_int_enable(val),
+        };
+
+    }
+}
 
-            _ => panic!("Unhandled write of %{:08b} to {:?}", val, reg)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::StandardClock;
+    use crate::mem::MemBus;
 
-                // PortA        => () ,
+    type Via = M6522<StandardClock>;
 
-                // T2CntL       => () ,
-                // T2CntH       => () ,
+    fn via() -> Via {
+        M6522::new(0xd000, 0x800, &Rc::new(RefCell::new(StandardClock::new(1_500_000))))
+    }
 
-                // T2Lo         => () ,
-                // T2Hi         => () ,
+    #[test]
+    fn shift_reg_read_clears_its_flag() {
+        let mut via = via();
+        via.poke(0xd00a, 0x5a);
+        via.poke(0xd00d, 0x04);
+        assert_eq!(via.peek(0xd00d), 0x04);
 
+        assert_eq!(via.load_byte(0xd00a), 0x5a);
+        assert_eq!(via.peek(0xd00d), 0x00);
 
+        // mirrored through the 2K
+        via.poke(0xd00d, 0x04);
+        assert_eq!(via.load_byte(0xd7fa), 0x5a);
+        assert_eq!(via.peek(0xd00d), 0x00);
+    }
 
-                // IntFlags     => () ,
-                // PortANhs     => () ,
-        };
+    #[test]
+    fn port_a_without_handshake() {
+        let mut via = via();
+        via.store_byte(0xd003, 0x0f);
+        via.store_byte(0xd00f, 0xff);
+
+        assert_eq!(via.load_byte(0xd00f), via.load_byte(0xd001));
+        assert_eq!(via.port_a.bits, 0x0f);
+    }
+
+    #[test]
+    fn shared_reads_reach_the_via() {
+        let mut mem = MemBus::new("test", via());
+        let ram = mem.add_ram("ram", 0x400);
+        mem.map_shared(0xd800..=0xdfff, ram, 0, ());
+
+        mem.store_byte(0xd80a, 0x33);
+        mem.devices_mut().poke(0xd00d, 0x44);
 
+        // the value's the RAM's but the VIA clears its flags
+        assert_eq!(mem.load_byte(0xd80a), 0x33);
+        assert_eq!(mem.load_byte(0xd804), 0x00);
+        assert_eq!(mem.devices().peek(0xd00d), 0x00);
     }
 }
@@ -1,64 +1,178 @@
-// #![feature(plugin)]
-// #![plugin(clippy)]
+// Command line front end, everything it runs lives in the rustrex library
 
-// #![allow(suspicious_arithmetic_impl)]
-// #![allow(redundant_field_names)]
-// #![allow(cast_lossless)]
-// #![allow(dead_code)]
-// #![allow(unused_variables)]
-
-#![allow(dead_code)]
-
-#[macro_use] extern crate lazy_static;
-#[macro_use] extern crate bitflags;
-#[macro_use] extern crate serde_derive;
-
-#[macro_use] extern crate glium;
-
-extern crate notify;
-extern crate image;
-
-extern crate serde_yaml;
-extern crate serde_json;
-extern crate sha1;
-extern crate separator;
-extern crate itertools;
-
-extern crate regex;
-extern crate num;
+extern crate rustrex;
 extern crate clap;
-
 extern crate env_logger;
 #[macro_use] extern crate log;
 
-#[macro_use] mod cpu;
-
-mod window;
-mod mem;
-mod symtab;
-mod utils;
-mod diss;
-mod proclog;
-mod breakpoints;
-mod tests; 
-mod timer;
-mod gdbstub;
-mod m6522;
-mod vectrex;
-mod simple;
-mod watcher;
-mod state;
-mod filewatcher;
-
-use crate::tests::{GregTest, JsonTest, Tester};
+use rustrex::tests::{GregTest, JsonTest, Bench, Tester};
 use clap::{Arg, App, SubCommand, ArgMatches};
 
+fn is_addr(text : String) -> Result<(), String> {
+    rustrex::romdiss::parse_addr(&text).map(|_| ())
+}
+
+fn is_count(text : String) -> Result<(), String> {
+    text.parse::<usize>().map(|_| ()).map_err(|_| format!("{} isn't a number", text))
+}
+
 fn do_test<T : Tester>(matches : &ArgMatches) -> T{
     let mut tester = T::from_matches(matches);
     tester.run();
     tester
 }
 
+fn do_asm(matches : &ArgMatches) -> Result<(), String> {
+    use std::fs;
+    use std::path::Path;
+
+    let source = matches.value_of("SOURCE").unwrap();
+
+    let mut asm = rustrex::Assembler::new();
+
+    if let Some(dp) = matches.value_of("dp") {
+        asm = asm.with_dp(Some(rustrex::romdiss::parse_addr(dp)? as u8));
+    }
+
+    if let Some(file) = matches.value_of("import") {
+        asm = asm.with_symbols(rustrex::SymbolTable::from_file(file)?);
+    }
+
+    let done = asm.assemble_file(source).map_err(|e| e.to_string())?;
+
+    let out = match matches.value_of("output") {
+        Some(file) => file.to_string(),
+        None => Path::new(source).with_extension("bin").to_string_lossy().into_owned(),
+    };
+
+    fs::write(&out, done.raw()).map_err(|e| format!("{}: {}", out, e))?;
+    info!("Wrote {} bytes to {}", done.len(), out);
+
+    if let Some(file) = matches.value_of("symbols") {
+        fs::write(file, done.symbols.to_yaml()).map_err(|e| format!("{}: {}", file, e))?;
+    }
+
+    Ok(())
+}
+
+// What the ROM tracer takes, for diss and xref
+fn rom_trace_args<'a, 'b>(cmd : App<'a, 'b>) -> App<'a, 'b> {
+    cmd
+        .arg(Arg::with_name("base")
+             .long("base")
+             .takes_value(true)
+             .value_name("ADDR")
+             .validator(is_addr)
+             .help("Load address, $0000 for a Vectrex cart otherwise so the ROM ends at $ffff"))
+        .arg(Arg::with_name("entry")
+             .short("e")
+             .long("entry")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("ADDR")
+             .validator(is_addr)
+             .help("Trace code from ADDR as well"))
+        .arg(Arg::with_name("jump-table")
+             .long("jump-table")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("ADDR:COUNT")
+             .help("COUNT code pointers at ADDR"))
+        .arg(Arg::with_name("dp")
+             .long("dp")
+             .takes_value(true)
+             .validator(is_addr)
+             .help("Direct page to assume for symbols and setdp"))
+        .arg(Arg::with_name("symbols")
+             .long("symbols")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("FILE")
+             .help("Symbols to use for labels and equates, lwasm, asm6809, as09 or yaml"))
+        .arg(Arg::with_name("trace-symbols")
+             .long("trace-symbols")
+             .help("Trace code from every symbol inside the ROM too"))
+        .arg(Arg::with_name("ROM FILE")
+             .required(true)
+             .index(1)
+             .help("Set the ROM file"))
+}
+
+fn do_diss(matches : &ArgMatches) -> Result<(), String> {
+    let mut diss = rustrex::RomDisassembler::from_matches(matches)?;
+    diss.run(matches.value_of("output"))
+}
+
+fn do_xref(matches : &ArgMatches) -> Result<(), String> {
+    let mut diss = rustrex::RomDisassembler::from_matches(matches)?;
+    diss.trace();
+
+    let mut xrefs = rustrex::Xrefs::new();
+    diss.add_xrefs(&mut xrefs);
+
+    if let Some(files) = matches.values_of("merge") {
+        for file in files {
+            xrefs.merge(&rustrex::Xrefs::from_file(file)?);
+        }
+    }
+
+    let syms = diss.symbols();
+    let kind = matches.value_of("kind").and_then(rustrex::XrefKind::from_name);
+
+    if let Some(targets) = matches.values_of("to") {
+        for target in targets {
+            let addr = rustrex::xref::parse_target(target, syms)?;
+
+            for line in xrefs.report(addr, kind, syms) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    match matches.value_of("output") {
+        Some(file) => {
+            xrefs.write(file, syms)?;
+            info!("Wrote {} cross referenced addresses to {}", xrefs.len(), file);
+        }
+        None if !matches.is_present("to") => println!("{}", xrefs.to_json(syms)),
+        None => (),
+    }
+
+    Ok(())
+}
+
+fn do_annotate(matches : &ArgMatches) -> Result<(), String> {
+    let rom_file = matches.value_of("ROM FILE").unwrap();
+    let data = std::fs::read(rom_file).map_err(|e| format!("{}: {}", rom_file, e))?;
+
+    let base = match matches.value_of("base") {
+        Some(text) => rustrex::romdiss::parse_addr(text)?,
+        None => (0x1_0000 - data.len().min(0x1_0000)) as u16,
+    };
+
+    let notes = rustrex::Annotations::load(matches.value_of("SOURCE").unwrap(), &data, base)?;
+
+    println!("{} annotated addresses for {}", notes.len(), notes.image());
+    println!("sha1 {}", notes.sha1());
+
+    let stats = notes.stats();
+
+    if stats != Default::default() {
+        println!("{} lines matched, {} mismatched, {} cut off and {} skipped",
+                 stats.matched, stats.mismatched, stats.truncated, stats.skipped);
+    }
+
+    if let Some(file) = matches.value_of("output") {
+        notes.write(file)?;
+        info!("Wrote annotations to {}", file);
+    }
+
+    Ok(())
+}
+
 fn main() {
     use std::env;
     env::set_var("RUST_LOG", "info");
@@ -76,10 +190,75 @@ fn main() {
                          .short("g")
                          .long("enable-gdb")
                          .help("Enable GDB debugging"))
+                    .arg(Arg::with_name("cpu")
+                         .long("cpu")
+                         .takes_value(true)
+                         .possible_values(&["6809", "6309"])
+                         .default_value("6809")
+                         .help("Set the cpu type"))
+                    .arg(Arg::with_name("undocumented")
+                         .long("undocumented")
+                         .takes_value(true)
+                         .possible_values(&["strict", "emulate"])
+                         .default_value("strict")
+                         .help("Emulate undocumented 6809 opcodes or stop on them"))
+                    .arg(Arg::with_name("cycle-accurate")
+                         .long("cycle-accurate")
+                         .help("Run the cpu bus cycle by cycle"))
+                    .arg(Arg::with_name("unmapped")
+                         .long("unmapped")
+                         .takes_value(true)
+                         .possible_values(&["warn", "open-bus", "halt"])
+                         .default_value("warn")
+                         .help("What reading or writing unmapped memory does other than hit the open bus"))
+                    .arg(Arg::with_name("halt-on-rom-write")
+                         .long("halt-on-rom-write")
+                         .help("Stop with a segfault on a write to ROM rather than ignoring it"))
+                    .arg(Arg::with_name("mapper")
+                         .long("mapper")
+                         .takes_value(true)
+                         .possible_values(&["auto", "plain", "pb6", "latch"])
+                         .default_value("auto")
+                         .help("How the cart is banked, auto works it out from its size and header"))
+                    .arg(Arg::with_name("profile")
+                         .long("profile")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Profile the cpu and write a report to FILE"))
+                    .arg(Arg::with_name("folded")
+                         .long("folded")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Profile the cpu and write folded stacks for flamegraph to FILE"))
+                    .arg(Arg::with_name("xref")
+                         .long("xref")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Record what reads, writes, jumps to and calls what and write it as json to FILE"))
+                    .arg(Arg::with_name("symbols")
+                         .long("symbols")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("[REGION[:BANK]=]FILE")
+                         .default_value("resources/syms.yaml")
+                         .help("Symbols for disassembly and backtraces, only while REGION (and BANK) is mapped if given"))
+                    .arg(Arg::with_name("listing")
+                         .long("listing")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("FILE")
+                         .help("lwasm listing to map addresses to source lines"))
+                    .arg(Arg::with_name("annotations")
+                         .long("annotations")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("BIOS labels and comments for traces, as09 source like utils/vrom.txt or saved by annotate"))
                     .arg(Arg::with_name("ROM FILE")
                          .required(true)
                          .index(1)
-                         .help("Set the ROM file")))
+                         .help("Set the cart ROM file")))
 
         .subcommand(SubCommand::with_name("simple")
                     .arg(Arg::with_name("enable-gdb")
@@ -92,6 +271,53 @@ fn main() {
                          .long("watch-rom")
                          .help("Watch ROM file, reload and reset if changed"))
 
+                    .arg(Arg::with_name("cpu")
+                         .long("cpu")
+                         .takes_value(true)
+                         .possible_values(&["6809", "6309"])
+                         .default_value("6809")
+                         .help("Set the cpu type"))
+                    .arg(Arg::with_name("undocumented")
+                         .long("undocumented")
+                         .takes_value(true)
+                         .possible_values(&["strict", "emulate"])
+                         .default_value("strict")
+                         .help("Emulate undocumented 6809 opcodes or stop on them"))
+                    .arg(Arg::with_name("cycle-accurate")
+                         .long("cycle-accurate")
+                         .help("Run the cpu bus cycle by cycle"))
+
+                    .arg(Arg::with_name("profile")
+                         .long("profile")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Profile the cpu and write a report to FILE"))
+                    .arg(Arg::with_name("folded")
+                         .long("folded")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Profile the cpu and write folded stacks for flamegraph to FILE"))
+                    .arg(Arg::with_name("xref")
+                         .long("xref")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Record what reads, writes, jumps to and calls what and write it as json to FILE"))
+
+                    .arg(Arg::with_name("symbols")
+                         .long("symbols")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("[REGION[:BANK]=]FILE")
+                         .help("Symbols for disassembly and backtraces, only while REGION (and BANK) is mapped if given"))
+                    .arg(Arg::with_name("listing")
+                         .long("listing")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("FILE")
+                         .help("lwasm listing to map addresses to source lines"))
+
                     .arg(Arg::with_name("ROM FILE")
                          .required(true)
                          .index(1)
@@ -128,24 +354,157 @@ fn main() {
                          .short("n")
                          .long("no-hash-check")
                          .help("disable memory hash testing"))
+                    .arg(Arg::with_name("symbols")
+                         .long("symbols")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("[REGION[:BANK]=]FILE")
+                         .help("Symbols to show in the disassembly, only while REGION (and BANK) is mapped if given"))
+                    .arg(Arg::with_name("listing")
+                         .long("listing")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("FILE")
+                         .help("lwasm listing to map addresses to source lines"))
                     .arg(Arg::with_name("log-memory")
                          .short("l")
                          .long("log-memory")
                          .help("enable memory logging")))
+
+        .subcommand(rom_trace_args(SubCommand::with_name("diss")
+                    .about("Disassemble a whole ROM to lwasm source"))
+                    .arg(Arg::with_name("annotations")
+                         .long("annotations")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Labels and comments to add, as09 source like utils/vrom.txt or saved by annotate"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write the source to FILE rather than stdout")))
+
+        .subcommand(SubCommand::with_name("annotate")
+                    .about("Line commented as09 source up with a ROM image, or check saved annotations match it")
+                    .arg(Arg::with_name("base")
+                         .long("base")
+                         .takes_value(true)
+                         .value_name("ADDR")
+                         .validator(is_addr)
+                         .help("Load address, by default the ROM ends at $ffff"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write the annotations as json to FILE"))
+                    .arg(Arg::with_name("SOURCE")
+                         .required(true)
+                         .index(1)
+                         .help("as09 source like utils/vrom.txt, or annotations saved by -o"))
+                    .arg(Arg::with_name("ROM FILE")
+                         .required(true)
+                         .index(2)
+                         .help("ROM image they're for")))
+
+        .subcommand(rom_trace_args(SubCommand::with_name("xref")
+                    .about("Cross reference a ROM, what reads, writes, jumps to and calls each address"))
+                    .arg(Arg::with_name("merge")
+                         .long("merge")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("FILE")
+                         .help("Add cross references recorded by emu or simple --xref"))
+                    .arg(Arg::with_name("to")
+                         .long("to")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("SYMBOL|ADDR")
+                         .help("Show what refers to SYMBOL or ADDR"))
+                    .arg(Arg::with_name("kind")
+                         .long("kind")
+                         .takes_value(true)
+                         .possible_values(&["read", "write", "jump", "call"])
+                         .help("Only show references of this kind"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write them all as json to FILE, stdout if there's no --to")))
+
+        .subcommand(SubCommand::with_name("asm")
+                    .about("Assemble 6809 source to a raw binary")
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Binary to write, defaults to the source with a .bin extension"))
+                    .arg(Arg::with_name("symbols")
+                         .short("s")
+                         .long("symbols")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write the symbols as yaml to FILE"))
+                    .arg(Arg::with_name("import")
+                         .long("import")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Symbols the source can use without defining, like syms.yaml"))
+                    .arg(Arg::with_name("dp")
+                         .long("dp")
+                         .takes_value(true)
+                         .validator(is_addr)
+                         .help("Direct page to assume before any setdp, default 0"))
+                    .arg(Arg::with_name("SOURCE")
+                         .required(true)
+                         .index(1)
+                         .help("Source file to assemble")))
+
+        .subcommand(SubCommand::with_name("bench")
+                    .arg(Arg::with_name("JSON FILE")
+                         .required(true)
+                         .index(1)
+                         .help("json log file to load"))
+                    .arg(Arg::with_name("instructions")
+                         .short("n")
+                         .long("instructions")
+                         .takes_value(true)
+                         .validator(is_count)
+                         .help("number of instructions to run (default from the log)"))
+                    .arg(Arg::with_name("cycle-accurate")
+                         .long("cycle-accurate")
+                         .help("Run the cpu bus cycle by cycle")))
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("emu") {
         info!("Running EMU");
-        let mut emu = vectrex::Vectrex::from_matches(matches);
+        let mut emu = rustrex::Vectrex::from_matches(matches);
 
         emu.run();
     }
 
-    if let Some(matches) = matches.subcommand_matches("simple") {
-        info!("Running simple machine");
+    #[cfg(feature = "window")]
+    {
+        if let Some(matches) = matches.subcommand_matches("simple") {
+            info!("Running simple machine");
+
+            let mut simple = rustrex::Simple::from_matches(matches);
+            simple.run();
+        }
+    }
 
-        let mut simple = simple::Simple::from_matches(matches);
-        simple.run();
+    #[cfg(not(feature = "window"))]
+    {
+        if matches.subcommand_matches("simple").is_some() {
+            println!("The simple machine needs rustrex built with the window feature");
+        }
     }
 
 
@@ -156,6 +515,38 @@ fn main() {
     if let Some(matches) = matches.subcommand_matches("test") {
         do_test::<JsonTest>(matches);
     }
+
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        do_test::<Bench>(matches);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("diss") {
+        if let Err(e) = do_diss(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("xref") {
+        if let Err(e) = do_xref(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("annotate") {
+        if let Err(e) = do_annotate(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("asm") {
+        if let Err(e) = do_asm(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -124,8 +124,13 @@ impl MemoryIO for LoggingMemMap {
         self.mem_map.update_sha1(digest)
     }
 
-    fn upload(&mut self, addr : u16, data : &[u8]) {
-        self.mem_map.upload(addr,data);
+    // Debugger access isn't logged
+    fn peek(&self, addr:u16) -> u8 {
+        self.mem_map.peek(addr)
+    }
+
+    fn poke(&mut self, addr:u16, val:u8) {
+        self.mem_map.poke(addr, val)
     }
 
     fn get_name(&self) -> String {
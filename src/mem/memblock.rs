@@ -1,5 +1,5 @@
 use std::vec::Vec;
-use crate::mem::{ MemoryIO, MemMap, MemMapIO };
+use crate::mem::{ MemoryIO, MemMap };
 use sha1::Sha1;
 
 pub struct MemBlock {
@@ -39,7 +39,7 @@ impl MemBlock {
             assert!(last_byte < 0x1_0000);
         }
 
-        let mut r = MemBlock::new(name, writeable, addr, data.len() );
+        let mut r = MemBlock::new(name, !writeable, addr, data.len() );
         r.data = data.to_vec();
         r
     }
@@ -47,24 +47,25 @@ impl MemBlock {
 
 impl MemMap {
     pub fn add_mem_block(&mut self, name : &str, writable : bool, base : u16, size : usize) {
-        let mb = Box::new(MemBlock::new(name, writable, base, size));
-        self.add_memory(mb);
+        self.add_block(MemBlock::new(name, !writable, base, size));
     }
 }
 
 impl MemoryIO for MemBlock {
 
-    fn inspect_byte(&self, addr:u16) -> u8 {
+    fn peek(&self, addr:u16) -> u8 {
         assert!(addr >= self.base && addr <= self.last_mem);
         self.data[(addr - self.base) as usize]
     }
 
-    fn update_sha1(&self, digest : &mut Sha1) {
-        digest.update(&self.data);
+    // Read only or not
+    fn poke(&mut self, addr:u16, val:u8) {
+        assert!(addr >= self.base && addr <= self.last_mem);
+        self.data[(addr - self.base) as usize] = val;
     }
 
-    fn upload(&mut self, _addr : u16, _data : &[u8]) {
-        panic!("not done")
+    fn update_sha1(&self, digest : &mut Sha1) {
+        digest.update(&self.data);
     }
 
     fn get_name(&self) -> String {
@@ -75,11 +76,13 @@ impl MemoryIO for MemBlock {
         (self.base, self.last_mem)
     }
 
+    #[inline]
     fn load_byte(&mut self, addr:u16) -> u8 {
         assert!(addr >= self.base && addr <= self.last_mem);
         self.data[(addr - self.base) as usize]
     }
 
+    #[inline]
     fn store_byte(&mut self, addr:u16, val:u8) {
         assert!(addr >= self.base && addr <= self.last_mem);
         let idx = (addr - self.base) as usize;
@@ -0,0 +1,843 @@
+// Page table memory decoder
+//
+// Each 256 byte page of the address space points at RAM, ROM, a device or
+// nothing. RAM and ROM are slices of one buffer the bus owns so accesses to
+// them never go through a trait object. Devices are owned by the bus too and
+// found by an id the machine picks, a lone device is its own set with ().
+//
+// A page that doesn't follow on evenly from its first address, a device
+// window smaller than a page or a mirror that wraps part way through, gets
+// a table of its own with an entry for every address.
+//
+// Machines lay out their map with map_mem and map_device. Later calls win
+// where they overlap and can be made while running to remap.
+//
+// Reading somewhere nothing answers gives whatever was last on the data
+// bus, the unmapped policy says if that's all that happens. Accesses that
+// should stop the cpu, unmapped ones if that's the policy, writes to ROM if
+// asked for and watchpoints, are held as a MemError for it to take.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use sha1::Sha1;
+
+use crate::mem::{MemoryIO, CheckedMemoryIo, MemError, MemMapping, MemScope, BusAccess};
+
+// The devices on a bus
+pub trait Devices {
+    type Id : Copy + PartialEq + fmt::Debug;
+
+    fn device(&self, id : Self::Id) -> &dyn MemoryIO;
+    fn device_mut(&mut self, id : Self::Id) -> &mut dyn MemoryIO;
+}
+
+impl<T : MemoryIO> Devices for T {
+    type Id = ();
+
+    fn device(&self, _id : ()) -> &dyn MemoryIO {
+        self
+    }
+
+    fn device_mut(&mut self, _id : ()) -> &mut dyn MemoryIO {
+        self
+    }
+}
+
+impl Devices for Vec<Box<dyn MemoryIO>> {
+    type Id = usize;
+
+    fn device(&self, id : usize) -> &dyn MemoryIO {
+        self[id].as_ref()
+    }
+
+    fn device_mut(&mut self, id : usize) -> &mut dyn MemoryIO {
+        self[id].as_mut()
+    }
+}
+
+// For a bus that's only memory
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoDevices;
+
+impl Devices for NoDevices {
+    type Id = ();
+
+    fn device(&self, _id : ()) -> &dyn MemoryIO {
+        panic!("No devices on this bus")
+    }
+
+    fn device_mut(&mut self, _id : ()) -> &mut dyn MemoryIO {
+        panic!("No devices on this bus")
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// What an access to an unmapped address does other than hit the open bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unmapped {
+    OpenBus,
+    Warn,
+    // Stop in the debugger with a MemError::AddressError
+    Halt,
+}
+
+impl Unmapped {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "open-bus" => Some(Unmapped::OpenBus),
+            "warn" => Some(Unmapped::Warn),
+            "halt" => Some(Unmapped::Halt),
+            _ => None,
+        }
+    }
+}
+
+// Bits in MemBus::watches
+const WATCH_READ : u8 = 1;
+const WATCH_WRITE : u8 = 2;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target<Id> {
+    Unmapped,
+    // Index into the bus's memory
+    Ram(usize),
+    Rom(usize),
+    // The device sees the address less the delta
+    Device(Id, u16),
+    // RAM and a device both selected, reads come from the RAM but the device
+    // sees them too, writes go to both
+    Shared(usize, Id, u16),
+}
+
+impl<Id : Copy> Target<Id> {
+    // What the next address would be if it carried on the same way
+    fn next(self) -> Self {
+        match self {
+            Target::Ram(i) => Target::Ram(i + 1),
+            Target::Rom(i) => Target::Rom(i + 1),
+            Target::Shared(i, id, delta) => Target::Shared(i + 1, id, delta),
+            t => t,
+        }
+    }
+
+    fn offset(self, ofs : usize) -> Self {
+        match self {
+            Target::Ram(i) => Target::Ram(i + ofs),
+            Target::Rom(i) => Target::Rom(i + ofs),
+            Target::Shared(i, id, delta) => Target::Shared(i + ofs, id, delta),
+            t => t,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Page<Id> {
+    // Target of the page's first address, the rest follow on from it
+    Whole(Target<Id>),
+    // Index of its own table
+    Split(usize),
+}
+
+// What's mapped where, as a name for symbol scopes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Owner<Id> {
+    Nothing,
+    Block(usize),
+    Device(Id),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockId(usize);
+
+#[derive(Debug, Clone)]
+struct Block {
+    name : String,
+    start : usize,
+    len : usize,
+    read_only : bool,
+}
+
+pub struct MemBus<D : Devices> {
+    name : String,
+    mem : Vec<u8>,
+    blocks : Vec<Block>,
+    pages : [Page<D::Id>; 0x100],
+    splits : Vec<[Target<D::Id>; 0x100]>,
+    // Split tables no page uses any more
+    free_splits : Vec<usize>,
+    // Rebuilt on every remap, banks are kept over that
+    mapping : MemMapping,
+    banks : Vec<(String, Option<u16>)>,
+    devices : D,
+    unmapped : Unmapped,
+    // Last byte read or written, what an open bus reads as
+    last_data : u8,
+    halt_on_rom_write : bool,
+    // WATCH_ bits for every address, only looked at if there are any
+    watches : Vec<u8>,
+    watching : bool,
+    error : Option<MemError>,
+    // Writes to ROM in here are kept for whoever's watching for them, like
+    // a cart's bank register
+    latch : Option<RangeInclusive<u16>>,
+    latched : Option<(u16, u8)>,
+}
+
+impl<D : Devices> fmt::Debug for MemBus<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names : Vec<&str> = self.blocks.iter().map(|b| b.name.as_str()).collect();
+        write!(f, "{} : {}", self.name, names.join(" "))
+    }
+}
+
+impl<D : Devices> MemBus<D> {
+    pub fn new(name : &str, devices : D) -> Self {
+        Self {
+            name : name.to_string(),
+            mem : vec![],
+            blocks : vec![],
+            pages : [Page::Whole(Target::Unmapped); 0x100],
+            splits : vec![],
+            free_splits : vec![],
+            mapping : MemMapping::default(),
+            banks : vec![],
+            devices,
+            unmapped : Unmapped::OpenBus,
+            last_data : 0,
+            halt_on_rom_write : false,
+            watches : vec![0; 0x1_0000],
+            watching : false,
+            error : None,
+            latch : None,
+            latched : None,
+        }
+    }
+
+    fn add_block(&mut self, name : &str, data : &[u8], read_only : bool) -> BlockId {
+        assert!(!data.is_empty(), "{} is empty", name);
+
+        self.blocks.push(Block {
+            name : name.to_string(),
+            start : self.mem.len(),
+            len : data.len(),
+            read_only,
+        });
+
+        self.mem.extend_from_slice(data);
+        BlockId(self.blocks.len() - 1)
+    }
+
+    // Memory that isn't mapped anywhere until it's given to map_mem
+    pub fn add_ram(&mut self, name : &str, size : usize) -> BlockId {
+        self.add_block(name, &vec![0; size], false)
+    }
+
+    // Writes to ROM are dropped, upload still changes it
+    pub fn add_rom(&mut self, name : &str, data : &[u8]) -> BlockId {
+        self.add_block(name, data, true)
+    }
+
+    pub fn block(&self, name : &str) -> Option<BlockId> {
+        self.blocks.iter().position(|b| b.name == name).map(BlockId)
+    }
+
+    pub fn block_data(&self, block : BlockId) -> &[u8] {
+        let b = &self.blocks[block.0];
+        &self.mem[b.start .. b.start + b.len]
+    }
+
+    pub fn is_writable(&self, block : BlockId) -> bool {
+        !self.blocks[block.0].read_only
+    }
+
+    pub fn block_data_mut(&mut self, block : BlockId) -> &mut [u8] {
+        let b = &self.blocks[block.0];
+        &mut self.mem[b.start .. b.start + b.len]
+    }
+
+    pub fn devices(&self) -> &D {
+        &self.devices
+    }
+
+    pub fn devices_mut(&mut self) -> &mut D {
+        &mut self.devices
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Mapping
+
+    // range shows block from offset on, wrapping round to mirror it if the
+    // range is longer than what's left
+    pub fn map_mem(&mut self, range : RangeInclusive<u16>, block : BlockId, offset : usize) {
+        let Block { start, len, read_only, .. } = self.blocks[block.0];
+        let lo = *range.start();
+
+        self.map(range, |addr| {
+            let index = start + (offset + usize::from(addr - lo)) % len;
+
+            if read_only {
+                Target::Rom(index)
+            } else {
+                Target::Ram(index)
+            }
+        })
+    }
+
+    // range shows the device, mirrored if it's bigger than the device is
+    pub fn map_device(&mut self, range : RangeInclusive<u16>, id : D::Id) {
+        let (first, _) = self.devices.device(id).get_range();
+        self.map_device_at(range, id, first)
+    }
+
+    // As map_device but the start of range is dev_addr to the device
+    pub fn map_device_at(&mut self, range : RangeInclusive<u16>, id : D::Id, dev_addr : u16) {
+        let (first, last) = self.devices.device(id).get_range();
+        let size = u32::from(last) - u32::from(first) + 1;
+        let skip = u32::from(dev_addr.wrapping_sub(first));
+        let lo = *range.start();
+
+        self.map(range, |addr| {
+            let dev_addr = u32::from(first) + (skip + u32::from(addr - lo)) % size;
+            Target::Device(id, addr.wrapping_sub(dev_addr as u16))
+        })
+    }
+
+    // range selects both, block is mirrored as map_mem does and the device
+    // as map_device does
+    pub fn map_shared(&mut self, range : RangeInclusive<u16>, block : BlockId, offset : usize, id : D::Id) {
+        let Block { start, len, read_only, .. } = self.blocks[block.0];
+        assert!(!read_only, "{} can't share with a device", self.blocks[block.0].name);
+
+        let (first, last) = self.devices.device(id).get_range();
+        let size = u32::from(last) - u32::from(first) + 1;
+        let lo = *range.start();
+
+        self.map(range, |addr| {
+            let index = start + (offset + usize::from(addr - lo)) % len;
+            let dev_addr = u32::from(first) + u32::from(addr - lo) % size;
+            Target::Shared(index, id, addr.wrapping_sub(dev_addr as u16))
+        })
+    }
+
+    pub fn unmap(&mut self, range : RangeInclusive<u16>) {
+        self.map(range, |_| Target::Unmapped)
+    }
+
+    pub fn is_mapped(&self, addr : u16) -> bool {
+        self.target(addr) != Target::Unmapped
+    }
+
+    pub fn set_unmapped(&mut self, unmapped : Unmapped) {
+        self.unmapped = unmapped
+    }
+
+    // Otherwise they're dropped
+    pub fn set_halt_on_rom_write(&mut self, halt : bool) {
+        self.halt_on_rom_write = halt
+    }
+
+    // Stop when the cpu reads or writes addr
+    pub fn set_watch(&mut self, addr : u16, access : BusAccess, on : bool) {
+        let bit = match access {
+            BusAccess::Read => WATCH_READ,
+            BusAccess::Write => WATCH_WRITE,
+            BusAccess::Dummy => return,
+        };
+
+        let watch = &mut self.watches[usize::from(addr)];
+
+        if on {
+            *watch |= bit
+        } else {
+            *watch &= !bit
+        }
+
+        self.watching = self.watches.iter().any(|w| *w != 0);
+    }
+
+    // Only the first error of an instruction is kept
+    fn raise(&mut self, err : MemError) {
+        if self.error.is_none() {
+            self.error = Some(err)
+        }
+    }
+
+    #[inline(never)]
+    fn check_watch(&mut self, addr : u16, bit : u8) {
+        if self.watches[usize::from(addr)] & bit != 0 {
+            self.raise(if bit == WATCH_READ { MemError::BreakPointRead(addr) } else { MemError::BreakPointWrite(addr) })
+        }
+    }
+
+    // Kept out of line so the mapped case stays small enough to inline
+    #[inline(never)]
+    fn unmapped_access(&mut self, addr : u16, write : bool) {
+        match self.unmapped {
+            Unmapped::OpenBus => (),
+            Unmapped::Warn => {
+                let what = if write { "Write to" } else { "Read from" };
+                warn!("{} unmapped ${:04x}", what, addr)
+            }
+            Unmapped::Halt => self.raise(MemError::AddressError(addr)),
+        }
+    }
+
+    // ROM writes in range are latched rather than dropped, take_latch has
+    // the last one
+    pub fn set_latch(&mut self, range : Option<RangeInclusive<u16>>) {
+        self.latch = range;
+        self.latched = None;
+    }
+
+    pub fn take_latch(&mut self) -> Option<(u16, u8)> {
+        self.latched.take()
+    }
+
+    #[inline(never)]
+    fn rom_write(&mut self, addr : u16, val : u8) {
+        if self.latch.as_ref().is_some_and(|r| r.contains(&addr)) {
+            self.latched = Some((addr, val))
+        } else if self.halt_on_rom_write {
+            self.raise(MemError::IllegalWrite(addr))
+        }
+    }
+
+    // The mapping says which bank of region is in
+    pub fn set_bank(&mut self, region : &str, bank : Option<u16>) {
+        let region = region.to_lowercase();
+        self.mapping.set_bank(&region, bank);
+
+        match self.banks.iter_mut().find(|(r, _)| *r == region) {
+            Some(entry) => entry.1 = bank,
+            None => self.banks.push((region, bank)),
+        }
+    }
+
+    fn map(&mut self, range : RangeInclusive<u16>, target : impl Fn(u16) -> Target<D::Id>) {
+        let (lo, hi) = (*range.start(), *range.end());
+        let mut moved = false;
+
+        for page in usize::from(lo >> 8) ..= usize::from(hi >> 8) {
+            let mut entries = self.entries(page);
+
+            let first = lo.max((page << 8) as u16);
+            let last = hi.min((page << 8 | 0xff) as u16);
+
+            for addr in first ..= last {
+                let entry = &mut entries[usize::from(addr & 0xff)];
+                let new = target(addr);
+                moved |= self.owner_of(*entry) != self.owner_of(new);
+                *entry = new;
+            }
+
+            self.set_page(page, entries);
+        }
+
+        // Bank switches only move where in a block a range points
+        if moved {
+            self.mapping = self.build_mapping();
+        }
+    }
+
+    fn entries(&self, page : usize) -> [Target<D::Id>; 0x100] {
+        match self.pages[page] {
+            Page::Whole(t) => {
+                let mut ret = [t; 0x100];
+
+                for (i, entry) in ret.iter_mut().enumerate() {
+                    *entry = t.offset(i);
+                }
+
+                ret
+            }
+
+            Page::Split(i) => self.splits[i],
+        }
+    }
+
+    fn set_page(&mut self, page : usize, entries : [Target<D::Id>; 0x100]) {
+        let whole = entries.windows(2).all(|w| w[0].next() == w[1]);
+
+        if let Page::Split(i) = self.pages[page] {
+            if whole {
+                self.free_splits.push(i);
+            } else {
+                self.splits[i] = entries;
+                return;
+            }
+        }
+
+        self.pages[page] = if whole {
+            Page::Whole(entries[0])
+        } else if let Some(i) = self.free_splits.pop() {
+            self.splits[i] = entries;
+            Page::Split(i)
+        } else {
+            self.splits.push(entries);
+            Page::Split(self.splits.len() - 1)
+        };
+    }
+
+    #[inline]
+    fn target(&self, addr : u16) -> Target<D::Id> {
+        match self.pages[usize::from(addr >> 8)] {
+            Page::Whole(t) => t.offset(usize::from(addr & 0xff)),
+            Page::Split(i) => self.splits[i][usize::from(addr & 0xff)],
+        }
+    }
+
+    fn owner(&self, addr : u16) -> Owner<D::Id> {
+        self.owner_of(self.target(addr))
+    }
+
+    fn owner_of(&self, target : Target<D::Id>) -> Owner<D::Id> {
+        match target {
+            Target::Unmapped => Owner::Nothing,
+            Target::Device(id, _) => Owner::Device(id),
+            Target::Ram(i) | Target::Rom(i) | Target::Shared(i, ..) => {
+                let block = self.blocks.iter().position(|b| (b.start .. b.start + b.len).contains(&i));
+                block.map(Owner::Block).unwrap_or(Owner::Nothing)
+            }
+        }
+    }
+
+    fn build_mapping(&self) -> MemMapping {
+        let mut ranges = vec![];
+        let mut start = 0u32;
+        let mut owner = self.owner(0);
+
+        for addr in 0 ..= 0xffff_u32 {
+            let next = if addr < 0xffff { Some(self.owner(addr as u16 + 1)) } else { None };
+
+            if next == Some(owner) {
+                continue;
+            }
+
+            let name = match owner {
+                Owner::Nothing => None,
+                Owner::Block(i) => Some(self.blocks[i].name.clone()),
+                Owner::Device(id) => Some(self.devices.device(id).get_name()),
+            };
+
+            if let Some(name) = name {
+                ranges.push((start as u16, addr as u16, MemScope::new(&name, None)));
+            }
+
+            start = addr + 1;
+            owner = next.unwrap_or(Owner::Nothing);
+        }
+
+        let mut ret = MemMapping::new(ranges);
+
+        for (region, bank) in &self.banks {
+            ret.set_bank(region, *bank);
+        }
+
+        ret
+    }
+}
+
+impl<D : Devices> MemoryIO for MemBus<D> {
+    fn peek(&self, addr : u16) -> u8 {
+        match self.target(addr) {
+            Target::Ram(i) | Target::Rom(i) | Target::Shared(i, ..) => self.mem[i],
+            Target::Device(id, delta) => self.devices.device(id).peek(addr.wrapping_sub(delta)),
+            Target::Unmapped => self.last_data,
+        }
+    }
+
+    // Into ROM as well, for loading and patching
+    fn poke(&mut self, addr : u16, val : u8) {
+        match self.target(addr) {
+            Target::Ram(i) | Target::Rom(i) => self.mem[i] = val,
+            Target::Device(id, delta) => self.devices.device_mut(id).poke(addr.wrapping_sub(delta), val),
+            Target::Shared(i, id, delta) => {
+                self.mem[i] = val;
+                self.devices.device_mut(id).poke(addr.wrapping_sub(delta), val)
+            }
+            Target::Unmapped => (),
+        }
+    }
+
+    fn get_range(&self) -> (u16, u16) {
+        (0, 0xffff)
+    }
+
+    fn update_sha1(&self, digest : &mut Sha1) {
+        for b in &self.blocks {
+            digest.update(&self.mem[b.start .. b.start + b.len]);
+        }
+    }
+
+    #[inline]
+    fn load_byte(&mut self, addr : u16) -> u8 {
+        let val = match self.target(addr) {
+            Target::Ram(i) | Target::Rom(i) => self.mem[i],
+            Target::Device(id, delta) => self.devices.device_mut(id).load_byte(addr.wrapping_sub(delta)),
+            Target::Shared(i, id, delta) => {
+                // for the read's side effects, like clearing interrupt flags
+                self.devices.device_mut(id).load_byte(addr.wrapping_sub(delta));
+                self.mem[i]
+            }
+            Target::Unmapped => {
+                self.unmapped_access(addr, false);
+                self.last_data
+            }
+        };
+
+        if self.watching {
+            self.check_watch(addr, WATCH_READ)
+        }
+
+        self.last_data = val;
+        val
+    }
+
+    #[inline]
+    fn store_byte(&mut self, addr : u16, val : u8) {
+        self.last_data = val;
+
+        if self.watching {
+            self.check_watch(addr, WATCH_WRITE)
+        }
+
+        match self.target(addr) {
+            Target::Ram(i) => self.mem[i] = val,
+            Target::Device(id, delta) => self.devices.device_mut(id).store_byte(addr.wrapping_sub(delta), val),
+            Target::Shared(i, id, delta) => {
+                self.mem[i] = val;
+                self.devices.device_mut(id).store_byte(addr.wrapping_sub(delta), val)
+            }
+            Target::Rom(_) => self.rom_write(addr, val),
+            Target::Unmapped => self.unmapped_access(addr, true),
+        }
+    }
+
+    // A device gets words written to it whole
+    fn store_word(&mut self, addr : u16, val : u16) {
+        let next = addr.wrapping_add(1);
+
+        match (self.target(addr), self.target(next)) {
+            (Target::Device(id, delta), Target::Device(next_id, next_delta)) if id == next_id && delta == next_delta => {
+                self.last_data = val as u8;
+
+                if self.watching {
+                    self.check_watch(addr, WATCH_WRITE);
+                    self.check_watch(next, WATCH_WRITE);
+                }
+
+                self.devices.device_mut(id).store_word(addr.wrapping_sub(delta), val)
+            }
+
+            _ => {
+                self.store_byte(addr, (val >> 8) as u8);
+                self.store_byte(next, val as u8);
+            }
+        }
+    }
+
+    fn bus_cycle(&mut self, cycle : u64, addr : u16, access : BusAccess) {
+        if let Target::Device(id, delta) | Target::Shared(_, id, delta) = self.target(addr) {
+            self.devices.device_mut(id).bus_cycle(cycle, addr.wrapping_sub(delta), access)
+        }
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn mapping(&self) -> MemMapping {
+        self.mapping.clone()
+    }
+}
+
+impl<D : Devices> CheckedMemoryIo for MemBus<D> {
+    fn take_error(&mut self) -> Option<MemError> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemBlock;
+
+    type Bus = MemBus<Vec<Box<dyn MemoryIO>>>;
+
+    // 16 registers at $d000
+    const VIA : usize = 0;
+
+    fn bus() -> Bus {
+        let devices : Vec<Box<dyn MemoryIO>> = vec![Box::new(MemBlock::new("via", false, 0xd000, 0x10))];
+        Bus::new("test", devices)
+    }
+
+    fn via(bus : &Bus, reg : u16) -> u8 {
+        bus.devices()[VIA].peek(0xd000 + reg)
+    }
+
+    #[test]
+    fn split_pages() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x100);
+        bus.map_mem(0x1000..=0x10ff, ram, 0);
+        bus.map_device(0x1080..=0x108f, VIA);
+        assert!(matches!(bus.pages[0x10], Page::Split(_)));
+
+        bus.store_byte(0x107f, 1);
+        bus.store_byte(0x1080, 2);
+        bus.store_byte(0x108f, 3);
+        bus.store_byte(0x1090, 4);
+
+        assert_eq!(bus.block_data(ram)[0x7f], 1);
+        assert_eq!(bus.block_data(ram)[0x80], 0);
+        assert_eq!(via(&bus, 0), 2);
+        assert_eq!(via(&bus, 0xf), 3);
+        assert_eq!(bus.load_byte(0x1090), 4);
+
+        // mapping the RAM back makes it whole again and frees the table
+        bus.map_mem(0x1000..=0x10ff, ram, 0);
+        assert_eq!(bus.pages[0x10], Page::Whole(Target::Ram(0)));
+        assert_eq!(bus.free_splits.len(), 1);
+        assert_eq!(bus.load_byte(0x1080), 0);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x400);
+        bus.map_mem(0xc800..=0xcfff, ram, 0);
+        bus.map_device(0xd000..=0xd7ff, VIA);
+
+        bus.store_byte(0xcc05, 0x55);
+        assert_eq!(bus.load_byte(0xc805), 0x55);
+        assert_eq!(bus.block_data(ram)[5], 0x55);
+
+        bus.store_byte(0xd7f3, 0xaa);
+        assert_eq!(via(&bus, 3), 0xaa);
+        assert_eq!(bus.load_byte(0xd013), 0xaa);
+
+        // a mirror that wraps part way through a page
+        let rom = bus.add_rom("rom", &[1, 2, 3]);
+        bus.map_mem(0x0000..=0x00ff, rom, 1);
+        assert_eq!((0 .. 4).map(|a| bus.load_byte(a)).collect::<Vec<_>>(), vec![2, 3, 1, 2]);
+        assert!(matches!(bus.pages[0], Page::Split(_)));
+    }
+
+    #[test]
+    fn shared_reads_ram_and_writes_both() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x400);
+        bus.map_shared(0xd800..=0xdfff, ram, 0, VIA);
+
+        bus.store_byte(0xd812, 0x42);
+        assert_eq!(bus.block_data(ram)[0x12], 0x42);
+        assert_eq!(via(&bus, 2), 0x42);
+
+        bus.devices_mut()[VIA].poke(0xd002, 0x99);
+        assert_eq!(bus.load_byte(0xd812), 0x42);
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x100);
+        bus.map_mem(0x0000..=0x00ff, ram, 0);
+        bus.store_byte(0x0010, 0x5a);
+
+        assert!(!bus.is_mapped(0x8000));
+        assert_eq!(bus.load_byte(0x8000), 0x5a);
+        bus.store_byte(0x8000, 0x33);
+        assert_eq!(bus.load_byte(0x8000), 0x33);
+        assert_eq!(bus.take_error(), None);
+
+        // warn only logs
+        bus.set_unmapped(Unmapped::Warn);
+        bus.load_byte(0x8000);
+        assert_eq!(bus.take_error(), None);
+    }
+
+    #[test]
+    fn halt_on_unmapped() {
+        let mut bus = bus();
+        bus.set_unmapped(Unmapped::Halt);
+
+        bus.load_byte(0x8000);
+        bus.store_byte(0x9000, 0);
+        assert_eq!(bus.take_error(), Some(MemError::AddressError(0x8000)));
+        assert_eq!(bus.take_error(), None);
+    }
+
+    #[test]
+    fn rom_writes() {
+        let mut bus = bus();
+        let rom = bus.add_rom("rom", &[1; 0x100]);
+        bus.map_mem(0xe000..=0xe0ff, rom, 0);
+
+        bus.store_byte(0xe000, 9);
+        assert_eq!(bus.load_byte(0xe000), 1);
+        assert_eq!(bus.take_error(), None);
+
+        bus.set_halt_on_rom_write(true);
+        bus.store_byte(0xe001, 9);
+        assert_eq!(bus.take_error(), Some(MemError::IllegalWrite(0xe001)));
+
+        // a latch keeps them instead
+        bus.set_latch(Some(0xe080..=0xe0ff));
+        bus.store_byte(0xe080, 7);
+        assert_eq!(bus.take_error(), None);
+        assert_eq!(bus.take_latch(), Some((0xe080, 7)));
+        assert_eq!(bus.take_latch(), None);
+
+        bus.poke(0xe002, 9);
+        assert_eq!(bus.peek(0xe002), 9);
+    }
+
+    #[test]
+    fn watches() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x100);
+        bus.map_mem(0x0000..=0x00ff, ram, 0);
+
+        bus.set_watch(0x10, BusAccess::Read, true);
+        bus.set_watch(0x20, BusAccess::Write, true);
+
+        bus.store_byte(0x10, 1);
+        bus.load_byte(0x20);
+        assert_eq!(bus.take_error(), None);
+
+        bus.load_byte(0x10);
+        assert_eq!(bus.take_error(), Some(MemError::BreakPointRead(0x10)));
+
+        bus.store_word(0x1f, 0x1234);
+        assert_eq!(bus.take_error(), Some(MemError::BreakPointWrite(0x20)));
+
+        // peek and poke don't trip them
+        bus.poke(0x20, 0);
+        bus.peek(0x10);
+        assert_eq!(bus.take_error(), None);
+
+        bus.set_watch(0x10, BusAccess::Read, false);
+        bus.set_watch(0x20, BusAccess::Write, false);
+        assert!(!bus.watching);
+
+        bus.load_byte(0x10);
+        bus.store_byte(0x20, 0);
+        assert_eq!(bus.take_error(), None);
+    }
+
+    #[test]
+    fn mapping_names_regions() {
+        let mut bus = bus();
+        let ram = bus.add_ram("ram", 0x400);
+        bus.map_mem(0xc800..=0xcfff, ram, 0);
+        bus.map_device(0xd000..=0xd7ff, VIA);
+        bus.set_bank("ram", Some(1));
+
+        assert_eq!(bus.mapping().report(), vec!["$c800-$cfff ram:1".to_string(), "$d000-$d7ff via".to_string()]);
+    }
+}
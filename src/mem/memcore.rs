@@ -1,31 +1,138 @@
 // memory trait
 use std::vec::Vec;
 use std::ops::Range;
+use std::fmt;
 pub use sha1::Sha1;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MemError {
+    // Nothing's mapped there
     AddressError(u16),
+    // Write to ROM
     IllegalWrite(u16),
+    // Watchpoints
     BreakPointRead(u16),
     BreakPointWrite(u16),
 }
 
-pub fn build_addr_to_region<E : Copy>(illegal : E, mem_tab :  &[(E, &dyn MemoryIO )]) -> [E; 0x1_0000] {
+impl MemError {
+    pub fn addr(&self) -> u16 {
+        match *self {
+            MemError::AddressError(addr)
+            | MemError::IllegalWrite(addr)
+            | MemError::BreakPointRead(addr)
+            | MemError::BreakPointWrite(addr) => addr,
+        }
+    }
 
-    let mut ret = [illegal; 0x1_0000];
+    // Watchpoints let the instruction finish, anything else is a fault
+    pub fn is_watch(&self) -> bool {
+        matches!(self, MemError::BreakPointRead(_) | MemError::BreakPointWrite(_))
+    }
+}
 
-    for (i, id) in ret.iter_mut().enumerate() {
-        for &(this_id, mem) in mem_tab {
-            if mem.is_in_range(i as u16) {
-                *id = this_id;
-            }
+impl fmt::Display for MemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemError::AddressError(addr) => write!(f, "Access to unmapped ${:04x}", addr),
+            MemError::IllegalWrite(addr) => write!(f, "Write to ROM at ${:04x}", addr),
+            MemError::BreakPointRead(addr) => write!(f, "Read watchpoint at ${:04x}", addr),
+            MemError::BreakPointWrite(addr) => write!(f, "Write watchpoint at ${:04x}", addr),
+        }
+    }
+}
+
+// What the cpu is doing on a bus cycle, dummy cycles move no data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusAccess {
+    Read,
+    Write,
+    Dummy,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// What's mapped at an address, symbols can be scoped to a region or to one
+// bank of it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemScope {
+    pub region : String,
+    pub bank : Option<u16>,
+}
+
+impl MemScope {
+    pub fn new(region : &str, bank : Option<u16>) -> Self {
+        Self { region : region.to_lowercase(), bank }
+    }
+
+    // region or region:bank
+    pub fn parse(text : &str) -> Option<Self> {
+        let (region, bank) = match text.split_once(':') {
+            Some((region, bank)) => (region, Some(bank.parse().ok()?)),
+            None => (text, None),
+        };
+
+        if region.is_empty() {
+            None
+        } else {
+            Some(Self::new(region, bank))
         }
     }
 
-    ret
+    // Without a bank this covers every bank of the region
+    pub fn covers(&self, mapped : &MemScope) -> bool {
+        self.region == mapped.region && (self.bank.is_none() || self.bank == mapped.bank)
+    }
 }
 
+impl fmt::Display for MemScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{}:{}", self.region, bank),
+            None => write!(f, "{}", self.region),
+        }
+    }
+}
+
+// The scope over each range of addresses, regions are named by their
+// memory's get_name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemMapping {
+    ranges : Vec<(u16, u16, MemScope)>,
+}
+
+impl MemMapping {
+    pub fn new(ranges : Vec<(u16, u16, MemScope)>) -> Self {
+        Self { ranges }
+    }
+
+    pub fn scope_at(&self, addr : u16) -> Option<&MemScope> {
+        self.ranges
+            .iter()
+            .find(|(lo, hi, _)| (*lo..=*hi).contains(&addr))
+            .map(|(_, _, scope)| scope)
+    }
+
+    // A line per range, the bank's shown for a banked region
+    pub fn report(&self) -> Vec<String> {
+        self.ranges
+            .iter()
+            .map(|(lo, hi, scope)| format!("${:04x}-${:04x} {}", lo, hi, scope))
+            .collect()
+    }
+
+    // Switching banks changes what a region shows
+    pub fn set_bank(&mut self, region : &str, bank : Option<u16>) {
+        for (_, _, scope) in &mut self.ranges {
+            if scope.region == region {
+                scope.bank = bank;
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 fn to_mem_range( address : u16, size :u16 ) -> Range<u32> {
     use std::cmp::min;
     let last_mem = u32::from(address) + u32::from(size);
@@ -41,25 +148,29 @@ pub fn as_bytes(val : u16) -> (u8,u8) {
 }
 
 
-pub trait CheckedMemoryIo {
-    fn inspect_byte(&self, _addr:u16) -> Result<(), MemError>;
-    fn load_byte(&mut self, _addr:u16) -> Result<(), MemError>;
-    fn store_byte(&mut self, _addr:u16, _val:u8) -> Result<(), MemError>;
+// Memory that can refuse an access or stop on one. A refused access doesn't
+// happen, a load gives whatever's on the bus, and the error is held until
+// the cpu takes it, see cpu::step_checked
+pub trait CheckedMemoryIo : MemoryIO {
+    fn take_error(&mut self) -> Option<MemError>;
 }
 
 pub trait MemoryIO {
-    fn inspect_word(&self, _addr:u16) -> u16 {
-        let lo = self.inspect_byte(_addr.wrapping_add(1));
-        let hi = self.inspect_byte(_addr);
+    fn peek_word(&self, addr:u16) -> u16 {
+        let lo = self.peek(addr.wrapping_add(1));
+        let hi = self.peek(addr);
         as_word(lo, hi)
     }
 
     // Min implementation
-    fn inspect_byte(&self, _addr:u16) -> u8 {
-        panic!("TBD")
-    }
 
-    fn upload(&mut self, _addr : u16, _data : &[u8]);
+    // Debugger access, what a read would give without the read's side
+    // effects
+    fn peek(&self, _addr:u16) -> u8;
+
+    // Debugger access, writes ROM and sets device registers without
+    // anything else a write would do
+    fn poke(&mut self, _addr:u16, _val:u8);
 
     fn get_range(&self) -> (u16, u16);
 
@@ -71,11 +182,34 @@ pub trait MemoryIO {
 
     // Min implementation end
 
+    // Load data at addr, ROM included
+    fn upload(&mut self, addr : u16, data : &[u8]) {
+        for (i, val) in data.iter().enumerate() {
+            self.poke(addr.wrapping_add(i as u16), *val)
+        }
+    }
+
 
     fn get_name(&self) -> String {
         "default".to_string()
     }
 
+    // What's mapped where right now, for symbols scoped to a region or bank
+    fn mapping(&self) -> MemMapping {
+        MemMapping::default()
+    }
+
+    // Cycle accurate bus only, see cpu::CycleBus
+
+    // Called with the clock's cycle count before every access or dummy
+    // cycle
+    fn bus_cycle(&mut self, _cycle : u64, _addr : u16, _access : BusAccess) {
+    }
+
+    // A cycle that moves no data, a no-op unless this is the cycle bus
+    fn dummy_cycle(&mut self, _addr : u16) {
+    }
+
     fn get_sha1_string(&self) -> String {
         let mut m = Sha1::new();
         self.update_sha1(&mut m);
@@ -107,7 +241,7 @@ pub trait MemoryIO {
         let mut v : Vec<String> = Vec::new();
 
         for a in r {
-            let b = self.inspect_byte(a as u16);
+            let b = self.peek(a as u16);
             let t = format!("{:02X}", b);
             v.push(t);
         }
@@ -116,3 +250,38 @@ pub trait MemoryIO {
     }
 }
 
+// Reads through peek for code that loads what it looks at, like the
+// disassembler, writes go nowhere
+pub struct PeekOnly<'a, M : MemoryIO>(pub &'a M);
+
+impl<'a, M : MemoryIO> MemoryIO for PeekOnly<'a, M> {
+    fn peek(&self, addr : u16) -> u8 {
+        self.0.peek(addr)
+    }
+
+    fn poke(&mut self, _addr : u16, _val : u8) {
+    }
+
+    fn get_range(&self) -> (u16, u16) {
+        self.0.get_range()
+    }
+
+    fn update_sha1(&self, digest : &mut Sha1) {
+        self.0.update_sha1(digest)
+    }
+
+    fn load_byte(&mut self, addr : u16) -> u8 {
+        self.0.peek(addr)
+    }
+
+    fn store_byte(&mut self, _addr : u16, _val : u8) {
+    }
+
+    fn get_name(&self) -> String {
+        self.0.get_name()
+    }
+
+    fn mapping(&self) -> MemMapping {
+        self.0.mapping()
+    }
+}
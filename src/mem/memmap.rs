@@ -1,5 +1,5 @@
 // use mem::Memory;
-use crate::mem::{ MemoryIO };
+use crate::mem::{ MemoryIO, BusAccess, MemBlock, MemBus, BlockId, Devices };
 use std::fmt;
 use sha1::Sha1;
 
@@ -7,9 +7,20 @@ pub trait MemMapIO {
     fn add_memory(&mut self, mem : Box<dyn MemoryIO> ) ;
 }
 
+// Memory put together at run time from blocks and devices, for tests and
+// tools. Each block keeps its own storage, read only ones are ROM. Where
+// regions overlap the first one added is what's read and writes go to all
+// of them, so layered memory keeps working.
+enum Region {
+    Block(String, u16, u16, BlockId),
+    Device(usize),
+}
+
 pub struct MemMap {
-    all_memory: Vec< Box<dyn MemoryIO>>,
-    name : String,
+    bus : MemBus<Vec<Box<dyn MemoryIO>>>,
+    all_memory : Vec<Region>,
+    // Some regions overlap so writes can't just go through the bus
+    overlapped : bool,
 }
 
 impl fmt::Debug for MemMap {
@@ -19,7 +30,7 @@ impl fmt::Debug for MemMap {
         let mut strs : Vec<String> = Vec::new();
 
         for m in &self.all_memory {
-            strs.push(m.get_name().clone())
+            strs.push(self.region_name(m))
         }
 
         write!(f, "{}", strs.join(" "))
@@ -28,66 +39,203 @@ impl fmt::Debug for MemMap {
 
 impl MemoryIO for MemMap {
 
-    fn update_sha1(&self, digest : &mut Sha1) {
-        for m in &self.all_memory {
-            m.update_sha1(digest);
-        }
+    fn peek(&self, addr : u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    fn poke(&mut self, addr : u16, val : u8) {
+        self.bus.poke(addr, val)
     }
 
-    fn upload(&mut self, addr : u16, data : &[u8]) {
-        for (i, item) in data.iter().enumerate() {
-            let a = addr.wrapping_add(i as u16);
-            self.store_byte(a, *item)
+    fn update_sha1(&self, digest : &mut Sha1) {
+        for m in &self.all_memory {
+            match *m {
+                Region::Block(_, _, _, block) => digest.update(self.bus.block_data(block)),
+                Region::Device(i) => self.bus.devices()[i].update_sha1(digest),
+            }
         }
     }
 
     fn get_name(&self) -> String {
-        self.name.clone()
+        self.bus.get_name()
     }
 
     fn get_range(&self) -> (u16, u16) {
         (0, 0xffff)
     }
 
+    #[inline]
     fn load_byte(&mut self, addr:u16) -> u8 {
-        for m in &mut self.all_memory {
-            if m.is_in_range(addr) {
-                return m.load_byte(addr)
-            }
-        }
-        0
+        self.bus.load_byte(addr)
     }
 
+    #[inline]
     fn store_byte(&mut self, addr:u16, val:u8) {
-        for m in &mut self.all_memory {
-            if m.is_in_range(addr) {
-                m.store_byte(addr, val)
-            }
+        if self.overlapped {
+            self.store_all(addr, val)
+        } else {
+            self.bus.store_byte(addr, val)
+        }
+    }
+
+    fn store_word(&mut self, addr:u16, val:u16) {
+        if self.overlapped {
+            self.store_byte(addr, (val >> 8) as u8);
+            self.store_byte(addr.wrapping_add(1), val as u8);
+        } else {
+            self.bus.store_word(addr, val)
         }
     }
+
+    fn bus_cycle(&mut self, cycle : u64, addr : u16, access : BusAccess) {
+        self.bus.bus_cycle(cycle, addr, access)
+    }
+}
+
+impl Default for MemMap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemMap {
     pub fn new() -> MemMap {
         MemMap {
+            bus : MemBus::new("all memory", vec![]),
             all_memory : Vec::new(),
-            name : "all memory".to_string(),
+            overlapped : false,
+        }
+    }
+
+    pub fn add_block(&mut self, mem : MemBlock) {
+        let (base, last) = mem.get_range();
+
+        let block = if mem.read_only {
+            self.bus.add_rom(&mem.name, &mem.data)
+        } else {
+            let block = self.bus.add_ram(&mem.name, mem.data.len());
+            self.bus.block_data_mut(block).copy_from_slice(&mem.data);
+            block
+        };
+
+        self.map_unmapped(base, last, |bus, lo, hi| bus.map_mem(lo..=hi, block, usize::from(lo - base)));
+        self.all_memory.push(Region::Block(mem.name, base, last, block));
+    }
+
+    fn region_name(&self, region : &Region) -> String {
+        match region {
+            Region::Block(name, ..) => name.clone(),
+            Region::Device(i) => self.bus.devices().device(*i).get_name(),
         }
     }
 
-    // pub fn load_roms<'a>(&mut self, roms : &[(&'a str, u16)]) -> &mut Self{
-    //     use utils::load_file;
-    //     for rom in roms.iter() {
-    //         let data = load_file(rom.0);
-    //         self.upload(rom.1, &data);
-    //     }
-    //     self
-    // }
+    // Every region at addr gets the write, ROM drops it
+    #[inline(never)]
+    fn store_all(&mut self, addr : u16, val : u8) {
+        for region in &self.all_memory {
+            match *region {
+                Region::Block(_, base, last, block) => {
+                    if (base..=last).contains(&addr) && self.bus.is_writable(block) {
+                        self.bus.block_data_mut(block)[usize::from(addr - base)] = val
+                    }
+                }
+
+                Region::Device(i) => {
+                    let dev = &mut self.bus.devices_mut()[i];
+
+                    if dev.is_in_range(addr) {
+                        dev.store_byte(addr, val)
+                    }
+                }
+            }
+        }
+    }
+
+    // Earlier regions keep what they have
+    fn map_unmapped(&mut self, base : u16, last : u16, map : impl Fn(&mut MemBus<Vec<Box<dyn MemoryIO>>>, u16, u16)) {
+        self.overlapped |= (base..=last).any(|a| self.bus.is_mapped(a));
+
+        let mut addr = u32::from(base);
+
+        while addr <= u32::from(last) {
+            if self.bus.is_mapped(addr as u16) {
+                addr += 1;
+                continue;
+            }
+
+            let lo = addr as u16;
+
+            while addr <= u32::from(last) && !self.bus.is_mapped(addr as u16) {
+                addr += 1;
+            }
+
+            map(&mut self.bus, lo, (addr - 1) as u16);
+        }
+    }
 }
 
 impl MemMapIO for MemMap {
     fn add_memory(&mut self, mem : Box<dyn MemoryIO> ) {
-        self.all_memory.push(mem)
+        let (base, last) = mem.get_range();
+        let id = self.bus.devices().len();
+
+        self.bus.devices_mut().push(mem);
+        self.map_unmapped(base, last, |bus, lo, hi| bus.map_device(lo..=hi, id));
+        self.all_memory.push(Region::Device(id));
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name : &str, base : u16, size : usize, writeable : bool) -> MemBlock {
+        MemBlock::from_data(base, name, &vec![0; size], writeable)
+    }
+
+    #[test]
+    fn writes_reach_every_overlapping_block() {
+        let mut mem = MemMap::new();
+        mem.add_block(block("low", 0x0000, 0x100, true));
+        mem.add_block(block("all", 0x0000, 0x1_0000, true));
+
+        mem.store_byte(0x0010, 0x12);
+        mem.store_word(0x0100, 0x3456);
+
+        assert_eq!(mem.load_byte(0x0010), 0x12);
+        assert_eq!(mem.load_word(0x0100), 0x3456);
+
+        // the block underneath got the write too
+        let all = mem.bus.block("all").unwrap();
+        assert_eq!(mem.bus.block_data(all)[0x10], 0x12);
+    }
+
+    #[test]
+    fn first_block_added_is_read() {
+        let mut mem = MemMap::new();
+        mem.add_block(MemBlock::from_data(0x1000, "first", &[1, 2], true));
+        mem.add_block(MemBlock::from_data(0x1000, "second", &[3, 4, 5], true));
+
+        assert_eq!(mem.load_byte(0x1000), 1);
+        assert_eq!(mem.load_byte(0x1001), 2);
+        assert_eq!(mem.load_byte(0x1002), 5);
+    }
+
+    #[test]
+    fn read_only_blocks_drop_writes() {
+        let mut mem = MemMap::new();
+        mem.add_block(MemBlock::from_data(0xe000, "rom", &[0xaa; 0x2000], false));
+        mem.add_block(block("ram", 0x0000, 0x100, true));
+
+        mem.store_byte(0xe000, 0x55);
+        mem.store_byte(0x0000, 0x55);
+        assert_eq!(mem.load_byte(0xe000), 0xaa);
+        assert_eq!(mem.load_byte(0x0000), 0x55);
+
+        // and still do with something layered over them
+        mem.add_block(block("over", 0xe000, 0x10, true));
+        mem.store_byte(0xe001, 0x66);
+        assert_eq!(mem.load_byte(0xe001), 0xaa);
+        assert_eq!(mem.peek(0xe001), 0xaa);
+    }
+}
@@ -1,10 +1,12 @@
 pub mod memcore;
 pub mod memblock;
 pub mod memmap;
+pub mod membus;
 pub mod lmemmap;
 
 pub use self::memcore::*;
 pub use self::memblock::*;
 pub use self::memmap::*;
+pub use self::membus::*;
 pub use self::lmemmap::*;
 
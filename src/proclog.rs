@@ -8,6 +8,7 @@ use regex::Regex;
 
 use crate::mem::{MemoryIO};
 use crate::diss::Disassembler;
+use crate::linetable::LineTable;
 use std::fmt;
 
 #[derive(Debug, Clone, Default)]
@@ -25,6 +26,8 @@ pub struct Step {
     pub mem          : Option<[ u8; 5]>,
     pub cycles       : usize,
     pub digest       : Option<String>,
+    // file:line and text the instruction was assembled from
+    pub source       : Option<String>,
 }
 
 impl fmt::Display for Step {
@@ -47,7 +50,13 @@ impl fmt::Display for Step {
                self.regs,
                diss,
                mem_str,
-               self.cycles)
+               self.cycles)?;
+
+        if let Some(ref source) = self.source {
+            write!(f, " ; {}", source)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -85,6 +94,12 @@ impl Step {
         step
     }
 
+    // Attach the source line for the step's pc, if the listing has one
+    pub fn with_source(mut self, lines : &LineTable) -> Step {
+        self.source = lines.lookup(self.regs.pc).map(|l| l.to_string());
+        self
+    }
+
     pub fn from_string(text :&str) -> Result<Step, String> {
 
         lazy_static!{
@@ -127,7 +142,8 @@ impl Step {
             s    : as_u16("s"),
             u    : as_u16("u"),
             dp   : as_u8("dp"),
-            flags: Flags::new(as_u8_from_bin("flags"))
+            flags: Flags::new(as_u8_from_bin("flags")),
+            .. Default::default()
         };
 
         // println!("{}", as_string("m0"));
@@ -0,0 +1,330 @@
+// Execution profiler
+//
+// An observer that charges every instruction's cycles to its pc and to the
+// calling context it ran in. Contexts come from a shadow call stack and are
+// kept as a tree, one node per distinct chain of call targets, each holding
+// counts per pc.
+//
+// Reports are built from that afterwards with a SymbolTable:
+//   exclusive - cycles of instructions inside the symbol
+//   inclusive - cycles while the symbol was anywhere on the call stack
+// and the same data can be written as folded stacks for flamegraph.pl and
+// friends.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+
+use separator::Separatable;
+
+use crate::cpu::{CallStack, InstructionDecoder, Interrupt, Observer, Regs, RegEnum};
+use crate::symtab::SymbolTable;
+
+// Rows in each part of the text report
+const REPORT_ROWS : usize = 40;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub instructions : u64,
+    pub cycles : u64,
+}
+
+impl Counts {
+    fn add(&mut self, other : &Counts) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+}
+
+// A calling context, root is node 0 and has no target
+#[derive(Debug, Clone, Default)]
+struct Node {
+    target : u16,
+    parent : Option<usize>,
+    by_pc : HashMap<u16, Counts>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    call_stack : CallStack,
+    nodes : Vec<Node>,
+    children : HashMap<(usize, u16), usize>,
+    // Node for each frame on the call stack, outermost first
+    context : Vec<usize>,
+    total : Counts,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            call_stack : CallStack::new(),
+            nodes : vec![Node::default()],
+            children : HashMap::new(),
+            context : vec![],
+            total : Counts::default(),
+        }
+    }
+
+    // For a machine reset, the counts are kept
+    pub fn reset(&mut self) {
+        self.call_stack.reset();
+        self.context.clear();
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    pub fn total(&self) -> Counts {
+        self.total
+    }
+
+    fn current_node(&self) -> usize {
+        self.context.last().cloned().unwrap_or(0)
+    }
+
+    fn child(&mut self, parent : usize, target : u16) -> usize {
+        if let Some(id) = self.children.get(&(parent, target)) {
+            return *id;
+        }
+
+        let id = self.nodes.len();
+
+        self.nodes.push(Node {
+            target,
+            parent : Some(parent),
+            by_pc : HashMap::new(),
+        });
+
+        self.children.insert((parent, target), id);
+        id
+    }
+
+    // Bring the context nodes back in line with the call stack
+    fn sync_context(&mut self) {
+        let depth = self.call_stack.depth();
+
+        self.context.truncate(depth);
+
+        while let Some(&node) = self.context.last() {
+            let i = self.context.len() - 1;
+            if self.nodes[node].target == self.call_stack.frames()[i].target {
+                break;
+            }
+            self.context.pop();
+        }
+
+        while self.context.len() < depth {
+            let target = self.call_stack.frames()[self.context.len()].target;
+            let parent = self.current_node();
+            let node = self.child(parent, target);
+            self.context.push(node);
+        }
+    }
+
+    // Targets from the outermost call in
+    fn node_path(&self, node : usize) -> Vec<u16> {
+        let mut path = vec![];
+        let mut n = node;
+
+        while let Some(parent) = self.nodes[n].parent {
+            path.push(self.nodes[n].target);
+            n = parent;
+        }
+
+        path.reverse();
+        path
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Reports
+
+    pub fn by_pc(&self) -> HashMap<u16, Counts> {
+        let mut ret : HashMap<u16, Counts> = HashMap::new();
+
+        for node in &self.nodes {
+            for (pc, counts) in &node.by_pc {
+                ret.entry(*pc).or_default().add(counts);
+            }
+        }
+
+        ret
+    }
+
+    // Symbol name -> (inclusive, exclusive)
+    pub fn by_symbol(&self, syms : &SymbolTable) -> HashMap<String, (Counts, Counts)> {
+        let mut ret : HashMap<String, (Counts, Counts)> = HashMap::new();
+        let mut pc_names : HashMap<u16, String> = HashMap::new();
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            let path_names : Vec<String> = self.node_path(id)
+                .into_iter()
+                .map(|t| symbol_name(syms, t))
+                .collect();
+
+            for (pc, counts) in &node.by_pc {
+                let pc_name = pc_names
+                    .entry(*pc)
+                    .or_insert_with(|| symbol_name(syms, *pc))
+                    .clone();
+
+                ret.entry(pc_name.clone()).or_default().1.add(counts);
+
+                // Recursion only counts once
+                let mut seen : HashSet<&String> = path_names.iter().collect();
+                seen.insert(&pc_name);
+
+                for name in seen {
+                    ret.entry(name.clone()).or_default().0.add(counts);
+                }
+            }
+        }
+
+        ret
+    }
+
+    // One line per distinct stack, frames separated by ; then the cycles
+    pub fn folded_stacks(&self, syms : &SymbolTable) -> Vec<String> {
+        let mut stacks : HashMap<String, u64> = HashMap::new();
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            let mut path_names : Vec<String> = vec!["root".to_string()];
+
+            path_names.extend(self.node_path(id)
+                .into_iter()
+                .map(|t| symbol_name(syms, t)));
+
+            for (pc, counts) in &node.by_pc {
+                let leaf = symbol_name(syms, *pc);
+
+                let stack = if path_names.last() == Some(&leaf) {
+                    path_names.join(";")
+                } else {
+                    format!("{};{}", path_names.join(";"), leaf)
+                };
+
+                *stacks.entry(stack).or_insert(0) += counts.cycles;
+            }
+        }
+
+        let mut ret : Vec<String> = stacks
+            .into_iter()
+            .map(|(stack, cycles)| format!("{} {}", stack, cycles))
+            .collect();
+
+        ret.sort();
+        ret
+    }
+
+    pub fn report(&self, syms : &SymbolTable) -> String {
+        let total = self.total;
+
+        let percent = |c : u64| {
+            if total.cycles == 0 {
+                0.0
+            } else {
+                c as f64 * 100.0 / total.cycles as f64
+            }
+        };
+
+        let mut out = vec![];
+
+        out.push(format!("instructions: {}", total.instructions.separated_string()));
+        out.push(format!("cycles:       {}", total.cycles.separated_string()));
+        out.push(String::new());
+
+        let mut symbols : Vec<(String, (Counts, Counts))> = self.by_symbol(syms).into_iter().collect();
+        symbols.sort_by(|a, b| (b.1).0.cycles.cmp(&(a.1).0.cycles).then(a.0.cmp(&b.0)));
+
+        out.push("By symbol".to_string());
+        out.push(format!("{:>14} {:>7} {:>14} {:>7} {:>12}  symbol", "inclusive", "%", "exclusive", "%", "instructions"));
+
+        for (name, (incl, excl)) in symbols.iter().take(REPORT_ROWS) {
+            out.push(format!("{:>14} {:>6.2}% {:>14} {:>6.2}% {:>12}  {}",
+                             incl.cycles.separated_string(), percent(incl.cycles),
+                             excl.cycles.separated_string(), percent(excl.cycles),
+                             excl.instructions.separated_string(), name));
+        }
+
+        out.push(String::new());
+
+        let mut pcs : Vec<(u16, Counts)> = self.by_pc().into_iter().collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+
+        out.push("By address".to_string());
+        out.push(format!("{:>14} {:>7} {:>12}  address", "cycles", "%", "instructions"));
+
+        for (pc, counts) in pcs.iter().take(REPORT_ROWS) {
+            let name = syms.lookup_nearest(*pc).map(|n| format!(" {}", n)).unwrap_or_default();
+            out.push(format!("{:>14} {:>6.2}% {:>12}  ${:04x}{}",
+                             counts.cycles.separated_string(), percent(counts.cycles),
+                             counts.instructions.separated_string(), pc, name));
+        }
+
+        out.push(String::new());
+        out.join("\n")
+    }
+
+    pub fn write_report(&self, file_name : &str, syms : &SymbolTable) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        file.write_all(self.report(syms).as_bytes())
+    }
+
+    pub fn write_folded(&self, file_name : &str, syms : &SymbolTable) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+
+        for line in self.folded_stacks(syms) {
+            writeln!(file, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Symbol containing addr, or the address if there isn't one
+fn symbol_name(syms : &SymbolTable, addr : u16) -> String {
+    syms.lookup_containing(addr)
+        .map(|(name, _)| name)
+        .unwrap_or_else(|| format!("${:04x}", addr))
+}
+
+impl Observer for Profiler {
+
+    fn before_execute(&mut self, ins : &InstructionDecoder, regs : &Regs) {
+        // Interrupts have been taken by now so the handler gets charged
+        self.call_stack.before_execute(ins, regs);
+        self.sync_context();
+    }
+
+    fn after_execute(&mut self, ins : &InstructionDecoder, before : &Regs, after : &Regs, cycles : u32) {
+        let counts = Counts { instructions : 1, cycles : u64::from(cycles) };
+
+        let node = self.current_node();
+        self.nodes[node].by_pc.entry(ins.addr).or_default().add(&counts);
+        self.total.add(&counts);
+
+        self.call_stack.after_execute(ins, before, after, cycles);
+    }
+
+    fn mem_read(&mut self, addr : u16, val : u8) {
+        self.call_stack.mem_read(addr, val)
+    }
+
+    fn interrupt(&mut self, int : Interrupt, regs : &Regs) {
+        self.call_stack.interrupt(int, regs)
+    }
+
+    fn stack_push(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.call_stack.stack_push(stack, addr, val)
+    }
+
+    fn stack_pull(&mut self, stack : RegEnum, addr : u16, val : u8) {
+        self.call_stack.stack_pull(stack, addr, val)
+    }
+}
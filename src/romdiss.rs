@@ -0,0 +1,663 @@
+// Whole ROM disassembler
+//
+// Traces code from its entry points to split a ROM image into code and data,
+// then writes the lot out as lwasm source that assembles back to the same
+// bytes with lwasm -9 -r.
+//
+// Entry points are the vectors if the image covers them, the start of code
+// after a Vectrex cart header and any given on the command line. Tracing
+// follows branches, calls, jumps to known addresses and jump tables indexed
+// off a register that was loaded with the table's address.
+//
+// Anything the tracer doesn't reach comes out as data, as does anything
+// lwasm wouldn't encode the same way (undocumented opcodes, odd postbytes).
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{self, Write};
+
+use clap::ArgMatches;
+
+use crate::cpu::{AddrMode, CpuKind, IndexedFlags, IndexModes, RegEnum, get_op_table, op_to_index};
+use crate::diss::{Disassembler, SymTab};
+use crate::mem::{MemBlock, MemMap};
+use crate::symtab::SymbolTable;
+use crate::xref::Xrefs;
+use crate::annotate::Annotations;
+
+// SWI3 up to reset
+const VECTORS : u16 = 0xfff2;
+
+// Longest jump table found without being told how long it is
+const MAX_TABLE : usize = 128;
+
+const FCB_PER_LINE : usize = 8;
+const FCC_PER_LINE : usize = 32;
+
+// Shortest run of printable bytes shown as text
+const MIN_FCC : usize = 4;
+
+// What each byte of the image has been taken as
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Claim {
+    Data,
+    // First byte of an instruction and its length
+    Code(u8),
+    // First byte of an fdb
+    Word,
+    // Rest of an instruction or word
+    Inside,
+}
+
+// Labels by address, equates for symbols that can't be labels and the
+// table operands are looked up in
+struct Labels {
+    labels : BTreeMap<u16, Vec<String>>,
+    equates : Vec<(String, u16)>,
+    table : SymbolTable,
+}
+
+pub struct RomDisassembler {
+    rom_file : String,
+    base : u16,
+    data : Vec<u8>,
+    mem : MemMap,
+    claims : Vec<Claim>,
+    entries : VecDeque<u16>,
+    // Addresses something refers to, they get labels if they can
+    refs : BTreeSet<u16>,
+    dp : Option<u8>,
+    syms : SymbolTable,
+    // Comments to add to the ones giving the address
+    notes : Annotations,
+}
+
+// Hex address with an optional $ or 0x
+pub fn parse_addr(text : &str) -> Result<u16, String> {
+    let hex = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(hex, 16).map_err(|_| format!("{} isn't a hex address", text))
+}
+
+// ADDR:COUNT, the count is in decimal
+fn parse_table(text : &str) -> Result<(u16, usize), String> {
+    let mut parts = text.splitn(2, ':');
+
+    let addr = parse_addr(parts.next().unwrap_or(""))?;
+    let count = parts.next()
+        .ok_or_else(|| format!("{} needs a count, ADDR:COUNT", text))?
+        .parse::<usize>()
+        .map_err(|_| format!("{} has a bad count", text))?;
+
+    Ok((addr, count))
+}
+
+fn is_cart(data : &[u8]) -> bool {
+    data.starts_with(b"g GCE")
+}
+
+// Vectrex strings end with $80
+fn string_end(data : &[u8], from : usize) -> Option<usize> {
+    data.get(from ..)?.iter().position(|b| *b == 0x80).map(|p| from + p)
+}
+
+fn is_printable(b : u8) -> bool {
+    (0x20 .. 0x7f).contains(&b) && b != b'"'
+}
+
+fn index_reg(r : RegEnum) -> Option<usize> {
+    match r {
+        RegEnum::X => Some(0),
+        RegEnum::Y => Some(1),
+        RegEnum::U => Some(2),
+        _ => None,
+    }
+}
+
+fn asm_line(op : &str, operand : &str) -> String {
+    format!("{:16}{:8}{}", "", op, operand)
+}
+
+fn with_comment(line : String, addr : u16, note : Option<&str>) -> String {
+    match note {
+        Some(note) => format!("{:<48}; ${:04x} {}", line, addr, note),
+        None => format!("{:<48}; ${:04x}", line, addr),
+    }
+}
+
+impl RomDisassembler {
+    pub fn new(data : &[u8], base : u16) -> Result<Self, String> {
+        if data.is_empty() || u32::from(base) + data.len() as u32 > 0x1_0000 {
+            return Err(format!("{} bytes won't fit at ${:04x}", data.len(), base));
+        }
+
+        let mut mem = MemMap::new();
+        mem.add_block(MemBlock::from_data(base, "rom", data, false));
+
+        Ok(Self {
+            rom_file : String::new(),
+            base,
+            data : data.to_vec(),
+            mem,
+            claims : vec![Claim::Data; data.len()],
+            entries : VecDeque::new(),
+            refs : BTreeSet::new(),
+            dp : None,
+            syms : SymbolTable::default(),
+            notes : Annotations::new(),
+        })
+    }
+
+    pub fn from_matches(matches : &ArgMatches) -> Result<Self, String> {
+        let rom_file = matches.value_of("ROM FILE").unwrap();
+        let data = std::fs::read(rom_file).map_err(|e| format!("{}: {}", rom_file, e))?;
+
+        // Carts live at 0, anything else is assumed to end at $ffff
+        let base = match matches.value_of("base") {
+            Some(text) => parse_addr(text)?,
+            None if is_cart(&data) => 0,
+            None => (0x1_0000 - data.len().min(0x1_0000)) as u16,
+        };
+
+        let mut ret = Self::new(&data, base)?;
+        ret.rom_file = rom_file.to_string();
+
+        if let Some(dp) = matches.value_of("dp") {
+            ret.dp = Some(parse_addr(dp)? as u8);
+        }
+
+        if let Some(files) = matches.values_of("symbols") {
+            let files : Vec<&str> = files.collect();
+
+            match SymbolTable::from_files(&files) {
+                Ok(syms) => ret.syms = syms,
+                Err(e) => warn!("No symbols loaded, {}", e),
+            }
+        }
+
+        if let Some(tables) = matches.values_of("jump-table") {
+            for text in tables {
+                let (addr, count) = parse_table(text)?;
+                ret.jump_table(addr, count, false);
+            }
+        }
+
+        // Routines only called from outside the ROM, like the Vectrex BIOS
+        if matches.is_present("trace-symbols") {
+            let addrs : Vec<u16> = ret.syms.iter().map(|(_, v)| *v).collect();
+
+            for addr in addrs {
+                ret.add_entry(addr);
+            }
+        }
+
+        if let Some(entries) = matches.values_of("entry") {
+            for text in entries {
+                ret.add_entry(parse_addr(text)?);
+            }
+        }
+
+        // Only names for things, they aren't traced from
+        if let Some(file) = matches.value_of("annotations") {
+            match Annotations::load(file, &data, base) {
+                Ok(notes) => ret.set_annotations(notes),
+                Err(e) => warn!("No annotations loaded, {}", e),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn run(&mut self, out_file : Option<&str>) -> Result<(), String> {
+        self.trace();
+
+        let res = match out_file {
+            Some(file) => File::create(file).and_then(|mut f| self.write(&mut f)),
+            None => self.write(&mut io::stdout()),
+        };
+
+        res.map_err(|e| format!("Couldn't write disassembly : {}", e))?;
+
+        let code = self.claims.iter().filter(|c| **c != Claim::Data).count();
+        info!("{} bytes of code and tables, {} of data", code, self.data.len() - code);
+        Ok(())
+    }
+
+    pub fn set_dp(&mut self, dp : u8) {
+        self.dp = Some(dp)
+    }
+
+    pub fn set_symbols(&mut self, syms : SymbolTable) {
+        self.syms = syms
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.syms
+    }
+
+    // Their labels join the symbols
+    pub fn set_annotations(&mut self, notes : Annotations) {
+        notes.add_labels(&mut self.syms);
+        self.notes = notes
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    fn in_image(&self, addr : u16) -> bool {
+        addr >= self.base && usize::from(addr - self.base) < self.data.len()
+    }
+
+    fn claim_at(&self, addr : u16) -> Option<Claim> {
+        if self.in_image(addr) {
+            Some(self.claims[usize::from(addr - self.base)])
+        } else {
+            None
+        }
+    }
+
+    fn is_free(&self, addr : u16, len : u16) -> bool {
+        (0 .. len).all(|i| self.claim_at(addr.wrapping_add(i)) == Some(Claim::Data))
+    }
+
+    fn claim(&mut self, addr : u16, len : u16, claim : Claim) {
+        let i = usize::from(addr - self.base);
+        self.claims[i] = claim;
+
+        for c in &mut self.claims[i + 1 .. i + usize::from(len)] {
+            *c = Claim::Inside;
+        }
+    }
+
+    fn word_at(&self, addr : u16) -> u16 {
+        let i = usize::from(addr - self.base);
+        (u16::from(self.data[i]) << 8) | u16::from(self.data[i + 1])
+    }
+
+    pub fn add_entry(&mut self, addr : u16) {
+        if self.in_image(addr) {
+            self.refs.insert(addr);
+            self.entries.push_back(addr);
+        }
+    }
+
+    // Words pointing at code, if guessing then stop at the first that
+    // doesn't look like it does
+    pub fn jump_table(&mut self, addr : u16, count : usize, guessing : bool) {
+        self.refs.insert(addr);
+
+        for i in 0 .. count {
+            let at = addr.wrapping_add((i * 2) as u16);
+
+            if !self.is_free(at, 2) {
+                break;
+            }
+
+            let dest = self.word_at(at);
+
+            let looks_like_code = matches!(self.claim_at(dest), Some(Claim::Data) | Some(Claim::Code(_)));
+
+            if guessing && !looks_like_code {
+                break;
+            }
+
+            self.claim(at, 2, Claim::Word);
+            self.add_entry(dest);
+        }
+    }
+
+    fn add_vectors(&mut self) {
+        if self.in_image(VECTORS) && self.in_image(0xffff) {
+            self.jump_table(VECTORS, 7, false);
+        }
+    }
+
+    // Copyright string, music pointer and title lines, returns where the
+    // code starts
+    fn add_cart_header(&mut self) -> Option<u16> {
+        if !is_cart(&self.data) {
+            return None;
+        }
+
+        let mut pos = string_end(&self.data, 0)? + 1;
+
+        if pos + 2 > self.data.len() {
+            return None;
+        }
+
+        let music = self.base + pos as u16;
+        self.claim(music, 2, Claim::Word);
+        self.refs.insert(self.word_at(music));
+        pos += 2;
+
+        // height, width, y, x then text, until a zero
+        while *self.data.get(pos)? != 0 {
+            pos = string_end(&self.data, pos + 4)? + 1;
+        }
+
+        Some(self.base + pos as u16 + 1)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Tracing
+
+    pub fn trace(&mut self) {
+        self.add_vectors();
+
+        if let Some(start) = self.add_cart_header() {
+            self.add_entry(start);
+        }
+
+        while let Some(addr) = self.entries.pop_front() {
+            self.trace_from(addr);
+        }
+    }
+
+    fn trace_from(&mut self, addr : u16) {
+        let ops = get_op_table(CpuKind::Mc6809, false);
+
+        let mut diss = Disassembler::for_lwasm();
+
+        if let Some(dp) = self.dp {
+            diss.set_dp(dp);
+        }
+
+        // Immediate loads of X, Y and U, for finding jump tables
+        let mut loads : [Option<u16>; 3] = [None; 3];
+
+        let mut pc = addr;
+
+        while self.claim_at(pc) == Some(Claim::Data) {
+            let (ins, _) = diss.diss(&self.mem, pc, None);
+            let len = ins.next_addr.wrapping_sub(pc);
+
+            // reset is in the table but nothing assembles it
+            let info = match ops[op_to_index(ins.op_code)] {
+                Some(info) if info.name != "reset" && diss.reassembles() && self.is_free(pc, len) => info,
+                _ => break,
+            };
+
+            self.claim(pc, len, Claim::Code(len as u8));
+
+            let operand = diss.operand_addr();
+            let op_len = if ins.op_code > 0xff { 2 } else { 1 };
+            let postbyte = self.data.get(usize::from(pc - self.base) + op_len).cloned().unwrap_or(0);
+
+            let is_pointer_load = matches!(ins.op_code, 0x8e | 0x108e | 0xce | 0x10ce);
+
+            if info.mode != AddrMode::Immediate || is_pointer_load {
+                if let Some(a) = operand {
+                    self.refs.insert(a);
+                }
+            }
+
+            match info.name {
+                "bra" | "lbra" => {
+                    self.add_entry(operand.unwrap());
+                    break;
+                }
+
+                "brn" | "lbrn" => (),
+
+                "jmp" => {
+                    self.follow_jump(info.mode, operand, postbyte, &loads);
+                    break;
+                }
+
+                "jsr" => self.follow_jump(info.mode, operand, postbyte, &loads),
+
+                "rts" | "rti" => break,
+
+                "puls" | "pulu" if postbyte & 0x80 != 0 => break,
+
+                "tfr" if postbyte & 0xf == 5 => break,
+
+                "exg" if postbyte >> 4 == 5 || postbyte & 0xf == 5 => break,
+
+                // Conditional branches, bsr and lbsr
+                _ if info.mode == AddrMode::Relative => self.add_entry(operand.unwrap()),
+
+                _ => (),
+            }
+
+            // Keep track of what X, Y and U were loaded with
+            match info.name {
+                "ldx" | "ldy" | "ldu" | "leax" | "leay" | "leau" => {
+                    let r = match info.name.chars().last() {
+                        Some('x') => 0,
+                        Some('y') => 1,
+                        _ => 2,
+                    };
+
+                    let is_pcr = info.mode == AddrMode::Indexed && (postbyte == 0x8c || postbyte == 0x8d);
+
+                    loads[r] = if is_pointer_load || is_pcr { operand } else { None };
+                }
+
+                "abx" => loads[0] = None,
+
+                "tfr" | "exg" | "puls" | "pulu" => loads = [None; 3],
+
+                _ => (),
+            }
+
+            pc = ins.next_addr;
+        }
+    }
+
+    fn follow_jump(&mut self, mode : AddrMode, operand : Option<u16>, postbyte : u8, loads : &[Option<u16>; 3]) {
+        match mode {
+            AddrMode::Direct | AddrMode::Extended => {
+                if let Some(dest) = operand {
+                    self.add_entry(dest);
+                }
+            }
+
+            AddrMode::Indexed => {
+                let flags = IndexedFlags::new(postbyte);
+                let indirect = flags.is_indirect();
+
+                match flags.get_index_type() {
+                    // Through a pointer
+                    IndexModes::Ea | IndexModes::PCAddi8 | IndexModes::PCAddi16 if indirect => {
+                        if let Some(ptr) = operand {
+                            self.jump_table(ptr, 1, true);
+                        }
+                    }
+
+                    IndexModes::PCAddi8 | IndexModes::PCAddi16 => {
+                        if let Some(dest) = operand {
+                            self.add_entry(dest);
+                        }
+                    }
+
+                    IndexModes::RZero(r) => {
+                        if let Some(addr) = index_reg(r).and_then(|i| loads[i]) {
+                            if indirect {
+                                self.jump_table(addr, 1, true);
+                            } else {
+                                self.add_entry(addr);
+                            }
+                        }
+                    }
+
+                    // jmp [b,x] and friends with x pointing at a table
+                    IndexModes::RAddA(r) | IndexModes::RAddB(r) | IndexModes::RAddD(r) if indirect => {
+                        if let Some(addr) = index_reg(r).and_then(|i| loads[i]) {
+                            self.jump_table(addr, MAX_TABLE, true);
+                        }
+                    }
+
+                    _ => (),
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    // Static cross references from everything trace took as code
+    pub fn add_xrefs(&mut self, xrefs : &mut Xrefs) {
+        let mut diss = Disassembler::for_lwasm();
+
+        if let Some(dp) = self.dp {
+            diss.set_dp(dp);
+        }
+
+        let code : Vec<u16> = self.claims
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(c, Claim::Code(_)))
+            .map(|(i, _)| self.base + i as u16)
+            .collect();
+
+        for addr in code {
+            if let Some(ins) = diss.decode(&self.mem, addr) {
+                xrefs.add_decoded(&ins);
+            }
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Output
+
+    fn labels(&self) -> Labels {
+        let mut labels : BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        let mut equates = vec![];
+        let mut table = SymbolTable::default();
+
+        let can_label = |addr : u16| !matches!(self.claim_at(addr), Some(Claim::Inside) | None);
+
+        for (name, val) in self.syms.iter() {
+            if can_label(*val) {
+                labels.entry(*val).or_default().push(name.clone());
+            } else {
+                equates.push((name.clone(), *val));
+            }
+
+            table.add(name.clone(), *val);
+        }
+
+        for addr in self.refs.iter().cloned().filter(|a| can_label(*a)) {
+            labels.entry(addr).or_insert_with(|| {
+                let name = format!("L_{:04X}", addr);
+                table.add(name.clone(), addr);
+                vec![name]
+            });
+        }
+
+        equates.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        Labels { labels, equates, table }
+    }
+
+    pub fn write(&mut self, out : &mut dyn Write) -> io::Result<()> {
+        let Labels { labels, equates, table } = self.labels();
+
+        writeln!(out, "; Disassembly of {}", self.rom_file)?;
+        writeln!(out, "; lwasm -9 -r to assemble")?;
+        writeln!(out)?;
+
+        for (name, val) in &equates {
+            writeln!(out, "{:<23} equ     ${:04X}", name, val)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{}", asm_line("org", &format!("${:04X}", self.base)))?;
+
+        if let Some(dp) = self.dp {
+            writeln!(out, "{}", asm_line("setdp", &format!("${:02X}", dp)))?;
+        }
+
+        let mut diss = Disassembler::for_lwasm();
+
+        if let Some(dp) = self.dp {
+            diss.set_dp(dp);
+        }
+
+        let mut i = 0;
+
+        while i < self.data.len() {
+            let addr = self.base + i as u16;
+
+            if let Some(names) = labels.get(&addr) {
+                writeln!(out)?;
+                for name in names {
+                    writeln!(out, "{}", name)?;
+                }
+            }
+
+            match self.claims[i] {
+                Claim::Code(len) => {
+                    let (_, txt) = diss.diss(&self.mem, addr, Some(&table));
+                    let mut parts = txt.trim().splitn(2, ' ');
+                    let op = parts.next().unwrap_or("");
+                    let operand = parts.next().unwrap_or("").trim();
+
+                    writeln!(out, "{}", with_comment(asm_line(op, operand), addr, self.notes.comment(addr)))?;
+                    i += usize::from(len);
+                }
+
+                Claim::Word => {
+                    let word = self.word_at(addr);
+                    let operand = table.get_near_symbol(word).unwrap_or_else(|| format!("${:04X}", word));
+
+                    writeln!(out, "{}", with_comment(asm_line("fdb", &operand), addr, self.notes.comment(addr)))?;
+                    i += 2;
+                }
+
+                _ => {
+                    // Up to the next thing that isn't plain data or has a label
+                    let mut end = i + 1;
+
+                    while end < self.data.len() && self.claims[end] == Claim::Data && !labels.contains_key(&(self.base + end as u16)) {
+                        end += 1;
+                    }
+
+                    for (ofs, line) in data_lines(&self.data[i .. end]) {
+                        let addr = addr + ofs as u16;
+                        writeln!(out, "{}", with_comment(line, addr, self.notes.comment(addr)))?;
+                    }
+
+                    i = end;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// fcc for runs of text, fcb for the rest, with each line's offset
+fn data_lines(data : &[u8]) -> Vec<(usize, String)> {
+    let mut ret = vec![];
+    let mut i = 0;
+
+    while i < data.len() {
+        let text_len = data[i ..].iter().take_while(|b| is_printable(**b)).count();
+
+        if text_len >= MIN_FCC {
+            let len = text_len.min(FCC_PER_LINE);
+            let text : String = data[i .. i + len].iter().map(|b| *b as char).collect();
+            ret.push((i, asm_line("fcc", &format!("\"{}\"", text))));
+            i += len;
+
+        } else {
+            // Bytes up to the next bit of text
+            let mut end = i;
+
+            while end < data.len() && end - i < FCB_PER_LINE {
+                if data[end ..].iter().take_while(|b| is_printable(**b)).count() >= MIN_FCC {
+                    break;
+                }
+                end += 1;
+            }
+
+            let end = end.max(i + 1);
+
+            let bytes : Vec<String> = data[i .. end].iter().map(|b| format!("${:02X}", b)).collect();
+            ret.push((i, asm_line("fcb", &bytes.join(","))));
+            i = end;
+        }
+    }
+
+    ret
+}
@@ -12,6 +12,12 @@ IO
                 b4 = Fire 1
                 b5 = Fire 2
     9831  switches 2
+
+    9833  R vblank irq status, reading acknowledges the irq
+          W b0 = enable vblank irq
+
+    Every vblank also pulses a sync line, separate from the irq, that
+    wakes a SYNC with the irq disabled so code can wait for a frame
 */
 
 use crate::mem::*;
@@ -20,6 +26,7 @@ const IO_BASE   : u16   = 0x9800;
 const IO_RASTER : u16   = 0x9830;
 const IO_SW_1   : u16   = 0x9831;
 const IO_SW_2   : u16   = 0x9832;
+const IO_IRQ    : u16   = 0x9833;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 struct Color {
@@ -32,16 +39,40 @@ struct Color {
 pub struct Io {
     pub palette : [u8 ; 16 * 3],
     halt : bool,
+    vblank_irq_enable : bool,
+    vblank_irq : bool,
+    vblank_pulse : bool,
 }
 
 impl Io {
     pub fn new() -> Self {
         Self {
             palette : [0; 16 * 3] ,
-            halt : false
+            halt : false,
+            vblank_irq_enable : false,
+            vblank_irq : false,
+            vblank_pulse : false,
         }
     }
 
+    pub fn vblank(&mut self) {
+        self.vblank_pulse = true;
+
+        if self.vblank_irq_enable {
+            self.vblank_irq = true;
+        }
+    }
+
+    // Whether there's been a vblank since last asked, for the sync line
+    pub fn take_vblank_pulse(&mut self) -> bool {
+        std::mem::replace(&mut self.vblank_pulse, false)
+    }
+
+    // State of the irq line into the cpu
+    pub fn irq(&self) -> bool {
+        self.vblank_irq
+    }
+
     pub fn clear_halt(&mut self) {
         self.halt = false;
     }
@@ -57,16 +88,25 @@ impl Io {
 
 impl MemoryIO for Io {
 
-    fn inspect_byte(&self, addr:u16) -> u8 {
+    fn peek(&self, addr:u16) -> u8 {
         if Io::is_palette(addr) {
             self.palette[addr.wrapping_sub(IO_BASE) as usize]
+        } else if addr == IO_RASTER {
+            0xff
+        } else if addr == IO_IRQ {
+            self.vblank_irq as u8
         } else {
             0
         }
     }
 
-    fn upload(&mut self, _addr : u16, _data : &[u8]) {
-        panic!("TBD")
+    // Sets the irq flag itself rather than its enable
+    fn poke(&mut self, addr:u16, val:u8) {
+        if Io::is_palette(addr) {
+            self.palette[addr.wrapping_sub(IO_BASE) as usize] = val
+        } else if addr == IO_IRQ {
+            self.vblank_irq = val & 1 == 1
+        }
     }
 
     fn get_range(&self) -> (u16, u16) {
@@ -84,6 +124,10 @@ impl MemoryIO for Io {
             self.palette[addr.wrapping_sub(IO_BASE) as usize]
         } else if addr == IO_RASTER {
             0xff
+        } else if addr == IO_IRQ {
+            let ret = self.vblank_irq as u8;
+            self.vblank_irq = false;
+            ret
         } else {
             0
         }
@@ -95,6 +139,12 @@ impl MemoryIO for Io {
         } else if addr == IO_RASTER {
             // if you write to IO_RASTER the cpu will halt until vsync
             self.halt = true
+        } else if addr == IO_IRQ {
+            self.vblank_irq_enable = val & 1 == 1;
+
+            if !self.vblank_irq_enable {
+                self.vblank_irq = false;
+            }
         }
     }
 
@@ -23,7 +23,14 @@ use crate::cpu;
 use crate::filewatcher::FileWatcher;
 
 use clap::{ArgMatches};
-use crate::cpu::{Regs, StandardClock};
+use crate::cpu::{Regs, StandardClock, Interrupts, RunState, CpuKind, UndocMode, BusMode, CallStack, Both};
+use crate::cpu::{InstructionDecoder, CpuErr};
+use crate::symtab::{SymbolTable, SymbolicFrame, SymKind};
+use crate::linetable::LineTable;
+use crate::diss::{Disassembler, SymTab};
+use crate::assembler::assemble_line;
+use crate::profiler::Profiler;
+use crate::xref::{Xrefs, XrefKind, parse_target};
 
 use crate::mem::*;
 
@@ -34,6 +41,7 @@ use std::rc::Rc;
 use std::cell::RefCell;
 
 use crate::gdbstub::{ ThreadedGdb, Message, Sigs};
+use crate::gdbstub;
 
 use crate::utils;
 use crate::state;
@@ -52,6 +60,7 @@ pub enum SimEvent {
     Debugger(Message),
     Halt(Sigs),
     HitSync,
+    HitCwai,
     Pause,
     Quit,
     RomChanged,
@@ -89,21 +98,7 @@ const SCR_BYTES : usize = W * H * 3;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum MemRegion {
-    Illegal,
-    Ram,
-    IO,
-    Screen,
-}
-
-struct SimpleMem {
-    pub ram            : MemBlock,
-    pub screen         : MemBlock,
-    pub io             : Io,
-    addr_to_region     : [MemRegion; 0x1_0000],
-    name               : String,
-}
+type SimpleMem = MemBus<Io>;
 
 fn pix_to_rgb(p : u8, palette : &[u8], dest : &mut[u8])  {
     let p = p as usize;
@@ -129,94 +124,18 @@ fn to_rgb(mem : &[u8], palette : &[u8]) -> [u8; SCR_BYTES]{
     ret
 }
 
-impl SimpleMem {
-    pub fn new() -> Self {
-
-        let screen    = MemBlock::new("screen", false, 0x0000,0x9800);
-        let ram       = MemBlock::new("ram", false, 0x9900, 0x1_0000 - 0x9900);
-        let name      = "simple".to_string();
-        let io        = Io::new();
-
-        let addr_to_region = {
-
-            use self::MemRegion::*;
-
-            let mems : &[(MemRegion, &dyn MemoryIO )] = &[
-                (IO, &io),
-                (Screen, &screen ),
-                (Ram, &ram ), ];
-
-            build_addr_to_region(Illegal, mems)
-        };
-
-        SimpleMem {
-            ram,screen,name, addr_to_region, io
-        }
-    }
-
-    fn get_region(&self, _addr : u16) -> &dyn MemoryIO {
-        let region = self.addr_to_region[_addr as usize];
-
-        use self::MemRegion::*;
-
-        match region {
-            Ram       => &self.ram,
-            IO        => &self.io,
-            Screen    => &self.screen,
-            Illegal   => panic!("Illegal! inspect from {:02x}", _addr),
-        }
-    }
-
-    fn get_region_mut(&mut self, _addr : u16) -> &mut dyn MemoryIO {
-        let region = self.addr_to_region[_addr as usize];
-        use self::MemRegion::*;
-
-        match region {
-            Ram       => &mut self.ram,
-            IO        => &mut self.io,
-            Screen    => &mut self.screen,
-            Illegal   => panic!("Illegal! inspect from {:02x}", _addr),
-        }
-    }
-
-}
-
-impl MemoryIO for SimpleMem {
-    fn upload(&mut self, addr : u16, _data : &[u8]) {
-        let mut addr = addr;
-
-        for i in _data {
-            self.store_byte(addr, *i);
-            addr = addr.wrapping_add(1);
-        }
-    }
-
-    fn get_range(&self) -> (u16, u16) {
-        (0, 0xffff)
-    }
-
-    fn update_sha1(&self, _digest : &mut Sha1) {
-        unimplemented!("TBD")
-    }
-
-    fn inspect_byte(&self, addr:u16) -> u8 {
-        let reg = self.get_region(addr);
-        reg.inspect_byte(addr)
-    }
+// Screen, then the io page, then ram
+fn make_simple_mem() -> (SimpleMem, BlockId) {
+    let mut mem = MemBus::new("simple", Io::new());
 
-    fn load_byte(&mut self, addr:u16) -> u8 {
-        let reg = self.get_region_mut(addr);
-        reg.load_byte(addr)
-    }
+    let screen = mem.add_ram("screen", 0x9800);
+    let ram = mem.add_ram("ram", 0x1_0000 - 0x9900);
 
-    fn store_byte(&mut self, addr:u16, val:u8) {
-        let reg = self.get_region_mut(addr);
-        reg.store_byte(addr, val)
-    }
+    mem.map_mem(0x0000..=0x97ff, screen, 0);
+    mem.map_device(0x9800..=0x98ff, ());
+    mem.map_mem(0x9900..=0xffff, ram, 0);
 
-    fn get_name(&self) -> String {
-        self.name.clone()
-    }
+    (mem, screen)
 }
 
 
@@ -238,7 +157,9 @@ I: Iterator<Item = &'a u8>,
 
 pub struct Simple {
     regs         : Regs,
+    ints         : Interrupts,
     mem          : SimpleMem,
+    screen       : BlockId,
     rc_clock     : Rc<RefCell<StandardClock>>,
     file         : Option<String>,
     watcher      : Option<FileWatcher>,
@@ -248,14 +169,23 @@ pub struct Simple {
     gdb          : ThreadedGdb,
     break_points : BreakPoints,
     verbose      : bool,
+    call_stack   : CallStack,
+    syms         : SymbolTable,
+    lines        : LineTable,
+    profiler     : Option<Profiler>,
+    profile_file : Option<String>,
+    folded_file  : Option<String>,
+    xrefs        : Option<Xrefs>,
+    xref_file    : Option<String>,
 }
 
 impl Simple {
     pub fn new() -> Self {
         let rc_clock = Rc::new(RefCell::new(StandardClock::new(2_000_000)));
 
-        let mem = SimpleMem::new();
+        let (mem, screen) = make_simple_mem();
         let regs = Regs::new();
+        let ints = Interrupts::new();
         let win = crate::window::Window::new("my lovely window", DIMS);
 
         let gdb = ThreadedGdb::new();
@@ -265,7 +195,15 @@ impl Simple {
         let verbose = false;
 
         Simple {
-            mem, regs, rc_clock, win, gdb, break_points, verbose,
+            mem, screen, regs, ints, rc_clock, win, gdb, break_points, verbose,
+            call_stack : CallStack::new(),
+            syms       : SymbolTable::default(),
+            lines      : LineTable::default(),
+            profiler     : None,
+            profile_file : None,
+            folded_file  : None,
+            xrefs        : None,
+            xref_file    : None,
             file    : None,
             watcher : None,
             events  : vec![],
@@ -284,21 +222,50 @@ impl Simple {
         } else {
 
             if self.verbose {
-                info!("dissassembly here : {:02x}", self.regs.pc);
+                let pc = self.regs.pc;
+
+                if let Some(source) = self.source_line(Some(pc)) {
+                    info!("{}", source);
+                }
+
+                for line in self.disassemble(Some(pc), 1) {
+                    info!("{}", line);
+                }
             }
 
 
-            let res = cpu::step(&mut self.regs, &mut self.mem, &self.rc_clock);
+            let irq = self.mem.devices().irq();
+            self.ints.set_irq(irq);
+
+            if self.mem.devices_mut().take_vblank_pulse() {
+                self.ints.sync_pulse();
+            }
+
+            let was = self.ints.get_run_state();
+            let res = self.step_cpu();
 
             let ret =  match res {
-                Ok(i) => {
-                    if i.op_code == 0x13 {
-                        Some(SimEvent::HitSync)
+                // Only when it starts waiting, not every step it waits
+                Ok(_) if self.ints.get_run_state() == was => None,
+
+                Ok(_) => {
+                    match self.ints.get_run_state() {
+                        RunState::Syncing => Some(SimEvent::HitSync),
+                        RunState::Waiting => Some(SimEvent::HitCwai),
+                        RunState::Running => None,
+                    }
+                }
+                Err(CpuErr::Memory(err)) => {
+                    warn!("{} by instruction at ${:04x}", err, self.regs.pc);
+
+                    if err.is_watch() {
+                        Some(SimEvent::Halt(Sigs::SIGTRAP))
                     } else {
-                        None
+                        Some(SimEvent::Halt(Sigs::SIGSEGV))
                     }
                 }
-                Err(_cpu_err) => {
+                Err(cpu_err) => {
+                    warn!("cpu error {:?}", cpu_err);
                     Some(SimEvent::Halt(Sigs::SIGILL))
                 }
             };
@@ -311,11 +278,130 @@ impl Simple {
         }
     }
 
+    // Watchpoints are checked by the memory as it's accessed
+    fn set_watch(&mut self, bp_type : &crate::gdbstub::BreakPointTypes, addr : u16, on : bool) {
+        use crate::gdbstub::BreakPointTypes::*;
+
+        match bp_type {
+            Read => self.mem.set_watch(addr, BusAccess::Read, on),
+            Write => self.mem.set_watch(addr, BusAccess::Write, on),
+            Exec => (),
+        }
+    }
+
+    // The profiler keeps its own call stack when it's running, xrefs are
+    // recorded alongside whichever it is
+    fn step_cpu(&mut self) -> Result<InstructionDecoder, CpuErr> {
+        match (self.profiler.as_mut(), self.xrefs.as_mut()) {
+            (Some(profiler), Some(xrefs)) => cpu::step_checked(&mut self.regs, &mut self.mem, &self.rc_clock, &mut self.ints, &mut Both(profiler, xrefs)),
+            (Some(profiler), None) => cpu::step_checked(&mut self.regs, &mut self.mem, &self.rc_clock, &mut self.ints, profiler),
+            (None, Some(xrefs)) => cpu::step_checked(&mut self.regs, &mut self.mem, &self.rc_clock, &mut self.ints, &mut Both(&mut self.call_stack, xrefs)),
+            (None, None) => cpu::step_checked(&mut self.regs, &mut self.mem, &self.rc_clock, &mut self.ints, &mut self.call_stack),
+        }
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        match self.profiler {
+            Some(ref profiler) => profiler.call_stack(),
+            None => &self.call_stack,
+        }
+    }
+
+    fn write_profile(&self) {
+        if let Some(ref profiler) = self.profiler {
+            if let Some(ref file) = self.profile_file {
+                match profiler.write_report(file, &self.syms) {
+                    Ok(_) => info!("Wrote profile to {}", file),
+                    Err(e) => warn!("Couldn't write profile to {} : {}", file, e),
+                }
+            }
+
+            if let Some(ref file) = self.folded_file {
+                match profiler.write_folded(file, &self.syms) {
+                    Ok(_) => info!("Wrote folded stacks to {}", file),
+                    Err(e) => warn!("Couldn't write folded stacks to {} : {}", file, e),
+                }
+            }
+        }
+    }
+
+    fn write_xrefs(&self) {
+        if let (Some(xrefs), Some(file)) = (self.xrefs.as_ref(), self.xref_file.as_ref()) {
+            match xrefs.write(file, &self.syms) {
+                Ok(_) => info!("Wrote {} cross referenced addresses to {}", xrefs.len(), file),
+                Err(e) => warn!("Couldn't write cross references : {}", e),
+            }
+        }
+    }
+
+    // What refers to target, a symbol or an address
+    pub fn xrefs(&self, target : &str, kind : Option<XrefKind>) -> Result<Vec<String>, String> {
+        let xrefs = self.xrefs.as_ref().ok_or("Not recording cross references, run with --xref")?;
+        let addr = parse_target(target, &self.syms)?;
+        let syms = self.syms.mapped(self.mem.mapping());
+        Ok(xrefs.report(addr, kind, &syms))
+    }
+
+    pub fn run_state(&self) -> RunState {
+        self.ints.get_run_state()
+    }
+
     pub fn reset(&mut self) {
+        self.ints.reset();
+        self.call_stack.reset();
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.reset();
+        }
         cpu::reset(&mut self.regs, &mut self.mem);
         info!("Reset! pc=${:03x}", self.regs.pc);
     }
 
+    // Symbols are looked up through whatever's mapped now
+    pub fn backtrace(&self) -> Vec<SymbolicFrame> {
+        self.syms.mapped(self.mem.mapping()).backtrace(self.call_stack(), self.regs.pc)
+    }
+
+    // Symbolic disassembly from addr, or the pc if there isn't one
+    pub fn disassemble(&mut self, addr : Option<u16>, count : usize) -> Vec<String> {
+        let mut diss = Disassembler::with_cpu(self.regs.cpu);
+        diss.set_dp(self.regs.dp);
+
+        let addr = addr.unwrap_or(self.regs.pc);
+        let syms = self.syms.mapped(self.mem.mapping());
+        diss.diss_lines(&self.mem, addr, count, Some(&syms as &dyn SymTab))
+    }
+
+    // Assemble text into memory at addr with the current dp
+    pub fn assemble(&mut self, addr : u16, text : &str) -> Result<Vec<u8>, String> {
+        let bytes = assemble_line(text, addr, Some(&self.syms), Some(self.regs.dp))?;
+        self.mem.upload(addr, &bytes);
+        Ok(bytes)
+    }
+
+    // Where addr, or the pc if there isn't one, is in the listings
+    pub fn source_line(&self, addr : Option<u16>) -> Option<String> {
+        let addr = addr.unwrap_or(self.regs.pc);
+        self.lines.lookup(addr).map(|l| l.to_string())
+    }
+
+    // The address of file:line in the listings
+    pub fn find_line(&self, file : &str, line : usize) -> Result<u16, String> {
+        self.lines.find_line(file, line).map(|l| l.addr)
+    }
+
+    fn log_backtrace(&self) {
+        info!("Backtrace:");
+
+        for (i, frame) in self.backtrace().iter().enumerate() {
+            info!("  #{:<2} {}", i, frame);
+        }
+
+        for mismatch in self.call_stack().mismatches() {
+            info!("  call stack mismatch {:?}", mismatch);
+        }
+    }
+
     fn handle_file_watcher(&mut self)  {
         let mut has_changed = false;
 
@@ -340,11 +426,69 @@ impl Simple {
             let data = utils::load_file(&file);
             self.mem.upload(0x9900, &data);
             info!("Loaded ROM: {}", file);
+
+            // What the new ROM's code refers to, along with what's been
+            // seen running so far
+            if let Some(ref mut xrefs) = self.xrefs {
+                xrefs.clear_static();
+
+                if let Err(e) = xrefs.add_rom(&data, 0x9900, &self.syms) {
+                    warn!("No static cross references, {}", e);
+                }
+            }
         }
     }
 
     pub fn from_matches(matches : &ArgMatches) -> Self {
         let mut ret = Self::new();
+
+        if let Some(cpu) = matches.value_of("cpu").and_then(CpuKind::from_name) {
+            ret.regs = Regs::with_cpu(cpu);
+        }
+
+        if let Some(undoc) = matches.value_of("undocumented").and_then(UndocMode::from_name) {
+            ret.regs.undoc = undoc;
+        }
+
+        if matches.is_present("cycle-accurate") {
+            ret.regs.bus = BusMode::CycleAccurate;
+        }
+
+        ret.profile_file = matches.value_of("profile").map(|f| f.to_string());
+        ret.folded_file = matches.value_of("folded").map(|f| f.to_string());
+
+        if ret.profile_file.is_some() || ret.folded_file.is_some() {
+            info!("Profiling");
+            ret.profiler = Some(Profiler::new());
+        }
+
+        ret.xref_file = matches.value_of("xref").map(|f| f.to_string());
+
+        if ret.xref_file.is_some() {
+            info!("Recording cross references");
+            ret.xrefs = Some(Xrefs::new());
+        }
+
+        if let Some(files) = matches.values_of("symbols") {
+            let files : Vec<&str> = files.collect();
+
+            match SymbolTable::from_files(&files) {
+                Ok(syms) => ret.syms = syms,
+                Err(e) => warn!("No symbols loaded, {}", e),
+            }
+        }
+
+        ret.syms.set_kind_in(0x9800..=0x98ff, SymKind::Hardware);
+
+        if let Some(files) = matches.values_of("listing") {
+            let files : Vec<&str> = files.collect();
+
+            match LineTable::from_files(&files) {
+                Ok(lines) => ret.lines = lines,
+                Err(e) => warn!("No source lines loaded, {}", e),
+            }
+        }
+
         let file = matches.value_of("ROM FILE").unwrap();
         ret.file = Some(file.to_string());
         ret.load_rom();
@@ -456,12 +600,14 @@ impl Simple {
                 }
 
                 Message::BreakPoint(bp_type, addr) => {
+                    self.set_watch(&bp_type, addr, true);
                     let break_point = BreakPoint::from_gdb_type(bp_type, addr);
                     self.break_points.add(&break_point);
                     self.gdb.ack()
                 }
 
                 Message::DeleteBreakPoint(bp_type, addr) => {
+                    self.set_watch(&bp_type, addr, false);
                     let break_point = BreakPoint::from_gdb_type(bp_type, addr);
                     self.break_points.remove(&break_point);
                     self.gdb.ack()
@@ -473,37 +619,19 @@ impl Simple {
                 }
 
                 Message::Examine(addr) => {
-                    let reply =  Message::Write( addr, self.mem.inspect_byte(addr));
+                    let reply =  Message::Write( addr, self.mem.peek(addr));
                     self.gdb.reply(reply);
                 }
 
-                Message::WriteRegisters(data) => {
-
-                    let mut _it = data.iter();
-
-                    macro_rules! take8 {
-                        () => { _it.next().unwrap().clone() }
-                    }
-
-                    macro_rules! take16 {
-                        () => ({
-                            let h = take8!() as u16;
-                            let l = take8!() as u16;
-                            h << 8 | l
-                        })
-                    }
+                Message::Write(addr, val) => {
+                    self.mem.poke(addr, val);
+                    self.gdb.ack()
+                }
 
+                Message::WriteRegisters(data) => {
                     let regs = &mut self.regs;
 
-                    regs.flags.set_flags(take8!());
-                    regs.a = take8!();
-                    regs.b = take8!();
-                    regs.dp = take8!();
-                    regs.x = take16!();
-                    regs.y = take16!();
-                    regs.s = take16!();
-                    regs.u = take16!();
-                    regs.pc = take16!();
+                    gdbstub::bytes_to_regs(regs, &data);
 
                     info!("received registers and pc = ${:04x}", regs.pc);
 
@@ -511,30 +639,48 @@ impl Simple {
                 }
 
                 Message::ReadRegisters => {
-                    let regs = &self.regs;
+                    let ret = gdbstub::regs_to_bytes(&self.regs);
 
-                    let cc = regs.flags.bits();
+                    self.gdb.reply(Message::WriteRegisters(ret));
+                }
 
-                    let ret : Vec<u8> = vec![
-                        cc, regs.a, regs.b, regs.dp,
+                Message::GetBacktrace => {
+                    let lines = self.backtrace()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, frame)| format!("#{:<2} {}", i, frame))
+                        .collect();
 
-                        (regs.x >> 8) as u8,
-                        regs.x as u8,
+                    self.gdb.reply(Message::Backtrace(lines));
+                }
 
-                        (regs.y >> 8) as u8,
-                        regs.y as u8,
+                Message::Disassemble(addr, count) => {
+                    let lines = self.disassemble(addr, count);
+                    self.gdb.reply(Message::Disassembly(lines));
+                }
+
+                Message::Assemble(addr, text) => {
+                    let res = self.assemble(addr, &text);
+                    self.gdb.reply(Message::Assembled(res));
+                }
 
-                        (regs.u >> 8) as u8,
-                        regs.u as u8,
+                Message::FindLine(file, line) => {
+                    let res = self.find_line(&file, line);
+                    self.gdb.reply(Message::LineAddr(res));
+                }
 
-                        (regs.s >> 8) as u8,
-                        regs.s as u8,
+                Message::GetSourceLine(addr) => {
+                    let source = self.source_line(addr);
+                    self.gdb.reply(Message::SourceLine(source));
+                }
 
-                        (regs.pc >> 8) as u8,
-                        regs.pc as u8,
-                    ];
+                Message::GetXrefs(target, kind) => {
+                    let res = self.xrefs(&target, kind);
+                    self.gdb.reply(Message::Xrefs(res));
+                }
 
-                    self.gdb.reply(Message::WriteRegisters(ret));
+                Message::GetMemoryMap => {
+                    self.gdb.reply(Message::MemoryMap(self.mem.mapping().report()));
                 }
 
                 _ => info!("unimplemented msg {:?}", msg),
@@ -544,8 +690,8 @@ impl Simple {
 
     pub fn update_texture(&mut self) {
         let buffer = {
-            let scr = &self.mem.screen.data;
-            let pal = &self.mem.io.palette;
+            let scr = self.mem.block_data(self.screen);
+            let pal = &self.mem.devices().palette;
             to_rgb(scr, pal)
         };
 
@@ -566,7 +712,7 @@ impl Simple {
             while let Some(event) = self.events.pop() {
                 match event {
                     RomChanged => self.rom_changed(),
-                    HitSync =>  self.update_texture(),
+                    HitSync | HitCwai =>  self.update_texture(),
                     ToggleVerbose => {
                         let v = self.verbose;
                         self.verbose = ! v;
@@ -582,6 +728,10 @@ impl Simple {
                             Quit => state.set(&SimState::Quitting),
 
                             Halt(sig) => {
+                                if self.ints.is_waiting() {
+                                    info!("Halted while cpu is in {:?}", self.run_state());
+                                }
+                                self.log_backtrace();
                                 self.gdb.reply(Message::Halt(sig));
                                 state.set(&SimState::Paused)
                             }
@@ -626,11 +776,14 @@ impl Simple {
 
             match state.get() {
                 SimState::Quitting => {
+                    self.write_profile();
+                    self.write_xrefs();
                     break;
                 },
 
                 SimState::Running => {
                     self.run_to_sync(2_000_000 / 60);
+                    self.mem.devices_mut().vblank();
                     self.win.draw();
                 }
 
@@ -0,0 +1,569 @@
+// Symbol files from assemblers
+//
+// SymbolTable's own format is a yaml map but the symbols we have come out of
+// lwasm, asm6809 and as09 in their own shapes. The format of a file is worked
+// out from its text, several files merge into one table and a name turning up
+// twice is kept as an issue to report rather than silently replaced. The
+// first file to define a name wins.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+
+use regex::Regex;
+
+use crate::symtab::{SymbolTable, Symbol, SymKind};
+use crate::mem::MemScope;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymFormat {
+    Yaml,
+    // lwasm -l listing, using its symbol table if it was built with -s
+    LwasmListing,
+    // lwlink or lwasm --map, Symbol: name (section) = XXXX
+    LwasmMap,
+    // asm6809 --symbols, name equ value separated by tabs
+    Asm6809,
+    // as09 source with ";   ADDR   Name" banners over each routine, like
+    // utils/vrom.txt
+    As09,
+    // NAME EQU $XXXX
+    Equates,
+}
+
+lazy_static! {
+    static ref YAML_RE : Regex =
+        Regex::new(r#"^"?[^\s":]+"?\s*:\s*(0[xX][[:xdigit:]]+|\d+)\s*$"#).unwrap();
+
+    static ref LISTING_RE : Regex =
+        Regex::new(r"^(?P<prefix>[^(]*)\(\s*(?P<file>[^)]*)\):(?P<line>\d+)(?P<source>.*)$").unwrap();
+
+    static ref LISTING_SYM_RE : Regex =
+        Regex::new(r"^\[(?P<flags>..)\]\s+(?P<name>\S+)\s+(?P<val>[[:xdigit:]]+)\s*$").unwrap();
+
+    static ref MAP_RE : Regex =
+        Regex::new(r"^Symbol:\s+(?P<name>\S+)\s+\([^)]*\)\s+=\s+(?P<val>[[:xdigit:]]+)\s*$").unwrap();
+
+    static ref ASM6809_RE : Regex =
+        Regex::new(r"^(?P<name>\S+)\tequ\t(?P<val>\S+)\s*$").unwrap();
+
+    static ref AS09_RE : Regex =
+        Regex::new(r"^;\s+(?P<val>[[:xdigit:]]{4})\s+(?P<name>[A-Za-z_][A-Za-z0-9_]*)").unwrap();
+
+    static ref EQU_RE : Regex =
+        Regex::new(r"(?i)^(?P<name>[a-z_.][a-z0-9_.$]*):?\s+(equ|=)\s+(?P<val>[$%]?[[:xdigit:]]+h?|0x[[:xdigit:]]+)\s*(;.*)?$").unwrap();
+}
+
+// A number as assemblers write them, $ff 0xff 0ffh %101 or decimal
+pub fn parse_value(text : &str) -> Option<u16> {
+    let lower = text.to_lowercase();
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix('%') {
+        (bin, 2)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        (hex, 16)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    let val = i64::from_str_radix(digits, radix).ok()?;
+
+    if (-0x8000..=0xffff).contains(&val) {
+        Some(val as u16)
+    } else {
+        None
+    }
+}
+
+fn hex(text : &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+// The address and name from an as09 banner line
+pub fn as09_banner(line : &str) -> Option<(String, u16)> {
+    let caps = AS09_RE.captures(line.trim_end())?;
+    hex(&caps["val"]).map(|val| (caps["name"].to_string(), val))
+}
+
+fn hex_bytes(text : &str) -> Vec<u8> {
+    text.as_bytes()
+        .chunks_exact(2)
+        .filter_map(|pair| std::str::from_utf8(pair).ok())
+        .filter_map(|pair| u8::from_str_radix(pair, 16).ok())
+        .collect()
+}
+
+// Symbols from lwasm's table with no label line, struct.field and
+// sizeof{struct} are offsets
+fn listing_sym_kind(name : &str) -> SymKind {
+    if name.contains('.') || name.starts_with("sizeof{") {
+        SymKind::Equate
+    } else {
+        SymKind::default()
+    }
+}
+
+// lwasm's local labels
+fn is_local(name : &str) -> bool {
+    name.contains('@') || name.contains('?')
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// One line of an lwasm listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    // Where the line's code or data is
+    pub addr : Option<u16>,
+    // What an equ or set gave the label
+    pub value : Option<u16>,
+    // Code or data at addr, long data only has its first few bytes listed
+    pub bytes : Vec<u8>,
+    pub file : String,
+    pub line : usize,
+    pub source : String,
+}
+
+impl ListingLine {
+    pub fn parse(text : &str) -> Option<Self> {
+        let caps = LISTING_RE.captures(text)?;
+        let prefix = &caps["prefix"];
+
+        // struct offsets are shown as 0004s, they aren't addresses
+        let addr = match prefix.get(0..5) {
+            Some(a) if a.ends_with(' ') => hex(&a[..4]),
+            _ => None,
+        };
+
+        let bytes = match (addr, prefix.get(5..)) {
+            (Some(_), Some(b)) => hex_bytes(b.split_whitespace().next().unwrap_or("")),
+            _ => vec![],
+        };
+
+        let value = match prefix.get(0..10) {
+            Some(v) if v.starts_with("     ") && v.ends_with(' ') => hex(&v[5..9]),
+            _ => None,
+        };
+
+        // the source is after 9 columns of padding
+        let source = caps["source"].get(9..).unwrap_or("").to_string();
+
+        Some(Self {
+            addr, value, bytes, source,
+            file : caps["file"].to_string(),
+            line : caps["line"].parse().ok()?,
+        })
+    }
+
+    // The label in column 0, if there is one
+    pub fn label(&self) -> Option<&str> {
+        let first = self.source.chars().next()?;
+
+        if first.is_alphabetic() || "_.@?".contains(first) {
+            self.source.split_whitespace().next().map(|l| l.trim_end_matches(':'))
+        } else {
+            None
+        }
+    }
+
+    // What the line's label names, from the value and the op after it
+    pub fn label_kind(&self) -> SymKind {
+        if self.value.is_some() {
+            return SymKind::Equate;
+        }
+
+        let op = self.source
+            .split_whitespace()
+            .nth(1)
+            .map(|op| op.to_lowercase());
+
+        match op.as_deref() {
+            Some("fcb") | Some("fdb") | Some("fcc") | Some("fcn") | Some("fcs") |
+            Some("rmb") | Some("rmd") | Some("zmb") | Some("zmd") | Some("fill") |
+            Some("includebin") => SymKind::Data,
+            _ => SymKind::Code,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl SymFormat {
+    pub fn detect(text : &str) -> Option<SymFormat> {
+        let lines = || text.lines().map(|l| l.trim_end()).filter(|l| !l.is_empty());
+
+        // yaml is all name: value after any comments and ---
+        let first = lines().find(|l| !l.starts_with('#') && *l != "---");
+
+        if let Some(first) = first {
+            if YAML_RE.is_match(first) {
+                return Some(SymFormat::Yaml);
+            }
+        }
+
+        if lines().any(|l| l == "Symbol Table:" || LISTING_RE.is_match(l)) {
+            return Some(SymFormat::LwasmListing);
+        }
+
+        if lines().any(|l| MAP_RE.is_match(l)) {
+            return Some(SymFormat::LwasmMap);
+        }
+
+        if lines().any(|l| ASM6809_RE.is_match(l)) {
+            return Some(SymFormat::Asm6809);
+        }
+
+        if lines().any(|l| AS09_RE.is_match(l)) {
+            return Some(SymFormat::As09);
+        }
+
+        if lines().any(|l| EQU_RE.is_match(l)) {
+            return Some(SymFormat::Equates);
+        }
+
+        None
+    }
+
+    // Symbols in the order they're in the text. Formats that don't say what
+    // a symbol is give the default kind, as09 banners are over BIOS routines.
+    pub fn parse(self, text : &str) -> Result<Vec<(String, u16, SymKind)>, String> {
+        let mut ret = vec![];
+
+        match self {
+            SymFormat::Yaml => {
+                let map : BTreeMap<String, u16> = serde_yaml::from_str(text)
+                    .map_err(|e| e.to_string())?;
+                ret.extend(map.into_iter().map(|(name, val)| (name, val, SymKind::default())));
+            }
+
+            SymFormat::LwasmListing => {
+                // the lines say what each label is either way
+                let mut kinds = HashMap::new();
+
+                for line in text.lines().filter_map(ListingLine::parse) {
+                    let val = line.value.or(line.addr);
+
+                    if let (Some(label), Some(val)) = (line.label(), val) {
+                        if !is_local(label) {
+                            kinds.insert(label.to_string(), line.label_kind());
+                            ret.push((label.to_string(), val, line.label_kind()));
+                        }
+                    }
+                }
+
+                // -s puts a symbol table at the end, that's better than
+                // guessing from the labels
+                if let Some(start) = text.lines().position(|l| l.trim_end() == "Symbol Table:") {
+                    ret.clear();
+
+                    for line in text.lines().skip(start + 1) {
+                        if let Some(caps) = LISTING_SYM_RE.captures(line.trim_end()) {
+                            if !caps["flags"].contains('L') {
+                                if let Some(val) = hex(&caps["val"]) {
+                                    let kind = kinds.get(&caps["name"]).copied().unwrap_or_else(|| listing_sym_kind(&caps["name"]));
+                                    ret.push((caps["name"].to_string(), val, kind));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            _ => {
+                let re : &Regex = match self {
+                    SymFormat::LwasmMap => &MAP_RE,
+                    SymFormat::Asm6809 => &ASM6809_RE,
+                    SymFormat::As09 => &AS09_RE,
+                    _ => &EQU_RE,
+                };
+
+                for line in text.lines() {
+                    if let Some(caps) = re.captures(line.trim_end()) {
+                        let val = match self {
+                            SymFormat::LwasmMap | SymFormat::As09 => hex(&caps["val"]),
+                            _ => parse_value(&caps["val"]),
+                        };
+
+                        let kind = match self {
+                            SymFormat::As09 => SymKind::Bios,
+                            SymFormat::Equates => SymKind::Equate,
+                            _ => SymKind::default(),
+                        };
+
+                        if let Some(val) = val {
+                            ret.push((caps["name"].to_string(), val, kind));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for SymFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SymFormat::Yaml => "yaml",
+            SymFormat::LwasmListing => "lwasm listing",
+            SymFormat::LwasmMap => "lwasm map",
+            SymFormat::Asm6809 => "asm6809 symbols",
+            SymFormat::As09 => "as09 source",
+            SymFormat::Equates => "equates",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A name defined more than once, where from and with what values
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymIssue {
+    // Same value again, harmless but noisy
+    Duplicate { name : String, val : u16, first : String, again : String },
+    // Different value, the first one is kept
+    Conflict { name : String, kept : (u16, String), ignored : (u16, String) },
+}
+
+impl SymIssue {
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, SymIssue::Conflict { .. })
+    }
+}
+
+impl fmt::Display for SymIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymIssue::Duplicate { name, val, first, again } =>
+                write!(f, "{} = ${:04x} in {} is also in {}", name, val, first, again),
+
+            SymIssue::Conflict { name, kept, ignored } =>
+                write!(f, "{} is ${:04x} in {} but ${:04x} in {}, keeping ${:04x}",
+                       name, kept.0, kept.1, ignored.0, ignored.1, kept.0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolLoader {
+    syms : SymbolTable,
+    // Which file each symbol came from
+    origin : HashMap<(String, Option<MemScope>), String>,
+    issues : Vec<SymIssue>,
+}
+
+impl SymbolLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_file(&mut self, file : &str) -> Result<SymFormat, String> {
+        self.load_file_in(file, None)
+    }
+
+    // Load with every symbol, apart from equates, scoped to a region or bank
+    pub fn load_file_in(&mut self, file : &str, scope : Option<MemScope>) -> Result<SymFormat, String> {
+        let text = fs::read_to_string(file)
+            .map_err(|e| format!("{}: {}", file, e))?;
+
+        self.load_str_in(file, &text, scope)
+    }
+
+    // Merge the symbols in text, file is the name issues refer to it by
+    pub fn load_str(&mut self, file : &str, text : &str) -> Result<SymFormat, String> {
+        self.load_str_in(file, text, None)
+    }
+
+    pub fn load_str_in(&mut self, file : &str, text : &str, scope : Option<MemScope>) -> Result<SymFormat, String> {
+        let format = SymFormat::detect(text)
+            .ok_or_else(|| format!("{}: not a symbol file I know", file))?;
+
+        let syms = format.parse(text)
+            .map_err(|e| format!("{}: {}", file, e))?;
+
+        info!("{} symbols from {} ({})", syms.len(), file, format);
+
+        for (name, val, kind) in syms {
+            // equates are numbers rather than addresses, they mean the same
+            // whatever's mapped
+            let scope = if kind == SymKind::Equate { None } else { scope.clone() };
+            self.add(file, Symbol { name, val, kind, scope });
+        }
+
+        Ok(format)
+    }
+
+    fn add(&mut self, file : &str, sym : Symbol) {
+        let key = (sym.name.clone(), sym.scope.clone());
+
+        match self.syms.get_scoped(&sym.name, sym.scope.as_ref()).map(|old| old.val) {
+            Some(old) => {
+                let first = self.origin[&key].clone();
+                let (name, val) = (sym.name, sym.val);
+
+                let issue = if old == val {
+                    SymIssue::Duplicate { name, val, first, again : file.to_string() }
+                } else {
+                    SymIssue::Conflict { name, kept : (old, first), ignored : (val, file.to_string()) }
+                };
+
+                self.issues.push(issue);
+            }
+
+            None => {
+                self.origin.insert(key, file.to_string());
+                self.syms.insert(sym);
+            }
+        }
+    }
+
+    pub fn issues(&self) -> &[SymIssue] {
+        &self.issues
+    }
+
+    // Where a symbol was loaded from
+    pub fn origin(&self, name : &str, scope : Option<&MemScope>) -> Option<&str> {
+        self.origin.get(&(name.to_string(), scope.cloned())).map(|s| s.as_str())
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.syms
+    }
+
+    pub fn into_symbols(self) -> SymbolTable {
+        self.syms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(files : &[&str]) -> SymbolLoader {
+        let mut loader = SymbolLoader::new();
+
+        for file in files {
+            loader.load_file(file).unwrap();
+        }
+
+        loader
+    }
+
+    fn detect_file(file : &str) -> Option<SymFormat> {
+        SymFormat::detect(&fs::read_to_string(file).unwrap())
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(detect_file("resources/syms.yaml"), Some(SymFormat::Yaml));
+        assert_eq!(detect_file("asm/out/all.syms"), Some(SymFormat::LwasmListing));
+        assert_eq!(detect_file("utils/vrom.txt"), Some(SymFormat::As09));
+
+        assert_eq!(SymFormat::detect("Symbol: start (all.o) = 9900\n"), Some(SymFormat::LwasmMap));
+        assert_eq!(SymFormat::detect("start\tequ\t$9900\n"), Some(SymFormat::Asm6809));
+        assert_eq!(SymFormat::detect("START EQU $9900\n"), Some(SymFormat::Equates));
+        assert_eq!(SymFormat::detect("just some text\n"), None);
+    }
+
+    #[test]
+    fn yaml() {
+        let syms = load(&["resources/syms.yaml"]).into_symbols();
+        assert_eq!(syms.len(), 248);
+        assert_eq!(syms.get("Vec_Btn_State").map(|s| s.val), Some(0xc80f));
+        assert_eq!(syms.get("Warm_Start").map(|s| s.kind), Some(SymKind::Code));
+    }
+
+    #[test]
+    fn lwasm_listing_uses_symbol_table() {
+        // 60 globals in the table, the locals are left out
+        let syms = load(&["asm/out/all.syms"]).into_symbols();
+        assert_eq!(syms.len(), 60);
+        assert!(syms.symbols().all(|s| !is_local(&s.name)));
+
+        let kind = |name| syms.get(name).map(|s| (s.val, s.kind));
+        assert_eq!(kind("clear_screen"), Some((0x993a, SymKind::Code)));
+        assert_eq!(kind("init_d"), Some((0x0004, SymKind::Equate)));
+        assert_eq!(kind("num_of_tasks"), Some((0x0064, SymKind::Equate)));
+    }
+
+    #[test]
+    fn as09_banners_are_bios() {
+        let syms = load(&["utils/vrom.txt"]).into_symbols();
+        assert_eq!(syms.len(), 129);
+        assert_eq!(syms.get("Warm_Start").map(|s| (s.val, s.kind)), Some((0xf06c, SymKind::Bios)));
+    }
+
+    #[test]
+    fn other_formats() {
+        let parse = |format : SymFormat, text| format.parse(text).unwrap();
+
+        assert_eq!(parse(SymFormat::LwasmMap, "Symbol: start (all.o) = 9900\n"),
+                   vec![("start".to_string(), 0x9900, SymKind::Code)]);
+
+        assert_eq!(parse(SymFormat::Asm6809, "start\tequ\t$9900\nend\tequ\t39168\n"),
+                   vec![("start".to_string(), 0x9900, SymKind::Code),
+                        ("end".to_string(), 0x9900, SymKind::Code)]);
+
+        assert_eq!(parse(SymFormat::Equates, "A EQU $10\nB: = 0x20 ; two\nC equ 30h\nD equ %11\n"),
+                   vec![("A".to_string(), 0x10, SymKind::Equate),
+                        ("B".to_string(), 0x20, SymKind::Equate),
+                        ("C".to_string(), 0x30, SymKind::Equate),
+                        ("D".to_string(), 3, SymKind::Equate)]);
+    }
+
+    #[test]
+    fn merging_keeps_the_first() {
+        // 124 names are in both, all but Rot_VL_Mode with the same value
+        let loader = load(&["resources/syms.yaml", "utils/vrom.txt"]);
+        let issues = loader.issues();
+
+        assert_eq!(loader.symbols().len(), 248 + 5);
+        assert_eq!(issues.len(), 124);
+        assert_eq!(issues.iter().filter(|i| !i.is_conflict()).count(), 123);
+
+        let conflict = SymIssue::Conflict {
+            name : "Rot_VL_Mode".to_string(),
+            kept : (0xf62b, "resources/syms.yaml".to_string()),
+            ignored : (0xf61f, "utils/vrom.txt".to_string()),
+        };
+
+        assert!(issues.contains(&conflict));
+        assert_eq!(loader.symbols().get("Rot_VL_Mode").map(|s| s.val), Some(0xf62b));
+        assert_eq!(loader.origin("Warm_Start", None), Some("resources/syms.yaml"));
+    }
+
+    #[test]
+    fn duplicates_and_conflicts() {
+        let mut loader = SymbolLoader::new();
+        loader.load_str("a.equ", "ONE EQU 1\nTWO EQU 2\n").unwrap();
+        loader.load_str("b.equ", "ONE EQU 1\nTWO EQU 3\n").unwrap();
+
+        assert_eq!(loader.issues(), &[
+            SymIssue::Duplicate { name : "ONE".to_string(), val : 1, first : "a.equ".to_string(), again : "b.equ".to_string() },
+            SymIssue::Conflict { name : "TWO".to_string(), kept : (2, "a.equ".to_string()), ignored : (3, "b.equ".to_string()) },
+        ]);
+
+        assert_eq!(loader.symbols().get("TWO").map(|s| s.val), Some(2));
+    }
+
+    #[test]
+    fn scoped_symbols_dont_clash() {
+        let mut loader = SymbolLoader::new();
+        loader.load_str_in("bank0.sym", "start\tequ\t$1000\n", Some(MemScope::new("cart", Some(0)))).unwrap();
+        loader.load_str_in("bank1.sym", "start\tequ\t$2000\n", Some(MemScope::new("cart", Some(1)))).unwrap();
+        assert!(loader.issues().is_empty());
+
+        // equates aren't scoped, so SIZE is loaded twice
+        loader.load_str_in("bank0.equ", "SIZE EQU 4\n", Some(MemScope::new("cart", Some(0)))).unwrap();
+        loader.load_str_in("bank1.equ", "SIZE EQU 4\n", Some(MemScope::new("cart", Some(1)))).unwrap();
+        assert_eq!(loader.issues().len(), 1);
+        assert_eq!(loader.symbols().len(), 3);
+    }
+}
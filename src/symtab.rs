@@ -1,52 +1,579 @@
 use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
 
 use crate::diss;
+use crate::symfile::SymbolLoader;
+use crate::mem::{MemScope, MemMapping};
+use crate::cpu::{CallStack, FrameKind};
 
-use serde_yaml;
+
+// Furthest an operand can be past a symbol and still be shown relative to it
+const NEAR_SYMBOL_RANGE : u16 = 0xff;
 
 impl diss::SymTab for SymbolTable {
 
     fn get_symbol(&self, val : u16) -> Option<String> {
-        self.lookup_from_val(val)
+        self.all().get_symbol(val)
+    }
+
+    fn get_near_symbol(&self, val : u16) -> Option<String> {
+        self.all().get_near_symbol(val)
+    }
+
+    fn get_constant(&self, val : u16) -> Option<String> {
+        self.all().get_constant(val)
+    }
+}
+
+// Where a pc can be
+const CODE_KINDS : [SymKind; 2] = [SymKind::Code, SymKind::Bios];
+
+fn with_offset(sym : &Symbol, offset : u16) -> String {
+    if offset == 0 {
+        sym.name.clone()
+    } else {
+        format!("{}+${:x}", sym.name, offset)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// What a symbol names, so a display can pick the right one when several
+// share a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SymKind {
+    // Also what a symbol is when where it came from doesn't say
+    #[default]
+    Code,
+    Data,
+    Hardware,
+    Bios,
+    Equate,
+}
+
+impl SymKind {
+    // Higher is shown first, labels over equates
+    pub fn priority(self) -> u8 {
+        match self {
+            SymKind::Code => 5,
+            SymKind::Bios => 4,
+            SymKind::Data => 3,
+            SymKind::Hardware => 2,
+            SymKind::Equate => 1,
+        }
+    }
+
+    pub fn is_code(self) -> bool {
+        CODE_KINDS.contains(&self)
+    }
+
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "code" => Some(SymKind::Code),
+            "data" => Some(SymKind::Data),
+            "hardware" => Some(SymKind::Hardware),
+            "bios" => Some(SymKind::Bios),
+            "equate" => Some(SymKind::Equate),
+            _ => None,
+        }
     }
 }
 
+impl fmt::Display for SymKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SymKind::Code => "code",
+            SymKind::Data => "data",
+            SymKind::Hardware => "hardware",
+            SymKind::Bios => "bios",
+            SymKind::Equate => "equate",
+        };
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name : String,
+    pub val : u16,
+    pub kind : SymKind,
+    // Only means something while this region or bank is mapped at val
+    pub scope : Option<MemScope>,
+}
+
+impl Symbol {
+    fn key(&self) -> SymKey {
+        (self.name.clone(), self.scope.clone())
+    }
+}
+
+// A name can be used again in another scope, unscoped sorts first
+type SymKey = (String, Option<MemScope>);
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct SymbolTable {
-    syms_to_val : BTreeMap<String, u16>,
+    syms : BTreeMap<SymKey, Symbol>,
+    // Symbols at each value, best first
+    val_to_syms : BTreeMap<u16, Vec<SymKey>>,
+    // How many code or BIOS symbols are at each value, so finding the
+    // routine a pc is in doesn't walk back over data and equates
+    code_vals : BTreeMap<u16, usize>,
 }
 
 impl SymbolTable {
-    pub fn new(file_name : &'static str) -> Self {
-        use crate::utils::load_file_as_string;
-        let s = load_file_as_string(&file_name.to_string());
-        let v : BTreeMap<String,u16> = serde_yaml::from_str(&s).unwrap(); 
+    pub fn new(file_name : &str) -> Self {
+        Self::from_file(file_name).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Like new but a missing or broken file is an error rather than a panic.
+    // Any of the formats in symfile can be loaded, not just yaml
+    pub fn from_file(file_name : &str) -> Result<Self, String> {
+        Self::from_files(&[file_name])
+    }
+
+    // Several symbol files merged, the first to define a name wins and
+    // names defined again are logged. region=file or region:bank=file
+    // scopes a file's symbols to what's mapped.
+    pub fn from_files(files : &[&str]) -> Result<Self, String> {
+        let mut loader = SymbolLoader::new();
 
-        SymbolTable {
-            syms_to_val : v
+        for spec in files {
+            let (scope, file) = match spec.split_once('=') {
+                Some((scope, file)) => {
+                    let scope = MemScope::parse(scope).ok_or_else(|| format!("bad scope {}", scope))?;
+                    (Some(scope), file)
+                }
+                None => (None, *spec),
+            };
+
+            loader.load_file_in(file, scope)?;
+        }
+
+        let (conflicts, dups) : (Vec<_>, Vec<_>) = loader.issues().iter().partition(|i| i.is_conflict());
+
+        for issue in conflicts {
+            warn!("Symbol conflict, {}", issue);
+        }
+
+        if !dups.is_empty() {
+            info!("{} symbols defined more than once with the same value", dups.len());
         }
+
+        Ok(loader.into_symbols())
     }
 
     pub fn add(&mut self, name : String, val : u16 ) -> &mut Self {
-        self.syms_to_val.insert(name, val);
+        self.add_kind(name, val, SymKind::default())
+    }
+
+    pub fn add_kind(&mut self, name : String, val : u16, kind : SymKind) -> &mut Self {
+        self.insert(Symbol { name, val, kind, scope : None })
+    }
+
+    // Adding a name again in the same scope replaces it
+    pub fn insert(&mut self, sym : Symbol) -> &mut Self {
+        let key = sym.key();
+
+        self.remove(&sym.name, sym.scope.as_ref());
+
+        if CODE_KINDS.contains(&sym.kind) {
+            *self.code_vals.entry(sym.val).or_default() += 1;
+        }
+
+        let val = sym.val;
+        self.syms.insert(key.clone(), sym);
+
+        let keys = self.val_to_syms.entry(val).or_default();
+        keys.push(key);
+
+        // stable, so equal priorities stay in the order they were added
+        let syms = &self.syms;
+        keys.sort_by_key(|k| std::cmp::Reverse(syms[k].kind.priority()));
+
         self
     }
 
+    pub fn remove(&mut self, name : &str, scope : Option<&MemScope>) -> Option<Symbol> {
+        let key = (name.to_string(), scope.cloned());
+        let sym = self.syms.remove(&key)?;
+
+        if let Some(keys) = self.val_to_syms.get_mut(&sym.val) {
+            keys.retain(|k| *k != key);
+
+            if keys.is_empty() {
+                self.val_to_syms.remove(&sym.val);
+            }
+        }
+
+        if CODE_KINDS.contains(&sym.kind) {
+            if let Some(count) = self.code_vals.get_mut(&sym.val) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.code_vals.remove(&sym.val);
+                }
+            }
+        }
+
+        Some(sym)
+    }
+
+    // Give symbols in range that only have the default kind, or are
+    // equates, a kind from where they are. Machines know what's ROM and
+    // what's hardware, the symbol files don't always say.
+    pub fn set_kind_in(&mut self, range : RangeInclusive<u16>, kind : SymKind) {
+        let syms : Vec<Symbol> = self.val_to_syms
+            .range(range)
+            .flat_map(|(_, keys)| keys.iter())
+            .map(|k| &self.syms[k])
+            .filter(|sym| matches!(sym.kind, SymKind::Code | SymKind::Equate))
+            .cloned()
+            .collect();
+
+        for sym in syms {
+            self.insert(Symbol { kind, ..sym });
+        }
+    }
+
+    // Every symbol whatever its scope
+    pub fn all(&self) -> MappedSymbols<'_> {
+        MappedSymbols { syms : self, mapping : None }
+    }
+
+    // Only the symbols that mean something with this mapping, the
+    // unscoped ones and those scoped to what's mapped at their value
+    pub fn mapped(&self, mapping : MemMapping) -> MappedSymbols<'_> {
+        MappedSymbols { syms : self, mapping : Some(mapping) }
+    }
+
+    pub fn symbols_at(&self, val : u16) -> Vec<&Symbol> {
+        self.all().symbols_at(val)
+    }
+
+    pub fn lookup_kind(&self, val : u16, kinds : &[SymKind]) -> Option<&Symbol> {
+        self.all().lookup_kind(val, kinds)
+    }
+
     pub fn lookup_from_val(&self, addr : u16) -> Option<String> {
-        for (key, value) in &self.syms_to_val {
-            if *value == addr {
-                return Some(key.clone());
+        self.all().lookup_from_val(addr)
+    }
+
+    pub fn nearest(&self, addr : u16, max_dist : Option<u16>) -> Option<(&Symbol, u16)> {
+        self.all().nearest(addr, max_dist)
+    }
+
+    pub fn nearest_kind(&self, addr : u16, max_dist : Option<u16>, kinds : &[SymKind]) -> Option<(&Symbol, u16)> {
+        self.all().nearest_kind(addr, max_dist, kinds)
+    }
+
+    pub fn nearest_code(&self, addr : u16) -> Option<(&Symbol, u16)> {
+        self.all().nearest_code(addr)
+    }
+
+    pub fn lookup_containing(&self, addr : u16) -> Option<(String, u16)> {
+        self.all().lookup_containing(addr)
+    }
+
+    pub fn lookup_nearest(&self, addr : u16) -> Option<String> {
+        self.all().lookup_nearest(addr)
+    }
+
+    pub fn backtrace(&self, stack : &CallStack, pc : u16) -> Vec<SymbolicFrame> {
+        self.all().backtrace(stack, pc)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u16)> {
+        self.syms.values().map(|sym| (&sym.name, &sym.val))
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.syms.values()
+    }
+
+    // The unscoped symbol called name, or the first scoped one
+    pub fn get(&self, name : &str) -> Option<&Symbol> {
+        self.syms
+            .range((name.to_string(), None)..)
+            .next()
+            .filter(|((n, _), _)| n == name)
+            .map(|(_, sym)| sym)
+    }
+
+    pub fn get_scoped(&self, name : &str, scope : Option<&MemScope>) -> Option<&Symbol> {
+        self.syms.get(&(name.to_string(), scope.cloned()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.syms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.syms.is_empty()
+    }
+
+    // The same shape as syms.yaml, so from_file can read it back
+    pub fn to_yaml(&self) -> String {
+        let plain = |name : &str| name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.');
+
+        let mut ret = String::from("---\n");
+
+        for (name, val) in self.iter() {
+            if plain(name) {
+                ret.push_str(&format!("{}: 0x{:04X}\n", name, val));
+            } else {
+                ret.push_str(&format!("\"{}\": 0x{:04X}\n", name, val));
             }
         }
-        None
+
+        ret
     }
 
     pub fn lookup(&self, name : &str) -> Option<u16> {
-        match self.syms_to_val.get(name) {
-            Some(u16ref) => Some(*u16ref),
-            None => None,
+        self.get(name).map(|sym| sym.val)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Lookups by value through what's mapped, a symbol scoped to a region or bank
+// is only seen while that's what's at its address
+pub struct MappedSymbols<'a> {
+    syms : &'a SymbolTable,
+    // None sees every symbol
+    mapping : Option<MemMapping>,
+}
+
+impl<'a> MappedSymbols<'a> {
+    fn visible(&self, sym : &Symbol) -> bool {
+        match (&sym.scope, &self.mapping) {
+            (Some(scope), Some(mapping)) => mapping.scope_at(sym.val).is_some_and(|m| scope.covers(m)),
+            _ => true,
         }
     }
+
+    // Every symbol with this value, best first
+    pub fn symbols_at(&self, val : u16) -> Vec<&'a Symbol> {
+        let syms = self.syms;
+
+        syms.val_to_syms
+            .get(&val)
+            .into_iter()
+            .flatten()
+            .map(|k| &syms.syms[k])
+            .filter(|sym| self.visible(sym))
+            .collect()
+    }
+
+    // Best symbol at val of one of kinds, earlier kinds first
+    pub fn lookup_kind(&self, val : u16, kinds : &[SymKind]) -> Option<&'a Symbol> {
+        let at = self.symbols_at(val);
+
+        kinds
+            .iter()
+            .find_map(|k| at.iter().find(|sym| sym.kind == *k).copied())
+    }
+
+    pub fn lookup_from_val(&self, addr : u16) -> Option<String> {
+        self.symbols_at(addr).first().map(|sym| sym.name.clone())
+    }
+
+    // Closest symbol at or below addr and how far past it addr is, None if
+    // that's further than max_dist
+    pub fn nearest(&self, addr : u16, max_dist : Option<u16>) -> Option<(&'a Symbol, u16)> {
+        self.nearest_by(addr, max_dist, |val| self.symbols_at(val).first().copied())
+    }
+
+    // Like nearest but only symbols of one of kinds count
+    pub fn nearest_kind(&self, addr : u16, max_dist : Option<u16>, kinds : &[SymKind]) -> Option<(&'a Symbol, u16)> {
+        self.nearest_by(addr, max_dist, |val| self.lookup_kind(val, kinds))
+    }
+
+    fn nearest_by(&self, addr : u16, max_dist : Option<u16>, at : impl Fn(u16) -> Option<&'a Symbol>) -> Option<(&'a Symbol, u16)> {
+        self.syms.val_to_syms
+            .range(..=addr)
+            .rev()
+            .take_while(|(val, _)| max_dist.is_none_or(|max| addr - **val <= max))
+            .find_map(|(val, _)| at(*val))
+            .map(|sym| (sym, addr - sym.val))
+    }
+
+    // Closest code or BIOS symbol at or below addr, the routine a pc is in
+    pub fn nearest_code(&self, addr : u16) -> Option<(&'a Symbol, u16)> {
+        self.syms.code_vals
+            .range(..=addr)
+            .rev()
+            .find_map(|(val, _)| self.lookup_kind(*val, &CODE_KINDS))
+            .map(|sym| (sym, addr - sym.val))
+    }
+
+    // The routine a pc is in and its address
+    pub fn lookup_containing(&self, addr : u16) -> Option<(String, u16)> {
+        self.nearest_code(addr).map(|(sym, _)| (sym.name.clone(), sym.val))
+    }
+
+    // The routine a pc is in, as name or name+$offset
+    pub fn lookup_nearest(&self, addr : u16) -> Option<String> {
+        self.nearest_code(addr).map(|(sym, offset)| with_offset(sym, offset))
+    }
+
+    // Backtrace for a call stack stopped at pc, innermost frame first
+    pub fn backtrace(&self, stack : &CallStack, pc : u16) -> Vec<SymbolicFrame> {
+        let frames = stack.frames();
+
+        let mut ret = vec![];
+        let mut addr = pc;
+
+        for frame in frames.iter().rev() {
+            ret.push(SymbolicFrame::new(addr, self.lookup_nearest(addr), Some(frame.kind)));
+            addr = frame.call_site;
+        }
+
+        ret.push(SymbolicFrame::new(addr, self.lookup_nearest(addr), None));
+        ret
+    }
 }
 
+impl diss::SymTab for MappedSymbols<'_> {
+
+    fn get_symbol(&self, val : u16) -> Option<String> {
+        self.lookup_from_val(val)
+    }
+
+    fn get_near_symbol(&self, val : u16) -> Option<String> {
+        self.nearest(val, Some(NEAR_SYMBOL_RANGE)).map(|(sym, offset)| with_offset(sym, offset))
+    }
+
+    // Immediates are more likely to be constants than addresses
+    fn get_constant(&self, val : u16) -> Option<String> {
+        self.lookup_kind(val, &[SymKind::Equate])
+            .or_else(|| self.symbols_at(val).first().copied())
+            .map(|sym| sym.name.clone())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+
+// One line of a backtrace, entered_by is how the frame was got into, None
+// for the outermost
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolicFrame {
+    pub addr : u16,
+    pub symbol : Option<String>,
+    pub entered_by : Option<FrameKind>,
+}
+
+impl SymbolicFrame {
+    pub fn new(addr : u16, symbol : Option<String>, entered_by : Option<FrameKind>) -> Self {
+        Self { addr, symbol, entered_by }
+    }
+}
+
+impl fmt::Display for SymbolicFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04x}", self.addr)?;
+
+        if let Some(ref sym) = self.symbol {
+            write!(f, " in {}", sym)?;
+        }
+
+        match self.entered_by {
+            Some(FrameKind::Swi(1)) => write!(f, " <swi>"),
+            Some(FrameKind::Swi(n)) => write!(f, " <swi{}>", n),
+            Some(FrameKind::Interrupt(int)) => write!(f, " <{}>", format!("{:?}", int).to_lowercase()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(name : &str, val : u16, kind : SymKind, scope : Option<MemScope>) -> Symbol {
+        Symbol { name : name.to_string(), val, kind, scope }
+    }
+
+    // A routine with data and an equate after it, then a hardware register
+    fn table() -> SymbolTable {
+        let mut syms = SymbolTable::default();
+        syms.insert(sym("SIZE", 0x1000, SymKind::Equate, None));
+        syms.insert(sym("start", 0x1000, SymKind::Code, None));
+        syms.insert(sym("table", 0x1010, SymKind::Data, None));
+        syms.insert(sym("COUNT", 0x1020, SymKind::Equate, None));
+        syms.insert(sym("VIA_port_b", 0xd000, SymKind::Hardware, None));
+        syms
+    }
+
+    fn name(found : Option<(&Symbol, u16)>) -> Option<(&str, u16)> {
+        found.map(|(sym, offset)| (sym.name.as_str(), offset))
+    }
+
+    #[test]
+    fn labels_before_equates() {
+        let syms = table();
+        assert_eq!(syms.lookup_from_val(0x1000).as_deref(), Some("start"));
+        assert_eq!(syms.lookup_kind(0x1000, &[SymKind::Equate]).map(|s| s.name.as_str()), Some("SIZE"));
+        assert_eq!(syms.symbols_at(0x1000).len(), 2);
+    }
+
+    #[test]
+    fn nearest_is_whatever_is_closest() {
+        let syms = table();
+        assert_eq!(name(syms.nearest(0x1000, None)), Some(("start", 0)));
+        assert_eq!(name(syms.nearest(0x1018, None)), Some(("table", 8)));
+        assert_eq!(name(syms.nearest(0x1030, None)), Some(("COUNT", 0x10)));
+        assert_eq!(name(syms.nearest(0x0fff, None)), None);
+    }
+
+    #[test]
+    fn nearest_stops_at_max_dist() {
+        let syms = table();
+        assert_eq!(name(syms.nearest(0x1030, Some(0x10))), Some(("COUNT", 0x10)));
+        assert_eq!(name(syms.nearest(0x1031, Some(0x10))), None);
+        assert_eq!(name(syms.nearest(0xd0ff, Some(0xff))), Some(("VIA_port_b", 0xff)));
+        assert_eq!(name(syms.nearest(0xd100, Some(0xff))), None);
+    }
+
+    #[test]
+    fn nearest_kind_skips_other_kinds() {
+        let syms = table();
+        assert_eq!(name(syms.nearest_kind(0x1030, None, &[SymKind::Data])), Some(("table", 0x20)));
+        assert_eq!(name(syms.nearest_kind(0x1030, Some(0x1f), &[SymKind::Data])), None);
+        assert_eq!(name(syms.nearest_kind(0x1030, None, &[SymKind::Equate])), Some(("COUNT", 0x10)));
+        assert_eq!(name(syms.nearest_kind(0x1005, None, &[SymKind::Equate])), Some(("SIZE", 5)));
+    }
+
+    #[test]
+    fn nearest_code_walks_back_to_the_routine() {
+        let mut syms = table();
+        assert_eq!(name(syms.nearest_code(0x1030)), Some(("start", 0x30)));
+        assert_eq!(syms.lookup_nearest(0x1030).as_deref(), Some("start+$30"));
+        assert_eq!(syms.lookup_containing(0xd000), Some(("start".to_string(), 0x1000)));
+
+        syms.insert(sym("Wait_Recal", 0xf192, SymKind::Bios, None));
+        assert_eq!(name(syms.nearest_code(0xf200)), Some(("Wait_Recal", 0x6e)));
+        assert_eq!(name(syms.nearest_code(0x0fff)), None);
+    }
+
+    #[test]
+    fn scoped_symbols_follow_the_mapping() {
+        let mut syms = SymbolTable::default();
+        syms.insert(sym("bank0", 0x1000, SymKind::Code, Some(MemScope::new("cart", Some(0)))));
+        syms.insert(sym("bank1", 0x1000, SymKind::Code, Some(MemScope::new("cart", Some(1)))));
+
+        let mut mapping = MemMapping::new(vec![(0x0000, 0x7fff, MemScope::new("cart", Some(0)))]);
+        assert_eq!(name(syms.mapped(mapping.clone()).nearest_code(0x1004)), Some(("bank0", 4)));
+
+        mapping.set_bank("cart", Some(1));
+        assert_eq!(name(syms.mapped(mapping.clone()).nearest(0x1004, None)), Some(("bank1", 4)));
+
+        mapping.set_bank("cart", Some(2));
+        assert_eq!(name(syms.mapped(mapping).nearest(0x1004, None)), None);
+        assert_eq!(syms.all().symbols_at(0x1000).len(), 2);
+    }
+}
@@ -0,0 +1,92 @@
+// Runs the program from a json test log flat out and reports how fast the
+// core is, nothing gets checked
+
+use crate::cpu::{Regs, StandardClock, Clock, Interrupts, BusMode, run};
+use crate::mem::MemMap;
+use crate::tests::{tester, load_run_log};
+use crate::timer::Timer;
+use clap::{ArgMatches};
+use separator::Separatable;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct Bench {
+    json_file    : String,
+    instructions : usize,
+    mem          : MemMap,
+    start_regs   : Regs,
+    regs         : Regs,
+    ints         : Interrupts,
+    clock        : Rc<RefCell<StandardClock>>,
+}
+
+impl tester::Tester for Bench {
+
+    fn from_matches( matches : &ArgMatches ) -> Bench {
+        let json_file = matches.value_of("JSON FILE").unwrap().to_string();
+        let run_log = load_run_log(&json_file);
+
+        // Falls back to the log's own instruction count, the argument's
+        // validator has already checked it's a number
+        let instructions = matches.value_of("instructions")
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(run_log.instructions)
+            .max(1);
+
+        let mut start_regs = run_log.states[0].regs.clone();
+
+        if matches.is_present("cycle-accurate") {
+            start_regs.bus = BusMode::CycleAccurate;
+        }
+
+        Bench {
+            json_file,
+            instructions,
+            mem          : run_log.create_memmap(),
+            regs         : start_regs.clone(),
+            start_regs,
+            ints         : Interrupts::new(),
+            clock        : Rc::new(RefCell::new(StandardClock::new(1_500_000))),
+        }
+    }
+
+    fn run(&mut self) {
+        println!("Benchmarking {} for {} instructions", self.json_file, self.instructions.separated_string());
+
+        let mut restarts = 0;
+        let mut timer = Timer::new();
+
+        let mut done = 0;
+
+        while done < self.instructions {
+            let (stats, res) = run(&mut self.regs, &mut self.mem, &self.clock, &mut self.ints, self.instructions - done);
+            done += stats.instructions;
+
+            // Programs that fall over get restarted so we keep measuring,
+            // the failing instruction counts as run
+            if res.is_err() {
+                self.regs = self.start_regs.clone();
+                restarts += 1;
+                done += 1;
+            }
+        }
+
+        let secs = timer.get().secs();
+        let cycles = self.clock.borrow().get_cycles();
+
+        let ins_per_second = self.instructions as f64 / secs;
+        let mhz = cycles as f64 / secs / 1_000_000.0;
+
+        println!("instructions: {}", self.instructions.separated_string());
+        println!("cycles:       {}", cycles.separated_string());
+        println!("secs:         {:.4}", secs);
+
+        if restarts > 0 {
+            println!("restarts:     {}", restarts);
+        }
+
+        println!("{} instructions per second", ( ins_per_second as u64 ).separated_string());
+        println!("{:0.02}mhz emulated", mhz);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::mem::{MemoryIO, LoggingMemMap, LogEntry, MemMap};
-use crate::cpu::{Regs, StandardClock};
-use crate::diss::Disassembler;
+use crate::cpu::{Regs, StandardClock, Interrupts};
+use crate::diss::{Disassembler, SymTab};
+use crate::symtab::SymbolTable;
 use clap::{ArgMatches};
 
 use crate::cpu::step;
@@ -10,6 +11,7 @@ use std::rc::Rc;
 
 use crate::tests::tester;
 use crate::proclog::{Step};
+use crate::linetable::LineTable;
 use separator::Separatable;
 
 // use utils;
@@ -20,16 +22,18 @@ use std::fs::File;
 use std::io::prelude::*;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-struct MemInit {
+pub struct MemInit {
     pub base : u16,
     pub size : usize,
     pub writeable : bool,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-struct RunLog {
+pub struct RunLog {
     pub file_name : String,
     pub load_addr : u16,
+    #[serde(default)]
+    pub instructions : usize,
     pub memory : Vec<MemInit>,
     pub states : Vec<Step>,
 }
@@ -63,6 +67,12 @@ impl RunLog {
     }
 }
 
+pub fn load_run_log(json_file : &str) -> RunLog {
+    let mut json_contents = String::new();
+    File::open(json_file).unwrap().read_to_string(&mut json_contents).unwrap();
+    serde_json::from_str(&json_contents).unwrap()
+}
+
 pub struct JsonTest {
 
     check_cycles    : bool,
@@ -73,7 +83,9 @@ pub struct JsonTest {
     mem             : MemMap,
     steps           : Vec<Step>,
     regs            : Regs,
+    ints            : Interrupts,
     clock           : Rc<RefCell<StandardClock>>,
+    syms            : Option<SymbolTable>,
 }
 
 
@@ -102,9 +114,7 @@ impl tester::Tester for JsonTest {
         // };
 
         let string_loader = || -> RunLog {
-            let mut json_contents = String::new();
-            File::open(&json_file).unwrap().read_to_string(&mut json_contents).unwrap();
-            serde_json::from_str(&json_contents).unwrap()
+            load_run_log(&json_file)
         };
 
         let (dur, run_log) = time_func(&string_loader);
@@ -119,17 +129,36 @@ impl tester::Tester for JsonTest {
 
         let start_regs = run_log.states[0].regs.clone();
 
+        let syms = matches.values_of("symbols").map(|files| {
+            let files : Vec<&str> = files.collect();
+            SymbolTable::from_files(&files).unwrap_or_else(|e| panic!("Can't load symbols {}", e))
+        });
+
+        let mem = run_log.create_memmap();
+
+        // the log's steps get the source lines they ran
+        let steps = match matches.values_of("listing") {
+            Some(files) => {
+                let files : Vec<&str> = files.collect();
+                let lines = LineTable::from_files(&files).unwrap_or_else(|e| panic!("Can't load listing {}", e));
+                run_log.states.into_iter().map(|s| s.with_source(&lines)).collect()
+            }
+            None => run_log.states,
+        };
+
         JsonTest {
             json_file       : json_file.clone(),
             dont_check_hash : matches.is_present("no-hash-check"),
             log_memory      : matches.is_present("log-memory"),
-            mem             : run_log.create_memmap(),
+            mem,
             // cpu             : Cpu::from_regs(&start_regs),
-            steps           : run_log.states,
+            steps,
             check_cycles    : matches.is_present("check-cycles"),
             verbose         : matches.is_present("show-disassembly"),
             regs            : start_regs.clone(),
+            ints            : Interrupts::new(),
             clock           : rc_clock,
+            syms,
         }
     }
 
@@ -163,7 +192,9 @@ impl tester::Tester for JsonTest {
 
             let pc = self.regs.pc;
 
-            let ins = step(&mut self.regs, &mut self.mem, &self.clock);
+            diss.set_dp(self.regs.dp);
+
+            let ins = step(&mut self.regs, &mut self.mem, &self.clock, &mut self.ints);
 
             if let Ok(ins) = ins {
 
@@ -181,8 +212,14 @@ impl tester::Tester for JsonTest {
                 }
             };
 
+            let syms = self.syms.as_ref().map(|s| s as &dyn SymTab);
+
             if self.verbose {
-                let (_, txt) =  diss.diss(&mut self.mem, pc, None);
+                if let Some(label) = syms.and_then(|s| s.get_label(pc)) {
+                    println!("{}", label);
+                }
+
+                let (_, txt) =  diss.diss(&self.mem, pc, syms);
                 println!("({:5}) : ${:04x}   {:20} : {} ", ins.cycles, pc, txt, sim);
             }
 
@@ -198,10 +235,15 @@ impl tester::Tester for JsonTest {
 
                 println!("Error after {} instructions", i);
 
+                if let Some(ref source) = log_before.source {
+                    println!("Source: {}", source);
+                }
+
                 // let writes_str = get_writes_as_str(&mem);
                 // println!("{:04x}   {:20}{:20} : {}", pc, txt, writes_str, sim);
 
-                let (_, txt) =  diss.diss(&mut self.mem, self.regs.pc, None);
+                diss.set_dp(self.regs.dp);
+                let (_, txt) =  diss.diss(&self.mem, self.regs.pc, syms);
                 println!();
 
                 println!("Next op:");
@@ -1,8 +1,10 @@
 mod gregtest;
 mod jsontest;
 mod tester;
+mod bench;
 
 pub use self::gregtest::*;
 pub use self::jsontest::*;
 pub use self::tester::*;
+pub use self::bench::*;
 
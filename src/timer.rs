@@ -42,7 +42,7 @@ impl RunTime {
 
     pub fn secs(&self) -> f64 {
         let nanos = self.nanos();
-        nanos / 1_000_000_000.0
+        self.dur.as_secs() as f64 + nanos / 1_000_000_000.0
     }
 
     pub fn millis(&self) -> f64 {
@@ -0,0 +1,332 @@
+// Cartridge ROM and how it's banked
+//
+// The cart slot only sees A0-A14, anything over 32K has to find another way
+// to pick what's at $0000-$7fff. The two usual ones are wiring PB6 of the
+// VIA in as A15 and a register the cart latches writes to its own space in.
+
+use crate::mem::{MemBus, MemoryIO};
+use crate::cpu::Clock;
+use crate::m6522::M6522;
+
+// Region name of the cart in the memory map and symbol scopes
+pub const CART : &str = "cart";
+
+const BANK_SIZE : usize = 0x8000;
+
+// Every cart starts with this, the BIOS won't run one that doesn't
+const COPYRIGHT : &[u8] = b"g GCE";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapper {
+    // One bank, a smaller image mirrors through it
+    Plain,
+    // PB6 is A15, it's pulled up so an input selects bank 1
+    Pb6,
+    // The last byte written anywhere in $0000-$7fff is the bank
+    Latch,
+}
+
+impl Mapper {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Mapper::Plain),
+            "pb6" => Some(Mapper::Pb6),
+            "latch" => Some(Mapper::Latch),
+            _ => None,
+        }
+    }
+
+    // 64K is two PB6 banks and anything bigger is latched, unless
+    // everything past the first bank is padding or copies of it. The header
+    // isn't looked at, only the bank the BIOS boots needs the copyright and
+    // the others can hold anything so it doesn't say how a cart's banked.
+    // Cart::attach warns if the boot bank hasn't got one.
+    pub fn detect(data : &[u8]) -> Self {
+        if data.len() <= BANK_SIZE {
+            return Mapper::Plain;
+        }
+
+        let (first, rest) = data.split_at(BANK_SIZE);
+        let padding = rest.iter().all(|b| *b == rest[0]);
+        let mirrored = rest.chunks(BANK_SIZE).all(|bank| first.starts_with(bank));
+
+        if padding || mirrored {
+            Mapper::Plain
+        } else if data.len() <= 2 * BANK_SIZE {
+            Mapper::Pb6
+        } else {
+            Mapper::Latch
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Cart {
+    // Whole banks
+    data : Vec<u8>,
+    mapper : Mapper,
+    bank : u16,
+}
+
+impl Cart {
+    // Worked out from data if there's no mapper. An image smaller than a
+    // bank is on a ROM that doesn't decode every address line, it's padded
+    // to the ROM's size and repeats through the bank. Bigger ones are padded
+    // to whole banks.
+    pub fn new(data : &[u8], mapper : Option<Mapper>) -> Self {
+        let mapper = mapper.unwrap_or_else(|| Mapper::detect(data));
+
+        let data = if data.len() < BANK_SIZE {
+            let mut rom = data.to_vec();
+            rom.resize(data.len().max(1).next_power_of_two(), 0);
+            rom.iter().copied().cycle().take(BANK_SIZE).collect()
+        } else {
+            let mut data = data.to_vec();
+            data.resize(data.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+            data
+        };
+
+        Self { data, mapper, bank : 0 }
+    }
+
+    pub fn from_file(file : &str, mapper : Option<Mapper>) -> Result<Self, String> {
+        let data = std::fs::read(file).map_err(|e| format!("{}: {}", file, e))?;
+
+        if data.is_empty() {
+            return Err(format!("{}: empty", file));
+        }
+
+        let ret = Self::new(&data, mapper);
+        info!("Cart {} {}K, {:?} with {} banks", file, data.len() / 1024, ret.mapper, ret.banks());
+
+        Ok(ret)
+    }
+
+    // An empty slot
+    pub fn empty() -> Self {
+        Self::new(&[], Some(Mapper::Plain))
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        self.mapper
+    }
+
+    // Nothing in the slot, or nothing but zeros
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|b| *b == 0)
+    }
+
+    // What's at $0000-$7fff now
+    pub fn bank_data(&self) -> &[u8] {
+        let start = usize::from(self.bank) * BANK_SIZE;
+        &self.data[start .. start + BANK_SIZE]
+    }
+
+    pub fn banks(&self) -> u16 {
+        (self.data.len() / BANK_SIZE) as u16
+    }
+
+    // None if it doesn't bank
+    pub fn bank(&self) -> Option<u16> {
+        if self.mapper != Mapper::Plain && self.banks() > 1 {
+            Some(self.bank)
+        } else {
+            None
+        }
+    }
+
+    // Adds the ROM to mem and maps it at $0000
+    pub fn attach<C : Clock>(&mut self, mem : &mut MemBus<M6522<C>>) {
+        let block = mem.add_rom(CART, &self.data);
+        mem.map_mem(0x0000..=0x7fff, block, 0);
+
+        if self.mapper == Mapper::Latch {
+            mem.set_latch(Some(0x0000..=0x7fff));
+        }
+
+        self.reset(mem);
+
+        let header : Vec<u8> = (0 .. COPYRIGHT.len() as u16).map(|addr| mem.peek(addr)).collect();
+        if header != COPYRIGHT && !self.is_empty() {
+            warn!("No copyright in cart bank {}, the BIOS won't start it", self.bank);
+        }
+    }
+
+    // A latch powers up as bank 0, PB6 is whatever the VIA says
+    pub fn reset<C : Clock>(&mut self, mem : &mut MemBus<M6522<C>>) {
+        self.select(mem, 0);
+        self.update(mem);
+    }
+
+    // After every instruction, follows PB6 or the last write to the latch
+    pub fn update<C : Clock>(&mut self, mem : &mut MemBus<M6522<C>>) {
+        let bank = match self.mapper {
+            Mapper::Plain => return,
+            Mapper::Pb6 => u16::from(mem.devices().port_b_pin(6)),
+            Mapper::Latch => match mem.take_latch() {
+                Some((_, val)) => u16::from(val),
+                None => return,
+            },
+        };
+
+        if bank % self.banks() != self.bank {
+            self.select(mem, bank)
+        }
+    }
+
+    fn select<C : Clock>(&mut self, mem : &mut MemBus<M6522<C>>, bank : u16) {
+        self.bank = bank % self.banks();
+
+        let block = mem.block(CART).expect("cart isn't attached");
+        mem.map_mem(0x0000..=0x7fff, block, usize::from(self.bank) * BANK_SIZE);
+
+        if self.bank().is_some() {
+            mem.set_bank(CART, Some(self.bank));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::cpu::StandardClock;
+
+    type VecMem = MemBus<M6522<StandardClock>>;
+
+    // Bank n is filled with n + 1 apart from $0100-$01ff, a bank that's all
+    // one value is padding
+    fn banks(count : usize) -> Vec<u8> {
+        let bank = |n : usize| (0 .. BANK_SIZE).map(move |i| if i >> 8 == 1 { i as u8 } else { n as u8 + 1 });
+        (0 .. count).flat_map(bank).collect()
+    }
+
+    fn attached(cart : &mut Cart) -> VecMem {
+        let clock = Rc::new(RefCell::new(StandardClock::new(1_500_000)));
+        let mut mem = MemBus::new("test", M6522::new(0xd000, 0x800, &clock));
+        mem.map_device(0xd000..=0xd7ff, ());
+        cart.attach(&mut mem);
+        mem
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Mapper::detect(&banks(1)), Mapper::Plain);
+        assert_eq!(Mapper::detect(&[1; 0x1000]), Mapper::Plain);
+
+        let mut padded = banks(1);
+        padded.resize(2 * BANK_SIZE, 0xff);
+        assert_eq!(Mapper::detect(&padded), Mapper::Plain);
+
+        let mirrored = banks(1).repeat(2);
+        assert_eq!(Mapper::detect(&mirrored), Mapper::Plain);
+        assert_eq!(Mapper::detect(&banks(1).repeat(4)), Mapper::Plain);
+
+        assert_eq!(Mapper::detect(&banks(2)), Mapper::Pb6);
+        assert_eq!(Mapper::detect(&banks(3)), Mapper::Latch);
+        assert_eq!(Mapper::detect(&banks(4)), Mapper::Latch);
+    }
+
+    #[test]
+    fn detect_ignores_the_header() {
+        let with_header = |mut data : Vec<u8>, banks : &[usize]| {
+            for bank in banks {
+                data[bank * BANK_SIZE ..][.. COPYRIGHT.len()].copy_from_slice(COPYRIGHT);
+            }
+            data
+        };
+
+        assert_eq!(Mapper::detect(&with_header(banks(1), &[0])), Mapper::Plain);
+        assert_eq!(Mapper::detect(&with_header(banks(1).repeat(2), &[0, 1])), Mapper::Plain);
+
+        // Which banks have one makes no difference
+        assert_eq!(Mapper::detect(&with_header(banks(2), &[1])), Mapper::Pb6);
+        assert_eq!(Mapper::detect(&with_header(banks(2), &[0, 1])), Mapper::Pb6);
+        assert_eq!(Mapper::detect(&with_header(banks(4), &[0])), Mapper::Latch);
+        assert_eq!(Mapper::detect(&with_header(banks(4), &[0, 1, 2, 3])), Mapper::Latch);
+    }
+
+    #[test]
+    fn small_images_mirror() {
+        let mut image = vec![0; 0x1000];
+        image[0] = 0x67;
+        image[0xfff] = 0x42;
+
+        let cart = Cart::new(&image, None);
+        assert_eq!(cart.banks(), 1);
+        assert_eq!(cart.bank_data()[0x7000], 0x67);
+        assert_eq!(cart.bank_data()[0x7fff], 0x42);
+
+        // a 5K image is on an 8K ROM
+        let cart = Cart::new(&[1; 0x1400], None);
+        assert_eq!(cart.bank_data()[0x13ff], 1);
+        assert_eq!(cart.bank_data()[0x1400], 0);
+        assert_eq!(cart.bank_data()[0x2000], 1);
+
+        assert!(Cart::empty().is_empty());
+        assert_eq!(Cart::empty().bank_data().len(), BANK_SIZE);
+    }
+
+    #[test]
+    fn plain_doesnt_switch() {
+        let mut cart = Cart::new(&banks(2), Some(Mapper::Plain));
+        let mut mem = attached(&mut cart);
+
+        mem.store_byte(0x0000, 1);
+        cart.update(&mut mem);
+
+        assert_eq!(cart.bank(), None);
+        assert_eq!(mem.load_byte(0x1000), 1);
+    }
+
+    #[test]
+    fn pb6_follows_the_via() {
+        let mut cart = Cart::new(&banks(2), None);
+        let mut mem = attached(&mut cart);
+
+        // an input is pulled up
+        assert_eq!(cart.bank(), Some(1));
+        assert_eq!(mem.load_byte(0x1000), 2);
+
+        // PB6 an output, low
+        mem.store_byte(0xd002, 0x40);
+        mem.store_byte(0xd000, 0x00);
+        cart.update(&mut mem);
+        assert_eq!(cart.bank(), Some(0));
+        assert_eq!(mem.load_byte(0x1000), 1);
+
+        mem.store_byte(0xd000, 0x40);
+        cart.update(&mut mem);
+        assert_eq!(cart.bank(), Some(1));
+        assert_eq!(mem.load_byte(0x7fff), 2);
+        assert!(mem.mapping().report().contains(&"$0000-$7fff cart:1".to_string()));
+    }
+
+    #[test]
+    fn latch_follows_writes() {
+        let mut cart = Cart::new(&banks(4), None);
+        let mut mem = attached(&mut cart);
+        assert_eq!(cart.mapper(), Mapper::Latch);
+        assert_eq!(cart.bank(), Some(0));
+
+        mem.store_byte(0x7fff, 2);
+        cart.update(&mut mem);
+        assert_eq!(cart.bank(), Some(2));
+        assert_eq!(mem.load_byte(0x0000), 3);
+
+        // the write doesn't reach the ROM and nothing changes without one
+        assert_eq!(cart.bank_data()[0x7fff], 3);
+        cart.update(&mut mem);
+        assert_eq!(cart.bank(), Some(2));
+
+        // banks wrap
+        mem.store_byte(0x1234, 5);
+        cart.update(&mut mem);
+        assert_eq!(cart.bank(), Some(1));
+
+        cart.reset(&mut mem);
+        assert_eq!(cart.bank(), Some(0));
+    }
+}
@@ -1,9 +1,10 @@
+mod cart;
 mod dac;
 mod veccore;
 mod window;
 
+pub use self::cart::*;
 pub use self::dac::*;
 pub use self::veccore::*;
 pub use self::window::*;
 
-
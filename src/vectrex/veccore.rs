@@ -3,169 +3,81 @@
 use clap::{ArgMatches};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::gdbstub;
-use crate::diss::Disassembler;
+use crate::diss::{Disassembler, SymTab};
+use crate::assembler::assemble_line;
 use crate::mem::*;
-use crate::cpu::{Regs, StandardClock, Clock, InstructionDecoder};
+use crate::cpu::{Regs, StandardClock, Clock, InstructionDecoder, Interrupts, RunState, CpuKind, UndocMode, BusMode, CallStack, Both};
+use crate::cpu::CpuErr;
+use crate::symtab::{SymbolTable, SymbolicFrame, SymKind};
+use crate::linetable::LineTable;
+use crate::profiler::Profiler;
+use crate::xref::{Xrefs, XrefKind, parse_target};
+use crate::annotate::Annotations;
 use crate::cpu;
 
 use crate::m6522::M6522;
 use crate::vectrex::window;
-use crate::vectrex::dac;
+use crate::vectrex::{Cart, Mapper, CART};
 
-use crate::vectrex::dac::Dac;
 
 
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum MemRegion {
-    Illegal,
-    Ram,
-    Rom,
-    Cart,
-    VIA,
-}
-
 static FAST_ROM: &[u8] = include_bytes!("../../resources/fastrom.dat");
 static SYS_ROM: &[u8]  = include_bytes!("../../resources/rom.dat");
 
 // Contains memory and memmapped perihpherals
 // decodes memory map
+//
+// The cart is selected by A15 low, RAM by A11 and the VIA by A12 when
+// A13-15 are 110. Neither decodes every line so RAM mirrors through
+// $c800-$cfff, the VIA's 16 registers through $d000-$d7ff and $d800-$dfff
+// selects both. The cart maps itself, it knows how it's banked.
 
-struct VecMem<C : Clock> {
-    via            : M6522<C>,
-    dac            : dac::Dac,
-    sys_rom        : MemBlock,
-    cart_rom       : MemBlock,
-    ram            : MemBlock,
-    addr_to_region : [MemRegion; 0x1_0000],
-    name           : String,
-}
-
-
-
-impl<C: Clock> VecMem<C> {
-
-    pub fn new(rc_clock : &Rc<RefCell<C>>) -> VecMem<C> {
-
-        info!("creaying vecmem");
-
-        let name = "VecMem".to_string();
+type VecMem<C> = MemBus<M6522<C>>;
 
-        let via = M6522::new(0xd000,0x800, rc_clock);
-
-        let sys_rom   = MemBlock::from_data(0xe000, "sys_rom", FAST_ROM, false);
-        let cart_rom  = MemBlock::new("cart", true, 0, 16 * 1024);
-        let ram       = MemBlock::new("ram", false, 0xc800, 1024);
-
-        let dac       = Dac {};
-
-        let addr_to_region = {
-
-            use self::MemRegion::*;
-
-            let mems : &[(MemRegion, &dyn MemoryIO )] = &[
-                (Rom, &sys_rom ),
-                (Cart, &cart_rom ), 
-                (Ram, &ram ),
-                (VIA, &via) ];
-
-            build_addr_to_region(Illegal, mems)
-        };
-
-        info!("created vecmem");
-
-        VecMem {
-            sys_rom, cart_rom, ram, dac, name,via, addr_to_region
-        }
-    }
+fn make_vec_mem<C : Clock>(rc_clock : &Rc<RefCell<C>>, cart : &mut Cart) -> VecMem<C> {
+    info!("creating vecmem");
 
-    fn get_region(&self, _addr : u16) -> &dyn MemoryIO {
-        let region = self.addr_to_region[_addr as usize];
+    let via = M6522::new(0xd000,0x800, rc_clock);
 
-        use self::MemRegion::*;
+    let mut mem = MemBus::new("VecMem", via);
 
-        match region {
-            Ram     => &self.ram,
-            Rom     => &self.sys_rom,
-            Cart    => &self.cart_rom,
-            VIA     => &self.via,
-            Illegal => panic!("Illegal! read from {:02x}", _addr),
-        }
-    }
+    let ram = mem.add_ram("ram", 1024);
+    let sys_rom = mem.add_rom("sys_rom", FAST_ROM);
 
-    fn get_region_mut(&mut self, _addr : u16) -> &mut dyn MemoryIO {
-        let region = self.addr_to_region[_addr as usize];
+    cart.attach(&mut mem);
+    mem.map_mem(0xc800..=0xcfff, ram, 0);
+    mem.map_device(0xd000..=0xd7ff, ());
+    mem.map_shared(0xd800..=0xdfff, ram, 0, ());
+    mem.map_mem(0xe000..=0xffff, sys_rom, 0);
 
-        use self::MemRegion::*;
+    info!("created vecmem");
 
-        match region {
-            Ram     => &mut self.ram,
-            Rom     => &mut self.sys_rom,
-            Cart    => &mut self.cart_rom,
-            VIA     => &mut self.via,
-            Illegal => panic!("Illegal! read from {:02x}", _addr),
-        }
-    }
-}
-
-impl<C : Clock> MemoryIO for VecMem<C> {
-
-    fn upload(&mut self, _addr : u16, _data : &[u8]) {
-        unimplemented!("TBD")
-    }
-
-    fn get_range(&self) -> (u16, u16) {
-        (0, 0xffff)
-    }
-
-    fn update_sha1(&self, _digest : &mut Sha1) {
-        unimplemented!("TBD")
-    }
-
-    fn inspect_byte(&self, addr:u16) -> u8 {
-        let region = self.get_region(addr);
-        region.inspect_byte(addr)
-    }
-
-    fn load_byte(&mut self, addr:u16) -> u8 {
-        let region = self.get_region_mut(addr);
-        region.load_byte(addr)
-    }
-
-    fn store_byte(&mut self, addr:u16, val:u8) {
-        let region = self.get_region_mut(addr);
-        region.store_byte(addr, val)
-    }
-
-    fn store_word(&mut self, addr:u16, val:u16) {
-        let ah = addr.wrapping_add(1);
-
-        let r1 = self.addr_to_region[addr as usize];
-        let r2 = self.addr_to_region[ah as usize];
-
-        if r1 == r2 {
-            let region = self.get_region_mut(addr);
-            region.store_word(addr, val)
-
-        } else {
-            self.store_byte(addr, ( val >> 8 ) as u8);
-            self.store_byte(ah, val as u8);
-        }
-    }
-
-    fn get_name(&self) -> String {
-        self.name.clone()
-    }
+    mem
 }
 
 pub struct Vectrex {
     regs        : Regs,
+    ints        : Interrupts,
     rc_clock    : Rc<RefCell<StandardClock>>,
     vec_mem     : VecMem<StandardClock>,
+    cart        : Cart,
     window      : window::Window,
     gdb_enabled : bool,
+    call_stack  : CallStack,
+    syms        : SymbolTable,
+    lines       : LineTable,
+    profiler     : Option<Profiler>,
+    profile_file : Option<String>,
+    folded_file  : Option<String>,
+    xrefs        : Option<Xrefs>,
+    xref_file    : Option<String>,
+    notes        : Annotations,
+    // Why it stopped, if it's halted itself
+    halt         : Option<gdbstub::Sigs>,
 }
 
 fn mk_data_mem(addr : u16 ,name : &str, data : &[u8], writeable : bool ) -> Box<dyn MemoryIO> {
@@ -190,45 +102,36 @@ impl gdbstub::DebuggerHost for Vectrex {
     fn add_breakpoint(&mut self, _addr : u16)  {
 
     }
-    fn add_write_watchpoint (&mut self, _addr : u16) {
-
+    fn add_write_watchpoint (&mut self, addr : u16) {
+        self.vec_mem.set_watch(addr, BusAccess::Write, true)
     }
-    fn add_read_watchpoint(&mut self, _addr : u16) {
-
+    fn add_read_watchpoint(&mut self, addr : u16) {
+        self.vec_mem.set_watch(addr, BusAccess::Read, true)
     }
     fn del_breakpoint(&mut self, _addr : u16)  {
     }
 
-    fn del_write_watchpoint(&mut self, _addr : u16)  {
+    fn del_write_watchpoint(&mut self, addr : u16)  {
+        self.vec_mem.set_watch(addr, BusAccess::Write, false)
     }
 
-    fn del_read_watchpoint(&mut self, _addr : u16)  {
+    fn del_read_watchpoint(&mut self, addr : u16)  {
+        self.vec_mem.set_watch(addr, BusAccess::Read, false)
     }
 
     fn examine(&self, addr : u16) -> u8  {
-        self.vec_mem.inspect_byte(addr)
+        self.vec_mem.peek(addr)
     }
 
     fn write (&mut self, addr : u16, val : u8) {
-        self.vec_mem.store_byte(addr, val)
+        self.vec_mem.poke(addr, val)
     }
 
     fn read_registers(&self, reply : &mut gdbstub::Reply) {
 
-        let regs = &self.regs;
-
-        let cc = regs.flags.bits();
-
-        reply.push_u8(cc);
-        reply.push_u8(regs.a);
-        reply.push_u8(regs.b);
-        reply.push_u8(regs.dp);
-
-        reply.push_u16(regs.x);
-        reply.push_u16(regs.y);
-        reply.push_u16(regs.u);
-        reply.push_u16(regs.s);
-        reply.push_u16(regs.pc);
+        for b in gdbstub::regs_to_bytes(&self.regs) {
+            reply.push_u8(b);
+        }
     }
 
     fn write_registers(&mut self, _data : &[u8]) {
@@ -241,23 +144,70 @@ impl gdbstub::DebuggerHost for Vectrex {
     fn set_reg(&self, _r_num : usize, _val : u16) {
         unimplemented!("set_reg r = {}  v = {}", _r_num, _val)
     }
+
+    fn backtrace(&self) -> Vec<String> {
+        self.backtrace()
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| format!("#{:<2} {}", i, frame))
+            .collect()
+    }
+
+    fn disassemble(&mut self, addr : Option<u16>, count : usize) -> Vec<String> {
+        self.disassemble(addr, count)
+    }
+
+    fn assemble(&mut self, addr : u16, text : &str) -> Result<Vec<u8>, String> {
+        self.assemble(addr, text)
+    }
+
+    fn find_line(&self, file : &str, line : usize) -> Result<u16, String> {
+        self.lines.find_line(file, line).map(|l| l.addr)
+    }
+
+    fn source_line(&self, addr : Option<u16>) -> Option<String> {
+        self.source_line(addr)
+    }
+
+    fn xrefs(&self, target : &str, kind : Option<XrefKind>) -> Result<Vec<String>, String> {
+        self.xrefs(target, kind)
+    }
+
+    fn memory_map(&self) -> Vec<String> {
+        self.vec_mem.mapping().report()
+    }
 }
 
 impl Vectrex {
 
     pub fn new() -> Vectrex {
+        Self::with_cart(Cart::empty())
+    }
+
+    pub fn with_cart(mut cart : Cart) -> Vectrex {
 
         let window = window::Window::new();
 
         let rc_clock = Rc::new(RefCell::new(StandardClock::new(1_500_000)));
 
-        let vec_mem = VecMem::new(&rc_clock);
+        let vec_mem = make_vec_mem(&rc_clock, &mut cart);
         info!("back from vecmen!");
 
         let mut ret = Vectrex {
-            rc_clock, vec_mem, window,
+            rc_clock, vec_mem, cart, window,
             regs  : Regs::new(),
-            gdb_enabled : false
+            ints  : Interrupts::new(),
+            gdb_enabled : false,
+            call_stack  : CallStack::new(),
+            syms        : SymbolTable::default(),
+            lines       : LineTable::default(),
+            profiler     : None,
+            profile_file : None,
+            folded_file  : None,
+            xrefs        : None,
+            xref_file    : None,
+            notes        : Annotations::new(),
+            halt         : None,
         };
 
         ret.reset();
@@ -271,7 +221,18 @@ impl Vectrex {
         // use gdbstub::GdbRemote;
         // use std::sync::mpsc;
 
-        let mut ret = Vectrex::new();
+        let mapper = matches.value_of("mapper").and_then(Mapper::from_name);
+        let file = matches.value_of("ROM FILE").unwrap();
+
+        let cart = match Cart::from_file(file, mapper) {
+            Ok(cart) => cart,
+            Err(e) => {
+                warn!("No cart loaded, {}", e);
+                Cart::empty()
+            }
+        };
+
+        let mut ret = Vectrex::with_cart(cart);
 
         info!("back from vecmen");
 
@@ -281,48 +242,326 @@ impl Vectrex {
 
         ret.gdb_enabled = gdb_enabled;
 
+        if let Some(cpu) = matches.value_of("cpu").and_then(CpuKind::from_name) {
+            info!("cpu {:?}", cpu);
+            ret.regs = Regs::with_cpu(cpu);
+            ret.reset();
+        }
+
+        if let Some(undoc) = matches.value_of("undocumented").and_then(UndocMode::from_name) {
+            ret.regs.undoc = undoc;
+        }
+
+        if matches.is_present("cycle-accurate") {
+            ret.regs.bus = BusMode::CycleAccurate;
+        }
+
+        if let Some(unmapped) = matches.value_of("unmapped").and_then(Unmapped::from_name) {
+            info!("unmapped accesses {:?}", unmapped);
+            ret.vec_mem.set_unmapped(unmapped);
+        }
+
+        ret.vec_mem.set_halt_on_rom_write(matches.is_present("halt-on-rom-write"));
+
+        ret.profile_file = matches.value_of("profile").map(|f| f.to_string());
+        ret.folded_file = matches.value_of("folded").map(|f| f.to_string());
+
+        if ret.profile_file.is_some() || ret.folded_file.is_some() {
+            info!("Profiling");
+            ret.profiler = Some(Profiler::new());
+        }
+
+        if let Some(files) = matches.values_of("symbols") {
+            let files : Vec<&str> = files.collect();
+
+            match SymbolTable::from_files(&files) {
+                Ok(syms) => ret.syms = syms,
+                Err(e) => warn!("No symbols loaded, {}", e),
+            }
+        }
+
+        // Checked against the BIOS that's running, its labels are BIOS ones
+        if let Some(file) = matches.value_of("annotations") {
+            match Annotations::load(file, FAST_ROM, 0xe000) {
+                Ok(notes) => {
+                    notes.add_labels(&mut ret.syms);
+                    ret.notes = notes;
+                }
+                Err(e) => warn!("No annotations loaded, {}", e),
+            }
+        }
+
+        // symbol files don't say what's BIOS, RAM or the VIA but the memory
+        // map does
+        ret.syms.set_kind_in(0xe000..=0xffff, SymKind::Bios);
+        ret.syms.set_kind_in(0xd000..=0xd7ff, SymKind::Hardware);
+        ret.syms.set_kind_in(0xc800..=0xcfff, SymKind::Data);
+
+        if let Some(bank) = ret.cart.bank() {
+            info!("{} starts in bank {} of {}", CART, bank, ret.cart.banks());
+        }
+
+        ret.xref_file = matches.value_of("xref").map(|f| f.to_string());
+
+        if ret.xref_file.is_some() {
+            info!("Recording cross references");
+            let mut xrefs = Xrefs::new();
+
+            if let Err(e) = xrefs.add_rom(FAST_ROM, 0xe000, &ret.syms) {
+                warn!("No static cross references, {}", e);
+            }
+
+            // Only the bank it starts in, other banks are seen as they run
+            if !ret.cart.is_empty() {
+                if let Err(e) = xrefs.add_rom(ret.cart.bank_data(), 0x0000, &ret.syms) {
+                    warn!("No static cross references for the cart, {}", e);
+                }
+            }
+
+            ret.xrefs = Some(xrefs);
+        }
+
+        if let Some(files) = matches.values_of("listing") {
+            let files : Vec<&str> = files.collect();
+
+            match LineTable::from_files(&files) {
+                Ok(lines) => ret.lines = lines,
+                Err(e) => warn!("No source lines loaded, {}", e),
+            }
+        }
+
         info!("done reset");
 
         ret
     }
 
+    // Runs until it stops itself or ctrl-c, then writes whatever reports
+    // were asked for. The window's a stub and --enable-gdb doesn't start a
+    // debugger yet, so ctrl-c is the only way to ask it to stop. Without
+    // catching it the process dies and the reports are never written.
     pub fn run(&mut self) {
+        let quit = Arc::new(AtomicBool::new(false));
+        let handler_quit = quit.clone();
+
+        if let Err(e) = ctrlc::set_handler(move || handler_quit.store(true, Ordering::Relaxed)) {
+            warn!("Can't catch ctrl-c, {}", e);
+        }
+
+        info!("Running, ctrl-c to stop");
+
+        let mut instructions = 0u64;
+
+        while !quit.load(Ordering::Relaxed) {
+            if self.update().is_none() {
+                break;
+            }
+            instructions += 1;
+        }
+
+        match self.take_halt() {
+            Some(sig) => info!("Stopped with {:?} at ${:04x}", sig, self.regs.pc),
+            None => info!("Quit at ${:04x}", self.regs.pc),
+        }
+
+        info!("Ran {} instructions, {} cycles", instructions, self.rc_clock.borrow().get_cycles());
+
+        self.write_profile();
+        self.write_xrefs();
+    }
+
+    // The profiler keeps its own call stack when it's running, xrefs are
+    // recorded alongside whichever it is. A bank switch shows from the next
+    // instruction on.
+    fn step_cpu(&mut self) -> Result<InstructionDecoder, CpuErr> {
+        let res = match (self.profiler.as_mut(), self.xrefs.as_mut()) {
+            (Some(profiler), Some(xrefs)) => cpu::step_checked(&mut self.regs, &mut self.vec_mem, &self.rc_clock, &mut self.ints, &mut Both(profiler, xrefs)),
+            (Some(profiler), None) => cpu::step_checked(&mut self.regs, &mut self.vec_mem, &self.rc_clock, &mut self.ints, profiler),
+            (None, Some(xrefs)) => cpu::step_checked(&mut self.regs, &mut self.vec_mem, &self.rc_clock, &mut self.ints, &mut Both(&mut self.call_stack, xrefs)),
+            (None, None) => cpu::step_checked(&mut self.regs, &mut self.vec_mem, &self.rc_clock, &mut self.ints, &mut self.call_stack),
+        };
+
+        self.cart.update(&mut self.vec_mem);
+        res
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        match self.profiler {
+            Some(ref profiler) => profiler.call_stack(),
+            None => &self.call_stack,
+        }
+    }
+
+    fn write_profile(&self) {
+        if let Some(ref profiler) = self.profiler {
+            if let Some(ref file) = self.profile_file {
+                match profiler.write_report(file, &self.syms) {
+                    Ok(_) => info!("Wrote profile to {}", file),
+                    Err(e) => warn!("Couldn't write profile to {} : {}", file, e),
+                }
+            }
+
+            if let Some(ref file) = self.folded_file {
+                match profiler.write_folded(file, &self.syms) {
+                    Ok(_) => info!("Wrote folded stacks to {}", file),
+                    Err(e) => warn!("Couldn't write folded stacks to {} : {}", file, e),
+                }
+            }
+        }
+    }
+
+    fn write_xrefs(&self) {
+        if let (Some(xrefs), Some(file)) = (self.xrefs.as_ref(), self.xref_file.as_ref()) {
+            match xrefs.write(file, &self.syms) {
+                Ok(_) => info!("Wrote {} cross referenced addresses to {}", xrefs.len(), file),
+                Err(e) => warn!("Couldn't write cross references : {}", e),
+            }
+        }
+    }
 
+    // What refers to target, a symbol or an address
+    pub fn xrefs(&self, target : &str, kind : Option<XrefKind>) -> Result<Vec<String>, String> {
+        let xrefs = self.xrefs.as_ref().ok_or("Not recording cross references, run with --xref")?;
+        let addr = parse_target(target, &self.syms)?;
+        let syms = self.syms.mapped(self.vec_mem.mapping());
+        Ok(xrefs.report(addr, kind, &syms))
     }
 
-    pub fn update(&mut self) -> InstructionDecoder {
-        let ins = cpu::step(&mut self.regs, &mut self.vec_mem, &self.rc_clock);
+    fn update_irqs(&mut self) {
+        let irq = self.vec_mem.devices().irq();
+        self.ints.set_irq(irq);
+    }
+
+    // Stops it for the debugger, memory errors as a segfault or a trap and
+    // anything else the cpu can't run as an illegal instruction
+    fn check_step(&mut self, res : Result<InstructionDecoder, CpuErr>, pc : u16) -> Option<InstructionDecoder> {
+        match res {
+            Ok(ins) => Some(ins),
+
+            Err(CpuErr::Memory(err)) => {
+                warn!("{} by instruction at ${:04x}", err, pc);
+                self.log_backtrace();
 
-        if let Ok(ins) = ins {
-            ins
-        } else {
-            panic!("fucked")
+                self.halt = Some(if err.is_watch() {
+                    gdbstub::Sigs::SIGTRAP
+                } else {
+                    gdbstub::Sigs::SIGSEGV
+                });
+
+                None
+            }
+
+            Err(e) => {
+                warn!("cpu error {:?} at ${:04x}", e, pc);
+                self.log_backtrace();
+                self.halt = Some(gdbstub::Sigs::SIGILL);
+                None
+            }
         }
     }
 
-    pub fn step(&mut self) -> InstructionDecoder {
+    // Why it stopped since last asked
+    pub fn take_halt(&mut self) -> Option<gdbstub::Sigs> {
+        self.halt.take()
+    }
+
+    // None if it stopped, take_halt says why
+    pub fn update(&mut self) -> Option<InstructionDecoder> {
+        self.update_irqs();
+
+        let pc = self.regs.pc;
+        let res = self.step_cpu();
+        self.check_step(res, pc)
+    }
+
+    pub fn step(&mut self) -> Option<InstructionDecoder> {
 
-        let mut diss = Disassembler::new();
+        let mut diss = Disassembler::with_cpu(self.regs.cpu);
+        diss.set_dp(self.regs.dp);
 
         let pc = self.regs.pc;
-        let (_, txt) =  diss.diss(&mut self.vec_mem, pc, None);
+        let syms = self.syms.mapped(self.vec_mem.mapping());
+        let label = syms.get_label(pc);
+        let (_, txt) =  diss.diss(&self.vec_mem, pc, Some(&syms));
+
+        let txt = match self.notes.comment(pc) {
+            Some(comment) => format!("{:20} ; {}", txt, comment),
+            None => txt,
+        };
 
-        if let Ok(ins) = cpu::step(&mut self.regs, &mut self.vec_mem, &self.rc_clock) {
-        if self.vec_mem.via.is_dirty() {
+        self.update_irqs();
+
+        let res = self.step_cpu();
+        let ins = self.check_step(res, pc)?;
+
+        if self.vec_mem.devices().is_dirty() {
+            if let Some(label) = label {
+                println!("{}", label);
+            }
+            if let Some(source) = self.source_line(Some(pc)) {
+                println!("{}", source);
+            }
             println!("${:04x}   {:20} : {} ",  pc, txt, self.regs);
             println!();
-            self.vec_mem.via.clear_dirty();
+            self.vec_mem.devices_mut().clear_dirty();
         }
-        ins
 
-        } else {
-            panic!("fix this!");
+        Some(ins)
+    }
+
+    pub fn run_state(&self) -> RunState {
+        self.ints.get_run_state()
+    }
+
+    // Symbols are looked up through whatever's mapped now
+    pub fn backtrace(&self) -> Vec<SymbolicFrame> {
+        self.syms.mapped(self.vec_mem.mapping()).backtrace(self.call_stack(), self.regs.pc)
+    }
+
+    // Symbolic disassembly from addr, or the pc if there isn't one
+    pub fn disassemble(&mut self, addr : Option<u16>, count : usize) -> Vec<String> {
+        let mut diss = Disassembler::with_cpu(self.regs.cpu);
+        diss.set_dp(self.regs.dp);
+
+        let addr = addr.unwrap_or(self.regs.pc);
+        let syms = self.syms.mapped(self.vec_mem.mapping());
+        let notes = &self.notes;
+        diss.diss_lines_with(&self.vec_mem, addr, count, Some(&syms), |a| notes.comment(a))
+    }
 
+    // Where addr, or the pc if there isn't one, is in the listings
+    pub fn source_line(&self, addr : Option<u16>) -> Option<String> {
+        let addr = addr.unwrap_or(self.regs.pc);
+        self.lines.lookup(addr).map(|l| l.to_string())
+    }
+
+    // Assemble text into memory at addr, with the BIOS symbols and the
+    // current dp
+    pub fn assemble(&mut self, addr : u16, text : &str) -> Result<Vec<u8>, String> {
+        let bytes = assemble_line(text, addr, Some(&self.syms), Some(self.regs.dp))?;
+
+        self.vec_mem.upload(addr, &bytes);
+
+        Ok(bytes)
+    }
+
+    fn log_backtrace(&self) {
+        warn!("Backtrace:");
+
+        for (i, frame) in self.backtrace().iter().enumerate() {
+            warn!("  #{:<2} {}", i, frame);
         }
     }
 
     pub fn reset(&mut self) {
 
+        self.ints.reset();
+        self.call_stack.reset();
+        self.halt = None;
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.reset();
+        }
+        self.cart.reset(&mut self.vec_mem);
         cpu::reset(&mut self.regs, &mut self.vec_mem);
     }
 }
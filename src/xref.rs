@@ -0,0 +1,384 @@
+// Cross references
+//
+// For every address something refers to, the instructions that read it,
+// write it, jump to it or call it. They come from two places:
+//   static  - operands of the code the ROM disassembler traced, known
+//             without running anything
+//   dynamic - an observer on the cpu, which also sees where indexed and
+//             indirect operands really went
+// and any one reference can have been found by either or both.
+//
+// Saved as json keyed by symbol name, $xxxx where there isn't one, and
+// loaded back from that so runs can be merged with each other and with a
+// static pass over the ROM.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+use crate::cpu::{InstructionDecoder, Observer, Regs, RegEnum, is_call};
+use crate::decoded::{Decoded, EffectiveAddr, Flow};
+use crate::diss::SymTab;
+use crate::romdiss::{RomDisassembler, parse_addr};
+use crate::symtab::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XrefKind {
+    Read,
+    Write,
+    Jump,
+    Call,
+}
+
+impl XrefKind {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "read" => Some(XrefKind::Read),
+            "write" => Some(XrefKind::Write),
+            "jump" => Some(XrefKind::Jump),
+            "call" => Some(XrefKind::Call),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for XrefKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            XrefKind::Read => "read",
+            XrefKind::Write => "write",
+            XrefKind::Jump => "jump",
+            XrefKind::Call => "call",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Xref {
+    // Address of the instruction
+    pub from : u16,
+    pub kind : XrefKind,
+    // Found by decoding the ROM
+    pub is_static : bool,
+    // Times it was seen running, 0 if it never was
+    pub hits : u64,
+}
+
+impl Xref {
+    // from+symbol, what and how it was found
+    pub fn describe(&self, syms : &dyn SymTab) -> String {
+        let name = syms.get_near_symbol(self.from).map(|n| format!(" {}", n)).unwrap_or_default();
+
+        let seen = match (self.is_static, self.hits) {
+            (true, 0) => "static".to_string(),
+            (true, hits) => format!("static, {} hits", hits),
+            (false, hits) => format!("{} hits", hits),
+        };
+
+        format!("${:04x}{:24} {:5} ({})", self.from, name, self.kind, seen)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Json, one entry per target
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonRef {
+    from : u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from_sym : Option<String>,
+    kind : XrefKind,
+    #[serde(rename = "static")]
+    is_static : bool,
+    hits : u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonTarget {
+    addr : u16,
+    refs : Vec<JsonRef>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default)]
+pub struct Xrefs {
+    // Target to its refs by where they're from and what they do
+    refs : BTreeMap<u16, BTreeMap<(u16, XrefKind), Xref>>,
+    // Accesses by the instruction running now, kept until it's done so
+    // stack traffic can be left out
+    pending : Vec<(u16, XrefKind)>,
+    // End of the instruction so far, reads from here on are still fetching
+    // it. Jumps move the decoder's next_addr so it can't be used
+    fetched_to : u16,
+    // It pushed or pulled something
+    stacked : bool,
+}
+
+impl Xrefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file(file : &str) -> Result<Self, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        Self::from_json(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    // How many addresses are referred to
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+
+    fn entry(&mut self, target : u16, from : u16, kind : XrefKind) -> &mut Xref {
+        self.refs
+            .entry(target)
+            .or_default()
+            .entry((from, kind))
+            .or_insert(Xref { from, kind, is_static : false, hits : 0 })
+    }
+
+    pub fn add_static(&mut self, target : u16, from : u16, kind : XrefKind) {
+        self.entry(target, from, kind).is_static = true;
+    }
+
+    pub fn add_hits(&mut self, target : u16, from : u16, kind : XrefKind, hits : u64) {
+        self.entry(target, from, kind).hits += hits;
+    }
+
+    pub fn merge(&mut self, other : &Xrefs) {
+        for (target, xref) in other.iter() {
+            let ours = self.entry(target, xref.from, xref.kind);
+            ours.is_static |= xref.is_static;
+            ours.hits += xref.hits;
+        }
+    }
+
+    // Forget what the last static pass found, for when the ROM changes
+    pub fn clear_static(&mut self) {
+        for refs in self.refs.values_mut() {
+            refs.retain(|_, x| {
+                x.is_static = false;
+                x.hits > 0
+            });
+        }
+
+        self.refs.retain(|_, refs| !refs.is_empty());
+    }
+
+    // Trace a ROM from its vectors, any cart header and the code symbols
+    // inside it, then add what the code found refers to
+    pub fn add_rom(&mut self, data : &[u8], base : u16, syms : &SymbolTable) -> Result<(), String> {
+        let mut diss = RomDisassembler::new(data, base)?;
+
+        for sym in syms.symbols().filter(|s| s.kind.is_code()) {
+            diss.add_entry(sym.val);
+        }
+
+        diss.trace();
+        diss.add_xrefs(self);
+        Ok(())
+    }
+
+    // Everything the operands of one decoded instruction say it refers to
+    pub fn add_decoded(&mut self, ins : &Decoded) {
+        let from = ins.addr;
+
+        match ins.flow {
+            Flow::Call(Some(target)) => self.add_static(target, from, XrefKind::Call),
+            Flow::Jump(Some(target)) | Flow::Branch { target, .. } => self.add_static(target, from, XrefKind::Jump),
+            _ => (),
+        }
+
+        // Where it ends up isn't known, the pointer it goes through is
+        if let Some(EffectiveAddr::Indirect(ref inner)) = ins.ea {
+            if let EffectiveAddr::Absolute(ptr) = **inner {
+                self.add_static(ptr, from, XrefKind::Read);
+            }
+            return;
+        }
+
+        if let Some(addr) = ins.operand_addr() {
+            let (reads, writes) = ins.mem_access();
+
+            if reads {
+                self.add_static(addr, from, XrefKind::Read);
+            }
+
+            if writes {
+                self.add_static(addr, from, XrefKind::Write);
+            }
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Queries
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Xref)> {
+        self.refs
+            .iter()
+            .flat_map(|(target, refs)| refs.values().map(move |x| (*target, x)))
+    }
+
+    // Refs to target in address order
+    pub fn refs_to(&self, target : u16) -> Vec<&Xref> {
+        self.refs
+            .get(&target)
+            .map(|refs| refs.values().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn refs_to_kind(&self, target : u16, kind : XrefKind) -> Vec<&Xref> {
+        self.refs_to(target).into_iter().filter(|x| x.kind == kind).collect()
+    }
+
+    // Targets the instruction at from refers to
+    pub fn refs_from(&self, from : u16) -> Vec<(u16, &Xref)> {
+        self.iter().filter(|(_, x)| x.from == from).collect()
+    }
+
+    // A header then one line per ref, only those of kind if there is one,
+    // for the command line and debugger
+    pub fn report(&self, target : u16, kind : Option<XrefKind>, syms : &dyn SymTab) -> Vec<String> {
+        let name = syms.get_near_symbol(target).map(|n| format!(" {}", n)).unwrap_or_default();
+
+        let refs = match kind {
+            Some(kind) => self.refs_to_kind(target, kind),
+            None => self.refs_to(target),
+        };
+
+        let mut ret = vec![format!("${:04x}{}: {} refs", target, name, refs.len())];
+        ret.extend(refs.iter().map(|x| format!("  {}", x.describe(syms))));
+        ret
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Json
+
+    pub fn to_json(&self, syms : &dyn SymTab) -> String {
+        let mut targets : BTreeMap<String, JsonTarget> = BTreeMap::new();
+
+        for (target, refs) in &self.refs {
+            let refs = refs.values().map(|x| JsonRef {
+                from : x.from,
+                from_sym : syms.get_near_symbol(x.from),
+                kind : x.kind,
+                is_static : x.is_static,
+                hits : x.hits,
+            }).collect();
+
+            let by_addr = format!("${:04x}", target);
+
+            // Two targets can only share a name if it's scoped to different
+            // banks, the second goes by its address
+            let key = match syms.get_symbol(*target) {
+                Some(name) if !targets.contains_key(&name) => name,
+                _ => by_addr,
+            };
+
+            targets.insert(key, JsonTarget { addr : *target, refs });
+        }
+
+        serde_json::to_string_pretty(&targets).unwrap()
+    }
+
+    pub fn from_json(text : &str) -> Result<Self, String> {
+        let targets : BTreeMap<String, JsonTarget> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut ret = Self::new();
+
+        for target in targets.values() {
+            for r in &target.refs {
+                let x = ret.entry(target.addr, r.from, r.kind);
+                x.is_static |= r.is_static;
+                x.hits += r.hits;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn write(&self, file : &str, syms : &dyn SymTab) -> Result<(), String> {
+        fs::write(file, self.to_json(syms)).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    // The first byte of a word stands for both
+    fn access(&mut self, addr : u16, kind : XrefKind) {
+        if !self.pending.contains(&(addr.wrapping_sub(1), kind)) {
+            self.pending.push((addr, kind));
+        }
+    }
+
+    fn forget(&mut self, addr : u16, kind : XrefKind) {
+        if let Some(i) = self.pending.iter().rposition(|p| *p == (addr, kind)) {
+            self.pending.remove(i);
+        }
+    }
+}
+
+// A symbol or an address, for asking about a target
+pub fn parse_target(text : &str, syms : &SymbolTable) -> Result<u16, String> {
+    syms.lookup(text)
+        .map(Ok)
+        .unwrap_or_else(|| parse_addr(text).map_err(|_| format!("No symbol or address {}", text)))
+}
+
+impl Observer for Xrefs {
+
+    fn before_execute(&mut self, ins : &InstructionDecoder, _regs : &Regs) {
+        // Anything before now was the opcode or an interrupt being taken
+        self.pending.clear();
+        self.fetched_to = ins.next_addr;
+        self.stacked = false;
+    }
+
+    fn after_execute(&mut self, ins : &InstructionDecoder, _before : &Regs, after : &Regs, _cycles : u32) {
+        let from = ins.addr;
+
+        for (addr, kind) in std::mem::take(&mut self.pending) {
+            self.add_hits(addr, from, kind, 1);
+        }
+
+        // Other than calls anything that stacked on the way to somewhere
+        // else was a return, an SWI or an interrupt, none of them refer to
+        // where they went
+        if after.pc != self.fetched_to {
+            if is_call(ins.op_code) {
+                self.add_hits(after.pc, from, XrefKind::Call, 1);
+            } else if !self.stacked {
+                self.add_hits(after.pc, from, XrefKind::Jump, 1);
+            }
+        }
+    }
+
+    fn mem_read(&mut self, addr : u16, _val : u8) {
+        if addr == self.fetched_to {
+            self.fetched_to = addr.wrapping_add(1);
+        } else {
+            self.access(addr, XrefKind::Read)
+        }
+    }
+
+    fn mem_write(&mut self, addr : u16, _val : u8) {
+        self.access(addr, XrefKind::Write)
+    }
+
+    fn stack_push(&mut self, _stack : RegEnum, addr : u16, _val : u8) {
+        self.forget(addr, XrefKind::Write);
+        self.stacked = true;
+    }
+
+    fn stack_pull(&mut self, _stack : RegEnum, addr : u16, _val : u8) {
+        self.forget(addr, XrefKind::Read);
+        self.stacked = true;
+    }
+}